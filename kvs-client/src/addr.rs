@@ -0,0 +1,71 @@
+//! Where a server listens for connections.
+
+use std::fmt;
+use std::net::SocketAddr;
+use std::path::PathBuf;
+use std::str::FromStr;
+
+use anyhow::{ensure, Context, Error, Result};
+
+/// The address of a server: a TCP address, or the path of a Unix socket for a server on the same
+/// host.
+///
+/// Parses from either a socket address, like `127.0.0.1:4000`, or a `unix://` URL with an absolute
+/// path, like `unix:///run/kvs.sock`.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ServerAddr {
+    Tcp(SocketAddr),
+    Unix(PathBuf),
+}
+
+impl From<SocketAddr> for ServerAddr {
+    fn from(addr: SocketAddr) -> Self {
+        Self::Tcp(addr)
+    }
+}
+
+impl FromStr for ServerAddr {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self> {
+        match str.strip_prefix("unix://") {
+            Some(path) => {
+                ensure!(
+                    path.starts_with('/'),
+                    "A Unix socket address needs an absolute path, like unix:///run/kvs.sock"
+                );
+                Ok(Self::Unix(path.into()))
+            }
+            None => Ok(Self::Tcp(str.parse().context("Invalid socket address")?)),
+        }
+    }
+}
+
+impl fmt::Display for ServerAddr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Tcp(addr) => write!(f, "{addr}"),
+            Self::Unix(path) => write!(f, "unix://{}", path.display()),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_addresses() {
+        assert_eq!(
+            "127.0.0.1:4000".parse::<ServerAddr>().unwrap(),
+            ServerAddr::Tcp("127.0.0.1:4000".parse().unwrap())
+        );
+        let unix = "unix:///run/kvs.sock".parse::<ServerAddr>().unwrap();
+        assert_eq!(unix, ServerAddr::Unix("/run/kvs.sock".into()));
+        assert_eq!(unix.to_string(), "unix:///run/kvs.sock");
+
+        assert!("unix://kvs.sock".parse::<ServerAddr>().is_err());
+        assert!("/run/kvs.sock".parse::<ServerAddr>().is_err());
+        assert!("localhost".parse::<ServerAddr>().is_err());
+    }
+}
@@ -0,0 +1,204 @@
+//! A client for making network requests to a remote `KvsServer` from async code.
+//!
+//! Commands are pipelined on a single persistent connection: a writer task sends each command as
+//! soon as it's issued and hands the means to respond to it to a reader task, which matches
+//! responses to commands by their order. That way any number of commands can be in flight at once
+//! without waiting on each other's responses.
+
+use std::net::SocketAddr;
+
+use anyhow::{anyhow, Context, Error, Result};
+use protocol::{Cmd, Limits, Response};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::{OwnedReadHalf, OwnedWriteHalf};
+use tokio::net::TcpStream;
+use tokio::sync::{mpsc, oneshot};
+use tracing::{debug, info, warn};
+
+use crate::client::unexpected;
+
+/// Sends the response to a command back to whoever issued it.
+type Responder = oneshot::Sender<Result<Response<'static>>>;
+
+/// A client for making network requests to a remote `KvsServer` from async code. This needs to be
+/// used within a tokio runtime, which drives the connection.
+///
+/// Cloning a client is cheap, and clones share its connection. If the server closes the
+/// connection, e.g. because it went unused for too long, the next command reconnects.
+///
+/// Responses larger than the default [`Limits`] allow are errors, so a broken server can't make
+/// the client allocate for a huge response.
+#[derive(Clone)]
+pub struct AsyncClient {
+    requests: mpsc::UnboundedSender<(Cmd<'static>, Responder)>,
+}
+
+impl AsyncClient {
+    /// Connects to the server at the provided address.
+    pub async fn connect(addr: SocketAddr) -> Result<Self> {
+        Self::connect_with_token(addr, None).await
+    }
+
+    /// Connects to the server at the provided address, authenticating every connection with the
+    /// token if there is one.
+    pub async fn connect_with_token(addr: SocketAddr, token: Option<String>) -> Result<Self> {
+        let stream = connect(addr, token.as_deref()).await?;
+        let (requests, receiver) = mpsc::unbounded_channel();
+        tokio::spawn(write_cmds(addr, token, stream, receiver));
+
+        Ok(Self { requests })
+    }
+
+    /// Issues a set command for the key and value to the remote server. Resolves to `Ok(())` if it
+    /// succeeded and an `Err` otherwise.
+    pub async fn set(&self, key: &str, value: &str) -> Result<()> {
+        let cmd = Cmd::Set(key.to_owned().into(), value.to_owned().into());
+        match self.request(cmd).await? {
+            Response::SuccessfulSet => Ok(()),
+            other_response => Err(unexpected("set", other_response)),
+        }
+    }
+
+    /// Issues a get command for the key to the remote server. Resolves to `Ok(Some)` if the
+    /// command found a value, `Ok(None)` if the key wasn't present, and an `Err` otherwise.
+    pub async fn get(&self, key: &str) -> Result<Option<String>> {
+        let cmd = Cmd::Get(key.to_owned().into());
+        match self.request(cmd).await? {
+            Response::SuccessfulGet(value) => Ok(Some(value.into_owned())),
+            Response::KeyNotFound => Ok(None),
+            other_response => Err(unexpected("get", other_response)),
+        }
+    }
+
+    /// Issues an rm command for the key to the remote server. Resolves to `Ok(())` if the command
+    /// succeeded and an `Err` otherwise.
+    pub async fn rm(&self, key: &str) -> Result<()> {
+        let cmd = Cmd::Rm(key.to_owned().into());
+        match self.request(cmd).await? {
+            Response::SuccessfulRm => Ok(()),
+            other_response => Err(unexpected("rm", other_response)),
+        }
+    }
+
+    /// Hands a command to the writer task and waits for its response.
+    async fn request(&self, cmd: Cmd<'static>) -> Result<Response<'static>> {
+        let (responder, response) = oneshot::channel();
+        self.requests
+            .send((cmd, responder))
+            .map_err(|_| anyhow!("Connection task stopped"))?;
+        response.await.context("Connection task stopped")?
+    }
+}
+
+/// Connects to the server, authenticates if there's a token and asks the server to keep the
+/// connection alive.
+async fn connect(addr: SocketAddr, token: Option<&str>) -> Result<TcpStream> {
+    debug!(?addr, "Connecting to server");
+    let mut stream = TcpStream::connect(addr)
+        .await
+        .context("Connecting to server")?;
+
+    let mut bytes = Vec::new();
+    if let Some(token) = token {
+        Cmd::Auth(token.into()).write(&mut bytes)?;
+    }
+    Cmd::KeepAlive.write(&mut bytes)?;
+    stream
+        .write_all(&bytes)
+        .await
+        .context("Requesting keep-alive")?;
+
+    Ok(stream)
+}
+
+/// Writes commands to the server as they're issued, until every client is dropped. The reader task
+/// for the current connection is given each command's responder once the command is written.
+async fn write_cmds(
+    addr: SocketAddr,
+    token: Option<String>,
+    stream: TcpStream,
+    mut requests: mpsc::UnboundedReceiver<(Cmd<'static>, Responder)>,
+) {
+    let mut connection = Some(spawn_reader(stream));
+    let mut bytes = Vec::new();
+    while let Some((cmd, responder)) = requests.recv().await {
+        // The reader task stops once the connection is closed.
+        let connected = matches!(&connection, Some((_, responders)) if !responders.is_closed());
+        if !connected {
+            match connect(addr, token.as_deref()).await {
+                Ok(stream) => connection = Some(spawn_reader(stream)),
+                Err(e) => {
+                    let _ = responder.send(Err(e));
+                    continue;
+                }
+            }
+        }
+        let (stream, responders) = connection.as_mut().expect("connected above");
+
+        info!(?cmd, "Writing to server");
+        bytes.clear();
+        let written = match cmd.write(&mut bytes) {
+            Ok(_) => stream.write_all(&bytes).await.context("Writing to server"),
+            Err(e) => Err(e),
+        };
+        if let Err(e) = written {
+            warn!(?e, "Failed to write command");
+            let _ = responder.send(Err(e));
+            connection = None;
+            continue;
+        }
+
+        if let Err(mpsc::error::SendError(responder)) = responders.send(responder) {
+            let _ = responder.send(Err(anyhow!("Connection closed")));
+        }
+    }
+    debug!("Every client dropped, closing connection");
+}
+
+/// Spawns a task to read responses from the connection and returns the write half of the
+/// connection, along with a channel to give that task the responders for written commands.
+fn spawn_reader(stream: TcpStream) -> (OwnedWriteHalf, mpsc::UnboundedSender<Responder>) {
+    let (read_half, write_half) = stream.into_split();
+    let (responders, receiver) = mpsc::unbounded_channel();
+    tokio::spawn(read_responses(read_half, receiver));
+    (write_half, responders)
+}
+
+/// Reads responses from the connection and sends each to the responder for the command it's for,
+/// until the connection closes. Any commands still waiting on a response then get an error.
+async fn read_responses(
+    mut stream: OwnedReadHalf,
+    mut responders: mpsc::UnboundedReceiver<Responder>,
+) {
+    let mut buf = Vec::new();
+    let error = loop {
+        match Response::decode_frame(&buf, &Limits::default()) {
+            Ok(Some((response, bytes_read))) => {
+                let response = response.into_owned();
+                buf.drain(..bytes_read);
+                match responders.recv().await {
+                    Some(responder) => {
+                        let _ = responder.send(Ok(response));
+                    }
+                    // The writer task stopped, so nothing is waiting on responses.
+                    None => return,
+                }
+                continue;
+            }
+            Ok(None) => {}
+            Err(e) => break e.context("Parsing response"),
+        }
+
+        match stream.read_buf(&mut buf).await {
+            Ok(0) => break anyhow!("Connection closed"),
+            Ok(_) => {}
+            Err(e) => break Error::from(e).context("Reading response"),
+        }
+    };
+
+    debug!(?error, "Stopped reading responses");
+    responders.close();
+    while let Ok(responder) = responders.try_recv() {
+        let _ = responder.send(Err(anyhow!("{error:#}")));
+    }
+}
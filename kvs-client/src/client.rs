@@ -2,48 +2,96 @@
 
 use std::borrow::Cow;
 use std::io::Read;
-use std::net::{Shutdown, SocketAddr, TcpStream};
+use std::sync::Arc;
+use std::thread;
 
-use anyhow::{anyhow, Context, Result};
-use protocol::{Cmd, Response};
-use tracing::{debug, info};
+use anyhow::{anyhow, Context, Error, Result};
+use protocol::{Cmd, Response, ServerInfo};
+use tracing::{debug, info, warn};
+
+use crate::retry::{self, RetryPolicy};
+use crate::{ClientTls, Connection, ServerAddr, ServerError};
+
+pub use builder::ClientBuilder;
+pub(crate) use builder::Timeouts;
+
+mod builder;
+mod pool;
+
+use pool::ConnectionPool;
 
 /// A client for making network requests to a remote `KvsServer`.
+///
+/// Cloning a client is cheap, and clones share its pool of connections, if it has one.
+#[derive(Clone)]
 pub struct Client {
     /// Address of remote `KvsServer`.
-    addr: SocketAddr,
+    addr: ServerAddr,
+
+    timeouts: Timeouts,
+
+    retry_policy: RetryPolicy,
+
+    /// Token to authenticate connections with, if the server requires it.
+    token: Option<String>,
+
+    /// How to connect over TLS, if the server uses it.
+    tls: Option<ClientTls>,
+
+    /// Idle persistent connections to reuse. `None` if every command gets its own connection.
+    pool: Option<Arc<ConnectionPool>>,
 
     /// Buffer to read responses into.
     response_buf: Vec<u8>,
 }
 
 impl Client {
-    /// Creates a new `Client` that will connect to the provided address.
-    pub fn new(addr: SocketAddr) -> Self {
-        Self {
-            addr,
-            response_buf: Vec::new(),
-        }
+    /// Creates a new `Client` that will connect to the provided address. Use [`Client::builder`]
+    /// to configure timeouts, pooling or retries.
+    pub fn new(addr: ServerAddr) -> Self {
+        Self::builder(addr).build()
+    }
+
+    /// Creates a builder for a `Client` that will connect to the provided address.
+    pub fn builder(addr: ServerAddr) -> ClientBuilder {
+        ClientBuilder::new(addr)
     }
 
     /// Issues a set command for the key and value to the remote server. Returns `Ok(())` if it
     /// succeeded and an `Err` otherwise.
+    ///
+    /// This is never retried, because another client might set the key between attempts. Use
+    /// [`Client::set_retrying`] if that's not a concern.
     pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
         let cmd = Cmd::Set(key.into(), value.into());
-        match self.write_cmd(cmd) {
-            Ok(Response::SuccessfulSet) => Ok(()),
-            other_response => Err(anyhow!("Unexpected set response {other_response:?}")),
+        match self.request(&cmd, false)? {
+            Response::SuccessfulSet => Ok(()),
+            other_response => Err(unexpected("set", other_response)),
+        }
+    }
+
+    /// Like [`Client::set`], but retried according to the retry policy.
+    ///
+    /// This isn't idempotent: the server can't tell a retry from a new set, so if a response is
+    /// lost after the value was set, the retry sets it again and can overwrite a value another
+    /// client set in between. Only use this when nothing else writes to the key.
+    pub fn set_retrying(&mut self, key: &str, value: &str) -> Result<()> {
+        let cmd = Cmd::Set(key.into(), value.into());
+        match self.request(&cmd, true)? {
+            Response::SuccessfulSet => Ok(()),
+            other_response => Err(unexpected("set", other_response)),
         }
     }
 
     /// Issues a get command for the key to the remote server. Returns `Ok(Some)` if the command
-    /// found a value, `Ok(None)` if the key wasn't present, and an `Err` otherwise.
+    /// found a value, `Ok(None)` if the key wasn't present, and an `Err` otherwise. This is retried
+    /// according to the retry policy.
     pub fn get(&mut self, key: &str) -> Result<Option<Cow<'_, str>>> {
         let cmd = Cmd::Get(key.into());
-        match self.write_cmd(cmd) {
-            Ok(Response::SuccessfulGet(value)) => Ok(Some(value)),
-            Ok(Response::KeyNotFound) => Ok(None),
-            other_response => Err(anyhow!("Unexpected get response {other_response:?}")),
+        match self.request(&cmd, true)? {
+            Response::SuccessfulGet(value) => Ok(Some(value)),
+            Response::KeyNotFound => Ok(None),
+            other_response => Err(unexpected("get", other_response)),
         }
     }
 
@@ -51,32 +99,139 @@ impl Client {
     /// succeeded and an `Err` otherwise.
     pub fn rm(&mut self, key: &str) -> Result<()> {
         let cmd = Cmd::Rm(key.into());
-        match self.write_cmd(cmd) {
-            Ok(Response::SuccessfulRm) => Ok(()),
-            other_response => Err(anyhow!("Unexpected rm response {other_response:?}")),
+        match self.request(&cmd, false)? {
+            Response::SuccessfulRm => Ok(()),
+            other_response => Err(unexpected("rm", other_response)),
+        }
+    }
+
+    /// Checks that the remote server is responding. This is retried according to the retry policy.
+    pub fn ping(&mut self) -> Result<()> {
+        match self.request(&Cmd::Ping, true)? {
+            Response::Pong => Ok(()),
+            other_response => Err(unexpected("ping", other_response)),
+        }
+    }
+
+    /// Asks the remote server to describe itself. This is retried according to the retry policy.
+    pub fn info(&mut self) -> Result<ServerInfo<'_>> {
+        match self.request(&Cmd::Info, true)? {
+            Response::Info(info) => Ok(info),
+            other_response => Err(unexpected("info", other_response)),
         }
     }
 
-    /// Writes a command to the remote server and reads the response.
-    fn write_cmd(&mut self, cmd: Cmd) -> Result<Response> {
+    /// Asks the remote server to compact its engine's files now. This fails if the engine doesn't
+    /// compact its files. This is retried according to the retry policy.
+    pub fn compact(&mut self) -> Result<()> {
+        match self.request(&Cmd::Compact, true)? {
+            Response::Compacted => Ok(()),
+            other_response => Err(unexpected("compact", other_response)),
+        }
+    }
+
+    /// Asks the remote server to make every completed write durable. This is retried according to
+    /// the retry policy.
+    pub fn flush(&mut self) -> Result<()> {
+        match self.request(&Cmd::Flush, true)? {
+            Response::Flushed => Ok(()),
+            other_response => Err(unexpected("flush", other_response)),
+        }
+    }
+
+    /// Writes a command to the remote server and reads the response. If the command is
+    /// idempotent, it's retried according to the retry policy.
+    fn request(&mut self, cmd: &Cmd, idempotent: bool) -> Result<Response<'_>> {
+        let max_retries = if idempotent {
+            self.retry_policy.max_retries()
+        } else {
+            0
+        };
+
+        let mut attempt = 0;
+        loop {
+            let retry = match self.write_cmd(cmd) {
+                Ok(()) => {
+                    attempt < max_retries
+                        && matches!(self.response(), Ok(Response::Err(code, _)) if code.is_retryable())
+                }
+                Err(e) if attempt < max_retries && retry::is_retryable(&e) => {
+                    warn!(?e, attempt, "Command failed");
+                    true
+                }
+                Err(e) => return Err(e),
+            };
+            if !retry {
+                return self.response();
+            }
+
+            let backoff = self.retry_policy.backoff(attempt);
+            debug!(?backoff, attempt, "Retrying command");
+            thread::sleep(backoff);
+            attempt += 1;
+        }
+    }
+
+    /// Writes a command to the remote server and reads the response into the response buffer.
+    fn write_cmd(&mut self, cmd: &Cmd) -> Result<()> {
+        let Some(pool) = &self.pool else {
+            return self.write_cmd_once(cmd);
+        };
+
+        let mut connection = match pool.take() {
+            Some(connection) => connection,
+            None => Connection::open(
+                &self.addr,
+                &self.timeouts,
+                self.tls.as_ref(),
+                self.token.as_deref(),
+            )?,
+        };
+        info!(?cmd, "Writing to server");
+        connection.exchange(cmd, &mut self.response_buf)?;
+
+        // Connections that failed are dropped, since they might be partway through a response.
+        pool.put(connection);
+        Ok(())
+    }
+
+    /// Writes a command to the remote server on a new connection and reads the response into the
+    /// response buffer.
+    fn write_cmd_once(&mut self, cmd: &Cmd) -> Result<()> {
         debug!(addr = ?self.addr, "Connecting to server");
-        let mut connection = TcpStream::connect(self.addr).context("Connecting to server")?;
+        let mut connection = self.timeouts.connect(&self.addr, self.tls.as_ref())?;
 
-        // TODO set timeout?
         info!(?cmd, "Writing to server");
+        if let Some(token) = &self.token {
+            Cmd::Auth(token.into()).write(&mut connection)?;
+        }
         cmd.write(&mut connection)?;
 
-        // NB We don't need to `.flush()` because, for `TcpStream`, that's a no-op
+        // NB We don't need to `.flush()` because finishing writing sends everything written
         debug!("Wrote to server. Shutting down write half.");
-        connection.shutdown(Shutdown::Write)?;
+        connection.finish_writing()?;
         debug!("Shut down write half");
 
-        // TODO set_read_timeout?
         debug!("Reading from server");
+        self.response_buf.clear();
         connection
             .read_to_end(&mut self.response_buf)
             .context("Reading response")?;
 
-        Ok(Response::from_bytes(&self.response_buf))
+        Ok(())
+    }
+
+    /// Parses the response in the response buffer.
+    fn response(&self) -> Result<Response<'_>> {
+        Response::from_bytes(&self.response_buf).context("Parsing response")
+    }
+}
+
+/// Converts a response that doesn't match the issued command into an error. Error responses
+/// become [`ServerError`]s.
+pub(crate) fn unexpected(cmd: &str, response: Response) -> Error {
+    match response {
+        Response::Err(code, message) => ServerError::new(code, message).into(),
+        other => anyhow!("Unexpected {cmd} response {other:?}"),
     }
 }
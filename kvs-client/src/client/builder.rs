@@ -0,0 +1,180 @@
+//! A [`ClientBuilder`] configures how a [`Client`] connects to the server.
+
+use std::fmt;
+use std::net::TcpStream;
+use std::os::unix::net::UnixStream;
+use std::sync::Arc;
+use std::time::Duration;
+
+use anyhow::{bail, Context, Result};
+
+use super::pool::ConnectionPool;
+use super::Client;
+use crate::stream::Stream;
+use crate::{ClientTls, RetryPolicy, ServerAddr};
+
+/// Builder for a [`Client`] with timeouts, pooled connections or retries. By default, a client
+/// has no timeouts, opens a new connection for every command and never retries.
+///
+/// ```no_run
+/// # use std::time::Duration;
+/// # use kvs_client::{Client, RetryPolicy};
+/// let mut client = Client::builder("127.0.0.1:4000".parse().unwrap())
+///     .connect_timeout(Duration::from_secs(1))
+///     .read_timeout(Duration::from_secs(5))
+///     .pool_max_idle(4)
+///     .retry_policy(RetryPolicy::new(3))
+///     .build();
+/// client.get("key").unwrap();
+/// ```
+#[derive(Clone)]
+pub struct ClientBuilder {
+    addr: ServerAddr,
+    timeouts: Timeouts,
+    pool_max_idle: usize,
+    pool_idle_timeout: Duration,
+    retry_policy: RetryPolicy,
+    token: Option<String>,
+    tls: Option<ClientTls>,
+}
+
+impl ClientBuilder {
+    /// Creates a builder for a client of the server at the provided address.
+    pub fn new(addr: ServerAddr) -> Self {
+        Self {
+            addr,
+            timeouts: Timeouts::default(),
+            pool_max_idle: 0,
+            pool_idle_timeout: Duration::from_secs(30),
+            retry_policy: RetryPolicy::none(),
+            token: None,
+            tls: None,
+        }
+    }
+
+    /// Sets how long to wait for a connection to the server to be established. Must be non-zero.
+    pub fn connect_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.connect = Some(timeout);
+        self
+    }
+
+    /// Sets how long to wait for the server to send a response. Must be non-zero.
+    pub fn read_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.read = Some(timeout);
+        self
+    }
+
+    /// Sets how long to wait for the server to accept a command. Must be non-zero.
+    pub fn write_timeout(mut self, timeout: Duration) -> Self {
+        self.timeouts.write = Some(timeout);
+        self
+    }
+
+    /// Keeps up to `max_idle` connections open between commands to reuse for later ones, instead
+    /// of opening a connection per command. The pool is shared by clones of the built client. 0,
+    /// the default, disables pooling, which works with servers that don't support persistent
+    /// connections.
+    pub fn pool_max_idle(mut self, max_idle: usize) -> Self {
+        self.pool_max_idle = max_idle;
+        self
+    }
+
+    /// Sets how long a pooled connection can go unused before it's closed instead of reused. This
+    /// should be shorter than the server's idle timeout, which closes the connection on the
+    /// server's side. Defaults to 30 seconds.
+    pub fn pool_idle_timeout(mut self, timeout: Duration) -> Self {
+        self.pool_idle_timeout = timeout;
+        self
+    }
+
+    /// Sets the policy for retrying idempotent commands and [`Client::set_retrying`].
+    pub fn retry_policy(mut self, retry_policy: RetryPolicy) -> Self {
+        self.retry_policy = retry_policy;
+        self
+    }
+
+    /// Authenticates every connection with the token, for servers that require authentication.
+    pub fn token(mut self, token: impl Into<String>) -> Self {
+        self.token = Some(token.into());
+        self
+    }
+
+    /// Connects to the server over TLS. Unix sockets don't support TLS.
+    pub fn tls(mut self, tls: ClientTls) -> Self {
+        self.tls = Some(tls);
+        self
+    }
+
+    /// Builds the client. Nothing connects to the server until a command is issued.
+    pub fn build(self) -> Client {
+        let pool = (self.pool_max_idle > 0).then(|| {
+            Arc::new(ConnectionPool::new(
+                self.pool_max_idle,
+                self.pool_idle_timeout,
+            ))
+        });
+
+        Client {
+            addr: self.addr,
+            timeouts: self.timeouts,
+            retry_policy: self.retry_policy,
+            token: self.token,
+            tls: self.tls,
+            pool,
+            response_buf: Vec::new(),
+        }
+    }
+}
+
+impl fmt::Debug for ClientBuilder {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientBuilder")
+            .field("addr", &self.addr)
+            .field("timeouts", &self.timeouts)
+            .field("pool_max_idle", &self.pool_max_idle)
+            .field("pool_idle_timeout", &self.pool_idle_timeout)
+            .field("retry_policy", &self.retry_policy)
+            .field("token", &self.token.as_ref().map(|_| "<redacted>"))
+            .field("tls", &self.tls)
+            .finish()
+    }
+}
+
+/// Timeouts for connections to the server. `None` waits forever.
+#[derive(Debug, Clone, Copy, Default)]
+pub(crate) struct Timeouts {
+    pub(crate) connect: Option<Duration>,
+    pub(crate) read: Option<Duration>,
+    pub(crate) write: Option<Duration>,
+}
+
+impl Timeouts {
+    /// Connects to the address, over TLS if there's a TLS config, and applies the read and write
+    /// timeouts to the connection. Connecting to a Unix socket doesn't wait, so it has no timeout.
+    pub(crate) fn connect(&self, addr: &ServerAddr, tls: Option<&ClientTls>) -> Result<Stream> {
+        let addr = match addr {
+            ServerAddr::Tcp(addr) => *addr,
+            ServerAddr::Unix(path) => {
+                if tls.is_some() {
+                    bail!("TLS isn't supported over Unix sockets");
+                }
+                let stream = UnixStream::connect(path).context("Connecting to server")?;
+                stream.set_read_timeout(self.read)?;
+                stream.set_write_timeout(self.write)?;
+                return Ok(Stream::Unix(stream));
+            }
+        };
+        let stream = match self.connect {
+            Some(timeout) => TcpStream::connect_timeout(&addr, timeout),
+            None => TcpStream::connect(addr),
+        }
+        .context("Connecting to server")?;
+
+        stream.set_read_timeout(self.read)?;
+        stream.set_write_timeout(self.write)?;
+        match tls {
+            Some(tls) => tls.connect(addr, stream),
+            None => Ok(Stream::Tcp(stream)),
+        }
+    }
+}
@@ -0,0 +1,48 @@
+//! A pool of idle persistent [`Connection`]s shared by clones of a [`Client`][crate::Client].
+
+use std::sync::{Mutex, PoisonError};
+use std::time::{Duration, Instant};
+
+use crate::Connection;
+
+pub(crate) struct ConnectionPool {
+    /// Idle connections and when they were last used, most recently used last.
+    idle: Mutex<Vec<(Connection, Instant)>>,
+    max_idle: usize,
+    idle_timeout: Duration,
+}
+
+impl ConnectionPool {
+    pub(crate) fn new(max_idle: usize, idle_timeout: Duration) -> Self {
+        Self {
+            idle: Mutex::new(Vec::with_capacity(max_idle)),
+            max_idle,
+            idle_timeout,
+        }
+    }
+
+    /// Takes the most recently used connection out of the pool, if any haven't been idle for too
+    /// long.
+    pub(crate) fn take(&self) -> Option<Connection> {
+        // Every operation leaves the list valid, so it's fine to use after a panic.
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        match idle.pop() {
+            Some((connection, last_used)) if last_used.elapsed() < self.idle_timeout => {
+                Some(connection)
+            }
+            // The rest of the connections were used even longer ago.
+            _ => {
+                idle.clear();
+                None
+            }
+        }
+    }
+
+    /// Returns a connection to the pool, closing it instead if the pool is full.
+    pub(crate) fn put(&self, connection: Connection) {
+        let mut idle = self.idle.lock().unwrap_or_else(PoisonError::into_inner);
+        if idle.len() < self.max_idle {
+            idle.push((connection, Instant::now()));
+        }
+    }
+}
@@ -0,0 +1,203 @@
+//! A persistent connection to a remote `KvsServer` that many commands can be sent on.
+
+use std::borrow::Cow;
+use std::io::Write;
+
+use anyhow::{ensure, Context, Result};
+use protocol::{Cmd, Features, Hello, Limits, Response, ResponseReader};
+use tracing::{debug, info};
+
+use crate::client::{unexpected, Timeouts};
+use crate::stream::Stream;
+use crate::{ClientTls, ServerAddr};
+
+/// A connection to a remote `KvsServer` that stays open between commands. Unlike [`Client`],
+/// which opens a connection per command, this avoids connecting for every command and can
+/// pipeline commands, sending many before reading any of their responses.
+///
+/// The server closes the connection if it goes unused for longer than its idle timeout.
+/// Responses larger than the default [`Limits`] allow are errors, so a broken server can't make
+/// the client allocate for a huge response.
+///
+/// [`Client`]: crate::Client
+pub struct Connection {
+    stream: Stream,
+
+    /// Reader for the framed responses on `stream`.
+    response_reader: ResponseReader,
+
+    /// Buffer to write commands into before sending them.
+    request_buf: Vec<u8>,
+
+    /// What was agreed with the server, if the connection started with a handshake.
+    hello: Option<Hello>,
+}
+
+impl Connection {
+    /// Connects to the server at the provided address and asks it to keep the connection alive.
+    pub fn connect(addr: ServerAddr) -> Result<Self> {
+        Self::open(&addr, &Timeouts::default(), None, None)
+    }
+
+    /// Like [`Connection::connect`], first authenticating the connection with the token.
+    pub fn connect_with_token(addr: ServerAddr, token: &str) -> Result<Self> {
+        Self::open(&addr, &Timeouts::default(), None, Some(token))
+    }
+
+    /// Like [`Connection::connect`], first authenticating the connection with the token if there is
+    /// one, over TLS.
+    pub fn connect_tls(addr: ServerAddr, tls: &ClientTls, token: Option<&str>) -> Result<Self> {
+        Self::open(&addr, &Timeouts::default(), Some(tls), token)
+    }
+
+    /// Like [`Connection::connect`], but agrees on a protocol version and features with the server
+    /// instead of asking it to keep the connection alive, over TLS if there's a TLS config and
+    /// authenticated with the token if there is one. Fails if the server won't frame its
+    /// responses.
+    ///
+    /// Servers that predate the handshake don't understand it, so only use this with servers
+    /// known to support it.
+    pub fn connect_with_hello(
+        addr: ServerAddr,
+        tls: Option<&ClientTls>,
+        token: Option<&str>,
+    ) -> Result<Self> {
+        debug!(?addr, "Connecting to server");
+        let mut stream = Timeouts::default().connect(&addr, tls)?;
+        let mut features = Features::FRAMING;
+        if token.is_some() {
+            features = features.union(Features::AUTH);
+        }
+        Cmd::Hello(Hello::new(features))
+            .write(&mut stream)
+            .context("Sending hello")?;
+        let mut response_reader = ResponseReader::new();
+        let agreed = match response_reader
+            .read_response(&mut stream)
+            .context("Reading hello")?
+        {
+            Response::Hello(agreed) => agreed,
+            other_response => return Err(unexpected("hello", other_response)),
+        };
+        debug!(?agreed, "Agreed on protocol");
+        ensure!(
+            agreed.features.contains(Features::FRAMING),
+            "Server didn't agree to framed responses"
+        );
+
+        let mut request_buf = Vec::new();
+        if let Some(token) = token {
+            Cmd::Auth(token.into()).write(&mut request_buf)?;
+            stream.write_all(&request_buf).context("Authenticating")?;
+        }
+        Ok(Self {
+            stream,
+            response_reader,
+            request_buf,
+            hello: Some(agreed),
+        })
+    }
+
+    /// The protocol version and features agreed with the server, if the connection was opened
+    /// with [`Connection::connect_with_hello`].
+    pub fn hello(&self) -> Option<&Hello> {
+        self.hello.as_ref()
+    }
+
+    /// Like [`Connection::connect`], with timeouts applied to the connection, over TLS if there's
+    /// a TLS config and authenticated with the token if there is one.
+    pub(crate) fn open(
+        addr: &ServerAddr,
+        timeouts: &Timeouts,
+        tls: Option<&ClientTls>,
+        token: Option<&str>,
+    ) -> Result<Self> {
+        debug!(?addr, "Connecting to server");
+        let mut stream = timeouts.connect(addr, tls)?;
+        let mut request_buf = Vec::new();
+        if let Some(token) = token {
+            Cmd::Auth(token.into()).write(&mut request_buf)?;
+        }
+        Cmd::KeepAlive.write(&mut request_buf)?;
+        stream
+            .write_all(&request_buf)
+            .context("Requesting keep-alive")?;
+
+        Ok(Self {
+            stream,
+            response_reader: ResponseReader::new(),
+            request_buf,
+            hello: None,
+        })
+    }
+
+    /// Issues a set command for the key and value. Returns `Ok(())` if it succeeded and an `Err`
+    /// otherwise.
+    pub fn set(&mut self, key: &str, value: &str) -> Result<()> {
+        match self.send(Cmd::Set(key.into(), value.into()))? {
+            Response::SuccessfulSet => Ok(()),
+            other_response => Err(unexpected("set", other_response)),
+        }
+    }
+
+    /// Issues a get command for the key. Returns `Ok(Some)` if the command found a value,
+    /// `Ok(None)` if the key wasn't present, and an `Err` otherwise.
+    pub fn get(&mut self, key: &str) -> Result<Option<Cow<'_, str>>> {
+        match self.send(Cmd::Get(key.into()))? {
+            Response::SuccessfulGet(value) => Ok(Some(value)),
+            Response::KeyNotFound => Ok(None),
+            other_response => Err(unexpected("get", other_response)),
+        }
+    }
+
+    /// Issues an rm command for the key. Returns `Ok(())` if the command succeeded and an `Err`
+    /// otherwise.
+    pub fn rm(&mut self, key: &str) -> Result<()> {
+        match self.send(Cmd::Rm(key.into()))? {
+            Response::SuccessfulRm => Ok(()),
+            other_response => Err(unexpected("rm", other_response)),
+        }
+    }
+
+    /// Sends every command before reading any responses, then returns the responses in the order
+    /// of the commands they're for. Error responses are returned as [`Response::Err`] rather than
+    /// failing the whole pipeline.
+    ///
+    /// Every command is sent in one write, so very large pipelines should be split up to avoid
+    /// filling the server's send buffer before the responses start being read.
+    pub fn pipeline(&mut self, cmds: &[Cmd]) -> Result<Vec<Response<'static>>> {
+        self.request_buf.clear();
+        for cmd in cmds {
+            cmd.write(&mut self.request_buf)?;
+        }
+        info!(cmds = cmds.len(), "Writing pipeline to server");
+        self.stream
+            .write_all(&self.request_buf)
+            .context("Writing to server")?;
+
+        (0..cmds.len())
+            .map(|_| Ok(self.read_response()?.into_owned()))
+            .collect()
+    }
+
+    /// Writes a command to the server and reads its response.
+    fn send(&mut self, cmd: Cmd) -> Result<Response<'_>> {
+        info!(?cmd, "Writing to server");
+        cmd.write(&mut self.stream).context("Writing to server")?;
+        self.read_response()
+    }
+
+    /// Writes a command to the server and reads the bytes of its response into `buf`, for the
+    /// caller to parse.
+    pub(crate) fn exchange(&mut self, cmd: &Cmd, buf: &mut Vec<u8>) -> Result<()> {
+        cmd.write(&mut self.stream).context("Writing to server")?;
+        Response::read_frame(&mut self.stream, buf, &Limits::default()).context("Reading response")
+    }
+
+    fn read_response(&mut self) -> Result<Response<'_>> {
+        debug!("Reading from server");
+        self.response_reader
+            .read_response(&mut self.stream)
+            .context("Reading response")
+    }
+}
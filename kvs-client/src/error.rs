@@ -0,0 +1,52 @@
+//! Typed errors surfaced by the [`Client`][crate::Client].
+
+use std::fmt;
+
+use protocol::ErrorCode;
+
+/// An error reported by the remote server in response to a command.
+///
+/// [`Client`][crate::Client] methods return [`anyhow::Error`]s, from which a `ServerError` can be
+/// recovered with [`anyhow::Error::downcast_ref`] to decide how to react, e.g. whether to retry:
+///
+/// ```no_run
+/// # use kvs_client::{Client, ServerError};
+/// # let mut client = Client::new("127.0.0.1:4000".parse().unwrap());
+/// if let Err(e) = client.set("key", "value") {
+///     let retryable = e
+///         .downcast_ref::<ServerError>()
+///         .is_some_and(|e| e.code().is_retryable());
+/// }
+/// ```
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerError {
+    code: ErrorCode,
+    message: String,
+}
+
+impl ServerError {
+    pub(crate) fn new(code: ErrorCode, message: impl Into<String>) -> Self {
+        Self {
+            code,
+            message: message.into(),
+        }
+    }
+
+    /// The code classifying this error.
+    pub fn code(&self) -> ErrorCode {
+        self.code
+    }
+
+    /// The message the server sent describing this error.
+    pub fn message(&self) -> &str {
+        &self.message
+    }
+}
+
+impl fmt::Display for ServerError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{} ({})", self.message, self.code)
+    }
+}
+
+impl std::error::Error for ServerError {}
@@ -1,5 +1,21 @@
-//! Provides a [`Client`] that can be used for issuing commands to a `KvsServer`.
+//! Provides a [`Client`] that can be used for issuing commands to a `KvsServer`, a [`Connection`]
+//! for issuing many commands over one persistent connection, and an [`AsyncClient`] for issuing
+//! commands from async code.
 
+mod addr;
+mod async_client;
 mod client;
+mod connection;
+mod error;
+mod retry;
+mod stream;
+mod tls;
 
-pub use client::Client;
+pub use addr::ServerAddr;
+pub use async_client::AsyncClient;
+pub use client::{Client, ClientBuilder};
+pub use connection::Connection;
+pub use error::ServerError;
+pub use protocol::{ErrorCode, Features, Hello, ServerInfo};
+pub use retry::RetryPolicy;
+pub use tls::ClientTls;
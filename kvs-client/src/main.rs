@@ -1,6 +1,8 @@
+use std::path::PathBuf;
+
 use anyhow::{Context, Result};
 use clap::{Parser, Subcommand};
-use kvs_client::Client;
+use kvs_client::{Client, ClientTls, ServerAddr};
 use tracing::info;
 
 #[derive(Parser)]
@@ -9,15 +11,53 @@ struct Args {
     #[command(subcommand)]
     command: Command,
 
+    /// Address of the server, or `unix://` and the path of its Unix socket, e.g.
+    /// unix:///run/kvs.sock.
     #[clap(long, default_value = "127.0.0.1:4000", global = true)]
     addr: String,
+
+    /// Token to authenticate with, for servers started with `--auth-token-file`.
+    #[clap(long, env = "KVS_TOKEN", global = true, hide_env_values = true)]
+    token: Option<String>,
+
+    /// PEM file with the CA certificates to trust the server's certificate from. This connects over
+    /// TLS, trusting only these CAs.
+    #[clap(long, env = "KVS_TLS_CA", global = true)]
+    tls_ca: Option<PathBuf>,
+
+    /// Name the server's certificate must be issued for. Defaults to the IP address in `--addr`.
+    #[clap(long, env = "KVS_TLS_SERVER_NAME", global = true, requires = "tls_ca")]
+    tls_server_name: Option<String>,
+
+    /// PEM file with a client certificate chain, for servers that verify clients' certificates.
+    #[clap(long, env = "KVS_TLS_CLIENT_CERT", global = true, requires_all = ["tls_ca", "tls_key"])]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for `--tls-cert`.
+    #[clap(long, env = "KVS_TLS_CLIENT_KEY", global = true, requires = "tls_cert")]
+    tls_key: Option<PathBuf>,
 }
 
 #[derive(Subcommand)]
 enum Command {
-    Set { key: String, value: String },
-    Get { key: String },
-    Rm { key: String },
+    Set {
+        key: String,
+        value: String,
+    },
+    Get {
+        key: String,
+    },
+    Rm {
+        key: String,
+    },
+    /// Checks that the server is responding.
+    Ping,
+    /// Describes the server.
+    Info,
+    /// Compacts the server's data files now.
+    Compact,
+    /// Makes every completed write on the server durable.
+    Flush,
 }
 
 fn main() -> Result<()> {
@@ -26,11 +66,25 @@ fn main() -> Result<()> {
     let args = Args::parse();
 
     info!(?args.addr, "Connecting to server");
-    let address = args
+    let address: ServerAddr = args
         .addr
         .parse()
         .context(format!("Invalid address {:?}", args.addr))?;
-    let mut client = Client::new(address);
+    let mut builder = Client::builder(address);
+    if let Some(token) = &args.token {
+        builder = builder.token(token);
+    }
+    if let Some(ca) = &args.tls_ca {
+        let mut tls = ClientTls::pinned(ca)?;
+        if let Some(name) = &args.tls_server_name {
+            tls = tls.with_server_name(name)?;
+        }
+        if let (Some(cert), Some(key)) = (&args.tls_cert, &args.tls_key) {
+            tls = tls.with_client_cert(cert, key)?;
+        }
+        builder = builder.tls(tls);
+    }
+    let mut client = builder.build();
 
     match &args.command {
         Command::Set { key, value } => client.set(key, value)?,
@@ -39,6 +93,23 @@ fn main() -> Result<()> {
             Some(s) => println!("{s}"),
             None => println!("Key not found"),
         },
+        Command::Ping => {
+            client.ping()?;
+            println!("PONG");
+        }
+        Command::Info => {
+            let info = client.info()?;
+            println!("engine: {}", info.engine);
+            println!("version: {}", info.version);
+            println!("uptime_secs: {}", info.uptime.as_secs());
+            println!("keys: {}", info.keys);
+            match info.disk_usage {
+                Some(bytes) => println!("disk_usage_bytes: {bytes}"),
+                None => println!("disk_usage_bytes: unknown"),
+            }
+        }
+        Command::Compact => client.compact()?,
+        Command::Flush => client.flush()?,
     }
 
     Ok(())
@@ -0,0 +1,125 @@
+//! Policy for retrying idempotent commands that failed for transient reasons.
+
+use std::io;
+use std::time::Duration;
+
+use anyhow::Error;
+
+use crate::ServerError;
+
+/// How many times, and how long to wait between attempts, to retry idempotent commands whose
+/// failure might not happen again, e.g. because the connection was reset or the server's storage
+/// was temporarily unavailable.
+///
+/// The wait between attempts starts at the initial backoff and doubles after each attempt, up to
+/// the maximum backoff.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RetryPolicy {
+    max_retries: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+}
+
+impl RetryPolicy {
+    /// A policy that never retries.
+    pub fn none() -> Self {
+        Self::new(0)
+    }
+
+    /// A policy that retries up to `max_retries` times, waiting 50ms before the first retry and at
+    /// most 2s before any retry.
+    pub fn new(max_retries: u32) -> Self {
+        Self {
+            max_retries,
+            initial_backoff: Duration::from_millis(50),
+            max_backoff: Duration::from_secs(2),
+        }
+    }
+
+    /// Sets how long to wait before the first retry, and the most to wait before any retry.
+    pub fn with_backoff(self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    /// The most times a command is retried.
+    pub fn max_retries(&self) -> u32 {
+        self.max_retries
+    }
+
+    /// How long to wait before the retry following the `attempt`th attempt, counting from 0.
+    pub(crate) fn backoff(&self, attempt: u32) -> Duration {
+        let factor = 2u32.checked_pow(attempt).unwrap_or(u32::MAX);
+        self.initial_backoff
+            .saturating_mul(factor)
+            .min(self.max_backoff)
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        Self::none()
+    }
+}
+
+/// Whether a command that failed with the error might succeed if it's sent again.
+pub(crate) fn is_retryable(e: &Error) -> bool {
+    if let Some(e) = e.downcast_ref::<ServerError>() {
+        return e.code().is_retryable();
+    }
+
+    e.chain().any(|cause| {
+        cause.downcast_ref::<io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::BrokenPipe
+                    | io::ErrorKind::ConnectionAborted
+                    | io::ErrorKind::ConnectionRefused
+                    | io::ErrorKind::ConnectionReset
+                    | io::ErrorKind::Interrupted
+                    | io::ErrorKind::TimedOut
+                    | io::ErrorKind::UnexpectedEof
+                    | io::ErrorKind::WouldBlock
+            )
+        })
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Context;
+    use protocol::ErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn backoff_doubles_up_to_max() {
+        let policy = RetryPolicy::new(10)
+            .with_backoff(Duration::from_millis(10), Duration::from_millis(100));
+
+        let backoffs = (0..6).map(|attempt| policy.backoff(attempt).as_millis());
+        assert_eq!(backoffs.collect::<Vec<_>>(), [10, 20, 40, 80, 100, 100]);
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(100));
+    }
+
+    #[test]
+    fn classifies_errors() {
+        let reset = Error::from(io::Error::from(io::ErrorKind::ConnectionReset));
+        assert!(is_retryable(&reset.context("Reading response")));
+
+        let denied = Error::from(io::Error::from(io::ErrorKind::PermissionDenied));
+        assert!(!is_retryable(&denied));
+
+        let storage = ServerError::new(ErrorCode::Storage, "Failed to set key");
+        assert!(is_retryable(&storage.into()));
+
+        let bad_request = ServerError::new(ErrorCode::BadRequest, "Invalid utf8");
+        assert!(!is_retryable(&bad_request.into()));
+
+        let parse = Err::<(), _>(Error::msg("Empty response")).context("Parsing response");
+        assert!(!is_retryable(&parse.unwrap_err()));
+    }
+}
@@ -0,0 +1,139 @@
+//! TLS for connections to the server.
+
+use std::fmt;
+use std::net::{SocketAddr, TcpStream};
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{ensure, Context, Result};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer, ServerName};
+use rustls::{ClientConfig, ClientConnection, RootCertStore, StreamOwned};
+
+use crate::stream::Stream;
+
+/// How a [`Client`][crate::Client] connects to a server over TLS.
+///
+/// The server's certificate must be signed by one of the pinned CA certificates; publicly trusted
+/// CAs aren't trusted unless they're pinned too. By default, the certificate must be issued for the
+/// IP address connected to.
+///
+/// ```no_run
+/// # use kvs_client::{Client, ClientTls};
+/// let tls = ClientTls::pinned("ca.pem")
+///     .unwrap()
+///     .with_server_name("kvs.internal")
+///     .unwrap();
+/// let mut client = Client::builder("10.0.0.1:4000".parse().unwrap())
+///     .tls(tls)
+///     .build();
+/// ```
+#[derive(Clone)]
+pub struct ClientTls {
+    roots: Arc<RootCertStore>,
+    config: Arc<ClientConfig>,
+    server_name: Option<ServerName<'static>>,
+    has_client_cert: bool,
+}
+
+impl ClientTls {
+    /// Trusts servers with a certificate signed by one of the CA certificates in the PEM file.
+    pub fn pinned(ca: impl AsRef<Path>) -> Result<Self> {
+        let ca = ca.as_ref();
+        let mut roots = RootCertStore::empty();
+        for cert in read_certs(ca)? {
+            roots
+                .add(cert)
+                .with_context(|| format!("Invalid CA certificate in {}", ca.display()))?;
+        }
+        let roots = Arc::new(roots);
+
+        let config = config_builder(&roots)?.with_no_client_auth();
+        Ok(Self {
+            roots,
+            config: Arc::new(config),
+            server_name: None,
+            has_client_cert: false,
+        })
+    }
+
+    /// Presents the certificate chain and private key in the PEM files to servers that verify
+    /// their clients' certificates (mutual TLS).
+    pub fn with_client_cert(
+        mut self,
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+    ) -> Result<Self> {
+        let certs = read_certs(cert.as_ref())?;
+        let key = PrivateKeyDer::from_pem_file(key.as_ref())
+            .with_context(|| format!("Failed to read TLS key file {}", key.as_ref().display()))?;
+
+        let config = config_builder(&self.roots)?
+            .with_client_auth_cert(certs, key)
+            .context("The TLS client certificate doesn't match the key")?;
+        self.config = Arc::new(config);
+        self.has_client_cert = true;
+        Ok(self)
+    }
+
+    /// Requires the server's certificate to be issued for the DNS name or IP address, instead of
+    /// the IP address connected to.
+    pub fn with_server_name(mut self, name: impl Into<String>) -> Result<Self> {
+        let name = name.into();
+        let server_name = ServerName::try_from(name.as_str())
+            .with_context(|| format!("Invalid TLS server name {name:?}"))?
+            .to_owned();
+        self.server_name = Some(server_name);
+        Ok(self)
+    }
+
+    /// Completes a TLS handshake on the connection to the server at the address.
+    pub(crate) fn connect(&self, addr: SocketAddr, mut stream: TcpStream) -> Result<Stream> {
+        let server_name = self
+            .server_name
+            .clone()
+            .unwrap_or_else(|| ServerName::IpAddress(addr.ip().into()));
+        let mut connection = ClientConnection::new(Arc::clone(&self.config), server_name)
+            .context("Failed to start TLS session")?;
+        while connection.is_handshaking() {
+            connection
+                .complete_io(&mut stream)
+                .context("TLS handshake failed")?;
+        }
+        Ok(Stream::Tls(Box::new(StreamOwned::new(connection, stream))))
+    }
+}
+
+impl fmt::Debug for ClientTls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ClientTls")
+            .field("server_name", &self.server_name)
+            .field("has_client_cert", &self.has_client_cert)
+            .finish_non_exhaustive()
+    }
+}
+
+fn config_builder(
+    roots: &Arc<RootCertStore>,
+) -> Result<rustls::ConfigBuilder<ClientConfig, rustls::client::WantsClientCert>> {
+    Ok(
+        ClientConfig::builder_with_provider(Arc::new(ring::default_provider()))
+            .with_safe_default_protocol_versions()
+            .context("Failed to configure TLS")?
+            .with_root_certificates(Arc::clone(roots)),
+    )
+}
+
+/// Reads every certificate in a PEM file, which must have at least one.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate file {}", path.display()))?;
+    ensure!(
+        !certs.is_empty(),
+        "No certificates in TLS certificate file {}",
+        path.display()
+    );
+    Ok(certs)
+}
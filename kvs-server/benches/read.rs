@@ -30,7 +30,7 @@ fn read(c: &mut Criterion) {
         })
         .collect::<Vec<_>>();
 
-    for engine_type in [EngineType::Kvs, EngineType::Sled] {
+    for engine_type in [EngineType::Kvs, EngineType::Sled, EngineType::Memory] {
         group.bench_with_input(BenchmarkId::new(engine_type.to_string(), ""), "", |b, _| {
             let dir = tempfile::tempdir().unwrap();
             let mut engine = Engine::new_in(Some(engine_type), dir.path()).unwrap();
@@ -30,7 +30,7 @@ fn write(c: &mut Criterion) {
         })
         .collect::<Vec<_>>();
 
-    for engine_type in [EngineType::Kvs, EngineType::Sled] {
+    for engine_type in [EngineType::Kvs, EngineType::Sled, EngineType::Memory] {
         group.bench_with_input(BenchmarkId::new(engine_type.to_string(), ""), "", |b, _| {
             b.iter_custom(|iters| {
                 let mut total = Duration::ZERO;
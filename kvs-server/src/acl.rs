@@ -0,0 +1,263 @@
+//! Per-token authorization of commands, read from a TOML file:
+//!
+//! ```toml
+//! [[grants]]
+//! name = "analytics"
+//! token = "analytics-secret"
+//! allow = ["read"]
+//!
+//! [[grants]]
+//! name = "billing"
+//! token = "billing-secret"
+//! allow = ["read", "write"]
+//! key_prefixes = ["billing/"]
+//! ```
+//!
+//! A connection presenting a grant's token with [`Cmd::Auth`] may only issue the commands its
+//! grant allows, on keys starting with one of its key prefixes. Grants without `key_prefixes` may
+//! use every key.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use protocol::Cmd;
+use serde::Deserialize;
+
+use crate::AuthToken;
+
+/// Kinds of commands a [`Grant`] can allow.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Permission {
+    /// [`Cmd::Get`] and [`Cmd::Info`].
+    Read,
+    /// [`Cmd::Set`] and [`Cmd::Rm`].
+    Write,
+    /// [`Cmd::Compact`] and [`Cmd::Flush`].
+    Admin,
+}
+
+impl Permission {
+    /// The permission needed to issue the command. `None` if every authenticated connection may
+    /// issue it.
+    pub fn required_for(cmd: &Cmd) -> Option<Self> {
+        match cmd {
+            Cmd::Get(_) | Cmd::Info => Some(Self::Read),
+            Cmd::Set(..) | Cmd::Rm(_) => Some(Self::Write),
+            Cmd::Compact | Cmd::Flush => Some(Self::Admin),
+            Cmd::Ping | Cmd::KeepAlive | Cmd::Auth(_) | Cmd::Hello(_) => None,
+        }
+    }
+}
+
+impl fmt::Display for Permission {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Read => f.write_str("read"),
+            Self::Write => f.write_str("write"),
+            Self::Admin => f.write_str("admin"),
+        }
+    }
+}
+
+/// What the holder of a token may do.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Grant {
+    /// Identifies the grant in audit logs, without revealing its token.
+    pub name: String,
+    pub allow: Vec<Permission>,
+    /// Keys the grant may use are those starting with one of these. `None` allows every key.
+    pub key_prefixes: Option<Vec<String>>,
+}
+
+impl Grant {
+    /// A grant allowing every command on every key.
+    pub fn full_access(name: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            allow: vec![Permission::Read, Permission::Write, Permission::Admin],
+            key_prefixes: None,
+        }
+    }
+
+    /// Whether the grant allows the command, and the key it uses if it has one.
+    pub fn allows(&self, cmd: &Cmd) -> bool {
+        let permitted =
+            Permission::required_for(cmd).is_none_or(|permission| self.allow.contains(&permission));
+        permitted && cmd_key(cmd).is_none_or(|key| self.allows_key(key))
+    }
+
+    fn allows_key(&self, key: &str) -> bool {
+        self.key_prefixes.as_ref().is_none_or(|prefixes| {
+            prefixes
+                .iter()
+                .any(|prefix| key.starts_with(prefix.as_str()))
+        })
+    }
+}
+
+/// Tokens and the [`Grant`] each one is given.
+#[derive(Debug, Clone)]
+pub struct Acl {
+    grants: Vec<(AuthToken, Grant)>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct AclFile {
+    grants: Vec<GrantEntry>,
+}
+
+#[derive(Deserialize)]
+#[serde(deny_unknown_fields)]
+struct GrantEntry {
+    name: String,
+    #[serde(deserialize_with = "deserialize_token")]
+    token: AuthToken,
+    allow: Vec<Permission>,
+    #[serde(default)]
+    key_prefixes: Option<Vec<String>>,
+}
+
+impl Acl {
+    /// Reads an ACL from a TOML file. Every grant needs a distinct name and token.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read ACL file {}", path.display()))?;
+        Self::from_toml(&contents).with_context(|| format!("Invalid ACL file {}", path.display()))
+    }
+
+    fn from_toml(toml: &str) -> Result<Self> {
+        let file: AclFile = toml::from_str(toml)?;
+        let grants: Vec<_> = file
+            .grants
+            .into_iter()
+            .map(|entry| {
+                let grant = Grant {
+                    name: entry.name,
+                    allow: entry.allow,
+                    key_prefixes: entry.key_prefixes,
+                };
+                (entry.token, grant)
+            })
+            .collect();
+        for (i, (token, grant)) in grants.iter().enumerate() {
+            for (other_token, other_grant) in &grants[..i] {
+                ensure!(
+                    grant.name != other_grant.name,
+                    "More than one grant is named {:?}",
+                    grant.name
+                );
+                ensure!(
+                    !token.same_as(other_token),
+                    "Grants {:?} and {:?} have the same token",
+                    other_grant.name,
+                    grant.name
+                );
+            }
+        }
+        Ok(Self { grants })
+    }
+
+    /// The grant for the presented token, if any. Every token is compared, so the time taken
+    /// doesn't reveal which grant matched.
+    pub fn authenticate(&self, presented: &str) -> Option<&Grant> {
+        self.grants
+            .iter()
+            .filter(|(token, _)| token.verify(presented))
+            .fold(None, |matched, (_, grant)| matched.or(Some(grant)))
+    }
+}
+
+/// The key the command uses, if it uses one.
+pub(crate) fn cmd_key<'c>(cmd: &'c Cmd) -> Option<&'c str> {
+    match cmd {
+        Cmd::Set(key, _) | Cmd::Get(key) | Cmd::Rm(key) => Some(key),
+        _ => None,
+    }
+}
+
+fn deserialize_token<'de, D: serde::Deserializer<'de>>(
+    deserializer: D,
+) -> Result<AuthToken, D::Error> {
+    AuthToken::new(String::deserialize(deserializer)?).map_err(serde::de::Error::custom)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const ACL: &str = r#"
+        [[grants]]
+        name = "analytics"
+        token = "analytics-secret"
+        allow = ["read"]
+
+        [[grants]]
+        name = "billing"
+        token = "billing-secret"
+        allow = ["read", "write"]
+        key_prefixes = ["billing/", "shared/"]
+    "#;
+
+    #[test]
+    fn authenticates_grants() {
+        let acl = Acl::from_toml(ACL).unwrap();
+        assert_eq!(
+            acl.authenticate("analytics-secret").unwrap().name,
+            "analytics"
+        );
+        assert_eq!(acl.authenticate("billing-secret").unwrap().name, "billing");
+        assert!(acl.authenticate("other").is_none());
+        assert!(acl.authenticate("").is_none());
+    }
+
+    #[test]
+    fn allows_commands_and_prefixes() {
+        let acl = Acl::from_toml(ACL).unwrap();
+        let analytics = acl.authenticate("analytics-secret").unwrap();
+        assert!(analytics.allows(&Cmd::Get("billing/a".into())));
+        assert!(analytics.allows(&Cmd::Info));
+        assert!(analytics.allows(&Cmd::Ping));
+        assert!(!analytics.allows(&Cmd::Set("a".into(), "b".into())));
+        assert!(!analytics.allows(&Cmd::Rm("a".into())));
+        assert!(!analytics.allows(&Cmd::Compact));
+
+        let billing = acl.authenticate("billing-secret").unwrap();
+        assert!(billing.allows(&Cmd::Set("billing/a".into(), "b".into())));
+        assert!(billing.allows(&Cmd::Rm("shared/a".into())));
+        assert!(!billing.allows(&Cmd::Get("analytics/a".into())));
+        assert!(!billing.allows(&Cmd::Set("billing".into(), "b".into())));
+        assert!(!billing.allows(&Cmd::Flush));
+
+        let admin = Grant::full_access("admin");
+        assert!(admin.allows(&Cmd::Flush));
+        assert!(admin.allows(&Cmd::Set("anything".into(), "b".into())));
+    }
+
+    #[test]
+    fn rejects_invalid_acls() {
+        let duplicate_name = r#"
+            [[grants]]
+            name = "a"
+            token = "one"
+            allow = ["read"]
+
+            [[grants]]
+            name = "a"
+            token = "two"
+            allow = ["read"]
+        "#;
+        assert!(Acl::from_toml(duplicate_name).is_err());
+        assert!(Acl::from_toml(
+            &duplicate_name
+                .replace("two", "one")
+                .replacen("\"a\"", "\"b\"", 1)
+        )
+        .is_err());
+        assert!(Acl::from_toml(&ACL.replace("\"read\"]", "\"delete\"]")).is_err());
+        assert!(Acl::from_toml(&ACL.replace("analytics-secret", "")).is_err());
+    }
+}
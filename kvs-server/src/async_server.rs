@@ -0,0 +1,353 @@
+//! Server to receive requests at an address and handle them with a specified [`Engine`] on an
+//! async runtime.
+//!
+//! Unlike [`Server`], which dedicates a thread to each connection while it's open, this server
+//! multiplexes connections onto a small number of runtime threads. Connections only tie up a thread
+//! while the engine is processing their command, which happens on the runtime's blocking thread
+//! pool so the event loop never stalls.
+
+use std::future;
+use std::io;
+use std::net::{IpAddr, SocketAddr};
+use std::sync::Arc;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{Context, Result};
+use protocol::{Cmd, Response};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::net::{TcpListener, TcpStream, UnixListener, UnixStream};
+use tokio::sync::watch;
+use tokio::task::JoinSet;
+use tokio::time::Instant;
+use tokio_rustls::TlsAcceptor;
+use tracing::{debug, info, warn};
+
+use crate::frontend;
+use crate::server::{Action, Client, Session, Shared, ShutdownTrigger, ACCEPT_BACKOFF};
+use crate::{memcached, resp};
+use crate::{Engine, Protocol, Server, ServerHandle, ServerOptions};
+
+pub struct AsyncServer {
+    addr: SocketAddr,
+    shared: Arc<Shared>,
+    threads: usize,
+}
+
+impl AsyncServer {
+    /// Creates a server that runs on an async runtime with the specified number of worker threads.
+    /// Every connection shares the same engine.
+    pub fn new(engine: Engine, addr: SocketAddr, threads: usize, options: ServerOptions) -> Self {
+        Self {
+            addr,
+            shared: Arc::new(Shared::new(engine, options)),
+            threads,
+        }
+    }
+
+    /// Starts the runtime, binds the address and Unix socket and serves connections on a
+    /// background thread until the returned handle shuts the server down.
+    pub fn bind(self) -> Result<ServerHandle> {
+        self.shared.check_listeners()?;
+        let runtime = tokio::runtime::Builder::new_multi_thread()
+            .worker_threads(self.threads)
+            .enable_all()
+            .build()
+            .context("Failed to build async runtime")?;
+
+        let mut listeners = Listeners {
+            tcp: None,
+            unix: None,
+        };
+        let mut local_addr = None;
+        if self.shared.options.tcp {
+            debug!(?self.addr, "Binding async server");
+            let listener = runtime
+                .block_on(TcpListener::bind(self.addr))
+                .context("Failed to bind address")?;
+            local_addr = Some(listener.local_addr()?);
+            listeners.tcp = Some(listener);
+        }
+        let metrics = self.shared.bind_metrics()?;
+        let gateway = self.shared.bind_gateway()?;
+        let unix_socket = self.shared.options.unix_socket.clone();
+        if let Some(unix_socket) = &unix_socket {
+            debug!(path = ?unix_socket.path, "Binding Unix socket");
+            let listener = unix_socket.bind()?;
+            listener.set_nonblocking(true)?;
+            let _runtime = runtime.enter();
+            listeners.unix = Some(UnixListener::from_std(listener)?);
+        }
+        debug!(?local_addr, "Async server bound");
+
+        let (trigger, shutdown) = watch::channel(false);
+        let thread = thread::Builder::new()
+            .name("kvs-server".to_owned())
+            .spawn(move || runtime.block_on(self.serve(listeners, shutdown)))
+            .context("Failed to spawn server thread")?;
+
+        Ok(ServerHandle::new(
+            local_addr,
+            unix_socket.map(|unix_socket| unix_socket.path),
+            ShutdownTrigger::Async(trigger),
+            thread,
+            metrics,
+            gateway,
+        ))
+    }
+
+    /// Starts the runtime and serves connections on it until the process exits.
+    pub fn run(self) -> Result<()> {
+        self.bind()?.wait()
+    }
+
+    async fn serve(self, listeners: Listeners, mut shutdown: watch::Receiver<bool>) -> Result<()> {
+        let mut connections = JoinSet::new();
+        loop {
+            tokio::select! {
+                // If the handle is dropped, the server runs until the process exits.
+                Ok(()) = shutdown.changed() => break,
+                // Reap finished connections so they don't pile up.
+                Some(_) = connections.join_next(), if !connections.is_empty() => {}
+                accepted = listeners.accept() => {
+                    let socket = match accepted {
+                        Ok(socket) => socket,
+                        Err(e) => {
+                            warn!(?e, "Failed to accept connection");
+                            tokio::time::sleep(ACCEPT_BACKOFF).await;
+                            continue;
+                        }
+                    };
+                    info!(?socket, "Received connection");
+
+                    // Connections that finished but haven't been reaped yet still count, so the
+                    // limit can briefly be reached early.
+                    let client = Client {
+                        ip: socket.peer_ip(),
+                        over_capacity: self.shared.over_capacity(connections.len() + 1),
+                    };
+                    let shared = Arc::clone(&self.shared);
+                    let shutdown = shutdown.clone();
+                    connections.spawn(async move {
+                        let _connection = shared.metrics.connection_opened();
+                        let served = Self::serve_connection(&shared, socket, client, shutdown);
+                        if let Err(e) = served.await {
+                            warn!(?e, "Failed to handle connection");
+                        }
+                    });
+                }
+            }
+        }
+
+        drop(listeners);
+        if let Some(unix_socket) = &self.shared.options.unix_socket {
+            unix_socket.remove();
+        }
+        info!("Shutting down, waiting for open connections");
+        while connections.join_next().await.is_some() {}
+        let shared = Arc::clone(&self.shared);
+        tokio::task::spawn_blocking(move || Server::flush(&shared.engine))
+            .await
+            .context("flushing engine")??;
+        info!("Server shut down");
+
+        Ok(())
+    }
+
+    /// Handles a connection, over TLS if the server uses it and the client connected over TCP.
+    async fn serve_connection(
+        shared: &Arc<Shared>,
+        socket: Socket,
+        client: Client,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<()> {
+        let stream = match socket {
+            Socket::Tcp(stream) => stream,
+            Socket::Unix(stream) => {
+                return Self::serve_protocol(shared, stream, None, client, shutdown).await;
+            }
+        };
+        let peer = Some(stream.peer_addr()?);
+        let Some(tls) = &shared.options.tls else {
+            return Self::serve_protocol(shared, stream, peer, client, shutdown).await;
+        };
+
+        let handshake = TlsAcceptor::from(tls.config()).accept(stream);
+        let stream = tokio::select! {
+            stream = tokio::time::timeout(shared.options.read_timeout, handshake) => {
+                stream
+                    .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))
+                    .and_then(|stream| stream)
+                    .context("TLS handshake failed")?
+            }
+            Ok(()) = shutdown.changed() => {
+                debug!("Server shutting down, closing connection");
+                return Ok(());
+            }
+        };
+        Self::serve_protocol(shared, stream, peer, client, shutdown).await
+    }
+
+    /// Handles a connection in the server's protocol.
+    async fn serve_protocol(
+        shared: &Arc<Shared>,
+        stream: impl AsyncRead + AsyncWrite + Unpin,
+        peer: Option<SocketAddr>,
+        client: Client,
+        shutdown: watch::Receiver<bool>,
+    ) -> Result<()> {
+        match shared.options.protocol {
+            Protocol::Kvs => Self::handle_connection(shared, stream, peer, client, shutdown).await,
+            Protocol::Resp => {
+                let session = resp::Session::new(shared, client);
+                frontend::serve_async(shared, session, stream, client, shutdown).await
+            }
+            Protocol::Memcached => {
+                let session = memcached::Session::new(shared, client);
+                frontend::serve_async(shared, session, stream, client, shutdown).await
+            }
+        }
+    }
+
+    /// Handles commands in the server's own protocol until the [`Session`] closes the connection
+    /// or the server shuts down.
+    async fn handle_connection(
+        shared: &Arc<Shared>,
+        mut stream: impl AsyncRead + AsyncWrite + Unpin,
+        peer: Option<SocketAddr>,
+        client: Client,
+        mut shutdown: watch::Receiver<bool>,
+    ) -> Result<()> {
+        let mut session = Session::new(shared, client, peer);
+        let mut buf = Vec::new();
+        loop {
+            let read = Self::read_cmd(&mut stream, &mut buf, shared, session.wait());
+            let read = tokio::select! {
+                read = read => read,
+                Ok(()) = shutdown.changed() => {
+                    debug!("Server shutting down, closing connection");
+                    return Ok(());
+                }
+            };
+            let reply = match session.on_read(read)? {
+                Action::Read => continue,
+                Action::Execute(cmd) => session.respond(&Self::execute(shared, cmd).await?)?,
+                Action::Reply(reply) => reply,
+                Action::Close => return Ok(()),
+            };
+            stream.write_all(&reply.bytes).await?;
+            shared.metrics.record_written(reply.bytes.len());
+            if reply.close {
+                stream.shutdown().await?;
+                return Ok(());
+            }
+        }
+    }
+
+    /// Reads the next command from the connection, buffering any bytes past it for the next call.
+    /// Returns `None` if the connection closed before any bytes of a command were sent. The client
+    /// can wait before it starts sending the command, then has the read timeout to send the rest,
+    /// or reading fails with [`io::ErrorKind::TimedOut`].
+    async fn read_cmd(
+        stream: &mut (impl AsyncRead + Unpin),
+        buf: &mut Vec<u8>,
+        shared: &Shared,
+        wait: Duration,
+    ) -> Result<Option<Cmd<'static>>> {
+        let read_timeout = shared.options.read_timeout;
+        let mut deadline = Instant::now() + wait;
+        let mut started = false;
+        loop {
+            if let Some(read_result) =
+                Cmd::decode(buf, &shared.options.limits).context("parsing command body")?
+            {
+                let bytes_read = read_result.bytes_read();
+                let cmd = read_result.into_cmd().into_owned();
+                buf.drain(..bytes_read);
+                return Ok(Some(cmd));
+            }
+            if !started && !buf.is_empty() {
+                started = true;
+                deadline = deadline.min(Instant::now() + read_timeout);
+            }
+
+            let read = tokio::time::timeout_at(deadline, stream.read_buf(buf))
+                .await
+                .map_err(|_| io::Error::from(io::ErrorKind::TimedOut))
+                .context("reading command")?;
+            let read = match read {
+                // TLS clients that close without a close_notify end like a plain TCP connection
+                // would. Commands carry their lengths, so a command that's cut short is still
+                // detected.
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+                read => read.context("reading command")?,
+            };
+            shared.metrics.record_read(read);
+            if read == 0 {
+                if buf.is_empty() {
+                    return Ok(None);
+                }
+                anyhow::bail!("Not enough bytes for command");
+            }
+        }
+    }
+
+    /// Executes the command on the runtime's blocking thread pool.
+    async fn execute(shared: &Arc<Shared>, cmd: Cmd<'static>) -> Result<Response<'static>> {
+        let shared = Arc::clone(shared);
+        tokio::task::spawn_blocking(move || Server::handle_cmd(&shared, cmd))
+            .await
+            .context("executing command")
+    }
+}
+
+/// The sockets an [`AsyncServer`] accepts connections on.
+struct Listeners {
+    tcp: Option<TcpListener>,
+    unix: Option<UnixListener>,
+}
+
+/// An accepted connection, before any TLS handshake.
+#[derive(Debug)]
+enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    /// The client's IP, if it connected over TCP.
+    fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.ip()),
+            Self::Unix(_) => None,
+        }
+    }
+}
+
+impl Listeners {
+    /// Waits for a connection on whichever socket gets one first.
+    async fn accept(&self) -> io::Result<Socket> {
+        let tcp = async {
+            match &self.tcp {
+                Some(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| Socket::Tcp(stream)),
+                None => future::pending().await,
+            }
+        };
+        let unix = async {
+            match &self.unix {
+                Some(listener) => listener
+                    .accept()
+                    .await
+                    .map(|(stream, _)| Socket::Unix(stream)),
+                None => future::pending().await,
+            }
+        };
+        tokio::select! {
+            accepted = tcp => accepted,
+            accepted = unix => accepted,
+        }
+    }
+}
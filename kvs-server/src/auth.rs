@@ -0,0 +1,76 @@
+//! Shared-secret authentication of connections.
+
+use std::fmt;
+use std::path::Path;
+
+use anyhow::{ensure, Context, Result};
+use subtle::ConstantTimeEq;
+
+/// A secret token that clients must present with [`Cmd::Auth`][protocol::Cmd::Auth] before the
+/// server accepts their commands. Its `Debug` output doesn't reveal the token.
+#[derive(Clone)]
+pub struct AuthToken(Vec<u8>);
+
+impl AuthToken {
+    /// Wraps a token. Tokens can't be empty.
+    pub fn new(token: impl Into<String>) -> Result<Self> {
+        let token = token.into();
+        ensure!(!token.is_empty(), "The auth token can't be empty");
+        Ok(Self(token.into_bytes()))
+    }
+
+    /// Reads a token from a file. Surrounding whitespace, like a trailing newline, is ignored.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read auth token file {}", path.display()))?;
+        Self::new(contents.trim())
+            .with_context(|| format!("Invalid auth token file {}", path.display()))
+    }
+
+    /// Whether the presented token matches. The comparison takes the same time wherever the tokens
+    /// differ, so the token can't be guessed a byte at a time by timing responses.
+    pub fn verify(&self, presented: &str) -> bool {
+        self.0.ct_eq(presented.as_bytes()).into()
+    }
+
+    /// Whether two tokens are the same, for checking configuration. Unlike [`AuthToken::verify`],
+    /// this isn't constant-time.
+    pub(crate) fn same_as(&self, other: &Self) -> bool {
+        self.0 == other.0
+    }
+}
+
+impl fmt::Debug for AuthToken {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("AuthToken(<redacted>)")
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn verifies_tokens() {
+        let token = AuthToken::new("secret").unwrap();
+        assert!(token.verify("secret"));
+        assert!(!token.verify("secreT"));
+        assert!(!token.verify("secret2"));
+        assert!(!token.verify(""));
+        assert_eq!(format!("{token:?}"), "AuthToken(<redacted>)");
+
+        assert!(AuthToken::new("").is_err());
+    }
+
+    #[test]
+    fn reads_token_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = dir.path().join("token");
+        std::fs::write(&path, "secret\n").unwrap();
+        assert!(AuthToken::from_file(&path).unwrap().verify("secret"));
+
+        std::fs::write(&path, "\n").unwrap();
+        assert!(AuthToken::from_file(&path).is_err());
+    }
+}
@@ -1,48 +1,418 @@
-use std::{net::SocketAddr, path::PathBuf};
+use std::{net::SocketAddr, path::PathBuf, sync::mpsc};
 
-use kvs_server::{Engine, EngineType, Server};
+use kvs_server::{
+    AsyncServer, Config, Engine, EngineType, Pool, PoolType, Protocol, Runtime, Server, SledConfig,
+    SledMode,
+};
 
 use anyhow::{Context, Result};
-use clap::Parser;
-use tracing::{debug, info, trace};
+use clap::{Args as ClapArgs, Parser, Subcommand};
+use tracing::{debug, info};
 
+// Every setting is optional so that only the ones passed override the config file. Defaults are
+// applied by `Config`.
 #[derive(Parser)]
 #[command(version)]
 struct Args {
-    /// Address to bind to and receive connections from.
-    #[clap(long, default_value = "127.0.0.1:4000")]
-    addr: SocketAddr,
+    #[command(subcommand)]
+    command: Option<Command>,
 
-    /// Type of underlying key-value storage to use. Either "kvs" or "sled".
+    /// TOML file to read settings from. Settings passed as arguments or `KVS_*` environment
+    /// variables take precedence over the file's.
+    #[clap(long, env = "KVS_CONFIG", global = true)]
+    config: Option<PathBuf>,
+
+    /// Print the effective settings, in the config file's format, and exit.
     #[clap(long)]
+    print_config: bool,
+
+    /// Address to bind to and receive connections from. Defaults to 127.0.0.1:4000.
+    #[clap(long, env = "KVS_ADDR")]
+    addr: Option<SocketAddr>,
+
+    /// Type of underlying key-value storage to use. Either "kvs", "sled", "memory" or the name of
+    /// a registered engine.
+    #[clap(long, env = "KVS_ENGINE")]
     engine: Option<EngineType>,
 
-    /// Directory for engine to store data files in.
-    #[clap(long)]
+    /// Directory for engine to store data files in. Defaults to the current directory.
+    #[clap(long, env = "KVS_DIR", global = true)]
     dir: Option<PathBuf>,
+
+    /// File for the memory engine to restore its data from on start and save its data to on
+    /// shutdown. Without this, the memory engine's data is lost on shutdown.
+    #[clap(long, env = "KVS_SNAPSHOT")]
+    snapshot: Option<PathBuf>,
+
+    /// How to handle connections. Either "sync", with blocking I/O on a thread pool, or "async",
+    /// with non-blocking I/O on an async runtime. Defaults to "sync".
+    #[clap(long, env = "KVS_RUNTIME")]
+    runtime: Option<Runtime>,
+
+    /// Type of thread pool to handle connections with in the sync runtime. Either "naive" (a
+    /// thread per connection), "shared-queue" or "work-stealing". Defaults to "shared-queue".
+    #[clap(long, env = "KVS_POOL")]
+    pool: Option<PoolType>,
+
+    /// Number of threads to handle connections with. In the sync runtime, a persistent connection
+    /// holds its thread until it's closed, and while every thread waits on an idle connection, up
+    /// to 16 more connections get their own threads. For the async runtime, this is the number of
+    /// runtime worker threads. Defaults to the available parallelism.
+    #[clap(long, env = "KVS_THREADS")]
+    threads: Option<usize>,
+
+    /// Protocol clients speak: "kvs", which `kvs-client` speaks, "resp", for Redis clients and
+    /// tools like redis-cli, or "memcached", for memcached clients. Defaults to "kvs".
+    #[clap(long, env = "KVS_PROTOCOL")]
+    protocol: Option<Protocol>,
+
+    /// How long, in seconds, a persistent connection can go without sending a command before the
+    /// server closes it. Defaults to 60.
+    #[clap(long, env = "KVS_IDLE_TIMEOUT_SECS")]
+    idle_timeout_secs: Option<u64>,
+
+    /// How long, in seconds, a client has to send the rest of a command once it starts sending
+    /// it, and a new connection has to start sending one. Slower clients are disconnected.
+    /// Defaults to 10.
+    #[clap(long, env = "KVS_READ_TIMEOUT_SECS")]
+    read_timeout_secs: Option<u64>,
+
+    /// Most connections to handle at once. Connections over the limit get a busy error. Not
+    /// limited unless this is set.
+    #[clap(long, env = "KVS_MAX_CONNECTIONS")]
+    max_connections: Option<usize>,
+
+    /// How many commands a second each client IP can send. Commands over the limit get a busy
+    /// error. Not limited unless this is set.
+    #[clap(long, env = "KVS_RATE_LIMIT")]
+    rate_limit: Option<u32>,
+
+    /// How many commands a client IP can send in a burst, above its rate limit. Defaults to a
+    /// second's worth.
+    #[clap(long, env = "KVS_RATE_LIMIT_BURST")]
+    rate_limit_burst: Option<u32>,
+
+    /// Largest key, in bytes, a client can send. Larger ones are rejected. Defaults to 64 KiB.
+    #[clap(long, env = "KVS_MAX_KEY_BYTES")]
+    max_key_bytes: Option<usize>,
+
+    /// Largest value, in bytes, a client can send. Larger ones are rejected. Defaults to 64 MiB.
+    #[clap(long, env = "KVS_MAX_VALUE_BYTES")]
+    max_value_bytes: Option<usize>,
+
+    /// Filter for log messages, in the same format as `RUST_LOG`, e.g. "debug" or
+    /// "kvs_server=debug,info". Defaults to `RUST_LOG`, or "info" if that isn't set.
+    #[clap(long, env = "KVS_LOG")]
+    log: Option<String>,
+
+    /// Address to serve Prometheus metrics on, at `/metrics`. Metrics aren't served unless this is
+    /// set.
+    #[clap(long, env = "KVS_METRICS_ADDR")]
+    metrics_addr: Option<SocketAddr>,
+
+    /// Address to serve the HTTP gateway on, which gets, sets and removes keys at `/keys/{key}`
+    /// and lists them at `/keys?prefix=`. The gateway isn't served unless this is set.
+    #[clap(long, env = "KVS_HTTP_ADDR")]
+    http_addr: Option<SocketAddr>,
+
+    /// File holding a token that connections must present before their commands are accepted.
+    /// Without this, commands are accepted from any connection.
+    #[clap(long, env = "KVS_AUTH_TOKEN_FILE")]
+    auth_token_file: Option<PathBuf>,
+
+    /// TOML file mapping tokens to the commands ("read", "write" or "admin") and key prefixes
+    /// they're allowed to use. With this, connections must present one of its tokens, or the
+    /// token from `--auth-token-file`, which is allowed everything.
+    #[clap(long, env = "KVS_ACL_FILE")]
+    acl_file: Option<PathBuf>,
+
+    /// PEM file with the certificate chain to serve connections over TLS with. Needs
+    /// `--tls-key`. Without this, connections use plain TCP.
+    #[clap(long, env = "KVS_TLS_CERT")]
+    tls_cert: Option<PathBuf>,
+
+    /// PEM file with the private key for `--tls-cert`.
+    #[clap(long, env = "KVS_TLS_KEY")]
+    tls_key: Option<PathBuf>,
+
+    /// PEM file with CA certificates that clients must present a certificate signed by (mutual
+    /// TLS). Without this, clients aren't asked for certificates.
+    #[clap(long, env = "KVS_TLS_CLIENT_CA")]
+    tls_client_ca: Option<PathBuf>,
+
+    /// Path of a Unix socket to listen on too, for clients on the same host. Connections over it
+    /// never use TLS.
+    #[clap(long, env = "KVS_UNIX")]
+    unix: Option<PathBuf>,
+
+    /// Permissions for the Unix socket file, in octal, which control who can connect. Defaults to
+    /// 660, letting the socket's owner and group connect.
+    #[clap(long, env = "KVS_UNIX_MODE", value_parser = parse_mode)]
+    unix_mode: Option<u32>,
+
+    /// Only listen on the Unix socket, not on `--addr`.
+    #[clap(long, env = "KVS_UNIX_ONLY")]
+    unix_only: bool,
+
+    #[command(flatten)]
+    sled: SledArgs,
 }
 
-fn main() -> Result<()> {
-    logging::configure();
+// Tuning for the sled engine. These are ignored by other engines.
+#[derive(ClapArgs)]
+#[command(next_help_heading = "Sled options")]
+struct SledArgs {
+    /// Maximum size, in bytes, of sled's page cache.
+    #[clap(long, env = "KVS_SLED_CACHE_CAPACITY")]
+    sled_cache_capacity: Option<u64>,
+
+    /// Compress sled's data with zstd.
+    #[clap(
+        long,
+        env = "KVS_SLED_COMPRESSION",
+        num_args = 0..=1,
+        require_equals = true,
+        default_missing_value = "true"
+    )]
+    sled_compression: Option<bool>,
+
+    /// How often, in milliseconds, sled flushes to disk in the background. 0 disables background
+    /// flushes. Defaults to 500.
+    #[clap(long, env = "KVS_SLED_FLUSH_EVERY_MS")]
+    sled_flush_every_ms: Option<u64>,
+
+    /// Whether sled optimizes for disk usage or for throughput. Either "low-space" or
+    /// "high-throughput". Defaults to "low-space".
+    #[clap(long, env = "KVS_SLED_MODE")]
+    sled_mode: Option<SledMode>,
+
+    /// Don't flush sled to disk after every set and remove. Writes since the last background
+    /// flush may be lost if the server crashes.
+    #[clap(long, env = "KVS_SLED_NO_FLUSH_ON_WRITE")]
+    sled_no_flush_on_write: bool,
+}
+
+impl Args {
+    /// Overrides the config's settings with the ones that were passed.
+    fn apply(self, config: &mut Config) {
+        let Self {
+            command: _,
+            config: _,
+            print_config: _,
+            addr,
+            engine,
+            dir,
+            snapshot,
+            runtime,
+            pool,
+            threads,
+            protocol,
+            idle_timeout_secs,
+            read_timeout_secs,
+            max_connections,
+            rate_limit,
+            rate_limit_burst,
+            max_key_bytes,
+            max_value_bytes,
+            log,
+            metrics_addr,
+            http_addr,
+            auth_token_file,
+            acl_file,
+            tls_cert,
+            tls_key,
+            tls_client_ca,
+            unix,
+            unix_mode,
+            unix_only,
+            sled,
+        } = self;
+
+        config.addr = addr.unwrap_or(config.addr);
+        config.engine.engine_type = engine.or(config.engine.engine_type);
+        config.engine.dir = dir.or(config.engine.dir.take());
+        config.engine.snapshot = snapshot.or(config.engine.snapshot.take());
+        config.server.runtime = runtime.unwrap_or(config.server.runtime);
+        config.server.pool = pool.unwrap_or(config.server.pool);
+        config.server.threads = threads.or(config.server.threads);
+        config.server.protocol = protocol.unwrap_or(config.server.protocol);
+        config.limits.idle_timeout_secs =
+            idle_timeout_secs.unwrap_or(config.limits.idle_timeout_secs);
+        config.limits.read_timeout_secs =
+            read_timeout_secs.unwrap_or(config.limits.read_timeout_secs);
+        config.limits.max_connections = max_connections.or(config.limits.max_connections);
+        config.limits.rate_limit_per_sec = rate_limit.or(config.limits.rate_limit_per_sec);
+        config.limits.rate_limit_burst = rate_limit_burst.or(config.limits.rate_limit_burst);
+        config.limits.max_key_bytes = max_key_bytes.unwrap_or(config.limits.max_key_bytes);
+        config.limits.max_value_bytes = max_value_bytes.unwrap_or(config.limits.max_value_bytes);
+        config.log.filter = log.or(config.log.filter.take());
+        config.metrics.addr = metrics_addr.or(config.metrics.addr);
+        config.http.addr = http_addr.or(config.http.addr);
+        config.auth.token_file = auth_token_file.or(config.auth.token_file.take());
+        config.auth.acl_file = acl_file.or(config.auth.acl_file.take());
+        config.tls.cert = tls_cert.or(config.tls.cert.take());
+        config.tls.key = tls_key.or(config.tls.key.take());
+        config.tls.client_ca = tls_client_ca.or(config.tls.client_ca.take());
+        config.unix.path = unix.or(config.unix.path.take());
+        config.unix.mode = unix_mode.unwrap_or(config.unix.mode);
+        if unix_only {
+            config.unix.only = true;
+        }
+        sled.apply(&mut config.engine.sled);
+    }
+}
+
+impl SledArgs {
+    fn apply(self, config: &mut SledConfig) {
+        config.cache_capacity = self.sled_cache_capacity.or(config.cache_capacity);
+        config.compression = self.sled_compression.unwrap_or(config.compression);
+        config.flush_every_ms = self.sled_flush_every_ms.unwrap_or(config.flush_every_ms);
+        config.mode = self.sled_mode.unwrap_or(config.mode);
+        if self.sled_no_flush_on_write {
+            config.flush_on_write = false;
+        }
+    }
+}
+
+fn parse_mode(mode: &str) -> Result<u32> {
+    u32::from_str_radix(mode, 8).with_context(|| format!("Invalid octal mode {mode:?}"))
+}
 
+#[derive(Subcommand)]
+enum Command {
+    /// Rewrites log files written by older versions of the kvs engine into the current format.
+    /// The server must not be running in the directory.
+    Upgrade,
+    /// Copies every key from a directory of one engine type into a fresh directory of another.
+    Migrate {
+        /// Directory containing the data to migrate. This must already hold an engine's data.
+        #[clap(long)]
+        from_dir: PathBuf,
+
+        /// Directory to migrate the data into. This must not contain any data yet.
+        #[clap(long)]
+        to_dir: PathBuf,
+
+        /// Type of engine to migrate the data into, "kvs" or "sled". The configured engine options,
+        /// like sled's tuning and the key and value limits, apply to it.
+        #[clap(long)]
+        to_engine: EngineType,
+    },
+}
+
+fn main() -> Result<()> {
     let mut args = Args::parse();
+    let command = args.command.take();
+    let print_config = args.print_config;
 
-    trace!(?args.dir, "Determining directory");
-    let dir = match args.dir.take() {
-        Some(dir) => dir,
-        None => std::env::current_dir().context("Failed to find current directory")?,
+    // Precedence, from highest to lowest, is arguments, environment variables (both handled by
+    // clap), the config file and then defaults.
+    let mut config = match &args.config {
+        Some(path) => Config::from_file(path)?,
+        None => Config::default(),
     };
+    args.apply(&mut config);
+
+    if config.engine.dir.is_none() {
+        let dir = std::env::current_dir().context("Failed to find current directory")?;
+        config.engine.dir = Some(dir);
+    }
+    if config.server.threads.is_none() {
+        let threads = std::thread::available_parallelism()
+            .context("Failed to determine available parallelism")?
+            .get();
+        config.server.threads = Some(threads);
+    }
+    config.validate()?;
+
+    if print_config {
+        print!("{}", config.to_toml()?);
+        return Ok(());
+    }
+
+    match &config.log.filter {
+        Some(filter) => logging::configure_with_filter(filter)
+            .with_context(|| format!("Invalid log filter {filter:?}"))?,
+        None => logging::configure(),
+    }
+
+    let dir = config.engine.dir.clone().expect("defaulted above");
     debug!(?dir, "Using directory");
 
-    debug!(?args.engine, "Opening engine");
-    let kvs = Engine::new_in(args.engine, &dir)?;
+    match command {
+        Some(Command::Upgrade) => {
+            let upgraded = kvs::upgrade_dir(&dir)?;
+            info!(?dir, upgraded, "Upgraded log files");
+            return Ok(());
+        }
+        Some(Command::Migrate {
+            from_dir,
+            to_dir,
+            to_engine,
+        }) => {
+            let mut from = kvs_server::open_source(&from_dir, &to_dir, to_engine)?;
+            std::fs::create_dir_all(&to_dir).context("Failed to create destination directory")?;
+            let options = config.engine_options();
+            let mut to = Engine::new_in_with_options(Some(to_engine), &to_dir, &options)?;
+            let migrated = kvs_server::migrate(&mut from, &mut to)?;
+            info!(?from_dir, ?to_dir, migrated, "Migrated data");
+            return Ok(());
+        }
+        None => {}
+    }
+
+    debug!(engine = ?config.engine.engine_type, "Opening engine");
+    let kvs =
+        Engine::new_in_with_options(config.engine.engine_type, &dir, &config.engine_options())?;
     info!(
-        ?args.addr,
+        addr = ?config.addr,
         ?dir,
         engine_type = %kvs.engine_type(),
+        runtime = %config.server.runtime,
+        protocol = %config.server.protocol,
         version=env!("CARGO_PKG_VERSION"),
         "Starting server",
     );
 
-    Server::new(kvs, args.addr).run()
+    let threads = config.server.threads.expect("defaulted above");
+    let options = config.server_options()?;
+    if let Some(tls) = &options.tls {
+        info!(?tls, "Serving connections over TLS");
+    }
+    let handle = match config.server.runtime {
+        Runtime::Sync => {
+            debug!(pool_type = %config.server.pool, threads, "Creating thread pool");
+            let pool = Pool::new(config.server.pool, threads)?;
+            Server::new(kvs, config.addr, pool, options).bind()?
+        }
+        Runtime::Async => {
+            debug!(threads, "Starting async runtime");
+            AsyncServer::new(kvs, config.addr, threads, options).bind()?
+        }
+    };
+    if let Some(local_addr) = handle.local_addr() {
+        info!(%local_addr, "Listening");
+    }
+    if let Some(unix_path) = handle.unix_path() {
+        info!(?unix_path, "Listening on Unix socket");
+    }
+    if let Some(metrics_addr) = handle.metrics_addr() {
+        info!(%metrics_addr, "Serving metrics");
+    }
+    if let Some(http_addr) = handle.http_addr() {
+        info!(%http_addr, "Serving HTTP gateway");
+    }
+
+    // Shut down on SIGINT or SIGTERM so in-flight commands finish and the engine is flushed.
+    let (sender, receiver) = mpsc::channel();
+    ctrlc::set_handler(move || {
+        let _ = sender.send(());
+    })
+    .context("Failed to set signal handler")?;
+    receiver
+        .recv()
+        .context("Signal handler dropped unexpectedly")?;
+
+    info!("Received shutdown signal");
+    handle.shutdown()
 }
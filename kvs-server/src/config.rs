@@ -0,0 +1,625 @@
+//! Settings for running a server, which can be read from a TOML file.
+//!
+//! Every setting has a default, so a file only needs the settings it changes:
+//!
+//! ```toml
+//! addr = "0.0.0.0:4000"
+//!
+//! [engine]
+//! type = "sled"
+//! dir = "/var/lib/kvs"
+//!
+//! [engine.sled]
+//! cache_capacity = 1073741824
+//! flush_on_write = false
+//!
+//! [server]
+//! runtime = "async"
+//! threads = 8
+//! protocol = "resp"
+//!
+//! [limits]
+//! idle_timeout_secs = 30
+//! read_timeout_secs = 5
+//! max_connections = 1024
+//! rate_limit_per_sec = 1000
+//! rate_limit_burst = 2000
+//! max_key_bytes = 1024
+//! max_value_bytes = 16777216
+//!
+//! [log]
+//! filter = "kvs_server=debug,info"
+//!
+//! [metrics]
+//! addr = "0.0.0.0:9100"
+//!
+//! [http]
+//! addr = "0.0.0.0:8080"
+//!
+//! [auth]
+//! token_file = "/etc/kvs/token"
+//! acl_file = "/etc/kvs/acl.toml"
+//!
+//! [tls]
+//! cert = "/etc/kvs/server.pem"
+//! key = "/etc/kvs/server.key"
+//! client_ca = "/etc/kvs/clients-ca.pem"
+//!
+//! [unix]
+//! path = "/run/kvs.sock"
+//! mode = 0o660
+//! ```
+
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+use protocol::Limits;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    Acl, AuthToken, EngineOptions, EngineType, PoolType, Protocol, RateLimit, Runtime,
+    ServerOptions, ServerTls, SledMode, SledOptions, UnixSocket,
+};
+
+/// Every setting for running a server.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    /// Address to bind to and receive connections from.
+    pub addr: SocketAddr,
+    pub engine: EngineConfig,
+    pub server: ServerConfig,
+    pub limits: LimitsConfig,
+    pub log: LogConfig,
+    pub metrics: MetricsConfig,
+    pub http: HttpConfig,
+    pub auth: AuthConfig,
+    pub tls: TlsConfig,
+    pub unix: UnixConfig,
+}
+
+/// Which engine stores the data, and how.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct EngineConfig {
+    /// Type of engine. `None` uses the engine that wrote the data directory, or "kvs" for a new
+    /// directory.
+    #[serde(
+        rename = "type",
+        with = "optional_display_from_str",
+        skip_serializing_if = "Option::is_none"
+    )]
+    pub engine_type: Option<EngineType>,
+    /// Directory to store data files in. `None` uses the current directory.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub dir: Option<PathBuf>,
+    /// File for the memory engine to restore its data from on start and save its data to on
+    /// shutdown.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub snapshot: Option<PathBuf>,
+    pub sled: SledConfig,
+}
+
+/// Tuning for the sled engine. These are ignored by other engines.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct SledConfig {
+    /// Maximum size, in bytes, of sled's page cache. `None` uses sled's default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cache_capacity: Option<u64>,
+    /// Compress sled's data with zstd.
+    pub compression: bool,
+    /// How often, in milliseconds, sled flushes to disk in the background. 0 disables background
+    /// flushes.
+    pub flush_every_ms: u64,
+    #[serde(with = "display_from_str")]
+    pub mode: SledMode,
+    /// Flush to disk after every set and remove.
+    pub flush_on_write: bool,
+}
+
+/// How connections are handled.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct ServerConfig {
+    #[serde(with = "display_from_str")]
+    pub runtime: Runtime,
+    /// Type of thread pool for the sync runtime.
+    #[serde(with = "display_from_str")]
+    pub pool: PoolType,
+    /// Number of threads to handle connections with. `None` uses the available parallelism.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub threads: Option<usize>,
+    /// The protocol clients speak.
+    #[serde(with = "display_from_str")]
+    pub protocol: Protocol,
+}
+
+/// Bounds on what clients can do.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LimitsConfig {
+    /// How long, in seconds, a persistent connection can go without sending a command before the
+    /// server closes it.
+    pub idle_timeout_secs: u64,
+    /// How long, in seconds, a client has to send the rest of a command once it starts sending
+    /// it, and a new connection has to start sending one.
+    pub read_timeout_secs: u64,
+    /// Most connections to handle at once. `None` doesn't limit them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub max_connections: Option<usize>,
+    /// How many commands a second each client IP can send. `None` doesn't limit them.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_per_sec: Option<u32>,
+    /// How many commands a client IP can send in a burst, above its rate limit. `None` allows a
+    /// second's worth.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rate_limit_burst: Option<u32>,
+    /// Largest key, in bytes, a client can send.
+    pub max_key_bytes: usize,
+    /// Largest value, in bytes, a client can send.
+    pub max_value_bytes: usize,
+}
+
+/// Where Prometheus metrics are served.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct MetricsConfig {
+    /// Address to serve metrics on, at `/metrics`. `None` doesn't serve metrics.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<SocketAddr>,
+}
+
+/// Where the HTTP gateway is served.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    /// Address to serve the gateway on. `None` doesn't serve it.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub addr: Option<SocketAddr>,
+}
+
+/// How connections authenticate.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// File holding the token connections must present before their commands are accepted.
+    /// `None` accepts commands from any connection.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub token_file: Option<PathBuf>,
+    /// File mapping tokens to the commands and keys they're allowed to use. See [`Acl`]. With an
+    /// ACL, connections have to authenticate even if there's no token file.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub acl_file: Option<PathBuf>,
+}
+
+/// TLS for connections. Without a certificate and key, connections use plain TCP.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsConfig {
+    /// PEM file with the server's certificate chain.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub cert: Option<PathBuf>,
+    /// PEM file with the server certificate's private key.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub key: Option<PathBuf>,
+    /// PEM file with CA certificates that clients' certificates must be signed by. `None` doesn't
+    /// ask clients for certificates.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub client_ca: Option<PathBuf>,
+}
+
+/// A Unix socket to listen on, for clients on the same host.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct UnixConfig {
+    /// Path of the socket. `None` only listens on TCP.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub path: Option<PathBuf>,
+    /// Permission bits for the socket file, which control who can connect.
+    pub mode: u32,
+    /// Only listen on the Unix socket, not on `addr`.
+    pub only: bool,
+}
+
+/// What gets logged.
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    /// Filter for log messages, in the same format as `RUST_LOG`. `None` uses `RUST_LOG`, or
+    /// "info" if that isn't set.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub filter: Option<String>,
+}
+
+impl Config {
+    /// Reads settings from a TOML file. Settings missing from the file get their defaults.
+    pub fn from_file(path: impl AsRef<Path>) -> Result<Self> {
+        let path = path.as_ref();
+        let contents = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read config file {}", path.display()))?;
+        toml::from_str(&contents).with_context(|| format!("Invalid config file {}", path.display()))
+    }
+
+    /// Writes the settings as TOML, in the same format [`Config::from_file`] reads.
+    pub fn to_toml(&self) -> Result<String> {
+        toml::to_string(self).context("Failed to serialize config")
+    }
+
+    /// Checks for settings that are invalid on their own or in combination.
+    pub fn validate(&self) -> Result<()> {
+        ensure!(
+            self.engine.snapshot.is_none() || self.engine.engine_type == Some(EngineType::Memory),
+            "A snapshot is only supported by the memory engine"
+        );
+        ensure!(
+            self.limits.idle_timeout_secs > 0,
+            "The idle timeout must be at least 1 second"
+        );
+        ensure!(
+            self.limits.read_timeout_secs > 0,
+            "The read timeout must be at least 1 second"
+        );
+        ensure!(
+            self.limits.max_connections != Some(0),
+            "The server needs to allow at least 1 connection"
+        );
+        ensure!(
+            self.limits.rate_limit_per_sec != Some(0) && self.limits.rate_limit_burst != Some(0),
+            "The rate limit must allow at least 1 command"
+        );
+        ensure!(
+            self.limits.rate_limit_burst.is_none() || self.limits.rate_limit_per_sec.is_some(),
+            "A rate limit burst needs a rate limit"
+        );
+        ensure!(
+            self.limits.max_key_bytes > 0,
+            "The key size limit must be at least 1 byte"
+        );
+        ensure!(
+            self.server.threads != Some(0),
+            "The server needs at least 1 thread"
+        );
+        ensure!(
+            self.tls.cert.is_some() == self.tls.key.is_some(),
+            "TLS needs both a certificate and a key"
+        );
+        ensure!(
+            self.tls.client_ca.is_none() || self.tls.cert.is_some(),
+            "Verifying client certificates needs a TLS certificate and key"
+        );
+        ensure!(
+            self.unix.mode <= 0o777,
+            "The Unix socket mode must be permission bits, at most 0o777"
+        );
+        ensure!(
+            !self.unix.only || self.unix.path.is_some(),
+            "Only listening on a Unix socket needs a socket path"
+        );
+        Ok(())
+    }
+
+    /// Options for opening the engine.
+    pub fn engine_options(&self) -> EngineOptions {
+        EngineOptions {
+            sled: (&self.engine.sled).into(),
+            memory_snapshot: self.engine.snapshot.clone(),
+            limits: self.limits.limits(),
+        }
+    }
+
+    /// Options for handling connections. This reads the auth token, ACL and TLS files, if there
+    /// are any.
+    pub fn server_options(&self) -> Result<ServerOptions> {
+        let auth_token = self
+            .auth
+            .token_file
+            .as_ref()
+            .map(AuthToken::from_file)
+            .transpose()?;
+        let acl = self
+            .auth
+            .acl_file
+            .as_ref()
+            .map(Acl::from_file)
+            .transpose()?;
+        let tls = match (&self.tls.cert, &self.tls.key) {
+            (Some(cert), Some(key)) => Some(ServerTls::from_files(
+                cert,
+                key,
+                self.tls.client_ca.as_deref(),
+            )?),
+            _ => None,
+        };
+        Ok(ServerOptions {
+            idle_timeout: Duration::from_secs(self.limits.idle_timeout_secs),
+            read_timeout: Duration::from_secs(self.limits.read_timeout_secs),
+            max_connections: self.limits.max_connections,
+            rate_limit: self.limits.rate_limit(),
+            limits: self.limits.limits(),
+            metrics_addr: self.metrics.addr,
+            http_addr: self.http.addr,
+            auth_token,
+            acl,
+            tls,
+            tcp: !self.unix.only,
+            unix_socket: self.unix.path.as_ref().map(|path| UnixSocket {
+                path: path.clone(),
+                mode: self.unix.mode,
+            }),
+            protocol: self.server.protocol,
+        })
+    }
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        Self {
+            addr: SocketAddr::from(([127, 0, 0, 1], 4000)),
+            engine: EngineConfig::default(),
+            server: ServerConfig::default(),
+            limits: LimitsConfig::default(),
+            log: LogConfig::default(),
+            metrics: MetricsConfig::default(),
+            http: HttpConfig::default(),
+            auth: AuthConfig::default(),
+            tls: TlsConfig::default(),
+            unix: UnixConfig::default(),
+        }
+    }
+}
+
+impl Default for SledConfig {
+    fn default() -> Self {
+        let options = SledOptions::default();
+        Self {
+            cache_capacity: options.cache_capacity,
+            compression: options.use_compression,
+            flush_every_ms: options
+                .flush_every
+                .map_or(0, |every| every.as_millis() as u64),
+            mode: options.mode,
+            flush_on_write: options.flush_on_write,
+        }
+    }
+}
+
+impl From<&SledConfig> for SledOptions {
+    fn from(config: &SledConfig) -> Self {
+        Self {
+            cache_capacity: config.cache_capacity,
+            use_compression: config.compression,
+            flush_every: (config.flush_every_ms > 0)
+                .then(|| Duration::from_millis(config.flush_every_ms)),
+            mode: config.mode,
+            flush_on_write: config.flush_on_write,
+        }
+    }
+}
+
+impl Default for ServerConfig {
+    fn default() -> Self {
+        Self {
+            runtime: Runtime::Sync,
+            pool: PoolType::SharedQueue,
+            threads: None,
+            protocol: Protocol::Kvs,
+        }
+    }
+}
+
+impl Default for UnixConfig {
+    fn default() -> Self {
+        Self {
+            path: None,
+            mode: UnixSocket::DEFAULT_MODE,
+            only: false,
+        }
+    }
+}
+
+impl Default for LimitsConfig {
+    fn default() -> Self {
+        Self {
+            idle_timeout_secs: ServerOptions::default().idle_timeout.as_secs(),
+            read_timeout_secs: ServerOptions::default().read_timeout.as_secs(),
+            max_connections: None,
+            rate_limit_per_sec: None,
+            rate_limit_burst: None,
+            max_key_bytes: Limits::DEFAULT_MAX_KEY_BYTES,
+            max_value_bytes: Limits::DEFAULT_MAX_VALUE_BYTES,
+        }
+    }
+}
+
+impl LimitsConfig {
+    /// The limit on how many commands each client IP can send.
+    fn rate_limit(&self) -> Option<RateLimit> {
+        self.rate_limit_per_sec.map(|per_sec| RateLimit {
+            per_sec,
+            burst: self.rate_limit_burst.unwrap_or(per_sec),
+        })
+    }
+
+    /// The limits on keys and values.
+    fn limits(&self) -> Limits {
+        Limits {
+            max_key_bytes: self.max_key_bytes,
+            max_value_bytes: self.max_value_bytes,
+        }
+    }
+}
+
+/// (De)serializes types by their `Display` and `FromStr` implementations, which are the same
+/// strings accepted on the command line.
+mod display_from_str {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{de, Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<T: Display, S: Serializer>(
+        value: &T,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        serializer.collect_str(value)
+    }
+
+    pub(super) fn deserialize<'de, T, D>(deserializer: D) -> Result<T, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        String::deserialize(deserializer)?
+            .parse()
+            .map_err(de::Error::custom)
+    }
+}
+
+/// Like [`display_from_str`], for optional values.
+mod optional_display_from_str {
+    use std::fmt::Display;
+    use std::str::FromStr;
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    pub(super) fn serialize<T: Display, S: Serializer>(
+        value: &Option<T>,
+        serializer: S,
+    ) -> Result<S::Ok, S::Error> {
+        match value {
+            Some(value) => super::display_from_str::serialize(value, serializer),
+            None => serializer.serialize_none(),
+        }
+    }
+
+    pub(super) fn deserialize<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
+    where
+        T: FromStr,
+        T::Err: Display,
+        D: Deserializer<'de>,
+    {
+        #[derive(Deserialize)]
+        struct Wrapper<T: FromStr>(#[serde(with = "super::display_from_str")] T)
+        where
+            T::Err: Display;
+
+        let wrapper = Option::<Wrapper<T>>::deserialize(deserializer)?;
+        Ok(wrapper.map(|Wrapper(value)| value))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn defaults_missing_settings() {
+        let config: Config = toml::from_str(
+            r#"
+            addr = "0.0.0.0:5000"
+
+            [engine]
+            type = "sled"
+
+            [engine.sled]
+            mode = "high-throughput"
+            "#,
+        )
+        .unwrap();
+
+        let expected = Config {
+            addr: "0.0.0.0:5000".parse().unwrap(),
+            engine: EngineConfig {
+                engine_type: Some(EngineType::Sled),
+                sled: SledConfig {
+                    mode: SledMode::HighThroughput,
+                    ..Default::default()
+                },
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        assert_eq!(config, expected);
+    }
+
+    #[test]
+    fn round_trips() {
+        let mut config = Config::default();
+        config.engine.engine_type = Some(EngineType::Memory);
+        config.engine.snapshot = Some("snapshot".into());
+        config.server.runtime = Runtime::Async;
+        config.server.threads = Some(3);
+        config.server.protocol = Protocol::Resp;
+        config.limits.read_timeout_secs = 5;
+        config.limits.max_connections = Some(100);
+        config.limits.rate_limit_per_sec = Some(50);
+        config.limits.rate_limit_burst = Some(200);
+        config.limits.max_key_bytes = 1024;
+        config.limits.max_value_bytes = 1 << 20;
+        config.log.filter = Some("debug".to_owned());
+        config.metrics.addr = Some("127.0.0.1:9100".parse().unwrap());
+        config.http.addr = Some("127.0.0.1:8080".parse().unwrap());
+        config.auth.token_file = Some("token".into());
+        config.auth.acl_file = Some("acl.toml".into());
+        config.tls.cert = Some("server.pem".into());
+        config.tls.key = Some("server.key".into());
+        config.unix.path = Some("/run/kvs.sock".into());
+        config.unix.mode = 0o600;
+        config.unix.only = true;
+
+        let toml = config.to_toml().unwrap();
+        assert_eq!(toml::from_str::<Config>(&toml).unwrap(), config);
+    }
+
+    #[test]
+    fn rejects_unknown_settings() {
+        assert!(toml::from_str::<Config>("adr = \"0.0.0.0:5000\"").is_err());
+        assert!(toml::from_str::<Config>("[engine]\ntype = \"postgres\"").is_err());
+    }
+
+    #[test]
+    fn validates_combinations() {
+        let mut config = Config::default();
+        config.engine.snapshot = Some("snapshot".into());
+        assert!(config.validate().is_err());
+
+        config.engine.engine_type = Some(EngineType::Memory);
+        assert!(config.validate().is_ok());
+
+        config.limits.idle_timeout_secs = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.limits.max_key_bytes = 0;
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.limits.rate_limit_burst = Some(10);
+        assert!(config.validate().is_err());
+        config.limits.rate_limit_per_sec = Some(5);
+        assert!(config.validate().is_ok());
+        config.limits.max_connections = Some(0);
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.tls.cert = Some("server.pem".into());
+        assert!(config.validate().is_err());
+        config.tls.key = Some("server.key".into());
+        assert!(config.validate().is_ok());
+        config.tls.cert = None;
+        config.tls.client_ca = Some("ca.pem".into());
+        assert!(config.validate().is_err());
+
+        let mut config = Config::default();
+        config.unix.only = true;
+        assert!(config.validate().is_err());
+        config.unix.path = Some("/run/kvs.sock".into());
+        assert!(config.validate().is_ok());
+        config.unix.mode = 0o1777;
+        assert!(config.validate().is_err());
+    }
+}
@@ -2,21 +2,28 @@
 
 use std::borrow::Borrow;
 use std::fmt;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::str::FromStr;
 
 use anyhow::{anyhow, bail, Context, Error, Result};
-use kvs::{KvStore, KvsEngine};
+use kvs::{KvStore, KvsEngine, Limits, StoreStats};
 
-use sled_engine::SledDb;
+pub use memory_engine::MemoryDb;
+pub use registry::{register_engine, DynKvsEngine, EngineRegistration};
+pub use sled_engine::{SledDb, SledMode, SledOptions};
 
+mod memory_engine;
+mod registry;
 mod sled_engine;
 
 /// Represents a type of backing engine store.
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum EngineType {
     Kvs,
     Sled,
+    Memory,
+    /// An engine registered with [`register_engine`], identified by its name.
+    Custom(&'static str),
 }
 
 impl fmt::Display for EngineType {
@@ -24,6 +31,8 @@ impl fmt::Display for EngineType {
         match self {
             Self::Kvs => f.write_str("kvs"),
             Self::Sled => f.write_str("sled"),
+            Self::Memory => f.write_str("memory"),
+            Self::Custom(name) => f.write_str(name),
         }
     }
 }
@@ -35,25 +44,44 @@ impl FromStr for EngineType {
         match str {
             "kvs" => Ok(Self::Kvs),
             "sled" => Ok(Self::Sled),
-            other => Err(anyhow!("unknown engine type {other:?}")),
+            "memory" => Ok(Self::Memory),
+            other => registry::find(other)
+                .map(|registration| Self::Custom(registration.name))
+                .ok_or_else(|| anyhow!("unknown engine type {other:?}")),
         }
     }
 }
 
-/// Static dispatch enum for `KvsEngine` implementations.
+/// Options for opening an [`Engine`]. Each engine only looks at its own options.
+#[derive(Debug, Clone, Default)]
+pub struct EngineOptions {
+    /// Tuning for the sled engine.
+    pub sled: SledOptions,
+    /// File for the memory engine to restore its data from and save its data to. See
+    /// [`MemoryDb::with_snapshot`].
+    pub memory_snapshot: Option<PathBuf>,
+    /// The largest keys and values the kvs engine accepts. See [`KvStore::with_limits`].
+    pub limits: Limits,
+}
+
+/// Gauges describing an [`Engine`]'s data.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct EngineStats {
+    /// Number of keys currently associated with a value.
+    pub live_keys: usize,
+    /// Bytes the engine's files take up on disk, if it keeps its data on disk.
+    pub disk_bytes: Option<u64>,
+    /// Statistics about the log files. Only the kvs engine has these.
+    pub store: Option<StoreStats>,
+}
+
+/// Static dispatch enum for `KvsEngine` implementations. Engines registered with
+/// [`register_engine`] are dynamically dispatched through the `Custom` variant.
 pub enum Engine {
     Kvs(KvStore),
     Sled(SledDb),
-}
-
-/// Enum describing inferred engine types from a directory's contents.
-enum PreviousEngine {
-    /// Data in the directory indicates the previous engine was of type `KvStore`.
-    Kvs,
-    /// Data in the directory indicates the previous engine was of type `SledDb`.
-    Sled,
-    /// Data in the directory does not indicate any engine type.
-    None,
+    Memory(MemoryDb),
+    Custom(&'static str, Box<dyn DynKvsEngine>),
 }
 
 impl Engine {
@@ -62,22 +90,42 @@ impl Engine {
     /// - If the specified engine type doesn't match data in the existing directory, an error is
     ///   returned.
     /// - If no type is specified, and no previous data exists, [`KvStore`] is used by default.
+    /// - A [`MemoryDb`] never uses the directory and can be opened regardless of its contents.
     pub fn new_in(engine: Option<EngineType>, p: impl AsRef<Path>) -> Result<Self> {
+        Self::new_in_with_options(engine, p, &EngineOptions::default())
+    }
+
+    /// Opens a new `Engine` like [`Engine::new_in`], configuring it with the provided options.
+    pub fn new_in_with_options(
+        engine: Option<EngineType>,
+        p: impl AsRef<Path>,
+        options: &EngineOptions,
+    ) -> Result<Self> {
         let prev_engine = Self::determine_previous_engine(p.as_ref())?;
-        match (prev_engine, engine) {
-            (PreviousEngine::Sled, Some(EngineType::Kvs)) => {
-                bail!("Can't open kvs engine in sled directory")
+        let engine_type = match (prev_engine, engine) {
+            (_, Some(EngineType::Memory)) => EngineType::Memory,
+            (Some(prev_engine), Some(engine)) if prev_engine != engine => {
+                bail!("Can't open {engine} engine in {prev_engine} directory")
             }
-            (PreviousEngine::Kvs, Some(EngineType::Sled)) => {
-                bail!("Can't open sled engine in kvs directory")
-            }
-
-            (_, Some(EngineType::Kvs)) => Ok(Engine::Kvs(KvStore::open(p.as_ref())?)),
-            (_, Some(EngineType::Sled)) => Ok(Engine::Sled(SledDb(sled::open(p)?))),
-
-            (PreviousEngine::Sled, None) => Ok(Engine::Sled(SledDb(sled::open(p.as_ref())?))),
-            (PreviousEngine::Kvs | PreviousEngine::None, None) => {
-                Ok(Engine::Kvs(KvStore::open(p.as_ref())?))
+            (_, Some(engine)) | (Some(engine), None) => engine,
+            (None, None) => EngineType::Kvs,
+        };
+
+        match engine_type {
+            EngineType::Kvs => Ok(Engine::Kvs(
+                KvStore::open(p.as_ref())?.with_limits(options.limits),
+            )),
+            EngineType::Sled => Ok(Engine::Sled(SledDb::open(p, &options.sled)?)),
+            EngineType::Memory => match &options.memory_snapshot {
+                Some(snapshot) => Ok(Engine::Memory(MemoryDb::with_snapshot(snapshot)?)),
+                None => Ok(Engine::Memory(MemoryDb::new())),
+            },
+            EngineType::Custom(name) => {
+                let registration = registry::find(name)
+                    .ok_or_else(|| anyhow!("Engine {name:?} is not registered"))?;
+                let engine = (registration.open)(p.as_ref())
+                    .with_context(|| format!("opening {name} engine"))?;
+                Ok(Engine::Custom(name, engine))
             }
         }
     }
@@ -87,22 +135,63 @@ impl Engine {
         match self {
             Self::Kvs(_) => EngineType::Kvs,
             Self::Sled(_) => EngineType::Sled,
+            Self::Memory(_) => EngineType::Memory,
+            Self::Custom(name, _) => EngineType::Custom(name),
+        }
+    }
+
+    /// Describes the engine's data. The built-in engines keep count of their keys, but engines
+    /// registered with [`register_engine`] have to list them to count them.
+    pub fn stats(&self) -> Result<EngineStats> {
+        match self {
+            Self::Kvs(k) => {
+                let store = k.stats();
+                Ok(EngineStats {
+                    live_keys: store.live_keys,
+                    disk_bytes: Some(store.disk_bytes),
+                    store: Some(store),
+                })
+            }
+            Self::Sled(s) => Ok(EngineStats {
+                live_keys: s.live_keys(),
+                disk_bytes: Some(s.size_on_disk()?),
+                store: None,
+            }),
+            Self::Memory(m) => Ok(EngineStats {
+                live_keys: m.live_keys(),
+                disk_bytes: None,
+                store: None,
+            }),
+            Self::Custom(_, c) => Ok(EngineStats {
+                live_keys: c.dyn_keys()?.len(),
+                disk_bytes: None,
+                store: None,
+            }),
+        }
+    }
+
+    /// Compacts the engine's files now. Returns `false` if the engine doesn't compact its files.
+    pub fn compact(&mut self) -> kvs::Result<bool> {
+        match self {
+            Self::Kvs(k) => k.compact().map(|()| true),
+            Self::Sled(_) | Self::Memory(_) | Self::Custom(..) => Ok(false),
         }
     }
 
     /// Checks the given directory path for files indicating which engine was previously used
-    /// there.
-    fn determine_previous_engine(p: &Path) -> Result<PreviousEngine> {
+    /// there. Returns `None` if the directory doesn't indicate any engine type.
+    pub(crate) fn determine_previous_engine(p: &Path) -> Result<Option<EngineType>> {
         for entry in std::fs::read_dir(p).context("reading previous engine dir")? {
             let file_name = entry?.file_name();
             if file_name == "conf" || file_name == "db" {
-                return Ok(PreviousEngine::Sled);
+                return Ok(Some(EngineType::Sled));
             } else if file_name.to_string_lossy().ends_with(".pingcap") {
-                return Ok(PreviousEngine::Kvs);
+                return Ok(Some(EngineType::Kvs));
             }
         }
 
-        Ok(PreviousEngine::None)
+        let registered = registry::detect(p).context("detecting registered engines")?;
+        Ok(registered.map(|registration| EngineType::Custom(registration.name)))
     }
 }
 
@@ -111,18 +200,40 @@ impl KvsEngine for Engine {
         match self {
             Self::Kvs(k) => k.set(key, value),
             Self::Sled(s) => s.set(key, value),
+            Self::Memory(m) => m.set(key, value),
+            Self::Custom(_, c) => c.dyn_set(key, value.as_ref()),
         }
     }
     fn get<K: Borrow<str>>(&mut self, key: K) -> kvs::Result<Option<String>> {
         match self {
             Self::Kvs(k) => k.get(key),
             Self::Sled(s) => s.get(key),
+            Self::Memory(m) => m.get(key),
+            Self::Custom(_, c) => c.dyn_get(key.borrow()),
         }
     }
     fn remove<K: Borrow<str>>(&mut self, key: K) -> kvs::Result<()> {
         match self {
             Self::Kvs(k) => k.remove(key),
             Self::Sled(s) => s.remove(key),
+            Self::Memory(m) => m.remove(key),
+            Self::Custom(_, c) => c.dyn_remove(key.borrow()),
+        }
+    }
+    fn keys(&self) -> kvs::Result<Vec<String>> {
+        match self {
+            Self::Kvs(k) => k.keys(),
+            Self::Sled(s) => s.keys(),
+            Self::Memory(m) => m.keys(),
+            Self::Custom(_, c) => c.dyn_keys(),
+        }
+    }
+    fn flush(&mut self) -> kvs::Result<()> {
+        match self {
+            Self::Kvs(k) => k.flush(),
+            Self::Sled(s) => s.flush(),
+            Self::Memory(m) => m.flush(),
+            Self::Custom(_, c) => c.dyn_flush(),
         }
     }
 }
@@ -0,0 +1,191 @@
+//! An in-process [`KvsEngine`] that keeps every key in memory, for tests and ephemeral caches.
+
+use std::borrow::Borrow;
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::path::PathBuf;
+
+use anyhow::Context;
+use kvs::{KeyNotFound, KvsEngine, Result};
+use protocol::{Cmd, Limits, Reader};
+use tracing::{debug, info};
+
+/// A [`KvsEngine`] backed by a `HashMap`.
+///
+/// Data only lives as long as the engine unless it's opened with a snapshot file (see
+/// [`MemoryDb::with_snapshot`]).
+#[derive(Default)]
+pub struct MemoryDb {
+    map: HashMap<String, String>,
+    snapshot: Option<PathBuf>,
+    /// Whether the map has changed since the snapshot was last written.
+    dirty: bool,
+}
+
+impl MemoryDb {
+    /// Creates an empty engine that doesn't persist its data anywhere.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates an engine that restores its data from the snapshot file, if it exists, and writes
+    /// its data back to the snapshot file when flushed.
+    ///
+    /// Changes that weren't flushed are written when the engine is dropped, but errors can only be
+    /// logged then, so callers that need to know the data was saved should flush first.
+    pub fn with_snapshot(path: impl Into<PathBuf>) -> Result<Self> {
+        let path = path.into();
+        let mut map = HashMap::new();
+
+        match File::open(&path) {
+            Ok(file) => {
+                let mut file = BufReader::new(file);
+                // The snapshot was written from accepted data, so it's read whatever its size.
+                let mut reader = Reader::with_limits(Limits::NONE);
+                while let Some(read_result) = reader
+                    .read_cmd(&mut file)
+                    .context("reading memory snapshot")?
+                {
+                    match read_result.into_cmd() {
+                        Cmd::Set(key, value) => map.insert(key.into_owned(), value.into_owned()),
+                        other => anyhow::bail!("Unexpected command in snapshot {other:?}"),
+                    };
+                }
+                info!(?path, keys = map.len(), "Restored memory snapshot");
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                debug!(?path, "No memory snapshot to restore");
+            }
+            Err(e) => return Err(e).context("opening memory snapshot"),
+        }
+
+        Ok(Self {
+            map,
+            snapshot: Some(path),
+            dirty: false,
+        })
+    }
+
+    /// Number of keys currently associated with a value.
+    pub fn live_keys(&self) -> usize {
+        self.map.len()
+    }
+
+    /// Writes every key to the snapshot file, if there is one. The snapshot is replaced
+    /// atomically so a failed write leaves the previous snapshot intact.
+    pub fn write_snapshot(&self) -> Result<()> {
+        let Some(path) = &self.snapshot else {
+            return Ok(());
+        };
+
+        // Appended rather than replacing the extension, so the temporary file can't be the
+        // snapshot itself or shared with another snapshot in the directory.
+        let mut tmp_path = path.clone().into_os_string();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut file = BufWriter::new(File::create(&tmp_path).context("creating snapshot")?);
+        for (key, value) in &self.map {
+            Cmd::Set(key.into(), value.into()).write(&mut file)?;
+        }
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        std::fs::rename(&tmp_path, path).context("replacing snapshot")?;
+
+        debug!(?path, keys = self.map.len(), "Wrote memory snapshot");
+        Ok(())
+    }
+}
+
+impl Drop for MemoryDb {
+    fn drop(&mut self) {
+        if !self.dirty {
+            return;
+        }
+        if let Err(e) = self.write_snapshot() {
+            tracing::warn!(?e, "Failed to write memory snapshot");
+        }
+    }
+}
+
+impl KvsEngine for MemoryDb {
+    fn set<V: AsRef<str>>(&mut self, key: String, value: V) -> Result<()> {
+        self.map.insert(key, value.as_ref().to_owned());
+        self.dirty = true;
+        Ok(())
+    }
+
+    fn get<K: Borrow<str>>(&mut self, key: K) -> Result<Option<String>> {
+        Ok(self.map.get(key.borrow()).cloned())
+    }
+
+    fn remove<K: Borrow<str>>(&mut self, key: K) -> Result<()> {
+        match self.map.remove(key.borrow()) {
+            Some(_) => {
+                self.dirty = true;
+                Ok(())
+            }
+            None => Err(KeyNotFound.into()),
+        }
+    }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.map.keys().cloned().collect())
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.write_snapshot()?;
+        self.dirty = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn remove_missing_key() {
+        let mut db = MemoryDb::new();
+        let e = db.remove("key").unwrap_err();
+        assert!(e.is::<KeyNotFound>());
+    }
+
+    #[test]
+    fn restores_snapshot() {
+        let dir = tempfile::tempdir().unwrap();
+        let snapshot = dir.path().join("memory.snapshot");
+
+        let mut db = MemoryDb::with_snapshot(&snapshot).unwrap();
+        db.set("key1".to_owned(), "value1").unwrap();
+        db.set("key2".to_owned(), "value2").unwrap();
+        db.remove("key2").unwrap();
+        drop(db);
+
+        let mut db = MemoryDb::with_snapshot(&snapshot).unwrap();
+        assert_eq!(db.get("key1").unwrap(), Some("value1".to_owned()));
+        assert_eq!(db.get("key2").unwrap(), None);
+    }
+
+    #[test]
+    fn flush_reports_snapshot_errors() {
+        let dir = tempfile::tempdir().unwrap();
+        // A snapshot named like a temporary file still gets a temporary file of its own.
+        let snapshot = dir.path().join("memory.tmp");
+
+        let mut db = MemoryDb::with_snapshot(&snapshot).unwrap();
+        db.set("key".to_owned(), "value").unwrap();
+        db.flush().unwrap();
+        assert_eq!(
+            MemoryDb::with_snapshot(&snapshot)
+                .unwrap()
+                .get("key")
+                .unwrap(),
+            Some("value".to_owned())
+        );
+
+        let mut db = MemoryDb::with_snapshot(dir.path().join("missing/memory.snapshot")).unwrap();
+        db.set("key".to_owned(), "value").unwrap();
+        assert!(db.flush().is_err());
+    }
+}
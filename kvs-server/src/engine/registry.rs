@@ -0,0 +1,156 @@
+//! A registry of engines defined outside this crate, so they can be opened by name like the
+//! built-in engines.
+//!
+//! Built-in engines are dispatched statically through [`Engine`][crate::Engine]'s variants.
+//! Registered engines are boxed as [`DynKvsEngine`] trait objects and stored in
+//! [`Engine::Custom`][crate::Engine::Custom].
+
+use std::path::Path;
+use std::sync::RwLock;
+
+use anyhow::{bail, Result};
+use kvs::KvsEngine;
+
+/// An object-safe version of [`KvsEngine`]. This is implemented for every `KvsEngine`, so
+/// registered engines only need to implement `KvsEngine`.
+///
+/// The methods are prefixed with `dyn_` so they don't clash with `KvsEngine`'s methods.
+pub trait DynKvsEngine: Send {
+    fn dyn_set(&mut self, key: String, value: &str) -> kvs::Result<()>;
+    fn dyn_get(&mut self, key: &str) -> kvs::Result<Option<String>>;
+    fn dyn_remove(&mut self, key: &str) -> kvs::Result<()>;
+    fn dyn_keys(&self) -> kvs::Result<Vec<String>>;
+    fn dyn_flush(&mut self) -> kvs::Result<()>;
+}
+
+impl<E: KvsEngine + Send> DynKvsEngine for E {
+    fn dyn_set(&mut self, key: String, value: &str) -> kvs::Result<()> {
+        KvsEngine::set(self, key, value)
+    }
+    fn dyn_get(&mut self, key: &str) -> kvs::Result<Option<String>> {
+        KvsEngine::get(self, key)
+    }
+    fn dyn_remove(&mut self, key: &str) -> kvs::Result<()> {
+        KvsEngine::remove(self, key)
+    }
+    fn dyn_keys(&self) -> kvs::Result<Vec<String>> {
+        KvsEngine::keys(self)
+    }
+    fn dyn_flush(&mut self) -> kvs::Result<()> {
+        KvsEngine::flush(self)
+    }
+}
+
+/// Describes how to find and open an engine defined outside this crate.
+#[derive(Clone, Copy)]
+pub struct EngineRegistration {
+    /// Name used to select the engine, e.g. with `kvs-server --engine <name>`.
+    pub name: &'static str,
+    /// Reports whether the directory contains data written by this engine.
+    pub detect: fn(&Path) -> Result<bool>,
+    /// Opens the engine in the directory.
+    pub open: fn(&Path) -> Result<Box<dyn DynKvsEngine>>,
+}
+
+static REGISTRY: RwLock<Vec<EngineRegistration>> = RwLock::new(Vec::new());
+
+/// Names that are taken by the built-in engines.
+const BUILT_IN_NAMES: [&str; 3] = ["kvs", "sled", "memory"];
+
+/// Registers an engine so that it can be parsed as an [`EngineType`][crate::EngineType] and opened
+/// with [`Engine::new_in`][crate::Engine::new_in].
+///
+/// Engines should be registered before any engine types are parsed, e.g. at the start of `main`
+/// before parsing command line arguments. Returns an `Err` if the name is already taken.
+pub fn register_engine(registration: EngineRegistration) -> Result<()> {
+    let mut registry = REGISTRY.write().expect("registry lock poisoned");
+    if BUILT_IN_NAMES.contains(&registration.name)
+        || registry.iter().any(|r| r.name == registration.name)
+    {
+        bail!("Engine {:?} is already registered", registration.name);
+    }
+    registry.push(registration);
+    Ok(())
+}
+
+/// Finds the registered engine with the given name.
+pub(crate) fn find(name: &str) -> Option<EngineRegistration> {
+    let registry = REGISTRY.read().expect("registry lock poisoned");
+    registry.iter().find(|r| r.name == name).copied()
+}
+
+/// Finds the registered engine, if any, whose data is in the directory.
+pub(crate) fn detect(p: &Path) -> Result<Option<EngineRegistration>> {
+    let registry = REGISTRY.read().expect("registry lock poisoned");
+    for registration in registry.iter() {
+        if (registration.detect)(p)? {
+            return Ok(Some(*registration));
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{Engine, EngineType, MemoryDb};
+
+    const MARKER: &str = "marker.test";
+
+    fn register() {
+        // Tests share the registry so only the first registration succeeds.
+        let _ = register_engine(EngineRegistration {
+            name: "test-engine",
+            detect: |p| Ok(p.join(MARKER).exists()),
+            open: |p| {
+                std::fs::write(p.join(MARKER), "")?;
+                Ok(Box::new(MemoryDb::new()))
+            },
+        });
+    }
+
+    #[test]
+    fn rejects_taken_names() {
+        register();
+        let registration = EngineRegistration {
+            name: "test-engine",
+            detect: |_| Ok(false),
+            open: |_| Ok(Box::new(MemoryDb::new())),
+        };
+        assert!(register_engine(registration).is_err());
+        assert!(register_engine(EngineRegistration {
+            name: "kvs",
+            ..registration
+        })
+        .is_err());
+    }
+
+    #[test]
+    fn opens_registered_engine() {
+        register();
+        let engine_type = "test-engine".parse::<EngineType>().unwrap();
+        assert_eq!(engine_type, EngineType::Custom("test-engine"));
+        assert_eq!(engine_type.to_string(), "test-engine");
+
+        let dir = tempfile::tempdir().unwrap();
+        let mut engine = Engine::new_in(Some(engine_type), dir.path()).unwrap();
+        kvs::KvsEngine::set(&mut engine, "key".to_owned(), "value").unwrap();
+        assert_eq!(
+            kvs::KvsEngine::get(&mut engine, "key").unwrap(),
+            Some("value".to_owned())
+        );
+        drop(engine);
+
+        // The engine type is detected from the directory's contents.
+        let engine = Engine::new_in(None, dir.path()).unwrap();
+        assert_eq!(engine.engine_type(), engine_type);
+        drop(engine);
+
+        assert!(Engine::new_in(Some(EngineType::Kvs), dir.path()).is_err());
+    }
+
+    #[test]
+    fn unknown_engine_type() {
+        assert!("not-registered".parse::<EngineType>().is_err());
+    }
+}
@@ -1,16 +1,139 @@
 //! A wrapper around [`sled::Db`] so it can be used as a [`KvsEngine`].
 
 use std::borrow::Borrow;
+use std::fmt;
+use std::path::Path;
+use std::str::FromStr;
+use std::time::Duration;
 
-use kvs::{Error, KvsEngine, Result};
+use anyhow::anyhow;
+use kvs::{Error, KeyNotFound, KvsEngine, Result};
+
+/// Tuning options for a [`SledDb`]. These are passed through to [`sled::Config`].
+#[derive(Debug, Clone)]
+pub struct SledOptions {
+    /// Maximum size, in bytes, of sled's page cache. `None` uses sled's default.
+    pub cache_capacity: Option<u64>,
+    /// Whether sled compresses its data with zstd.
+    pub use_compression: bool,
+    /// How often sled flushes to disk in the background. `None` disables background flushes.
+    pub flush_every: Option<Duration>,
+    /// Whether sled optimizes for disk usage or for throughput.
+    pub mode: SledMode,
+    /// Whether every set and remove is flushed to disk before it returns.
+    pub flush_on_write: bool,
+}
+
+impl Default for SledOptions {
+    fn default() -> Self {
+        Self {
+            cache_capacity: None,
+            use_compression: false,
+            // Matches sled's default
+            flush_every: Some(Duration::from_millis(500)),
+            mode: SledMode::LowSpace,
+            flush_on_write: true,
+        }
+    }
+}
+
+/// Mirrors [`sled::Mode`], which doesn't implement [`FromStr`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SledMode {
+    /// Favor disk space over throughput. This is sled's default.
+    LowSpace,
+    /// Favor throughput over disk space.
+    HighThroughput,
+}
+
+impl fmt::Display for SledMode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::LowSpace => f.write_str("low-space"),
+            Self::HighThroughput => f.write_str("high-throughput"),
+        }
+    }
+}
+
+impl FromStr for SledMode {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "low-space" => Ok(Self::LowSpace),
+            "high-throughput" => Ok(Self::HighThroughput),
+            other => Err(anyhow!("unknown sled mode {other:?}")),
+        }
+    }
+}
+
+impl From<SledMode> for sled::Mode {
+    fn from(mode: SledMode) -> Self {
+        match mode {
+            SledMode::LowSpace => Self::LowSpace,
+            SledMode::HighThroughput => Self::HighThroughput,
+        }
+    }
+}
 
 /// A wrapper around [`sled::Db`] so it can be used as a [`KvsEngine`].
-pub struct SledDb(pub(crate) sled::Db);
+pub struct SledDb {
+    db: sled::Db,
+    flush_on_write: bool,
+    /// Number of keys, kept up to date on writes since sled counts them by scanning every key.
+    live_keys: usize,
+}
+
+impl SledDb {
+    /// Opens a sled database in the directory with the provided options.
+    pub fn open(p: impl AsRef<Path>, options: &SledOptions) -> Result<Self> {
+        let mut config = sled::Config::new()
+            .path(p)
+            .use_compression(options.use_compression)
+            .flush_every_ms(options.flush_every.map(|every| every.as_millis() as u64))
+            .mode(options.mode.into());
+        if let Some(cache_capacity) = options.cache_capacity {
+            config = config.cache_capacity(cache_capacity);
+        }
+
+        let db = config.open()?;
+        Ok(Self {
+            live_keys: db.len(),
+            db,
+            flush_on_write: options.flush_on_write,
+        })
+    }
+
+    /// Number of keys currently associated with a value.
+    pub fn live_keys(&self) -> usize {
+        self.live_keys
+    }
+
+    /// Bytes sled's files take up on disk.
+    pub fn size_on_disk(&self) -> Result<u64> {
+        self.db.size_on_disk().map_err(|e| {
+            tracing::warn!(?e, "Failed to measure sled");
+            Error::msg("Failed to measure sled's size on disk")
+        })
+    }
+
+    /// Flushes after a write, if configured to.
+    fn flush_after_write(&self) {
+        if !self.flush_on_write {
+            return;
+        }
+
+        // The server flushes when it shuts down, but this keeps writes if the process crashes.
+        if let Err(e) = self.db.flush() {
+            tracing::warn!(?e, "Failed to flush sled");
+        }
+    }
+}
 
 impl Drop for SledDb {
     fn drop(&mut self) {
         // See https://docs.rs/sled/latest/sled/struct.Db.html#method.was_recovered
-        if let Err(e) = self.0.flush() {
+        if let Err(e) = self.db.flush() {
             tracing::warn!(?e, "Failed to flush sled");
         }
     }
@@ -18,21 +141,20 @@ impl Drop for SledDb {
 
 impl KvsEngine for SledDb {
     fn set<V: AsRef<str>>(&mut self, key: String, value: V) -> Result<()> {
-        let _ = self.0.insert(key, value.as_ref()).map_err(|e| {
+        let replaced = self.db.insert(key, value.as_ref()).map_err(|e| {
             tracing::warn!(?e, "Failed to insert into sled");
             Error::msg("Failed to insert into sled")
         })?;
-
-        // TODO This is still needed here despite the Drop impl. Maybe Drop isn't called when we
-        // get a SIGTERM. Might want a custom signal handler.
-        if let Err(e) = self.0.flush() {
-            tracing::warn!(?e, "Failed to flush sled");
+        if replaced.is_none() {
+            self.live_keys += 1;
         }
+
+        self.flush_after_write();
         Ok(())
     }
 
     fn get<K: Borrow<str>>(&mut self, key: K) -> Result<Option<String>> {
-        let maybe_result = sled::Tree::get(&self.0, key.borrow()).map_err(|e| {
+        let maybe_result = sled::Tree::get(&self.db, key.borrow()).map_err(|e| {
             tracing::warn!(?e, "Failed to get from sled");
             Error::msg("Failed to read from sled")
         })?;
@@ -49,17 +171,108 @@ impl KvsEngine for SledDb {
     }
 
     fn remove<K: Borrow<str>>(&mut self, key: K) -> Result<()> {
-        let result = sled::Tree::remove(&self.0, key.borrow());
-        if let Err(e) = self.0.flush() {
-            tracing::warn!(?e, "Failed to flush sled");
-        }
+        let result = sled::Tree::remove(&self.db, key.borrow());
+        self.flush_after_write();
         match result {
-            Ok(Some(_)) => Ok(()),
-            Ok(None) => Err(Error::msg("Key not found")),
+            Ok(Some(_)) => {
+                self.live_keys -= 1;
+                Ok(())
+            }
+            Ok(None) => Err(KeyNotFound.into()),
             Err(e) => {
                 tracing::warn!(?e, "Failed to remove from sled");
                 Err(Error::msg("Failed to remove from sled"))
             }
         }
     }
+
+    fn keys(&self) -> Result<Vec<String>> {
+        self.db
+            .iter()
+            .keys()
+            .map(|key| {
+                let key = key.map_err(|e| {
+                    tracing::warn!(?e, "Failed to iterate sled");
+                    Error::msg("Failed to read from sled")
+                })?;
+                String::from_utf8(key.to_vec()).map_err(|e| {
+                    tracing::warn!(?e, "Invalid utf8 from sled");
+                    Error::msg("Invalid utf8 from sled")
+                })
+            })
+            .collect()
+    }
+
+    fn flush(&mut self) -> Result<()> {
+        self.db.flush().map_err(|e| {
+            tracing::warn!(?e, "Failed to flush sled");
+            Error::msg("Failed to flush sled")
+        })?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Instant;
+
+    use super::*;
+
+    #[test]
+    fn opens_with_tuning() {
+        let dir = tempfile::tempdir().unwrap();
+        let options = SledOptions {
+            cache_capacity: Some(1024 * 1024),
+            use_compression: true,
+            flush_every: None,
+            mode: SledMode::HighThroughput,
+            flush_on_write: false,
+        };
+
+        let mut db = SledDb::open(dir.path(), &options).unwrap();
+        db.set("key".to_owned(), "value").unwrap();
+        drop(db);
+
+        let mut db = reopen(dir.path(), &options);
+        assert_eq!(db.get("key").unwrap(), Some("value".to_owned()));
+    }
+
+    #[test]
+    fn counts_keys_on_writes() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = SledDb::open(dir.path(), &SledOptions::default()).unwrap();
+        db.set("key1".to_owned(), "value").unwrap();
+        db.set("key1".to_owned(), "other").unwrap();
+        db.set("key2".to_owned(), "value").unwrap();
+        assert!(db.remove("missing").is_err());
+        assert_eq!(db.live_keys(), 2);
+
+        db.remove("key1").unwrap();
+        drop(db);
+        assert_eq!(reopen(dir.path(), &SledOptions::default()).live_keys(), 1);
+    }
+
+    /// Opens the db again, retrying while sled's background threads still hold the directory lock
+    /// from when it was last open.
+    fn reopen(path: &Path, options: &SledOptions) -> SledDb {
+        let deadline = Instant::now() + Duration::from_secs(10);
+        loop {
+            match SledDb::open(path, options) {
+                Ok(db) => return db,
+                Err(e) if Instant::now() < deadline => {
+                    tracing::debug!(?e, "Retrying opening sled");
+                    std::thread::sleep(Duration::from_millis(10));
+                }
+                Err(e) => panic!("Failed to reopen sled: {e:#}"),
+            }
+        }
+    }
+
+    #[test]
+    fn parses_mode() {
+        for mode in [SledMode::LowSpace, SledMode::HighThroughput] {
+            assert_eq!(mode.to_string().parse::<SledMode>().unwrap(), mode);
+        }
+        assert!("fast".parse::<SledMode>().is_err());
+    }
 }
@@ -0,0 +1,296 @@
+//! Serves connections speaking the protocols of other databases, like RESP and memcached's text
+//! protocol, so their clients can use the server. Each protocol parses commands into a [`Batch`]
+//! of [`Cmd`][protocol::Cmd]s, which runs through the same engine and metrics as the server's own
+//! protocol, and replies in its own format.
+
+use std::io::{self, Read, Write};
+use std::sync::Arc;
+
+use anyhow::{Context, Error, Result};
+use protocol::{ErrorCode, Limits};
+use tokio::io::{AsyncRead, AsyncReadExt, AsyncWrite, AsyncWriteExt};
+use tokio::sync::watch;
+use tokio::time::Instant;
+use tracing::{debug, warn};
+
+use crate::server::{is_timeout, Client, Counter, Deadline, ReadTimeout, Shared};
+
+/// A reply to a command.
+pub(crate) trait Reply: Send + 'static {
+    /// Appends the encoded reply to the buffer. Some replies, like those to commands that ask for
+    /// no reply, are empty.
+    fn write(&self, out: &mut Vec<u8>);
+
+    /// The reply to a command that failed with the error.
+    fn error(code: ErrorCode, message: &str) -> Self
+    where
+        Self: Sized;
+}
+
+/// Commands to execute on the engine, and how to reply once they've been executed.
+pub(crate) trait Batch: Send + 'static {
+    type Reply: Reply;
+
+    /// Executes the commands and replies to the command they carry out.
+    fn execute(self, shared: &Shared) -> Self::Reply;
+}
+
+/// What to do with a command.
+pub(crate) enum Action<B: Batch> {
+    /// Reply without using the engine.
+    Reply(B::Reply),
+    /// Execute commands on the engine, then reply.
+    Execute(B),
+    /// Reply, then close the connection.
+    Close(B::Reply),
+}
+
+impl<B: Batch> Action<B> {
+    /// Replies, then closes the connection if `close` is set.
+    pub(crate) fn reply(reply: B::Reply, close: bool) -> Self {
+        if close {
+            Self::Close(reply)
+        } else {
+            Self::Reply(reply)
+        }
+    }
+}
+
+/// The state of a connection, which turns the bytes it reads into actions.
+pub(crate) trait Session {
+    type Batch: Batch;
+
+    /// Takes the next complete command out of the buffer and decides what to do with it. Returns
+    /// `None` if the buffer doesn't hold a whole command yet.
+    fn next_action(&mut self, buf: &mut Vec<u8>) -> Option<Action<Self::Batch>>;
+}
+
+/// Room for the lines around a command's keys and values, like its name and the lengths of its
+/// arguments.
+const COMMAND_OVERHEAD: usize = 64 * 1024;
+
+/// Longest command a decoder accepts: one with the largest key and value the server's limits allow.
+/// A connection that buffers more than this without a whole command is closed, so a client can't
+/// make the server hold more of a command than it could ever execute.
+pub(crate) fn max_command_len(limits: &Limits) -> usize {
+    limits
+        .max_key_bytes
+        .saturating_add(limits.max_value_bytes)
+        .saturating_add(COMMAND_OVERHEAD)
+}
+
+/// The reply that closes a connection whose buffered command is longer than
+/// [`max_command_len`].
+fn too_large<R: Reply>(buffered: usize) -> R {
+    warn!(buffered, "Command too large, closing connection");
+    R::error(
+        ErrorCode::TooLarge,
+        "Command is larger than the server's limits",
+    )
+}
+
+/// The reply if the server is too busy to run a command from the client now, and whether to close
+/// the connection after it. Every call counts against the client's rate limit, so sessions call
+/// this for the commands they handle themselves that need limiting, like attempts to authenticate.
+pub(crate) fn busy<R: Reply>(shared: &Shared, client: &Client) -> Option<(R, bool)> {
+    let message = shared.admit(client).err()?;
+    // A connection over the limit is closed, so it stops counting against it.
+    Some((R::error(ErrorCode::Busy, message), client.over_capacity))
+}
+
+/// Serves commands on a connection until the client closes it, the session closes it or the
+/// client is too slow: it can idle between commands for the server's idle timeout, and has the
+/// read timeout to send the rest of a command once it starts.
+pub(crate) fn serve<S: Session>(
+    shared: &Shared,
+    mut session: S,
+    stream: &mut (impl Read + Write + ReadTimeout),
+    client: Client,
+    idle: Option<&Counter>,
+) -> Result<()> {
+    let options = &shared.options;
+    let mut stream =
+        Deadline::new(stream, options.idle_timeout, options.read_timeout).counting_idle(idle);
+    let mut buf = Vec::new();
+    let mut chunk = vec![0; 16 * 1024];
+    let max_len = max_command_len(&options.limits);
+    loop {
+        while let Some(action) = session.next_action(&mut buf) {
+            let (reply, close) = match action {
+                Action::Reply(reply) => (reply, false),
+                Action::Execute(batch) => {
+                    busy(shared, &client).unwrap_or_else(|| (batch.execute(shared), false))
+                }
+                Action::Close(reply) => (reply, true),
+            };
+            let mut bytes = Vec::new();
+            reply.write(&mut bytes);
+            stream.write_all(&bytes)?;
+            stream.flush()?;
+            shared.metrics.record_written(bytes.len());
+            if close {
+                return Ok(());
+            }
+            stream.next_command(options.idle_timeout);
+        }
+        if buf.len() > max_len {
+            let mut bytes = Vec::new();
+            too_large::<<S::Batch as Batch>::Reply>(buf.len()).write(&mut bytes);
+            stream.write_all(&bytes)?;
+            stream.flush()?;
+            shared.metrics.record_written(bytes.len());
+            return Ok(());
+        }
+
+        let read = match stream.read(&mut chunk) {
+            Ok(read) => read,
+            Err(e) => {
+                let e = Error::from(e);
+                if is_timeout(&e) {
+                    debug!("Connection idle or too slow, closing it");
+                    return Ok(());
+                }
+                return Err(e.context("reading command"));
+            }
+        };
+        shared.metrics.record_read(read);
+        if read == 0 {
+            debug!("Client closed connection");
+            return Ok(());
+        }
+        buf.extend_from_slice(&chunk[..read]);
+    }
+}
+
+/// Like [`serve`], on an async runtime. The connection is closed when the server shuts down.
+pub(crate) async fn serve_async<S: Session>(
+    shared: &Arc<Shared>,
+    mut session: S,
+    mut stream: impl AsyncRead + AsyncWrite + Unpin,
+    client: Client,
+    mut shutdown: watch::Receiver<bool>,
+) -> Result<()> {
+    let options = &shared.options;
+    let mut buf = Vec::new();
+    let max_len = max_command_len(&options.limits);
+    let mut deadline = Instant::now() + options.idle_timeout;
+    // Whether any bytes of the next command have been read.
+    let mut started = false;
+    loop {
+        while let Some(action) = session.next_action(&mut buf) {
+            let (reply, close) = match action {
+                Action::Reply(reply) => (reply, false),
+                Action::Execute(batch) => match busy(shared, &client) {
+                    None => {
+                        // The engine blocks, so it's used from the runtime's blocking thread pool.
+                        let shared = Arc::clone(shared);
+                        let reply = tokio::task::spawn_blocking(move || batch.execute(&shared))
+                            .await
+                            .context("executing command")?;
+                        (reply, false)
+                    }
+                    Some(busy) => busy,
+                },
+                Action::Close(reply) => (reply, true),
+            };
+            let mut bytes = Vec::new();
+            reply.write(&mut bytes);
+            stream.write_all(&bytes).await?;
+            shared.metrics.record_written(bytes.len());
+            if close {
+                stream.shutdown().await?;
+                return Ok(());
+            }
+            deadline = Instant::now() + options.idle_timeout;
+            started = false;
+        }
+        if buf.len() > max_len {
+            let mut bytes = Vec::new();
+            too_large::<<S::Batch as Batch>::Reply>(buf.len()).write(&mut bytes);
+            stream.write_all(&bytes).await?;
+            shared.metrics.record_written(bytes.len());
+            stream.shutdown().await?;
+            return Ok(());
+        }
+        if !started && !buf.is_empty() {
+            started = true;
+            deadline = deadline.min(Instant::now() + options.read_timeout);
+        }
+
+        let read = tokio::time::timeout_at(deadline, stream.read_buf(&mut buf));
+        let read = tokio::select! {
+            read = read => read,
+            Ok(()) = shutdown.changed() => {
+                debug!("Server shutting down, closing connection");
+                return Ok(());
+            }
+        };
+        let read = match read {
+            Ok(Err(e)) if e.kind() == io::ErrorKind::UnexpectedEof => 0,
+            Ok(read) => read.context("reading command")?,
+            Err(_) => {
+                debug!("Connection idle or too slow, closing it");
+                return Ok(());
+            }
+        };
+        shared.metrics.record_read(read);
+        if read == 0 {
+            debug!("Client closed connection");
+            return Ok(());
+        }
+    }
+}
+
+/// Helpers for testing sessions without a connection.
+#[cfg(test)]
+pub(crate) mod test_util {
+    use tempfile::TempDir;
+
+    use super::{Action, Batch, Reply};
+    use crate::server::{Client, Shared};
+    use crate::{AuthToken, Engine, EngineType, ServerOptions};
+
+    /// The arguments of a command made of the words.
+    pub(crate) fn args(words: &[&str]) -> Vec<Vec<u8>> {
+        words.iter().map(|word| word.as_bytes().to_vec()).collect()
+    }
+
+    /// A client connected over TCP, from localhost.
+    pub(crate) fn client() -> Client {
+        Client {
+            ip: Some("127.0.0.1".parse().unwrap()),
+            over_capacity: false,
+        }
+    }
+
+    /// State for a server with the options and a kvs engine in a temporary directory, which lasts
+    /// as long as the returned [`TempDir`].
+    pub(crate) fn shared(options: ServerOptions) -> (Shared, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        (Shared::new(engine, options), dir)
+    }
+
+    /// Options that require the auth token `secret`.
+    pub(crate) fn auth_options() -> ServerOptions {
+        ServerOptions {
+            auth_token: Some(AuthToken::new("secret").unwrap()),
+            ..ServerOptions::default()
+        }
+    }
+
+    /// Carries out the action, executing its batch on the engine, and returns the reply.
+    pub(crate) fn reply<B: Batch>(shared: &Shared, action: Action<B>) -> B::Reply {
+        match action {
+            Action::Execute(batch) => batch.execute(shared),
+            Action::Reply(reply) | Action::Close(reply) => reply,
+        }
+    }
+
+    /// The reply as it's written to the connection.
+    pub(crate) fn written(reply: impl Reply) -> String {
+        let mut bytes = Vec::new();
+        reply.write(&mut bytes);
+        String::from_utf8(bytes).unwrap()
+    }
+}
@@ -0,0 +1,619 @@
+//! An [`HttpGateway`] serves a server's keys over HTTP, for clients that would rather speak JSON
+//! than the binary protocol.
+//!
+//! | Request                  | Success                                  |
+//! |--------------------------|------------------------------------------|
+//! | `GET /keys/{key}`        | 200 with the value as the body           |
+//! | `PUT /keys/{key}`        | 201 creating the key, 204 replacing it   |
+//! | `DELETE /keys/{key}`     | 204                                      |
+//! | `GET /keys?prefix={p}`   | 200 with `{"keys": [...]}`, sorted       |
+//!
+//! Keys are percent-decoded. Keys and bodies are held to the server's limits on keys and values,
+//! and get a 413 if they're over them. Bodies need a `Content-Length`: requests with a
+//! `Transfer-Encoding`, like `chunked`, get a 501. Errors have a status like the [`ErrorCode`]'s
+//! HTTP analogy, except that storage failures are a 500, and a body like
+//! `{"error": {"code": 404, "message": "Key not found"}}`, where `code` is the [`ErrorCode`].
+//!
+//! If the server requires authentication, requests carry the token in an
+//! `Authorization: Bearer {token}` header and are checked against its grant like any other
+//! command. Requests count against the server's rate limit for the client's IP. The gateway holds
+//! its connections to the server's connection limit separately from the server's own connections,
+//! so the two can each have that many open. Clients have the server's read timeout to send the
+//! whole request.
+
+use std::borrow::Cow;
+use std::fmt;
+use std::io::{BufReader, Write};
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+
+use anyhow::{anyhow, Context, Result};
+use kvs::KvsEngine;
+use protocol::{Cmd, ErrorCode, Response, TooLarge};
+use serde_json::json;
+use tracing::{debug, warn};
+
+use crate::http::{self, Request};
+use crate::server::{is_timeout, wake_addr, Client, Deadline, Shared, ACCEPT_BACKOFF};
+use crate::{Grant, Permission, Server};
+
+/// Most requests handled at once. Connections beyond it get a 503 without their request being
+/// read, so a flood of connections can't start a thread for each one.
+const MAX_REQUESTS: usize = 64;
+
+/// Serves HTTP requests on a background thread until it's shut down. Each request is handled on
+/// its own thread, so a slow client can't hold up the others, up to [`MAX_REQUESTS`] at once.
+pub(crate) struct HttpGateway {
+    local_addr: SocketAddr,
+    shutting_down: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl HttpGateway {
+    pub(crate) fn bind(addr: SocketAddr, shared: Arc<Shared>) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context("Failed to bind HTTP address")?;
+        let local_addr = listener.local_addr()?;
+        debug!(?local_addr, "HTTP gateway bound");
+
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("kvs-http".to_owned())
+            .spawn({
+                let shutting_down = Arc::clone(&shutting_down);
+                move || Self::serve(listener, &shared, &shutting_down)
+            })
+            .context("Failed to spawn HTTP thread")?;
+
+        Ok(Self {
+            local_addr,
+            shutting_down,
+            thread,
+        })
+    }
+
+    /// The address the gateway is listening on.
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting requests and waits for the requests being handled to finish.
+    pub(crate) fn shutdown(self) -> Result<()> {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        if let Err(e) = TcpStream::connect(wake_addr(self.local_addr)) {
+            warn!(?e, "Failed to wake HTTP gateway");
+        }
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("HTTP gateway thread panicked"))
+    }
+
+    fn serve(listener: TcpListener, shared: &Shared, shutting_down: &AtomicBool) {
+        let in_flight = AtomicUsize::new(0);
+        thread::scope(|scope| {
+            for stream in listener.incoming() {
+                if shutting_down.load(Ordering::SeqCst) {
+                    break;
+                }
+                let stream = match stream {
+                    Ok(stream) => stream,
+                    Err(e) => {
+                        warn!(?e, "Failed to accept HTTP connection");
+                        thread::sleep(ACCEPT_BACKOFF);
+                        continue;
+                    }
+                };
+                let open = in_flight.fetch_add(1, Ordering::SeqCst) + 1;
+                if open > MAX_REQUESTS {
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                    let message = "The gateway has too many requests, try again later";
+                    let response = Shared::busy(message);
+                    shared.metrics.record_response(&response);
+                    if let Err(e) = Reply::from(response).write(&stream) {
+                        debug!(?e, "Failed to reject HTTP request");
+                    }
+                    continue;
+                }
+                let client = Client {
+                    ip: stream.peer_addr().ok().map(|addr| addr.ip()),
+                    over_capacity: shared.over_capacity(open),
+                };
+                let in_flight = &in_flight;
+                let spawned = thread::Builder::new()
+                    .name("kvs-http-request".to_owned())
+                    .spawn_scoped(scope, move || {
+                        if let Err(e) = Self::handle_connection(stream, shared, client) {
+                            warn!(?e, "Failed to serve HTTP request");
+                        }
+                        in_flight.fetch_sub(1, Ordering::SeqCst);
+                    });
+                if let Err(e) = spawned {
+                    warn!(?e, "Failed to spawn HTTP request thread");
+                    in_flight.fetch_sub(1, Ordering::SeqCst);
+                }
+            }
+        });
+    }
+
+    fn handle_connection(mut stream: TcpStream, shared: &Shared, client: Client) -> Result<()> {
+        let options = &shared.options;
+        let deadline = Deadline::new(&mut stream, options.read_timeout, options.read_timeout);
+        let request = Request::read(&mut BufReader::new(deadline), &options.limits);
+        let stream = &stream;
+        let request = match request {
+            Ok(Some(request)) => request,
+            Ok(None) => return Ok(()),
+            Err(e) if is_timeout(&e) => {
+                debug!("HTTP client too slow, closing connection");
+                return Ok(());
+            }
+            Err(e) if e.is::<TooLarge>() => {
+                // The body wasn't read, so the client may not see the reply.
+                let _ = Reply::error(413, ErrorCode::TooLarge, format!("{e:#}")).write(stream);
+                return Err(e);
+            }
+            Err(e) => {
+                // The client might still be listening, so tell it why before giving up.
+                let _ = Reply::error(400, ErrorCode::BadRequest, format!("{e:#}")).write(stream);
+                return Err(e);
+            }
+        };
+        debug!(method = request.method, path = request.path, "HTTP request");
+        if let Some(coding) = &request.transfer_encoding {
+            let message =
+                format!("Transfer-Encoding {coding:?} isn't supported, use Content-Length");
+            return Reply::error(501, ErrorCode::BadRequest, message).write(stream);
+        }
+        if let Err(message) = shared.admit(&client) {
+            return Reply::error(503, ErrorCode::Busy, message).write(stream);
+        }
+        Self::handle_request(shared, request).write(stream)
+    }
+
+    fn handle_request(shared: &Shared, request: Request) -> Reply {
+        let grant = match Self::authenticate(shared, request.authorization.as_deref()) {
+            Ok(grant) => grant,
+            Err(reply) => return reply,
+        };
+        let (path, query) = request.path.split_once('?').unwrap_or((&request.path, ""));
+        match path.strip_prefix("/keys") {
+            Some("" | "/") => Self::list_keys(shared, grant, &request.method, query),
+            Some(key) if key.starts_with('/') => {
+                Self::handle_key(shared, grant, &request.method, &key[1..], request.body)
+            }
+            _ => Reply::error(404, ErrorCode::NotFound, "No such resource"),
+        }
+    }
+
+    /// The grant for the request's bearer token, or the unauthorized reply if it has none it's
+    /// allowed to use.
+    fn authenticate<'s>(
+        shared: &'s Shared,
+        authorization: Option<&str>,
+    ) -> Result<&'s Grant, Reply> {
+        let access = match authorization {
+            None => shared.unauthenticated(),
+            Some(authorization) => authorization
+                .strip_prefix("Bearer ")
+                .and_then(|token| shared.authenticate(token.trim()))
+                .ok_or_else(|| {
+                    warn!(target: "audit", "Rejected HTTP auth token");
+                    "Invalid auth token"
+                }),
+        };
+        access.map_err(|message| {
+            let response = Shared::unauthorized(message);
+            shared.metrics.record_response(&response);
+            Reply::from(response)
+        })
+    }
+
+    fn handle_key(shared: &Shared, grant: &Grant, method: &str, key: &str, body: Vec<u8>) -> Reply {
+        let key: Cow<str> = match http::percent_decode(key) {
+            Ok(key) => Cow::Owned(key),
+            Err(e) => return Reply::error(400, ErrorCode::BadRequest, format!("{e:#}")),
+        };
+        if let Err(e) = shared.options.limits.check_key(key.len() as u64) {
+            return Reply::error(413, ErrorCode::TooLarge, e);
+        }
+        let cmd = match method {
+            "GET" => Cmd::Get(key),
+            "PUT" => match String::from_utf8(body) {
+                Ok(value) => Cmd::Set(key, value.into()),
+                Err(_) => return Reply::error(400, ErrorCode::BadRequest, "Values must be UTF-8"),
+            },
+            "DELETE" => Cmd::Rm(key),
+            _ => return Reply::method_not_allowed("GET, PUT, DELETE"),
+        };
+        if let Err(response) = shared.authorize(grant, &cmd) {
+            return response.into();
+        }
+        match cmd {
+            Cmd::Set(key, value) => {
+                match Server::handle_set_if(shared, key.into_owned(), &value, None) {
+                    // Replacing a value doesn't create anything.
+                    (true, Some(Response::SuccessfulSet)) => Reply::empty(204),
+                    (_, response) => response.expect("unconditional sets happen").into(),
+                }
+            }
+            cmd => Server::handle_cmd(shared, cmd).into(),
+        }
+    }
+
+    /// Lists the keys starting with the `prefix` query parameter that the grant can read.
+    fn list_keys(shared: &Shared, grant: &Grant, method: &str, query: &str) -> Reply {
+        if method != "GET" {
+            return Reply::method_not_allowed("GET");
+        }
+        if !grant.allow.contains(&Permission::Read) {
+            warn!(target: "audit", grant = grant.name, "Denied listing keys");
+            return Reply::error(
+                403,
+                ErrorCode::Forbidden,
+                format!("The {:?} grant doesn't allow listing keys", grant.name),
+            );
+        }
+        let prefix = match query_param(query, "prefix") {
+            Ok(prefix) => prefix.unwrap_or_default(),
+            Err(e) => return Reply::error(400, ErrorCode::BadRequest, format!("{e:#}")),
+        };
+
+        let keys = shared
+            .engine
+            .lock()
+            .map_err(|_| anyhow!("Engine lock poisoned"))
+            .and_then(|engine| engine.keys());
+        let mut keys = match keys {
+            Ok(keys) => keys,
+            Err(e) => {
+                warn!(?e, "Failed to list keys");
+                return Reply::error(500, ErrorCode::Storage, format!("{e:#}"));
+            }
+        };
+        keys.retain(|key| key.starts_with(&prefix) && grant.allows(&Cmd::Get(key.into())));
+        keys.sort_unstable();
+        Reply::json(200, &json!({ "keys": keys }))
+    }
+}
+
+/// The decoded value of a query string parameter, with `+` standing for a space.
+fn query_param(query: &str, name: &str) -> Result<Option<String>> {
+    query
+        .split('&')
+        .filter_map(|pair| pair.split_once('=').or(Some((pair, ""))))
+        .find(|(key, _)| *key == name)
+        .map(|(_, value)| http::percent_decode(&value.replace('+', " ")))
+        .transpose()
+}
+
+/// An HTTP response.
+#[derive(Debug, PartialEq)]
+struct Reply {
+    status: u16,
+    content_type: &'static str,
+    body: Vec<u8>,
+}
+
+impl Reply {
+    fn empty(status: u16) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            body: Vec::new(),
+        }
+    }
+
+    fn json(status: u16, value: &serde_json::Value) -> Self {
+        Self {
+            status,
+            content_type: "application/json",
+            body: value.to_string().into_bytes(),
+        }
+    }
+
+    /// An error in a JSON envelope.
+    fn error(status: u16, code: ErrorCode, message: impl fmt::Display) -> Self {
+        let envelope = json!({
+            "error": {
+                "code": code.as_u16(),
+                "message": message.to_string(),
+            }
+        });
+        Self::json(status, &envelope)
+    }
+
+    fn method_not_allowed(allowed: &str) -> Self {
+        let message = format!("Allowed methods: {allowed}");
+        Self::error(405, ErrorCode::BadRequest, message)
+    }
+
+    fn write(&self, mut writer: impl Write) -> Result<()> {
+        http::write_response(
+            &mut writer,
+            self.status,
+            http::reason(self.status),
+            self.content_type,
+            &self.body,
+        )
+    }
+}
+
+impl From<Response<'_>> for Reply {
+    fn from(response: Response) -> Self {
+        match response {
+            Response::SuccessfulGet(value) => Self {
+                status: 200,
+                content_type: "text/plain; charset=utf-8",
+                body: value.into_owned().into_bytes(),
+            },
+            Response::SuccessfulSet => Self::empty(201),
+            Response::SuccessfulRm => Self::empty(204),
+            Response::KeyNotFound => Self::error(404, ErrorCode::NotFound, "Key not found"),
+            Response::Err(code, message) => {
+                let status = match code {
+                    ErrorCode::BadRequest
+                    | ErrorCode::Unauthorized
+                    | ErrorCode::Forbidden
+                    | ErrorCode::NotFound
+                    | ErrorCode::TooLarge
+                    | ErrorCode::Busy => code.as_u16(),
+                    ErrorCode::Internal | ErrorCode::Storage | ErrorCode::Unknown(_) => 500,
+                };
+                Self::error(status, code, message)
+            }
+            other => Self::error(
+                500,
+                ErrorCode::Internal,
+                format!("Unexpected response {other:?}"),
+            ),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{Acl, AuthToken, Engine, EngineType, Pool, PoolType, ServerOptions};
+
+    /// Sends a request and returns the response's status line and body.
+    fn request(
+        addr: SocketAddr,
+        method: &str,
+        path: &str,
+        token: &str,
+        body: &str,
+    ) -> (String, String) {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        write!(
+            stream,
+            "{method} {path} HTTP/1.1\r\nAuthorization: Bearer {token}\r\n\
+             Content-Length: {}\r\n\r\n{body}",
+            body.len()
+        )
+        .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        let (head, body) = response.split_once("\r\n\r\n").unwrap();
+        let status = head.lines().next().unwrap();
+        (status.to_owned(), body.to_owned())
+    }
+
+    #[test]
+    fn serves_keys() {
+        let dir = tempfile::tempdir().unwrap();
+        let acl_file = dir.path().join("acl.toml");
+        std::fs::write(
+            &acl_file,
+            r#"
+            [[grants]]
+            name = "billing"
+            token = "billing-secret"
+            allow = ["read"]
+            key_prefixes = ["billing/"]
+            "#,
+        )
+        .unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        let pool = Pool::new(PoolType::SharedQueue, 2).unwrap();
+        let options = ServerOptions {
+            http_addr: Some("127.0.0.1:0".parse().unwrap()),
+            auth_token: Some(AuthToken::new("secret").unwrap()),
+            acl: Some(Acl::from_file(&acl_file).unwrap()),
+            limits: protocol::Limits {
+                max_key_bytes: 32,
+                ..protocol::Limits::default()
+            },
+            ..ServerOptions::default()
+        };
+        let handle = Server::new(engine, "127.0.0.1:0".parse().unwrap(), pool, options)
+            .bind()
+            .unwrap();
+        let addr = handle.http_addr().unwrap();
+        let ok = |status: &str, body: &str| (status.to_owned(), body.to_owned());
+        let error = |status: &str, code: u16, message: &str| {
+            let body = json!({ "error": { "code": code, "message": message } });
+            (status.to_owned(), body.to_string())
+        };
+
+        let created = ok("HTTP/1.1 201 Created", "");
+        for key in ["billing/a%20b", "billing/c", "other"] {
+            assert_eq!(
+                request(addr, "PUT", &format!("/keys/{key}"), "secret", "é"),
+                created
+            );
+        }
+        assert_eq!(
+            request(addr, "PUT", "/keys/other", "secret", "é"),
+            ok("HTTP/1.1 204 No Content", "")
+        );
+        assert_eq!(
+            request(addr, "GET", "/keys/billing%2Fa%20b", "secret", ""),
+            ok("HTTP/1.1 200 OK", "é")
+        );
+        assert_eq!(
+            request(addr, "GET", "/keys?prefix=billing%2F", "secret", ""),
+            ok("HTTP/1.1 200 OK", r#"{"keys":["billing/a b","billing/c"]}"#)
+        );
+        assert_eq!(
+            request(addr, "GET", "/keys/", "billing-secret", ""),
+            ok("HTTP/1.1 200 OK", r#"{"keys":["billing/a b","billing/c"]}"#)
+        );
+        assert_eq!(
+            request(addr, "DELETE", "/keys/other", "secret", ""),
+            ("HTTP/1.1 204 No Content".to_owned(), String::new())
+        );
+
+        let not_found = error("HTTP/1.1 404 Not Found", 404, "Key not found");
+        assert_eq!(request(addr, "GET", "/keys/other", "secret", ""), not_found);
+        let (status, _) = request(addr, "DELETE", "/keys/other", "secret", "");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        assert_eq!(
+            request(addr, "PUT", "/keys/billing/c", "billing-secret", "x"),
+            error(
+                "HTTP/1.1 403 Forbidden",
+                403,
+                "The \"billing\" grant doesn't allow this command"
+            )
+        );
+        assert_eq!(
+            request(addr, "GET", "/keys/billing/c", "wrong", ""),
+            error("HTTP/1.1 401 Unauthorized", 401, "Invalid auth token")
+        );
+        assert_eq!(
+            request(addr, "POST", "/keys/billing/c", "secret", ""),
+            error(
+                "HTTP/1.1 405 Method Not Allowed",
+                400,
+                "Allowed methods: GET, PUT, DELETE"
+            )
+        );
+        let (status, _) = request(
+            addr,
+            "GET",
+            &format!("/keys/{}", "k".repeat(33)),
+            "secret",
+            "",
+        );
+        assert_eq!(status, "HTTP/1.1 413 Payload Too Large");
+        assert_eq!(
+            request(addr, "GET", "/values", "secret", ""),
+            error("HTTP/1.1 404 Not Found", 404, "No such resource")
+        );
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"PUT /keys/a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n1\r\na\r\n0\r\n\r\n",
+            )
+            .unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 501 Not Implemented"),
+            "{response}"
+        );
+
+        handle.shutdown().unwrap();
+        assert!(TcpStream::connect(addr).is_err());
+    }
+
+    #[test]
+    fn limits_requests_in_flight() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        let pool = Pool::new(PoolType::SharedQueue, 2).unwrap();
+        let options = ServerOptions {
+            http_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..ServerOptions::default()
+        };
+        let handle = Server::new(engine, "127.0.0.1:0".parse().unwrap(), pool, options)
+            .bind()
+            .unwrap();
+        let addr = handle.http_addr().unwrap();
+
+        // Connections that haven't sent their request yet hold every slot.
+        let idle: Vec<_> = (0..MAX_REQUESTS)
+            .map(|_| TcpStream::connect(addr).unwrap())
+            .collect();
+        // The reply doesn't wait for the request.
+        let mut response = String::new();
+        let mut rejected = TcpStream::connect(addr).unwrap();
+        rejected.read_to_string(&mut response).unwrap();
+        assert!(
+            response.starts_with("HTTP/1.1 503 Service Unavailable"),
+            "{response}"
+        );
+
+        // The slots free up once the threads see the connections close.
+        drop(idle);
+        let start = std::time::Instant::now();
+        let status = loop {
+            let (status, _) = request(addr, "GET", "/keys/a", "any", "");
+            if !status.contains("503") || start.elapsed() > Duration::from_secs(5) {
+                break status;
+            }
+            thread::sleep(Duration::from_millis(10));
+        };
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn admits_like_the_server() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        let pool = Pool::new(PoolType::SharedQueue, 2).unwrap();
+        let options = ServerOptions {
+            http_addr: Some("127.0.0.1:0".parse().unwrap()),
+            max_connections: Some(1),
+            read_timeout: Duration::from_millis(300),
+            ..ServerOptions::default()
+        };
+        let handle = Server::new(engine, "127.0.0.1:0".parse().unwrap(), pool, options)
+            .bind()
+            .unwrap();
+        let addr = handle.http_addr().unwrap();
+
+        // A client trickling its request holds the only connection until the read timeout.
+        let mut slow = TcpStream::connect(addr).unwrap();
+        let (status, _) = request(addr, "GET", "/keys/a", "any", "");
+        assert_eq!(status, "HTTP/1.1 503 Service Unavailable");
+        let cut_off = b"GET /keys/a HTTP/1.1\r\n".iter().any(|byte| {
+            thread::sleep(Duration::from_millis(50));
+            slow.write_all(&[*byte]).is_err()
+        });
+        assert!(cut_off);
+
+        let (status, _) = request(addr, "GET", "/keys/a", "any", "");
+        assert_eq!(status, "HTTP/1.1 404 Not Found");
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn reads_query_params() {
+        assert_eq!(
+            query_param("a=1&prefix=x+y%2Fz", "prefix").unwrap(),
+            Some("x y/z".to_owned())
+        );
+        assert_eq!(
+            query_param("prefix", "prefix").unwrap(),
+            Some(String::new())
+        );
+        assert_eq!(query_param("", "prefix").unwrap(), None);
+        assert!(query_param("prefix=%", "prefix").is_err());
+    }
+
+    #[test]
+    fn maps_storage_errors_to_500() {
+        let reply = Reply::from(Response::Err(ErrorCode::Storage, "disk full".into()));
+        assert_eq!(reply.status, 500);
+        assert_eq!(
+            reply.body,
+            br#"{"error":{"code":507,"message":"disk full"}}"#.to_vec()
+        );
+    }
+}
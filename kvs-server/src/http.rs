@@ -0,0 +1,223 @@
+//! Just enough HTTP/1.1 to serve simple endpoints, like metrics and the HTTP gateway, without
+//! pulling in a web framework. Every response closes its connection.
+
+use std::io::{BufRead, Read, Write};
+
+use anyhow::{bail, Context, Result};
+use protocol::Limits;
+
+/// Longest request line or header line that's accepted.
+const MAX_LINE_BYTES: usize = 8 * 1024;
+/// Most headers a request can have.
+const MAX_HEADERS: usize = 64;
+
+/// An HTTP request. Header values other than the body's length, its transfer coding and the
+/// credentials aren't kept.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Request {
+    pub(crate) method: String,
+    /// The request target, including any query string.
+    pub(crate) path: String,
+    /// The `Authorization` header, if there is one.
+    pub(crate) authorization: Option<String>,
+    /// The `Transfer-Encoding` header, if there is one. Transfer codings like `chunked` aren't
+    /// supported, so the body of a request with one isn't read.
+    pub(crate) transfer_encoding: Option<String>,
+    pub(crate) body: Vec<u8>,
+}
+
+impl Request {
+    /// Reads a request, including its body if it has a `Content-Length` and no
+    /// `Transfer-Encoding`. Returns `None` if the connection closed before any bytes were sent.
+    ///
+    /// Bodies are values, so a body larger than the limit on values is rejected with a
+    /// [`TooLarge`][protocol::TooLarge] error before it's read.
+    pub(crate) fn read(reader: &mut impl BufRead, limits: &Limits) -> Result<Option<Self>> {
+        let Some(request_line) = read_line(reader)? else {
+            return Ok(None);
+        };
+        let mut parts = request_line.split(' ');
+        let (Some(method), Some(path), Some(version), None) =
+            (parts.next(), parts.next(), parts.next(), parts.next())
+        else {
+            bail!("Malformed request line {request_line:?}");
+        };
+        if !version.starts_with("HTTP/1.") {
+            bail!("Unsupported HTTP version {version:?}");
+        }
+
+        let mut content_length = 0;
+        let mut authorization = None;
+        let mut transfer_encoding = None;
+        for _ in 0..=MAX_HEADERS {
+            let line = read_line(reader)?.context("Connection closed in headers")?;
+            if line.is_empty() {
+                let mut body = Vec::new();
+                if transfer_encoding.is_none() {
+                    body.resize(content_length, 0);
+                    reader.read_exact(&mut body).context("reading body")?;
+                }
+                return Ok(Some(Self {
+                    method: method.to_owned(),
+                    path: path.to_owned(),
+                    authorization,
+                    transfer_encoding,
+                    body,
+                }));
+            }
+
+            let (name, value) = line
+                .split_once(':')
+                .with_context(|| format!("Malformed header {line:?}"))?;
+            if name.eq_ignore_ascii_case("content-length") {
+                let len = value.trim().parse().context("Invalid Content-Length")?;
+                limits.check_value(len)?;
+                content_length = len as usize;
+            } else if name.eq_ignore_ascii_case("authorization") {
+                authorization = Some(value.trim().to_owned());
+            } else if name.eq_ignore_ascii_case("transfer-encoding") {
+                transfer_encoding = Some(value.trim().to_owned());
+            }
+        }
+        bail!("More than {MAX_HEADERS} headers")
+    }
+}
+
+/// The reason phrase for a status code.
+pub(crate) fn reason(status: u16) -> &'static str {
+    match status {
+        200 => "OK",
+        201 => "Created",
+        204 => "No Content",
+        400 => "Bad Request",
+        401 => "Unauthorized",
+        403 => "Forbidden",
+        404 => "Not Found",
+        405 => "Method Not Allowed",
+        413 => "Payload Too Large",
+        501 => "Not Implemented",
+        503 => "Service Unavailable",
+        _ => "Internal Server Error",
+    }
+}
+
+/// Decodes the `%XX` escapes in part of a URL.
+pub(crate) fn percent_decode(encoded: &str) -> Result<String> {
+    let mut decoded = Vec::with_capacity(encoded.len());
+    let mut bytes = encoded.bytes();
+    while let Some(byte) = bytes.next() {
+        if byte != b'%' {
+            decoded.push(byte);
+            continue;
+        }
+        let hex = [bytes.next(), bytes.next()].map(|digit| (digit? as char).to_digit(16));
+        let [Some(high), Some(low)] = hex else {
+            bail!("Invalid percent escape in {encoded:?}");
+        };
+        decoded.push((high * 16 + low) as u8);
+    }
+    String::from_utf8(decoded).with_context(|| format!("Invalid utf8 in {encoded:?}"))
+}
+
+/// Writes a complete response with the body. A 204 response can't have a body.
+pub(crate) fn write_response(
+    mut writer: impl Write,
+    status: u16,
+    reason: &str,
+    content_type: &str,
+    body: &[u8],
+) -> Result<()> {
+    write!(writer, "HTTP/1.1 {status} {reason}\r\n")?;
+    if status != 204 {
+        write!(
+            writer,
+            "Content-Type: {content_type}\r\nContent-Length: {}\r\n",
+            body.len()
+        )?;
+    }
+    write!(writer, "Connection: close\r\n\r\n")?;
+    writer.write_all(body)?;
+    writer.flush()?;
+    Ok(())
+}
+
+/// Reads a line without its line ending, or `None` if the reader is already at its end.
+fn read_line(reader: &mut impl BufRead) -> Result<Option<String>> {
+    let mut line = Vec::new();
+    let read = reader
+        .by_ref()
+        .take(MAX_LINE_BYTES as u64 + 1)
+        .read_until(b'\n', &mut line)
+        .context("reading line")?;
+    if read == 0 {
+        return Ok(None);
+    }
+    if line.pop() != Some(b'\n') {
+        bail!("Line too long or connection closed mid-line");
+    }
+    if line.last() == Some(&b'\r') {
+        line.pop();
+    }
+    String::from_utf8(line)
+        .map(Some)
+        .context("Invalid utf8 in request")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_requests() {
+        let mut bytes: &[u8] = b"PUT /keys/a HTTP/1.1\r\nHost: x\r\ncontent-length: 3\r\n\
+            Authorization: Bearer secret\r\n\r\nabc";
+        let request = Request::read(&mut bytes, &Limits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(
+            request,
+            Request {
+                method: "PUT".to_owned(),
+                path: "/keys/a".to_owned(),
+                authorization: Some("Bearer secret".to_owned()),
+                transfer_encoding: None,
+                body: b"abc".to_vec(),
+            }
+        );
+
+        let mut bytes: &[u8] =
+            b"PUT /keys/a HTTP/1.1\r\nTransfer-Encoding: chunked\r\n\r\n3\r\nabc\r\n0\r\n\r\n";
+        let request = Request::read(&mut bytes, &Limits::default())
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.transfer_encoding.as_deref(), Some("chunked"));
+        assert!(request.body.is_empty());
+
+        assert_eq!(
+            Request::read(&mut &b""[..], &Limits::default()).unwrap(),
+            None
+        );
+        assert!(Request::read(&mut &b"GET /metrics\r\n\r\n"[..], &Limits::default()).is_err());
+        assert!(Request::read(&mut &b"GET / HTTP/1.1\r\nHost: x"[..], &Limits::default()).is_err());
+
+        let limits = Limits {
+            max_value_bytes: 2,
+            ..Limits::default()
+        };
+        let e = Request::read(
+            &mut &b"PUT / HTTP/1.1\r\nContent-Length: 3\r\n\r\n"[..],
+            &limits,
+        )
+        .unwrap_err();
+        assert!(e.is::<protocol::TooLarge>(), "{e:#}");
+    }
+
+    #[test]
+    fn decodes_percent_escapes() {
+        assert_eq!(percent_decode("a%20b%2Fc").unwrap(), "a b/c");
+        assert_eq!(percent_decode("%C3%A9").unwrap(), "é");
+        assert!(percent_decode("a%2").is_err());
+        assert!(percent_decode("%zz").is_err());
+        assert!(percent_decode("%FF").is_err());
+    }
+}
@@ -1,5 +1,39 @@
+mod acl;
+mod async_server;
+mod auth;
+mod config;
 mod engine;
+mod frontend;
+mod gateway;
+mod http;
+mod memcached;
+mod metrics;
+mod migrate;
+mod rate_limit;
+mod resp;
+mod runtime;
 mod server;
+mod thread_pool;
+mod tls;
+mod unix;
 
-pub use engine::{Engine, EngineType};
-pub use server::Server;
+pub use acl::{Acl, Grant, Permission};
+pub use async_server::AsyncServer;
+pub use auth::AuthToken;
+pub use config::{
+    AuthConfig, Config, EngineConfig, HttpConfig, LimitsConfig, LogConfig, MetricsConfig,
+    ServerConfig, SledConfig, TlsConfig, UnixConfig,
+};
+pub use engine::{
+    register_engine, DynKvsEngine, Engine, EngineOptions, EngineRegistration, EngineStats,
+    EngineType, MemoryDb, SledDb, SledMode, SledOptions,
+};
+pub use migrate::{migrate, open_source};
+pub use rate_limit::RateLimit;
+pub use runtime::Runtime;
+pub use server::{Protocol, Server, ServerHandle, ServerOptions};
+pub use thread_pool::{
+    NaiveThreadPool, Pool, PoolType, SharedQueueThreadPool, ThreadPool, WorkStealingThreadPool,
+};
+pub use tls::ServerTls;
+pub use unix::UnixSocket;
@@ -0,0 +1,649 @@
+//! A front end speaking memcached's text protocol, so services using memcached client libraries
+//! can move onto the server without changing their clients.
+//!
+//! `get`, `gets`, `set`, `add`, `replace`, `delete`, `version` and `quit` are supported, and run
+//! through the same authorization and metrics as [`Cmd`]s. The other storage commands, `cas`,
+//! `append` and `prepend`, have their data block read and get a `SERVER_ERROR` reply. Other
+//! commands get an `ERROR` reply.
+//! Values are stored as they are, so the other protocols see the same data. That means:
+//!
+//! - Flags aren't stored. Storage commands with flags other than 0 are rejected, and values are
+//!   always returned with flags of 0.
+//! - Items don't expire. Storage commands with an exptime other than 0 are rejected.
+//! - The cas unique `gets` returns is a hash of the value, since the engine doesn't version
+//!   values, so it only changes when the value does. `cas` itself isn't supported.
+//!
+//! If the server requires authentication, connections authenticate like they do with memcached's
+//! own text protocol authentication: with a `set` whose data is a username and the token,
+//! separated by a space. The key, flags and username are ignored.
+
+use std::hash::{DefaultHasher, Hash, Hasher};
+
+use anyhow::{ensure, Context, Result};
+use protocol::{Cmd, ErrorCode, Limits, Response};
+use tracing::{debug, warn};
+
+use crate::acl::cmd_key;
+use crate::frontend;
+use crate::server::{Client, Shared, MAX_AUTH_FAILURES};
+use crate::{Grant, Server};
+
+type Action = frontend::Action<Batch>;
+
+/// Longest command line accepted.
+const MAX_LINE_LEN: usize = 64 * 1024;
+/// Longest key memcached's protocol allows. Keys are also held to the server's limit on keys.
+const MAX_KEY_LEN: usize = 250;
+
+/// Parses a command from the start of the buffer: the words of its command line, followed by its
+/// data block if it's a storage command, even one that isn't supported. Returns the command's
+/// arguments and how many bytes it took up, or `None` if the buffer doesn't hold a whole command
+/// yet. A data block larger than the limit on values is rejected before it arrives.
+pub(crate) fn decode(buf: &[u8], limits: &Limits) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+    let Some(newline) = buf.iter().position(|&b| b == b'\n') else {
+        ensure!(buf.len() <= MAX_LINE_LEN, "line too long");
+        return Ok(None);
+    };
+    ensure!(newline <= MAX_LINE_LEN, "line too long");
+    let line = &buf[..newline];
+    let line = line.strip_suffix(b"\r").unwrap_or(line);
+    let mut args: Vec<_> = line
+        .split(|&b| b == b' ')
+        .filter(|word| !word.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect();
+    let mut used = newline + 1;
+
+    if args.first().is_some_and(|name| {
+        matches!(
+            name.as_slice(),
+            b"set" | b"add" | b"replace" | b"cas" | b"append" | b"prepend"
+        )
+    }) {
+        // The data block's length is the command's fifth word.
+        let len: u64 = args
+            .get(4)
+            .and_then(|len| std::str::from_utf8(len).ok())
+            .and_then(|len| len.parse().ok())
+            .context("bad command line format")?;
+        limits.check_value(len)?;
+        let end = used + len as usize;
+        let Some(terminator) = buf.get(end..end + 2) else {
+            return Ok(None);
+        };
+        ensure!(terminator == b"\r\n", "bad data chunk");
+        args.push(buf[used..end].to_vec());
+        used = end + 2;
+    }
+    Ok(Some((args, used)))
+}
+
+/// A reply to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reply {
+    /// A status line, like `STORED`.
+    Status(&'static str),
+    /// `ERROR`, or `CLIENT_ERROR` or `SERVER_ERROR` followed by a message.
+    Error(String),
+    /// The items `get` or `gets` found, with their cas uniques for `gets`.
+    Values {
+        items: Vec<(String, Vec<u8>)>,
+        cas: bool,
+    },
+    /// No reply, for commands sent with `noreply`.
+    Nothing,
+}
+
+impl frontend::Reply for Reply {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Status(status) => out.extend_from_slice(format!("{status}\r\n").as_bytes()),
+            Self::Error(message) => {
+                // Errors are a single line, so line breaks in messages would corrupt the reply.
+                let message = message.replace(['\r', '\n'], " ");
+                out.extend_from_slice(format!("{message}\r\n").as_bytes());
+            }
+            Self::Values { items, cas } => {
+                for (key, value) in items {
+                    let mut line = format!("VALUE {key} 0 {}", value.len());
+                    if *cas {
+                        line.push_str(&format!(" {}", cas_unique(value)));
+                    }
+                    out.extend_from_slice(line.as_bytes());
+                    out.extend_from_slice(b"\r\n");
+                    out.extend_from_slice(value);
+                    out.extend_from_slice(b"\r\n");
+                }
+                out.extend_from_slice(b"END\r\n");
+            }
+            Self::Nothing => {}
+        }
+    }
+
+    fn error(code: ErrorCode, message: &str) -> Self {
+        Self::from_error(code, message)
+    }
+}
+
+impl Reply {
+    /// The error reply for an error response. Errors caused by the request are client errors, and
+    /// the rest are server errors.
+    fn from_error(code: ErrorCode, message: &str) -> Self {
+        let kind = match code {
+            ErrorCode::BadRequest
+            | ErrorCode::Unauthorized
+            | ErrorCode::Forbidden
+            | ErrorCode::NotFound
+            | ErrorCode::TooLarge => "CLIENT_ERROR",
+            ErrorCode::Internal | ErrorCode::Busy | ErrorCode::Storage | ErrorCode::Unknown(_) => {
+                "SERVER_ERROR"
+            }
+        };
+        Self::Error(format!("{kind} {message}"))
+    }
+
+    fn client_error(message: &str) -> Self {
+        Self::Error(format!("CLIENT_ERROR {message}"))
+    }
+}
+
+/// The cas unique of a value, which is a hash of it.
+fn cas_unique(value: &[u8]) -> u64 {
+    let mut hasher = DefaultHasher::new();
+    value.hash(&mut hasher);
+    hasher.finish()
+}
+
+/// [`Cmd`]s that carry out a memcached command, and how to reply once they've been executed.
+pub(crate) struct Batch {
+    cmds: Vec<Cmd<'static>>,
+    kind: BatchKind,
+    /// Whether the client asked for no reply.
+    noreply: bool,
+}
+
+enum BatchKind {
+    /// Reply with the values found, and their cas uniques if `cas` is set.
+    Get {
+        cas: bool,
+    },
+    Set,
+    /// Only set the key if it doesn't exist.
+    Add,
+    /// Only set the key if it exists.
+    Replace,
+    Delete,
+}
+
+impl frontend::Batch for Batch {
+    type Reply = Reply;
+
+    /// Executes the commands in order and replies to the memcached command.
+    fn execute(self, shared: &Shared) -> Reply {
+        let reply = match self.kind {
+            BatchKind::Get { cas } => get(shared, self.cmds, cas),
+            // Every storage and delete batch has exactly one command.
+            BatchKind::Set => store(shared, self.cmds, None),
+            BatchKind::Add => store(shared, self.cmds, Some(false)),
+            BatchKind::Replace => store(shared, self.cmds, Some(true)),
+            BatchKind::Delete => delete(shared, self.cmds),
+        };
+        if self.noreply {
+            Reply::Nothing
+        } else {
+            reply
+        }
+    }
+}
+
+/// Gets each key, replying with the ones that exist.
+fn get(shared: &Shared, cmds: Vec<Cmd<'static>>, cas: bool) -> Reply {
+    let mut items = Vec::new();
+    for cmd in cmds {
+        let key = cmd_key(&cmd).unwrap_or_default().to_owned();
+        match Server::handle_cmd(shared, cmd) {
+            Response::SuccessfulGet(value) => items.push((key, value.into_owned().into_bytes())),
+            Response::KeyNotFound => {}
+            response => return unexpected(response),
+        }
+    }
+    Reply::Values { items, cas }
+}
+
+/// Sets the key, if `exists` is `None` or matches whether the key exists. Nothing else can write
+/// the key between the check and the write.
+fn store(shared: &Shared, mut cmds: Vec<Cmd<'static>>, exists: Option<bool>) -> Reply {
+    let Cmd::Set(key, value) = cmds.remove(0) else {
+        unreachable!("storage batches carry a set");
+    };
+    match Server::handle_set_if(shared, key.into_owned(), &value, exists) {
+        (_, Some(Response::SuccessfulSet)) => Reply::Status("STORED"),
+        (_, Some(response)) => unexpected(response),
+        (_, None) => Reply::Status("NOT_STORED"),
+    }
+}
+
+fn delete(shared: &Shared, mut cmds: Vec<Cmd<'static>>) -> Reply {
+    match Server::handle_cmd(shared, cmds.remove(0)) {
+        Response::SuccessfulRm => Reply::Status("DELETED"),
+        Response::Err(ErrorCode::NotFound, _) => Reply::Status("NOT_FOUND"),
+        response => unexpected(response),
+    }
+}
+
+/// The reply to an error response, or to a response that doesn't belong to the command.
+fn unexpected(response: Response) -> Reply {
+    match response {
+        Response::Err(code, message) => Reply::from_error(code, &message),
+        response => {
+            warn!(?response, "Unexpected response to memcached command");
+            Reply::Error("SERVER_ERROR unexpected response from the engine".to_owned())
+        }
+    }
+}
+
+/// The state of a connection: which grant its commands are checked against.
+pub(crate) struct Session<'s> {
+    shared: &'s Shared,
+    client: Client,
+    /// The grant commands are checked against, or why the connection can't run commands.
+    access: Result<&'s Grant, &'static str>,
+    /// How many tokens the connection has presented that were rejected.
+    auth_failures: u32,
+}
+
+impl<'s> Session<'s> {
+    pub(crate) fn new(shared: &'s Shared, client: Client) -> Self {
+        Self {
+            shared,
+            client,
+            access: shared.unauthenticated(),
+            auth_failures: 0,
+        }
+    }
+
+    /// Decides what to do with a command, given its name and arguments. Storage commands have
+    /// their data block as their last argument.
+    fn start(&mut self, mut args: Vec<Vec<u8>>) -> Action {
+        if args.is_empty() {
+            return Action::Reply(Reply::Error("ERROR".to_owned()));
+        }
+        let name = String::from_utf8_lossy(&args.remove(0)).into_owned();
+        debug!(
+            command = name,
+            args = args.len(),
+            "Parsed memcached command"
+        );
+
+        let action = match (name.as_str(), args.len()) {
+            ("get" | "gets", 1..) => {
+                let limits = &self.shared.options.limits;
+                let cmds = args
+                    .into_iter()
+                    .map(|arg| Ok(Cmd::Get(key(arg, limits)?.into())));
+                let cas = name == "gets";
+                cmds.collect::<Result<_, _>>()
+                    .and_then(|cmds| self.execute(cmds, BatchKind::Get { cas }, false))
+            }
+            ("set", 5 | 6) if self.access.is_err() => Ok(self.auth(&args[args.len() - 1])),
+            ("set" | "add" | "replace", 5 | 6) => self.store(&name, args),
+            ("delete", 1 | 2) => noreply(&args, 1).and_then(|noreply| {
+                let key = key(args.swap_remove(0), &self.shared.options.limits)?;
+                self.execute(vec![Cmd::Rm(key.into())], BatchKind::Delete, noreply)
+            }),
+            ("version", 0) => Ok(Action::Reply(Reply::Status(concat!(
+                "VERSION ",
+                env!("CARGO_PKG_VERSION")
+            )))),
+            ("quit", 0) => Ok(Action::Close(Reply::Nothing)),
+            // Their data block was read, so it isn't mistaken for a command.
+            ("cas" | "append" | "prepend", _) => {
+                Err(Reply::Error(format!("SERVER_ERROR {name} isn't supported")))
+            }
+            ("get" | "gets" | "set" | "add" | "replace" | "delete" | "version" | "quit", _) => {
+                Err(Reply::client_error("bad command line format"))
+            }
+            _ => Err(Reply::Error("ERROR".to_owned())),
+        };
+        action.unwrap_or_else(Action::Reply)
+    }
+
+    /// Starts `set`, `add` or `replace`, with the arguments `key flags exptime bytes [noreply]
+    /// data`.
+    fn store(&self, name: &str, mut args: Vec<Vec<u8>>) -> Result<Action, Reply> {
+        let value = args.pop().expect("storage commands have a data block");
+        let noreply = noreply(&args, 4)?;
+        let number = |arg: &[u8]| {
+            std::str::from_utf8(arg)
+                .ok()
+                .and_then(|arg| arg.parse::<i64>().ok())
+                .ok_or_else(|| Reply::client_error("bad command line format"))
+        };
+        if number(&args[1])? != 0 {
+            return Err(Reply::client_error("flags aren't supported, only 0"));
+        }
+        if number(&args[2])? != 0 {
+            return Err(Reply::client_error("expiration isn't supported, only 0"));
+        }
+        let key = key(args.swap_remove(0), &self.shared.options.limits)?;
+        let value =
+            String::from_utf8(value).map_err(|_| Reply::client_error("values must be UTF-8"))?;
+        let kind = match name {
+            "add" => BatchKind::Add,
+            "replace" => BatchKind::Replace,
+            _ => BatchKind::Set,
+        };
+        self.execute(vec![Cmd::Set(key.into(), value.into())], kind, noreply)
+    }
+
+    /// Authenticates the connection with the token in a `set`'s data, after the username. The
+    /// connection is closed once it's presented [`MAX_AUTH_FAILURES`] rejected tokens.
+    fn auth(&mut self, data: &[u8]) -> Action {
+        if let Some((reply, close)) = frontend::busy(self.shared, &self.client) {
+            return Action::reply(reply, close);
+        }
+        // The token alone picks the grant, so the username is ignored.
+        let grant = std::str::from_utf8(data)
+            .ok()
+            .and_then(|data| data.split_once(' '))
+            .and_then(|(_, token)| self.shared.authenticate(token.trim()));
+        match grant {
+            Some(grant) => {
+                debug!(grant = grant.name, "Connection authenticated");
+                self.access = Ok(grant);
+                Action::Reply(Reply::Status("STORED"))
+            }
+            None => {
+                warn!(target: "audit", ip = ?self.client.ip, "Rejected auth token");
+                let response = Shared::unauthorized("Invalid auth token");
+                self.shared.metrics.record_response(&response);
+                self.auth_failures += 1;
+                Action::reply(
+                    Reply::client_error("authentication failure"),
+                    self.auth_failures >= MAX_AUTH_FAILURES,
+                )
+            }
+        }
+    }
+
+    /// Executes the commands if the connection's grant allows every one of them.
+    fn execute(
+        &self,
+        cmds: Vec<Cmd<'static>>,
+        kind: BatchKind,
+        noreply: bool,
+    ) -> Result<Action, Reply> {
+        let grant = self.access.map_err(|message| {
+            let response = Shared::unauthorized(message);
+            self.shared.metrics.record_response(&response);
+            Reply::from_error(ErrorCode::Unauthorized, message)
+        })?;
+        for cmd in &cmds {
+            if let Err(Response::Err(code, message)) = self.shared.authorize(grant, cmd) {
+                return Err(Reply::from_error(code, &message));
+            }
+        }
+        Ok(Action::Execute(Batch {
+            cmds,
+            kind,
+            noreply,
+        }))
+    }
+}
+
+impl frontend::Session for Session<'_> {
+    type Batch = Batch;
+
+    /// A command that can't be parsed gets an error reply and closes the connection, since what
+    /// follows it can't be trusted to be the start of a command.
+    fn next_action(&mut self, buf: &mut Vec<u8>) -> Option<Action> {
+        match decode(buf, &self.shared.options.limits) {
+            Ok(Some((args, used))) => {
+                buf.drain(..used);
+                Some(self.start(args))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!(?e, "Failed to parse command");
+                buf.clear();
+                Some(Action::Close(Reply::client_error(&format!("{e:#}"))))
+            }
+        }
+    }
+}
+
+/// Whether the argument at `index`, if there is one, asks for no reply.
+fn noreply(args: &[Vec<u8>], index: usize) -> Result<bool, Reply> {
+    match args.get(index).map(Vec::as_slice) {
+        None => Ok(false),
+        Some(b"noreply") => Ok(true),
+        Some(_) => Err(Reply::client_error("bad command line format")),
+    }
+}
+
+/// Keys are strings in the engine, so they have to be UTF-8. Like memcached, long keys and keys
+/// with control characters are rejected.
+fn key(arg: Vec<u8>, limits: &Limits) -> Result<String, Reply> {
+    let key = String::from_utf8(arg).map_err(|_| Reply::client_error("keys must be UTF-8"))?;
+    if key.len() > MAX_KEY_LEN || key.chars().any(char::is_control) {
+        return Err(Reply::client_error("bad command line format"));
+    }
+    limits
+        .check_key(key.len() as u64)
+        .map_err(|e| Reply::client_error(&e.to_string()))?;
+    Ok(key)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::test_util::{args, auth_options, client, reply, shared, written};
+    use crate::{RateLimit, ServerOptions};
+
+    #[test]
+    fn decodes_commands() {
+        let limits = Limits::default();
+        let buf = b"set a 0 0 5\r\nhello\r\nget a b\r\nget";
+        let (decoded, used) = decode(buf, &limits).unwrap().unwrap();
+        assert_eq!(decoded, args(&["set", "a", "0", "0", "5", "hello"]));
+        let (decoded, next) = decode(&buf[used..], &limits).unwrap().unwrap();
+        assert_eq!(decoded, args(&["get", "a", "b"]));
+        assert_eq!(decode(&buf[used + next..], &limits).unwrap(), None);
+
+        assert_eq!(decode(b"set a 0 0 5\r\nhel", &limits).unwrap(), None);
+        // Unsupported storage commands still have their data block read.
+        let buf = b"cas k 0 0 10 1\r\ndelete foo\r\n";
+        let (decoded, used) = decode(buf, &limits).unwrap().unwrap();
+        assert_eq!(
+            decoded,
+            args(&["cas", "k", "0", "0", "10", "1", "delete foo"])
+        );
+        assert_eq!(used, buf.len());
+        let (decoded, _) = decode(b"append k 0 0 1\r\na\r\n", &limits)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, args(&["append", "k", "0", "0", "1", "a"]));
+        let (decoded, _) = decode(b"delete  a noreply\n", &limits).unwrap().unwrap();
+        assert_eq!(decoded, args(&["delete", "a", "noreply"]));
+
+        assert!(decode(b"set a 0 0\r\n", &limits).is_err());
+        assert!(decode(b"set a 0 0 x\r\n", &limits).is_err());
+        assert!(decode(b"set a 0 0 2\r\nabc\r\n", &limits).is_err());
+        assert!(decode(&vec![b'a'; MAX_LINE_LEN + 1], &limits).is_err());
+
+        // Too large before the data block arrives.
+        let small = Limits {
+            max_key_bytes: 3,
+            max_value_bytes: 5,
+        };
+        assert_eq!(decode(b"set a 0 0 5\r\n", &small).unwrap(), None);
+        assert!(decode(b"set a 0 0 6\r\n", &small).is_err());
+    }
+
+    #[test]
+    fn writes_replies() {
+        assert_eq!(written(Reply::Status("STORED")), "STORED\r\n");
+        assert_eq!(
+            written(Reply::Error("CLIENT_ERROR a\r\nb".into())),
+            "CLIENT_ERROR a  b\r\n"
+        );
+        assert_eq!(written(Reply::Nothing), "");
+        let items = vec![
+            ("a".to_owned(), b"1".to_vec()),
+            ("b".to_owned(), b"22".to_vec()),
+        ];
+        assert_eq!(
+            written(Reply::Values {
+                items: items.clone(),
+                cas: false
+            }),
+            "VALUE a 0 1\r\n1\r\nVALUE b 0 2\r\n22\r\nEND\r\n"
+        );
+        let with_cas = written(Reply::Values { items, cas: true });
+        assert!(with_cas.starts_with(&format!("VALUE a 0 1 {}\r\n", cas_unique(b"1"))));
+    }
+
+    #[test]
+    fn maps_commands_onto_engine() {
+        let (shared, _dir) = shared(ServerOptions::default());
+        let mut session = Session::new(&shared, client());
+        let mut run = |words: &[&str]| reply(&shared, session.start(args(words)));
+        let values = |items: &[(&str, &str)], cas| Reply::Values {
+            items: items
+                .iter()
+                .map(|(key, value)| (key.to_string(), value.as_bytes().to_vec()))
+                .collect(),
+            cas,
+        };
+        let client_error = |message: &str| Reply::Error(format!("CLIENT_ERROR {message}"));
+
+        assert_eq!(
+            run(&["set", "a", "0", "0", "1", "1"]),
+            Reply::Status("STORED")
+        );
+        assert_eq!(
+            run(&["add", "a", "0", "0", "1", "2"]),
+            Reply::Status("NOT_STORED")
+        );
+        assert_eq!(
+            run(&["add", "b", "0", "0", "1", "2"]),
+            Reply::Status("STORED")
+        );
+        assert_eq!(
+            run(&["replace", "c", "0", "0", "1", "3"]),
+            Reply::Status("NOT_STORED")
+        );
+        assert_eq!(
+            run(&["replace", "b", "0", "0", "2", "noreply", "22"]),
+            Reply::Nothing
+        );
+        assert_eq!(
+            run(&["get", "a", "missing", "b"]),
+            values(&[("a", "1"), ("b", "22")], false)
+        );
+        assert_eq!(run(&["gets", "a"]), values(&[("a", "1")], true));
+        assert_eq!(run(&["delete", "a"]), Reply::Status("DELETED"));
+        assert_eq!(run(&["delete", "a"]), Reply::Status("NOT_FOUND"));
+        assert_eq!(run(&["get", "a"]), values(&[], false));
+        assert_eq!(
+            run(&["version"]),
+            Reply::Status(concat!("VERSION ", env!("CARGO_PKG_VERSION")))
+        );
+
+        assert_eq!(
+            run(&["set", "a", "1", "0", "1", "1"]),
+            client_error("flags aren't supported, only 0")
+        );
+        assert_eq!(
+            run(&["set", "a", "0", "60", "1", "1"]),
+            client_error("expiration isn't supported, only 0")
+        );
+        assert_eq!(
+            run(&["set", "a", "0", "0", "1", "later", "1"]),
+            client_error("bad command line format")
+        );
+        assert_eq!(
+            run(&["get", &"k".repeat(MAX_KEY_LEN + 1)]),
+            client_error("bad command line format")
+        );
+        assert_eq!(run(&["get"]), client_error("bad command line format"));
+        assert_eq!(
+            run(&["cas", "a", "0", "0", "1", "1", "noreply", "1"]),
+            Reply::Error("SERVER_ERROR cas isn't supported".into())
+        );
+        assert_eq!(run(&["incr", "a", "1"]), Reply::Error("ERROR".into()));
+        assert_eq!(run(&["GET", "a"]), Reply::Error("ERROR".into()));
+        assert_eq!(run(&["quit"]), Reply::Nothing);
+    }
+
+    #[test]
+    fn requires_auth() {
+        let (shared, _dir) = shared(auth_options());
+        let mut session = Session::new(&shared, client());
+        let mut run = |words: &[&str]| reply(&shared, session.start(args(words)));
+
+        assert_eq!(
+            run(&["get", "a"]),
+            Reply::Error("CLIENT_ERROR Authentication required".into())
+        );
+        assert_eq!(
+            run(&["set", "auth", "0", "0", "10", "user wrong"]),
+            Reply::Error("CLIENT_ERROR authentication failure".into())
+        );
+        assert_eq!(
+            run(&["set", "auth", "0", "0", "11", "user secret"]),
+            Reply::Status("STORED")
+        );
+        assert_eq!(
+            run(&["get", "auth"]),
+            Reply::Values {
+                items: Vec::new(),
+                cas: false
+            }
+        );
+    }
+
+    #[test]
+    fn adds_once() {
+        let (shared, _dir) = shared(ServerOptions::default());
+        let stored = std::thread::scope(|scope| {
+            let adds: Vec<_> = (0..8)
+                .map(|i| {
+                    let shared = &shared;
+                    scope.spawn(move || {
+                        let cmd = Cmd::Set("key".into(), i.to_string().into());
+                        store(shared, vec![cmd], Some(false))
+                    })
+                })
+                .collect();
+            adds.into_iter()
+                .map(|add| add.join().unwrap())
+                .filter(|reply| *reply == Reply::Status("STORED"))
+                .count()
+        });
+        assert_eq!(stored, 1);
+    }
+
+    #[test]
+    fn limits_auth_attempts() {
+        let (shared, _dir) = shared(ServerOptions {
+            rate_limit: Some(RateLimit {
+                per_sec: 1,
+                burst: 3,
+            }),
+            ..auth_options()
+        });
+        let wrong = || args(&["set", "auth", "0", "0", "10", "user wrong"]);
+
+        let mut session = Session::new(&shared, client());
+        assert!(matches!(session.start(wrong()), Action::Reply(_)));
+        assert!(matches!(session.start(wrong()), Action::Reply(_)));
+        assert!(matches!(session.start(wrong()), Action::Close(_)));
+        // A new connection is still rate limited.
+        let mut session = Session::new(&shared, client());
+        assert!(matches!(
+            session.start(wrong()),
+            Action::Reply(Reply::Error(e)) if e.starts_with("SERVER_ERROR Too many")
+        ));
+    }
+}
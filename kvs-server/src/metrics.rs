@@ -0,0 +1,362 @@
+//! Counters and histograms describing what a server has been doing, rendered in the Prometheus
+//! text exposition format.
+//!
+//! Command latencies and byte counts are updated with atomic operations, so recording them doesn't
+//! contend with other connections. Error responses are rarer, and are counted by code in a map
+//! behind a mutex. See <https://prometheus.io/docs/instrumenting/exposition_formats/>.
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Write};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, PoisonError};
+use std::time::Duration;
+
+use protocol::{Cmd, ErrorCode, Response};
+
+use crate::EngineStats;
+
+pub(crate) use endpoint::MetricsEndpoint;
+
+mod endpoint;
+
+/// Upper bounds, in seconds, of the command latency histogram's buckets.
+const LATENCY_BUCKETS: [f64; 12] = [
+    0.0001, 0.00025, 0.0005, 0.001, 0.0025, 0.005, 0.01, 0.025, 0.05, 0.1, 0.25, 1.0,
+];
+
+/// The commands that are counted and timed. Keep-alives and auth aren't, since they only set up
+/// the connection.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum CommandKind {
+    Set,
+    Get,
+    Rm,
+    Ping,
+    Info,
+    Compact,
+    Flush,
+}
+
+impl CommandKind {
+    const ALL: [Self; 7] = [
+        Self::Set,
+        Self::Get,
+        Self::Rm,
+        Self::Ping,
+        Self::Info,
+        Self::Compact,
+        Self::Flush,
+    ];
+
+    pub(crate) fn of(cmd: &Cmd) -> Option<Self> {
+        match cmd {
+            Cmd::Set(..) => Some(Self::Set),
+            Cmd::Get(_) => Some(Self::Get),
+            Cmd::Rm(_) => Some(Self::Rm),
+            Cmd::Ping => Some(Self::Ping),
+            Cmd::Info => Some(Self::Info),
+            Cmd::Compact => Some(Self::Compact),
+            Cmd::Flush => Some(Self::Flush),
+            Cmd::KeepAlive | Cmd::Auth(_) | Cmd::Hello(_) => None,
+        }
+    }
+
+    pub(crate) fn label(self) -> &'static str {
+        match self {
+            Self::Set => "set",
+            Self::Get => "get",
+            Self::Rm => "rm",
+            Self::Ping => "ping",
+            Self::Info => "info",
+            Self::Compact => "compact",
+            Self::Flush => "flush",
+        }
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Metrics {
+    commands: [Histogram; CommandKind::ALL.len()],
+    /// Error responses, by their code.
+    errors: Mutex<BTreeMap<u16, u64>>,
+    open_connections: AtomicU64,
+    bytes_read: AtomicU64,
+    bytes_written: AtomicU64,
+}
+
+/// Decrements the open connections gauge when dropped.
+pub(crate) struct ConnectionMetric<'a> {
+    metrics: &'a Metrics,
+}
+
+#[derive(Default)]
+struct Histogram {
+    /// Observations in each bucket of [`LATENCY_BUCKETS`], not including smaller buckets.
+    buckets: [AtomicU64; LATENCY_BUCKETS.len()],
+    count: AtomicU64,
+    sum_nanos: AtomicU64,
+}
+
+impl Metrics {
+    /// Records a command taking `elapsed` to produce the response.
+    pub(crate) fn record_command(&self, kind: CommandKind, elapsed: Duration, response: &Response) {
+        self.commands[kind as usize].observe(elapsed);
+        self.record_response(response);
+    }
+
+    /// Records an error response, if it is one. Other responses aren't counted on their own.
+    pub(crate) fn record_response(&self, response: &Response) {
+        if let Response::Err(code, _) = response {
+            // Every operation leaves the map valid, so it's fine to use after a panic.
+            let mut errors = self.errors.lock().unwrap_or_else(PoisonError::into_inner);
+            *errors.entry(code.as_u16()).or_default() += 1;
+        }
+    }
+
+    /// Counts a connection as open until the returned guard is dropped.
+    pub(crate) fn connection_opened(&self) -> ConnectionMetric<'_> {
+        self.open_connections.fetch_add(1, Ordering::Relaxed);
+        ConnectionMetric { metrics: self }
+    }
+
+    pub(crate) fn record_read(&self, bytes: usize) {
+        self.bytes_read.fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    pub(crate) fn record_written(&self, bytes: usize) {
+        self.bytes_written
+            .fetch_add(bytes as u64, Ordering::Relaxed);
+    }
+
+    /// Writes every metric, along with the engine's, in the Prometheus text format.
+    pub(crate) fn render(&self, engine: &EngineStats, out: &mut impl Write) -> fmt::Result {
+        writeln!(
+            out,
+            "# HELP kvs_commands_total Commands handled, by command."
+        )?;
+        writeln!(out, "# TYPE kvs_commands_total counter")?;
+        for kind in CommandKind::ALL {
+            let count = self.commands[kind as usize].count.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "kvs_commands_total{{command=\"{}\"}} {count}",
+                kind.label()
+            )?;
+        }
+
+        writeln!(
+            out,
+            "# HELP kvs_command_duration_seconds Time to execute commands, by command."
+        )?;
+        writeln!(out, "# TYPE kvs_command_duration_seconds histogram")?;
+        for kind in CommandKind::ALL {
+            self.commands[kind as usize].render(
+                "kvs_command_duration_seconds",
+                kind.label(),
+                out,
+            )?;
+        }
+
+        writeln!(out, "# HELP kvs_errors_total Error responses, by code.")?;
+        writeln!(out, "# TYPE kvs_errors_total counter")?;
+        let errors = self.errors.lock().unwrap_or_else(PoisonError::into_inner);
+        for (&code, count) in errors.iter() {
+            let kind = error_kind(ErrorCode::from(code));
+            writeln!(
+                out,
+                "kvs_errors_total{{code=\"{code}\",kind=\"{kind}\"}} {count}"
+            )?;
+        }
+        drop(errors);
+
+        gauge(
+            out,
+            "kvs_open_connections",
+            "Connections currently open.",
+            self.open_connections.load(Ordering::Relaxed),
+        )?;
+        counter(
+            out,
+            "kvs_read_bytes_total",
+            "Bytes of commands read from connections.",
+            self.bytes_read.load(Ordering::Relaxed),
+        )?;
+        counter(
+            out,
+            "kvs_written_bytes_total",
+            "Bytes of responses written to connections.",
+            self.bytes_written.load(Ordering::Relaxed),
+        )?;
+
+        gauge(
+            out,
+            "kvs_engine_live_keys",
+            "Keys currently associated with a value.",
+            engine.live_keys,
+        )?;
+        // Only the kvs engine knows about its files.
+        if let Some(store) = &engine.store {
+            gauge(
+                out,
+                "kvs_engine_files",
+                "Log files, including the active file.",
+                store.files,
+            )?;
+            gauge(
+                out,
+                "kvs_engine_dead_bytes",
+                "Bytes of log files taken up by overwritten values and removals.",
+                store.dead_bytes,
+            )?;
+            counter(
+                out,
+                "kvs_engine_compactions_total",
+                "Compactions run since the engine was opened.",
+                store.compactions,
+            )?;
+            counter(
+                out,
+                "kvs_engine_compaction_seconds_total",
+                "Time spent compacting since the engine was opened.",
+                store.compaction_time.as_secs_f64(),
+            )?;
+            counter(
+                out,
+                "kvs_engine_syncs_total",
+                "Files and directories synced to disk since the engine was opened.",
+                store.syncs,
+            )?;
+        }
+
+        Ok(())
+    }
+}
+
+impl Drop for ConnectionMetric<'_> {
+    fn drop(&mut self) {
+        self.metrics
+            .open_connections
+            .fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Histogram {
+    fn observe(&self, elapsed: Duration) {
+        let seconds = elapsed.as_secs_f64();
+        if let Some(bucket) = LATENCY_BUCKETS.iter().position(|&le| seconds <= le) {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        let nanos = u64::try_from(elapsed.as_nanos()).unwrap_or(u64::MAX);
+        self.sum_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    /// Writes the histogram's samples with cumulative buckets, as Prometheus expects.
+    fn render(&self, name: &str, command: &str, out: &mut impl Write) -> fmt::Result {
+        let mut cumulative = 0;
+        for (le, bucket) in LATENCY_BUCKETS.iter().zip(&self.buckets) {
+            cumulative += bucket.load(Ordering::Relaxed);
+            writeln!(
+                out,
+                "{name}_bucket{{command=\"{command}\",le=\"{le}\"}} {cumulative}"
+            )?;
+        }
+        let count = self.count.load(Ordering::Relaxed);
+        let sum = Duration::from_nanos(self.sum_nanos.load(Ordering::Relaxed)).as_secs_f64();
+        writeln!(
+            out,
+            "{name}_bucket{{command=\"{command}\",le=\"+Inf\"}} {count}"
+        )?;
+        writeln!(out, "{name}_sum{{command=\"{command}\"}} {sum}")?;
+        writeln!(out, "{name}_count{{command=\"{command}\"}} {count}")
+    }
+}
+
+fn counter(out: &mut impl Write, name: &str, help: &str, value: impl fmt::Display) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} counter")?;
+    writeln!(out, "{name} {value}")
+}
+
+fn gauge(out: &mut impl Write, name: &str, help: &str, value: impl fmt::Display) -> fmt::Result {
+    writeln!(out, "# HELP {name} {help}")?;
+    writeln!(out, "# TYPE {name} gauge")?;
+    writeln!(out, "{name} {value}")
+}
+
+/// A label value for the code, which is stable unlike its `Display` text.
+fn error_kind(code: ErrorCode) -> &'static str {
+    match code {
+        ErrorCode::BadRequest => "bad_request",
+        ErrorCode::Unauthorized => "unauthorized",
+        ErrorCode::Forbidden => "forbidden",
+        ErrorCode::NotFound => "not_found",
+        ErrorCode::TooLarge => "too_large",
+        ErrorCode::Internal => "internal",
+        ErrorCode::Busy => "busy",
+        ErrorCode::Storage => "storage",
+        ErrorCode::Unknown(_) => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use kvs::StoreStats;
+
+    use super::*;
+
+    #[test]
+    fn renders_prometheus_text() {
+        let metrics = Metrics::default();
+        let set = CommandKind::of(&Cmd::Set("key".into(), "value".into())).unwrap();
+        metrics.record_command(set, Duration::from_micros(300), &Response::SuccessfulSet);
+        metrics.record_command(set, Duration::from_secs(2), &Response::SuccessfulSet);
+        let not_found = Response::Err(ErrorCode::NotFound, "Key not found".into());
+        metrics.record_command(CommandKind::Rm, Duration::from_micros(50), &not_found);
+        metrics.record_read(10);
+        metrics.record_written(3);
+        let _connection = metrics.connection_opened();
+        drop(metrics.connection_opened());
+
+        let engine = EngineStats {
+            live_keys: 4,
+            disk_bytes: Some(4096),
+            store: Some(StoreStats {
+                live_keys: 4,
+                files: 2,
+                dead_bytes: 128,
+                disk_bytes: 4096,
+                compactions: 1,
+                compaction_time: Duration::from_millis(1500),
+                syncs: 3,
+            }),
+        };
+        let mut out = String::new();
+        metrics.render(&engine, &mut out).unwrap();
+
+        let expected = [
+            "kvs_commands_total{command=\"set\"} 2",
+            "kvs_commands_total{command=\"rm\"} 1",
+            "kvs_command_duration_seconds_bucket{command=\"set\",le=\"0.00025\"} 0",
+            "kvs_command_duration_seconds_bucket{command=\"set\",le=\"0.0005\"} 1",
+            "kvs_command_duration_seconds_bucket{command=\"set\",le=\"1\"} 1",
+            "kvs_command_duration_seconds_bucket{command=\"set\",le=\"+Inf\"} 2",
+            "kvs_command_duration_seconds_sum{command=\"set\"} 2.0003",
+            "kvs_command_duration_seconds_count{command=\"get\"} 0",
+            "kvs_errors_total{code=\"404\",kind=\"not_found\"} 1",
+            "kvs_open_connections 1",
+            "kvs_read_bytes_total 10",
+            "kvs_written_bytes_total 3",
+            "kvs_engine_live_keys 4",
+            "kvs_engine_files 2",
+            "kvs_engine_dead_bytes 128",
+            "kvs_engine_compactions_total 1",
+            "kvs_engine_compaction_seconds_total 1.5",
+            "kvs_engine_syncs_total 3",
+        ];
+        let lines: Vec<_> = out.lines().collect();
+        for line in expected {
+            assert!(lines.contains(&line), "missing {line:?} in:\n{out}");
+        }
+    }
+}
@@ -0,0 +1,136 @@
+//! A [`MetricsEndpoint`] serves a server's [`Metrics`][super::Metrics] over HTTP for Prometheus to
+//! scrape.
+
+use std::io::BufReader;
+use std::net::{SocketAddr, TcpListener, TcpStream};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::{self, JoinHandle};
+use std::time::Duration;
+
+use anyhow::{anyhow, Context, Result};
+use protocol::Limits;
+use tracing::{debug, warn};
+
+use crate::http::{self, Request};
+use crate::server::{wake_addr, Shared, ACCEPT_BACKOFF};
+
+/// How long a scrape can take to send its request before it's dropped, so a stuck client can't
+/// block other scrapes.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(5);
+
+/// Serves `GET /metrics` on a background thread until it's shut down. Scrapes are handled one at
+/// a time, which is plenty for a monitoring system polling every few seconds.
+pub(crate) struct MetricsEndpoint {
+    local_addr: SocketAddr,
+    shutting_down: Arc<AtomicBool>,
+    thread: JoinHandle<()>,
+}
+
+impl MetricsEndpoint {
+    pub(crate) fn bind(addr: SocketAddr, shared: Arc<Shared>) -> Result<Self> {
+        let listener = TcpListener::bind(addr).context("Failed to bind metrics address")?;
+        let local_addr = listener.local_addr()?;
+        debug!(?local_addr, "Metrics endpoint bound");
+
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("kvs-metrics".to_owned())
+            .spawn({
+                let shutting_down = Arc::clone(&shutting_down);
+                move || Self::serve(listener, &shared, &shutting_down)
+            })
+            .context("Failed to spawn metrics thread")?;
+
+        Ok(Self {
+            local_addr,
+            shutting_down,
+            thread,
+        })
+    }
+
+    /// The address the endpoint is listening on.
+    pub(crate) fn local_addr(&self) -> SocketAddr {
+        self.local_addr
+    }
+
+    /// Stops accepting scrapes and waits for the endpoint's thread to finish.
+    pub(crate) fn shutdown(self) -> Result<()> {
+        self.shutting_down.store(true, Ordering::SeqCst);
+        if let Err(e) = TcpStream::connect(wake_addr(self.local_addr)) {
+            warn!(?e, "Failed to wake metrics endpoint");
+        }
+        self.thread
+            .join()
+            .map_err(|_| anyhow!("Metrics thread panicked"))
+    }
+
+    fn serve(listener: TcpListener, shared: &Shared, shutting_down: &AtomicBool) {
+        for stream in listener.incoming() {
+            if shutting_down.load(Ordering::SeqCst) {
+                break;
+            }
+            let stream = match stream {
+                Ok(stream) => stream,
+                Err(e) => {
+                    warn!(?e, "Failed to accept connection");
+                    thread::sleep(ACCEPT_BACKOFF);
+                    continue;
+                }
+            };
+            if let Err(e) = Self::handle_scrape(stream, shared) {
+                warn!(?e, "Failed to serve metrics");
+            }
+        }
+    }
+
+    fn handle_scrape(stream: TcpStream, shared: &Shared) -> Result<()> {
+        stream.set_read_timeout(Some(REQUEST_TIMEOUT))?;
+        // Scrapes don't have a body.
+        let limits = Limits {
+            max_value_bytes: 0,
+            ..Limits::default()
+        };
+        let Some(request) = Request::read(&mut BufReader::new(&stream), &limits)? else {
+            return Ok(());
+        };
+
+        match (request.method.as_str(), request.path.as_str()) {
+            ("GET", "/metrics") => {}
+            (_, "/metrics") => {
+                return http::write_response(&stream, 405, "Method Not Allowed", "text/plain", b"")
+            }
+            _ => return http::write_response(&stream, 404, "Not Found", "text/plain", b""),
+        }
+
+        let stats = shared
+            .engine
+            .lock()
+            .map_err(|_| anyhow!("Engine lock poisoned"))
+            .and_then(|engine| engine.stats());
+        let stats = match stats {
+            Ok(stats) => stats,
+            Err(e) => {
+                warn!(?e, "Failed to collect engine stats");
+                let body = format!("{e:#}");
+                return http::write_response(
+                    &stream,
+                    500,
+                    "Internal Server Error",
+                    "text/plain",
+                    body.as_bytes(),
+                );
+            }
+        };
+
+        let mut body = String::new();
+        shared.metrics.render(&stats, &mut body)?;
+        http::write_response(
+            &stream,
+            200,
+            "OK",
+            "text/plain; version=0.0.4",
+            body.as_bytes(),
+        )
+    }
+}
@@ -0,0 +1,161 @@
+//! Moving data from one [`Engine`] into another, e.g. to switch a directory's engine type.
+
+use std::path::Path;
+
+use anyhow::{bail, ensure, Context, Result};
+use kvs::KvsEngine;
+use tracing::{debug, info};
+
+use crate::{Engine, EngineType};
+
+/// Copies every live key from `from` into `to`, which must not contain any data yet and can't be
+/// a memory engine, which would drop the data. Returns the number of keys copied.
+///
+/// The keys are listed up front and then copied one at a time, so only a single value is held in
+/// memory at once alongside the list of keys. Once every key is copied, the number of keys in `to`
+/// is checked against the number read out of `from`.
+pub fn migrate(from: &mut Engine, to: &mut Engine) -> Result<usize> {
+    check_destination(to.engine_type())?;
+    ensure!(
+        to.keys().context("listing destination keys")?.is_empty(),
+        "Destination already contains data"
+    );
+
+    let keys = from.keys().context("listing source keys")?;
+    info!(
+        keys = keys.len(),
+        from = %from.engine_type(),
+        to = %to.engine_type(),
+        "Migrating keys",
+    );
+
+    let mut copied = 0;
+    for key in keys {
+        // The key may have been removed since listing the keys. There's nothing to copy then.
+        let Some(value) = from.get(&*key).context("reading source value")? else {
+            debug!(?key, "Key removed during migration");
+            continue;
+        };
+        to.set(key, value).context("writing destination value")?;
+        copied += 1;
+    }
+
+    let migrated = to.keys().context("listing destination keys")?.len();
+    ensure!(
+        migrated == copied,
+        "Copied {copied} keys but destination has {migrated}"
+    );
+    info!(copied, "Migrated keys");
+
+    Ok(copied)
+}
+
+/// Opens the engine whose data is in `from_dir`, to migrate it into an engine of type `to_engine`
+/// in `to_dir`.
+///
+/// Unlike [`Engine::new_in`], this never creates an engine, so an empty or mistyped directory is an
+/// error rather than a migration of no keys. Migrating a directory into itself is refused too, as
+/// is migrating into a memory engine.
+pub fn open_source(from_dir: &Path, to_dir: &Path, to_engine: EngineType) -> Result<Engine> {
+    check_destination(to_engine)?;
+    let from = from_dir
+        .canonicalize()
+        .with_context(|| format!("Source directory {} doesn't exist", from_dir.display()))?;
+    if to_dir.exists() && to_dir.canonicalize()? == from {
+        bail!("Source and destination are the same directory");
+    }
+    let Some(engine_type) = Engine::determine_previous_engine(&from)? else {
+        bail!("No engine data found in {}", from_dir.display());
+    };
+    Engine::new_in(Some(engine_type), from)
+}
+
+/// Checks the data can be migrated into an engine of the type. A memory engine only keeps data
+/// until it's closed, so migrating into one would lose every key.
+fn check_destination(engine_type: EngineType) -> Result<()> {
+    ensure!(
+        engine_type != EngineType::Memory,
+        "Can't migrate into a {engine_type} engine, which doesn't keep its data"
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn round_trip(from_type: EngineType, to_type: EngineType) {
+        let from_dir = tempfile::tempdir().unwrap();
+        let to_dir = tempfile::tempdir().unwrap();
+
+        let mut from = Engine::new_in(Some(from_type), from_dir.path()).unwrap();
+        for i in 0..100 {
+            from.set(format!("key{i}"), format!("value{i}")).unwrap();
+        }
+        from.remove("key0").unwrap();
+
+        let mut to = Engine::new_in(Some(to_type), to_dir.path()).unwrap();
+        assert_eq!(migrate(&mut from, &mut to).unwrap(), 99);
+
+        assert_eq!(to.get("key0").unwrap(), None);
+        for i in 1..100 {
+            assert_eq!(
+                to.get(format!("key{i}")).unwrap(),
+                Some(format!("value{i}"))
+            );
+        }
+    }
+
+    #[test]
+    fn kvs_to_sled() {
+        round_trip(EngineType::Kvs, EngineType::Sled);
+    }
+
+    #[test]
+    fn sled_to_kvs() {
+        round_trip(EngineType::Sled, EngineType::Kvs);
+    }
+
+    #[test]
+    fn refuses_non_empty_destination() {
+        let from_dir = tempfile::tempdir().unwrap();
+        let to_dir = tempfile::tempdir().unwrap();
+
+        let mut from = Engine::new_in(Some(EngineType::Kvs), from_dir.path()).unwrap();
+        from.set("key".to_owned(), "value").unwrap();
+        let mut to = Engine::new_in(Some(EngineType::Sled), to_dir.path()).unwrap();
+        to.set("other".to_owned(), "value").unwrap();
+
+        assert!(migrate(&mut from, &mut to).is_err());
+    }
+
+    #[test]
+    fn only_opens_existing_source() {
+        let from_dir = tempfile::tempdir().unwrap();
+        let to_dir = tempfile::tempdir().unwrap();
+
+        let missing = from_dir.path().join("missing");
+        assert!(open_source(&missing, to_dir.path(), EngineType::Sled).is_err());
+        assert!(open_source(from_dir.path(), to_dir.path(), EngineType::Sled).is_err());
+        // Nothing was created in the empty source.
+        assert_eq!(std::fs::read_dir(from_dir.path()).unwrap().count(), 0);
+
+        Engine::new_in(Some(EngineType::Kvs), from_dir.path()).unwrap();
+        assert!(open_source(from_dir.path(), from_dir.path(), EngineType::Sled).is_err());
+        let from = open_source(from_dir.path(), to_dir.path(), EngineType::Sled).unwrap();
+        assert_eq!(from.engine_type(), EngineType::Kvs);
+    }
+
+    #[test]
+    fn refuses_memory_destination() {
+        let from_dir = tempfile::tempdir().unwrap();
+        let to_dir = tempfile::tempdir().unwrap();
+        Engine::new_in(Some(EngineType::Kvs), from_dir.path()).unwrap();
+
+        assert!(open_source(from_dir.path(), to_dir.path(), EngineType::Memory).is_err());
+        let mut from = open_source(from_dir.path(), to_dir.path(), EngineType::Kvs).unwrap();
+        from.set("key".to_owned(), "value").unwrap();
+        let mut to = Engine::new_in(Some(EngineType::Memory), to_dir.path()).unwrap();
+        assert!(migrate(&mut from, &mut to).is_err());
+    }
+}
@@ -0,0 +1,211 @@
+//! Per-client rate limiting, so one client can't keep the server busy for everyone else.
+
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv6Addr};
+use std::sync::{Mutex, PoisonError};
+use std::time::Instant;
+
+/// How many commands each client IP can send. Every IP, or /64 prefix for IPv6, has a bucket of
+/// `burst` tokens, refilled at `per_sec` tokens a second, and each command takes a token. Commands
+/// that find the bucket empty are rejected with [`ErrorCode::Busy`][protocol::ErrorCode::Busy].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub per_sec: u32,
+    pub burst: u32,
+}
+
+impl RateLimit {
+    /// A limit of `per_sec` commands a second, with bursts of up to a second's worth.
+    pub fn per_sec(per_sec: u32) -> Self {
+        Self {
+            per_sec,
+            burst: per_sec,
+        }
+    }
+}
+
+/// Buckets kept before the full ones, of clients that haven't sent commands recently, are pruned.
+/// The next prune waits until the map has doubled, so its cost is spread over the commands that
+/// grew it.
+const PRUNE_AT: usize = 10_000;
+/// Most buckets kept. Beyond it, pruning also evicts the least recently used buckets, until half
+/// are left, so clients spread over many addresses can't grow the map without bound.
+const MAX_BUCKETS: usize = 100_000;
+
+/// Token buckets for each client. IPv6 clients are limited by their /64 prefix, which is usually
+/// a single host or network, since a client can pick any address in it.
+pub(crate) struct RateLimiter {
+    limit: RateLimit,
+    /// Most buckets kept, which is [`MAX_BUCKETS`] outside of tests.
+    max_buckets: usize,
+    buckets: Mutex<Buckets>,
+}
+
+struct Buckets {
+    map: HashMap<IpAddr, Bucket>,
+    /// How many buckets there can be before a new client prunes them.
+    prune_at: usize,
+}
+
+struct Bucket {
+    tokens: f64,
+    /// When the bucket was last refilled, which is when its client last sent a command.
+    refilled: Instant,
+}
+
+impl RateLimiter {
+    pub(crate) fn new(limit: RateLimit) -> Self {
+        Self::with_max_buckets(limit, MAX_BUCKETS)
+    }
+
+    fn with_max_buckets(limit: RateLimit, max_buckets: usize) -> Self {
+        Self {
+            limit,
+            max_buckets,
+            buckets: Mutex::new(Buckets {
+                map: HashMap::new(),
+                prune_at: PRUNE_AT.min(max_buckets),
+            }),
+        }
+    }
+
+    /// Takes a token from the IP's bucket, returning whether there was one.
+    pub(crate) fn try_acquire(&self, ip: IpAddr) -> bool {
+        self.try_acquire_at(ip, Instant::now())
+    }
+
+    fn try_acquire_at(&self, ip: IpAddr, now: Instant) -> bool {
+        let key = client_key(ip);
+        // Every operation leaves the map valid, so it's fine to use after a panic.
+        let mut buckets = self.buckets.lock().unwrap_or_else(PoisonError::into_inner);
+        if buckets.map.len() >= buckets.prune_at && !buckets.map.contains_key(&key) {
+            self.prune(&mut buckets, now);
+        }
+
+        let bucket = buckets.map.entry(key).or_insert(Bucket {
+            tokens: f64::from(self.limit.burst),
+            refilled: now,
+        });
+        if self.refill(bucket, now) < 1.0 {
+            return false;
+        }
+        bucket.tokens -= 1.0;
+        true
+    }
+
+    /// Removes the full buckets, and if that leaves more than half the most buckets allowed, the
+    /// least recently used ones until half are left.
+    fn prune(&self, buckets: &mut Buckets, now: Instant) {
+        let burst = f64::from(self.limit.burst);
+        buckets
+            .map
+            .retain(|_, bucket| self.tokens(bucket, now) < burst);
+
+        let keep = self.max_buckets / 2;
+        if buckets.map.len() > keep {
+            let mut refilled: Vec<_> = buckets.map.values().map(|bucket| bucket.refilled).collect();
+            let evict = refilled.len() - keep;
+            let (_, &mut newest_evicted, _) = refilled.select_nth_unstable(evict - 1);
+            buckets
+                .map
+                .retain(|_, bucket| bucket.refilled > newest_evicted);
+        }
+        buckets.prune_at =
+            (buckets.map.len() * 2).clamp(PRUNE_AT.min(self.max_buckets), self.max_buckets);
+    }
+
+    /// How many tokens the bucket has, counting the ones earned since it was last refilled.
+    fn tokens(&self, bucket: &Bucket, now: Instant) -> f64 {
+        let elapsed = now.saturating_duration_since(bucket.refilled).as_secs_f64();
+        (bucket.tokens + elapsed * f64::from(self.limit.per_sec)).min(f64::from(self.limit.burst))
+    }
+
+    /// Adds the tokens earned since the bucket was last refilled, returning how many it has.
+    fn refill(&self, bucket: &mut Bucket, now: Instant) -> f64 {
+        bucket.tokens = self.tokens(bucket, now);
+        bucket.refilled = now;
+        bucket.tokens
+    }
+}
+
+/// The key of the bucket an IP's commands are counted in: the IP itself, or its /64 prefix for
+/// IPv6. IPv4 addresses mapped into IPv6 count as the IPv4 address.
+fn client_key(ip: IpAddr) -> IpAddr {
+    match ip.to_canonical() {
+        IpAddr::V6(ip) => IpAddr::V6(Ipv6Addr::from(u128::from(ip) & !(u128::MAX >> 64))),
+        ip => ip,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::net::Ipv4Addr;
+    use std::time::Duration;
+
+    use super::*;
+
+    #[test]
+    fn limits_each_ip() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_sec: 10,
+            burst: 2,
+        });
+        let (a, b) = ("10.0.0.1".parse().unwrap(), "10.0.0.2".parse().unwrap());
+        let start = Instant::now();
+
+        assert!(limiter.try_acquire_at(a, start));
+        assert!(limiter.try_acquire_at(a, start));
+        assert!(!limiter.try_acquire_at(a, start));
+        assert!(limiter.try_acquire_at(b, start));
+
+        // A token is earned every 100ms, and no more than the burst are saved up.
+        assert!(!limiter.try_acquire_at(a, start + Duration::from_millis(50)));
+        assert!(limiter.try_acquire_at(a, start + Duration::from_millis(150)));
+        let later = start + Duration::from_secs(10);
+        assert!(limiter.try_acquire_at(a, later));
+        assert!(limiter.try_acquire_at(a, later));
+        assert!(!limiter.try_acquire_at(a, later));
+    }
+
+    #[test]
+    fn limits_ipv6_by_prefix() {
+        let limiter = RateLimiter::new(RateLimit {
+            per_sec: 1,
+            burst: 1,
+        });
+        let now = Instant::now();
+        let ip = |ip: &str| ip.parse().unwrap();
+
+        assert!(limiter.try_acquire_at(ip("2001:db8::1"), now));
+        assert!(!limiter.try_acquire_at(ip("2001:db8::ffff:2"), now));
+        assert!(limiter.try_acquire_at(ip("2001:db8:0:1::1"), now));
+        assert!(limiter.try_acquire_at(ip("10.0.0.1"), now));
+        assert!(!limiter.try_acquire_at(ip("::ffff:10.0.0.1"), now));
+    }
+
+    #[test]
+    fn caps_buckets() {
+        let limiter = RateLimiter::with_max_buckets(
+            RateLimit {
+                per_sec: 1,
+                burst: 2,
+            },
+            8,
+        );
+        let start = Instant::now();
+        let ip = |i: u32| IpAddr::from(Ipv4Addr::from(i));
+        let at = |i: u32| start + Duration::from_millis(u64::from(i));
+
+        // None of the buckets are full, so the least recently used are evicted instead.
+        for i in 0..100 {
+            assert!(limiter.try_acquire_at(ip(i), at(i)));
+            assert!(limiter.buckets.lock().unwrap().map.len() <= 8);
+        }
+        let recent = at(100);
+        assert!(limiter.try_acquire_at(ip(99), recent));
+        assert!(!limiter.try_acquire_at(ip(99), recent));
+        // An evicted client starts over with a full bucket.
+        assert!(limiter.try_acquire_at(ip(0), recent));
+        assert!(limiter.try_acquire_at(ip(0), recent));
+    }
+}
@@ -0,0 +1,537 @@
+//! A front end speaking RESP2, the protocol of Redis, so Redis client libraries and tools like
+//! `redis-cli` can use the server without a custom client.
+//!
+//! `GET`, `SET`, `DEL`, `EXISTS`, `PING`, `INFO`, `AUTH` and `QUIT` are supported, and run through
+//! the same authentication, authorization and metrics as [`Cmd`]s. Other commands, and options like
+//! `SET key value EX 10`, get an error reply. Connections stay open until the client closes them,
+//! sends `QUIT` or is idle for longer than the server's idle timeout.
+
+use anyhow::{ensure, Context, Result};
+use protocol::{Cmd, ErrorCode, Limits, Response, ServerInfo};
+use tracing::{debug, warn};
+
+use crate::frontend;
+use crate::server::{Client, Shared, MAX_AUTH_FAILURES};
+use crate::{Grant, Server};
+
+type Action = frontend::Action<Batch>;
+
+/// Longest inline command, or line of a command, accepted.
+const MAX_LINE_LEN: usize = 64 * 1024;
+
+/// Parses a command from the start of the buffer, either an array of bulk strings or an inline
+/// command of space-separated words. Returns the command's arguments and how many bytes it took
+/// up, or `None` if the buffer doesn't hold a whole command yet. Empty commands are skipped.
+///
+/// Arguments can be no larger than the largest key or value the limits allow, and whole commands
+/// no longer than [`frontend::max_command_len`], which is checked against the lengths the command
+/// claims before the bytes arrive.
+pub(crate) fn decode(buf: &[u8], limits: &Limits) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+    let mut start = 0;
+    loop {
+        let decoded = match buf.get(start) {
+            None => return Ok(None),
+            Some(b'*') => decode_array(&buf[start..], limits)?,
+            Some(_) => decode_inline(&buf[start..])?,
+        };
+        match decoded {
+            Some((args, used)) if args.is_empty() => start += used,
+            Some((args, used)) => return Ok(Some((args, start + used))),
+            None => return Ok(None),
+        }
+    }
+}
+
+fn decode_array(buf: &[u8], limits: &Limits) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+    let max_arg = limits.max_key_bytes.max(limits.max_value_bytes);
+    let max_len = frontend::max_command_len(limits);
+    let Some((count, mut pos)) = read_number(buf, 1)? else {
+        return Ok(None);
+    };
+    // Every argument takes at least the 6 bytes of `$0\r\n\r\n`.
+    ensure!(count <= (max_len / 6) as i64, "invalid multibulk length");
+    let mut args = Vec::with_capacity(count.clamp(0, 64) as usize);
+    for _ in 0..count {
+        let Some(&marker) = buf.get(pos) else {
+            return Ok(None);
+        };
+        ensure!(marker == b'$', "expected '$', got {:?}", char::from(marker));
+        let Some((len, data)) = read_number(buf, pos + 1)? else {
+            return Ok(None);
+        };
+        ensure!(len >= 0, "invalid bulk length");
+        ensure!(
+            len as u64 <= max_arg as u64,
+            "argument is {len} bytes, more than the limit of {max_arg} bytes"
+        );
+        let end = data.saturating_add(len as usize);
+        ensure!(
+            end.saturating_add(2) <= max_len,
+            "command is longer than the limit of {max_len} bytes"
+        );
+        let Some(terminator) = buf.get(end..end + 2) else {
+            return Ok(None);
+        };
+        ensure!(
+            terminator == b"\r\n",
+            "bulk string isn't terminated by CRLF"
+        );
+        args.push(buf[data..end].to_vec());
+        pos = end + 2;
+    }
+    Ok(Some((args, pos)))
+}
+
+fn decode_inline(buf: &[u8]) -> Result<Option<(Vec<Vec<u8>>, usize)>> {
+    let Some(newline) = buf.iter().position(|&b| b == b'\n') else {
+        ensure!(buf.len() <= MAX_LINE_LEN, "too big inline request");
+        return Ok(None);
+    };
+    let args = buf[..newline]
+        .split(|b| b.is_ascii_whitespace())
+        .filter(|word| !word.is_empty())
+        .map(<[u8]>::to_vec)
+        .collect();
+    Ok(Some((args, newline + 1)))
+}
+
+/// Parses a decimal number ending in CRLF, starting at `start`. Returns the number and the position
+/// after the CRLF, or `None` if the line isn't complete yet.
+fn read_number(buf: &[u8], start: usize) -> Result<Option<(i64, usize)>> {
+    let line = &buf[start.min(buf.len())..];
+    let Some(newline) = line.iter().position(|&b| b == b'\n') else {
+        ensure!(line.len() <= MAX_LINE_LEN, "line too long");
+        return Ok(None);
+    };
+    let number = line[..newline]
+        .strip_suffix(b"\r")
+        .and_then(|digits| std::str::from_utf8(digits).ok())
+        .and_then(|digits| digits.parse().ok())
+        .context("invalid length")?;
+    Ok(Some((number, start + newline + 1)))
+}
+
+/// A reply to a command.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub(crate) enum Reply {
+    Simple(&'static str),
+    Error(String),
+    Integer(i64),
+    /// `None` is the null bulk string Redis replies with for missing keys.
+    Bulk(Option<Vec<u8>>),
+}
+
+impl frontend::Reply for Reply {
+    fn write(&self, out: &mut Vec<u8>) {
+        match self {
+            Self::Simple(message) => {
+                out.extend_from_slice(format!("+{message}\r\n").as_bytes());
+            }
+            Self::Error(message) => {
+                // Errors are a single line, so line breaks in messages would corrupt the reply.
+                let message = message.replace(['\r', '\n'], " ");
+                out.extend_from_slice(format!("-{message}\r\n").as_bytes());
+            }
+            Self::Integer(n) => out.extend_from_slice(format!(":{n}\r\n").as_bytes()),
+            Self::Bulk(None) => out.extend_from_slice(b"$-1\r\n"),
+            Self::Bulk(Some(bytes)) => {
+                out.extend_from_slice(format!("${}\r\n", bytes.len()).as_bytes());
+                out.extend_from_slice(bytes);
+                out.extend_from_slice(b"\r\n");
+            }
+        }
+    }
+
+    fn error(code: ErrorCode, message: &str) -> Self {
+        Self::from_error(code, message)
+    }
+}
+
+impl Reply {
+    /// The error reply for an error response. Authentication and authorization errors get the
+    /// same prefixes Redis uses, which clients recognize.
+    fn from_error(code: ErrorCode, message: &str) -> Self {
+        let prefix = match code {
+            ErrorCode::Unauthorized => "NOAUTH",
+            ErrorCode::Forbidden => "NOPERM",
+            _ => "ERR",
+        };
+        Self::Error(format!("{prefix} {message}"))
+    }
+}
+
+/// [`Cmd`]s that carry out a RESP command, and how to reply once they've been executed.
+pub(crate) struct Batch {
+    cmds: Vec<Cmd<'static>>,
+    kind: BatchKind,
+}
+
+enum BatchKind {
+    /// Reply with the message if there is one, otherwise `PONG`.
+    Ping(Option<Vec<u8>>),
+    Get,
+    Set,
+    /// Reply with the number of keys removed.
+    Del,
+    /// Reply with the number of keys that exist.
+    Exists,
+    Info,
+}
+
+impl frontend::Batch for Batch {
+    type Reply = Reply;
+
+    /// Executes the commands in order and replies to the RESP command.
+    fn execute(self, shared: &Shared) -> Reply {
+        let responses: Vec<_> = self
+            .cmds
+            .into_iter()
+            .map(|cmd| Server::handle_cmd(shared, cmd))
+            .collect();
+        // Every batch has at least one command.
+        let first = &responses[0];
+        if let Response::Err(code, message) = first {
+            if !(matches!(self.kind, BatchKind::Del) && *code == ErrorCode::NotFound) {
+                return Reply::from_error(*code, message);
+            }
+        }
+
+        match (self.kind, first) {
+            (BatchKind::Ping(Some(message)), Response::Pong) => Reply::Bulk(Some(message)),
+            (BatchKind::Ping(None), Response::Pong) => Reply::Simple("PONG"),
+            (BatchKind::Get, Response::SuccessfulGet(value)) => {
+                Reply::Bulk(Some(value.as_bytes().to_vec()))
+            }
+            (BatchKind::Get, Response::KeyNotFound) => Reply::Bulk(None),
+            (BatchKind::Set, Response::SuccessfulSet) => Reply::Simple("OK"),
+            (BatchKind::Del, _) => count(&responses, |response| {
+                matches!(response, Response::SuccessfulRm)
+            }),
+            (BatchKind::Exists, _) => count(&responses, |response| {
+                matches!(response, Response::SuccessfulGet(_))
+            }),
+            (BatchKind::Info, Response::Info(info)) => Reply::Bulk(Some(info_text(info))),
+            (_, response) => {
+                warn!(?response, "Unexpected response to RESP command");
+                Reply::Error("ERR Unexpected response from the engine".to_owned())
+            }
+        }
+    }
+}
+
+/// Counts the responses that match, or replies with the first error that isn't a missing key.
+fn count(responses: &[Response], matches: impl Fn(&Response) -> bool) -> Reply {
+    let mut count = 0;
+    for response in responses {
+        match response {
+            Response::Err(code, _) if *code == ErrorCode::NotFound => {}
+            Response::Err(code, message) => return Reply::from_error(*code, message),
+            response if matches(response) => count += 1,
+            _ => {}
+        }
+    }
+    Reply::Integer(count)
+}
+
+/// Describes the server in the `key:value` lines `INFO` replies with in Redis.
+fn info_text(info: &ServerInfo) -> Vec<u8> {
+    let mut text = format!("# Server\r\nkvs_version:{}\r\n", info.version);
+    text.push_str(&format!("engine:{}\r\n", info.engine));
+    text.push_str(&format!("uptime_in_seconds:{}\r\n", info.uptime.as_secs()));
+    text.push_str(&format!("\r\n# Keyspace\r\nkeys:{}\r\n", info.keys));
+    if let Some(disk_usage) = info.disk_usage {
+        text.push_str(&format!("disk_usage_bytes:{disk_usage}\r\n"));
+    }
+    text.into_bytes()
+}
+
+/// The state of a connection: which grant its commands are checked against.
+pub(crate) struct Session<'s> {
+    shared: &'s Shared,
+    client: Client,
+    /// The grant commands are checked against, or why the connection can't run commands.
+    access: Result<&'s Grant, &'static str>,
+    /// How many tokens the connection has presented that were rejected.
+    auth_failures: u32,
+}
+
+impl<'s> Session<'s> {
+    pub(crate) fn new(shared: &'s Shared, client: Client) -> Self {
+        Self {
+            shared,
+            client,
+            access: shared.unauthenticated(),
+            auth_failures: 0,
+        }
+    }
+
+    /// Decides what to do with a command, given its name and arguments.
+    fn start(&mut self, args: Vec<Vec<u8>>) -> Action {
+        let mut args = args.into_iter();
+        let Some(name) = args.next() else {
+            return Action::Reply(Reply::Error("ERR empty command".to_owned()));
+        };
+        let name = String::from_utf8_lossy(&name).to_ascii_lowercase();
+        let args: Vec<_> = args.collect();
+        debug!(command = name, args = args.len(), "Parsed RESP command");
+
+        let action = match (name.as_str(), args.len()) {
+            // The token is the password. Any username is ignored, since the token alone picks the
+            // grant.
+            ("auth", 1 | 2) => Ok(self.auth(&args[args.len() - 1])),
+            ("quit", _) => Ok(Action::Close(Reply::Simple("OK"))),
+            ("ping", 0 | 1) => {
+                let message = args.into_iter().next();
+                self.execute(vec![Cmd::Ping], BatchKind::Ping(message))
+            }
+            ("get", 1) => keys(args).and_then(|mut keys| {
+                self.execute(vec![Cmd::Get(keys.remove(0).into())], BatchKind::Get)
+            }),
+            ("set", 2) => keys(args).and_then(|mut args| {
+                let value = args.remove(1);
+                let key = args.remove(0);
+                self.execute(vec![Cmd::Set(key.into(), value.into())], BatchKind::Set)
+            }),
+            ("set", _) if args.len() > 2 => {
+                Err(Reply::Error("ERR SET options aren't supported".to_owned()))
+            }
+            ("del", 1..) => keys(args).and_then(|keys| {
+                let cmds = keys.into_iter().map(|key| Cmd::Rm(key.into())).collect();
+                self.execute(cmds, BatchKind::Del)
+            }),
+            ("exists", 1..) => keys(args).and_then(|keys| {
+                let cmds = keys.into_iter().map(|key| Cmd::Get(key.into())).collect();
+                self.execute(cmds, BatchKind::Exists)
+            }),
+            // Sections aren't supported, so every section gets the whole description.
+            ("info", 0 | 1) => self.execute(vec![Cmd::Info], BatchKind::Info),
+            ("auth" | "ping" | "get" | "set" | "del" | "exists" | "info", _) => Err(Reply::Error(
+                format!("ERR wrong number of arguments for '{name}' command"),
+            )),
+            _ => Err(Reply::Error(format!("ERR unknown command '{name}'"))),
+        };
+        action.unwrap_or_else(Action::Reply)
+    }
+
+    /// Authenticates the connection with the token. Unlike with [`Cmd::Auth`], a rejected token
+    /// gets an error reply, and the connection keeps the grant it had, like in Redis, until it's
+    /// presented [`MAX_AUTH_FAILURES`] of them and is closed.
+    fn auth(&mut self, token: &[u8]) -> Action {
+        if let Some((reply, close)) = frontend::busy(self.shared, &self.client) {
+            return Action::reply(reply, close);
+        }
+        let grant = std::str::from_utf8(token)
+            .ok()
+            .and_then(|token| self.shared.authenticate(token));
+        match grant {
+            Some(grant) => {
+                debug!(grant = grant.name, "Connection authenticated");
+                self.access = Ok(grant);
+                Action::Reply(Reply::Simple("OK"))
+            }
+            None => {
+                warn!(target: "audit", ip = ?self.client.ip, "Rejected auth token");
+                let response = Shared::unauthorized("Invalid auth token");
+                self.shared.metrics.record_response(&response);
+                self.auth_failures += 1;
+                Action::reply(
+                    Reply::Error(
+                        "WRONGPASS invalid username-password pair or user is disabled.".to_owned(),
+                    ),
+                    self.auth_failures >= MAX_AUTH_FAILURES,
+                )
+            }
+        }
+    }
+
+    /// Executes the commands if the connection's grant allows every one of them.
+    fn execute(&self, cmds: Vec<Cmd<'static>>, kind: BatchKind) -> Result<Action, Reply> {
+        let grant = self.access.map_err(|message| {
+            let response = Shared::unauthorized(message);
+            self.shared.metrics.record_response(&response);
+            Reply::from_error(ErrorCode::Unauthorized, message)
+        })?;
+        for cmd in &cmds {
+            if let Err(Response::Err(code, message)) = self.shared.authorize(grant, cmd) {
+                return Err(Reply::from_error(code, &message));
+            }
+        }
+        Ok(Action::Execute(Batch { cmds, kind }))
+    }
+}
+
+impl frontend::Session for Session<'_> {
+    type Batch = Batch;
+
+    /// A command that can't be parsed gets an error reply and closes the connection, like it does
+    /// in Redis, since what follows it can't be trusted to be the start of a command.
+    fn next_action(&mut self, buf: &mut Vec<u8>) -> Option<Action> {
+        match decode(buf, &self.shared.options.limits) {
+            Ok(Some((args, used))) => {
+                buf.drain(..used);
+                Some(self.start(args))
+            }
+            Ok(None) => None,
+            Err(e) => {
+                warn!(?e, "Failed to parse command");
+                buf.clear();
+                Some(Action::Close(Reply::Error(format!(
+                    "ERR Protocol error: {e:#}"
+                ))))
+            }
+        }
+    }
+}
+
+/// Keys and values are strings in the engine, so they have to be UTF-8.
+fn keys(args: Vec<Vec<u8>>) -> Result<Vec<String>, Reply> {
+    args.into_iter()
+        .map(|arg| {
+            String::from_utf8(arg)
+                .map_err(|_| Reply::Error("ERR keys and values must be UTF-8".to_owned()))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::test_util::{args, auth_options, client, reply, shared, written};
+    use crate::{RateLimit, ServerOptions};
+
+    #[test]
+    fn decodes_commands() {
+        let limits = Limits::default();
+        let buf = b"*2\r\n$3\r\nGET\r\n$3\r\nkey\r\n*1\r\n$4\r\nPI";
+        let (decoded, used) = decode(buf, &limits).unwrap().unwrap();
+        assert_eq!(decoded, args(&["GET", "key"]));
+        assert_eq!(decode(&buf[used..], &limits).unwrap(), None);
+
+        let (decoded, used) = decode(b"*0\r\n\r\nset  a b\r\n", &limits).unwrap().unwrap();
+        assert_eq!(decoded, args(&["set", "a", "b"]));
+        assert_eq!(used, 16);
+
+        let (decoded, _) = decode(b"*1\r\n$0\r\n\r\n", &limits).unwrap().unwrap();
+        assert_eq!(decoded, args(&[""]));
+
+        assert!(decode(b"*1\r\n:3\r\n", &limits).is_err());
+        assert!(decode(b"*1\r\n$-5\r\n", &limits).is_err());
+        assert!(decode(b"*1\r\n$3\r\nkeyXX", &limits).is_err());
+        assert!(decode(b"*x\r\n", &limits).is_err());
+        assert!(decode(&vec![b'a'; MAX_LINE_LEN + 1], &limits).is_err());
+    }
+
+    #[test]
+    fn limits_commands() {
+        let limits = Limits {
+            max_key_bytes: 3,
+            max_value_bytes: 5,
+        };
+        let (decoded, _) = decode(b"*2\r\n$3\r\nkey\r\n$5\r\nvalue\r\n", &limits)
+            .unwrap()
+            .unwrap();
+        assert_eq!(decoded, args(&["key", "value"]));
+        // Too large before any of the argument arrives.
+        assert!(decode(b"*1\r\n$6\r\n", &limits).is_err());
+        assert!(decode(b"*1\r\n$9999999999\r\n", &Limits::NONE).is_ok());
+
+        let max_len = frontend::max_command_len(&limits);
+        let count = format!("*{}\r\n", max_len / 6 + 1);
+        assert!(decode(count.as_bytes(), &limits).is_err());
+        // Many arguments within the limit on each can still add up to too long a command.
+        let mut buf = format!("*{}\r\n", max_len / 6).into_bytes();
+        while buf.len() <= max_len {
+            buf.extend_from_slice(b"$1\r\na\r\n");
+        }
+        assert!(decode(&buf, &limits).is_err());
+    }
+
+    #[test]
+    fn writes_replies() {
+        assert_eq!(written(Reply::Simple("OK")), "+OK\r\n");
+        assert_eq!(written(Reply::Error("ERR a\r\nb".into())), "-ERR a  b\r\n");
+        assert_eq!(written(Reply::Integer(2)), ":2\r\n");
+        assert_eq!(written(Reply::Bulk(None)), "$-1\r\n");
+        assert_eq!(
+            written(Reply::Bulk(Some(b"value".to_vec()))),
+            "$5\r\nvalue\r\n"
+        );
+    }
+
+    #[test]
+    fn maps_commands_onto_engine() {
+        let (shared, _dir) = shared(ServerOptions::default());
+        let mut session = Session::new(&shared, client());
+        let mut run = |words: &[&str]| reply(&shared, session.start(args(words)));
+
+        assert_eq!(run(&["PING"]), Reply::Simple("PONG"));
+        assert_eq!(run(&["ping", "hi"]), Reply::Bulk(Some(b"hi".to_vec())));
+        assert_eq!(run(&["SET", "a", "1"]), Reply::Simple("OK"));
+        assert_eq!(run(&["set", "b", "2"]), Reply::Simple("OK"));
+        assert_eq!(run(&["GET", "a"]), Reply::Bulk(Some(b"1".to_vec())));
+        assert_eq!(run(&["GET", "missing"]), Reply::Bulk(None));
+        assert_eq!(run(&["EXISTS", "a", "b", "missing"]), Reply::Integer(2));
+        assert_eq!(run(&["DEL", "a", "missing"]), Reply::Integer(1));
+        assert_eq!(run(&["EXISTS", "a"]), Reply::Integer(0));
+        assert!(matches!(run(&["INFO"]), Reply::Bulk(Some(info)) if info.starts_with(b"# Server")));
+        assert_eq!(
+            run(&["GET"]),
+            Reply::Error("ERR wrong number of arguments for 'get' command".into())
+        );
+        assert_eq!(
+            run(&["SET", "a", "1", "EX", "10"]),
+            Reply::Error("ERR SET options aren't supported".into())
+        );
+        assert_eq!(
+            run(&["LPUSH", "list", "a"]),
+            Reply::Error("ERR unknown command 'lpush'".into())
+        );
+        assert_eq!(run(&["QUIT"]), Reply::Simple("OK"));
+    }
+
+    #[test]
+    fn requires_auth() {
+        let (shared, _dir) = shared(auth_options());
+        let mut session = Session::new(&shared, client());
+        let mut run = |words: &[&str]| reply(&shared, session.start(args(words)));
+
+        assert_eq!(
+            run(&["GET", "a"]),
+            Reply::Error("NOAUTH Authentication required".into())
+        );
+        assert!(matches!(run(&["AUTH", "wrong"]), Reply::Error(e) if e.starts_with("WRONGPASS")));
+        assert_eq!(run(&["AUTH", "default", "secret"]), Reply::Simple("OK"));
+        assert_eq!(run(&["GET", "a"]), Reply::Bulk(None));
+
+        // The third rejected token closes the connection.
+        assert!(matches!(
+            session.start(args(&["AUTH", "wrong"])),
+            Action::Reply(_)
+        ));
+        assert!(matches!(
+            session.start(args(&["AUTH", "wrong"])),
+            Action::Close(_)
+        ));
+    }
+
+    #[test]
+    fn rate_limits_auth() {
+        let (shared, _dir) = shared(ServerOptions {
+            rate_limit: Some(RateLimit {
+                per_sec: 1,
+                burst: 1,
+            }),
+            ..auth_options()
+        });
+        let mut session = Session::new(&shared, client());
+
+        assert!(matches!(
+            session.start(args(&["AUTH", "wrong"])),
+            Action::Reply(_)
+        ));
+        assert!(matches!(
+            session.start(args(&["AUTH", "secret"])),
+            Action::Reply(Reply::Error(e)) if e.starts_with("ERR Too many")
+        ));
+    }
+}
@@ -0,0 +1,37 @@
+//! The kinds of runtime a server can handle connections on.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+
+/// Represents how a server handles connections.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Runtime {
+    /// Blocking I/O with connections handled on a [`Pool`][crate::Pool]. See
+    /// [`Server`][crate::Server].
+    Sync,
+    /// Non-blocking I/O on an async runtime. See [`AsyncServer`][crate::AsyncServer].
+    Async,
+}
+
+impl fmt::Display for Runtime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Sync => f.write_str("sync"),
+            Self::Async => f.write_str("async"),
+        }
+    }
+}
+
+impl FromStr for Runtime {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "sync" => Ok(Self::Sync),
+            "async" => Ok(Self::Async),
+            other => Err(anyhow!("unknown runtime {other:?}")),
+        }
+    }
+}
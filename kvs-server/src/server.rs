@@ -1,76 +1,649 @@
 //! Server to receive requests at an address and handle them with a specified [`Engine`].
 
-use std::io::Write;
-use std::net::{SocketAddr, TcpListener};
+use std::fmt;
+use std::io::{self, Write};
+use std::net::{IpAddr, SocketAddr, TcpListener};
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+use std::thread;
+use std::time::{Duration, Instant};
 
-use anyhow::{Context, Result};
-use kvs::KvsEngine;
-use protocol::{Cmd, Reader, Response};
+use anyhow::{anyhow, ensure, Context, Error, Result};
+use kvs::{KeyNotFound, KvsEngine};
+use protocol::{Cmd, ErrorCode, Features, Hello, Limits, Reader, Response, ServerInfo, TooLarge};
 use tracing::{debug, info, warn};
 
-use crate::Engine;
+use crate::acl::cmd_key;
+use crate::frontend;
+use crate::gateway::HttpGateway;
+use crate::metrics::{CommandKind, Metrics, MetricsEndpoint};
+use crate::rate_limit::RateLimiter;
+use crate::thread_pool::Pool;
+use crate::{memcached, resp};
+use crate::{Acl, AuthToken, Engine, Grant, RateLimit, ServerTls, UnixSocket};
+
+pub use handle::ServerHandle;
+pub(crate) use handle::{wake_addr, ShutdownTrigger};
+
+mod connections;
+mod handle;
+mod session;
+mod stream;
+
+pub(crate) use connections::Counter;
+use connections::{ConnectionGuard, Connections, Counted};
+pub(crate) use session::{Action, Session};
+pub(crate) use stream::{Deadline, ReadTimeout};
+use stream::{Listener, Socket, Stream};
+
+/// Options for how a server handles connections.
+#[derive(Debug, Clone)]
+pub struct ServerOptions {
+    /// How long a connection kept alive with [`Cmd::KeepAlive`] can go without sending a command
+    /// before the server closes it. A [`Server`] handles new connections on threads outside its
+    /// pool while every pool thread waits on an idle connection, so they don't wait this long.
+    pub idle_timeout: Duration,
+    /// How long a client has to send the rest of a command once it starts sending it, and how
+    /// long a new connection can wait before it starts. Clients that are too slow are
+    /// disconnected, so they can't hold connections open by trickling bytes.
+    pub read_timeout: Duration,
+    /// Most connections to handle at once. Connections over the limit get an
+    /// [`ErrorCode::Busy`] response to their first command and are closed. The HTTP gateway holds
+    /// its own connections to the limit separately. `None` doesn't limit them.
+    pub max_connections: Option<usize>,
+    /// How many commands each client IP can send, counting attempts to authenticate. Connections
+    /// over a Unix socket aren't limited. `None` doesn't limit them.
+    pub rate_limit: Option<RateLimit>,
+    /// The largest keys and values clients can send, over any protocol or the HTTP gateway.
+    /// Commands over them are rejected with [`ErrorCode::TooLarge`], before the server allocates
+    /// memory for them.
+    pub limits: Limits,
+    /// Address to serve Prometheus metrics on, at `/metrics`. `None` doesn't serve metrics.
+    pub metrics_addr: Option<SocketAddr>,
+    /// Address to serve the HTTP gateway on, with keys at `/keys/{key}`. `None` doesn't serve it.
+    pub http_addr: Option<SocketAddr>,
+    /// Token connections must present with [`Cmd::Auth`] before their commands are accepted.
+    /// `None` accepts commands from any connection, unless there's an ACL. The token is given
+    /// full access.
+    pub auth_token: Option<AuthToken>,
+    /// Tokens connections can present with [`Cmd::Auth`], and the commands and keys each one is
+    /// allowed to use.
+    pub acl: Option<Acl>,
+    /// Certificate and key to serve connections over TLS with. `None` serves plain TCP.
+    pub tls: Option<ServerTls>,
+    /// Whether to listen on the server's TCP address. Only a server with a Unix socket can turn
+    /// this off.
+    pub tcp: bool,
+    /// Unix socket to listen on too, for clients on the same host. Connections over it never use
+    /// TLS.
+    pub unix_socket: Option<UnixSocket>,
+    /// The protocol clients speak on every connection.
+    pub protocol: Protocol,
+}
+
+impl Default for ServerOptions {
+    fn default() -> Self {
+        Self {
+            idle_timeout: Duration::from_secs(60),
+            read_timeout: Duration::from_secs(10),
+            max_connections: None,
+            rate_limit: None,
+            limits: Limits::default(),
+            metrics_addr: None,
+            http_addr: None,
+            auth_token: None,
+            acl: None,
+            tls: None,
+            tcp: true,
+            unix_socket: None,
+            protocol: Protocol::Kvs,
+        }
+    }
+}
+
+/// The protocols a server can speak to clients.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Protocol {
+    /// The binary protocol of [`Cmd`] and [`Response`], which `kvs-client` speaks.
+    Kvs,
+    /// RESP2, the protocol of Redis, so Redis clients and tools like `redis-cli` can connect.
+    /// Only the commands with an equivalent [`Cmd`] are supported.
+    Resp,
+    /// Memcached's text protocol, so memcached clients can connect. Only the storage and retrieval
+    /// commands are supported, without flags or expiration.
+    Memcached,
+}
+
+impl fmt::Display for Protocol {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Kvs => f.write_str("kvs"),
+            Self::Resp => f.write_str("resp"),
+            Self::Memcached => f.write_str("memcached"),
+        }
+    }
+}
+
+impl FromStr for Protocol {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "kvs" => Ok(Self::Kvs),
+            "resp" => Ok(Self::Resp),
+            "memcached" => Ok(Self::Memcached),
+            other => Err(anyhow!("unknown protocol {other:?}")),
+        }
+    }
+}
+
+/// Most rejected auth tokens a connection can present before it's closed, so guessing tokens
+/// takes a new connection every few guesses.
+pub(crate) const MAX_AUTH_FAILURES: u32 = 3;
+
+/// How long to wait after failing to accept a connection before accepting again, so running out
+/// of file descriptors doesn't spin the accept loop.
+pub(crate) const ACCEPT_BACKOFF: Duration = Duration::from_millis(50);
+
+/// Most connections a [`Server`] handles at once on threads outside its pool: connections over
+/// capacity, which get a busy response without waiting for the pool, and connections accepted
+/// while every pool thread is waiting on an idle connection.
+const MAX_EXTRA_THREADS: usize = 16;
+
+/// State shared by every connection to a server.
+pub(crate) struct Shared {
+    pub(crate) engine: Mutex<Engine>,
+    pub(crate) options: ServerOptions,
+    pub(crate) metrics: Metrics,
+    /// Grant for connections authenticated with the auth token, or every connection if the server
+    /// doesn't require authentication.
+    full_access: Grant,
+    rate_limiter: Option<RateLimiter>,
+    /// Pool threads of a [`Server`] waiting for a client to start sending a command.
+    idle_threads: Counter,
+    /// When the server was created, to report its uptime.
+    pub(crate) started: Instant,
+}
+
+impl Shared {
+    pub(crate) fn new(engine: Engine, options: ServerOptions) -> Self {
+        Self {
+            engine: Mutex::new(engine),
+            rate_limiter: options.rate_limit.map(RateLimiter::new),
+            options,
+            metrics: Metrics::default(),
+            full_access: Grant::full_access("full-access"),
+            idle_threads: Counter::default(),
+            started: Instant::now(),
+        }
+    }
+
+    /// The grant of a connection that hasn't authenticated, or why it can't issue commands if the
+    /// server requires authentication.
+    pub(crate) fn unauthenticated(&self) -> Result<&Grant, &'static str> {
+        if self.options.auth_token.is_some() || self.options.acl.is_some() {
+            Err("Authentication required")
+        } else {
+            Ok(&self.full_access)
+        }
+    }
+
+    /// The grant for the token presented by a connection, or `None` if the token isn't valid. Any
+    /// token gets full access if the server doesn't require authentication.
+    pub(crate) fn authenticate(&self, token: &str) -> Option<&Grant> {
+        if self.unauthenticated().is_ok() {
+            return Some(&self.full_access);
+        }
+        let auth_token = self.options.auth_token.as_ref();
+        if auth_token.is_some_and(|auth_token| auth_token.verify(token)) {
+            return Some(&self.full_access);
+        }
+        self.options.acl.as_ref()?.authenticate(token)
+    }
+
+    /// Checks that the grant allows the command before it's executed. Denied commands are logged
+    /// to the "audit" target and get a forbidden response, which is counted in the metrics.
+    pub(crate) fn authorize(&self, grant: &Grant, cmd: &Cmd) -> Result<(), Response<'static>> {
+        if grant.allows(cmd) {
+            return Ok(());
+        }
+        warn!(
+            target: "audit",
+            grant = grant.name,
+            command = CommandKind::of(cmd).map(CommandKind::label),
+            key = cmd_key(cmd),
+            "Denied command"
+        );
+        let response = Response::Err(
+            ErrorCode::Forbidden,
+            format!("The {:?} grant doesn't allow this command", grant.name).into(),
+        );
+        self.metrics.record_response(&response);
+        Err(response)
+    }
+
+    /// Agrees on a protocol version and features with the hello a client sent, or the error
+    /// response if they can't agree. A hello has to be the first command on a connection.
+    pub(crate) fn greet(&self, hello: &Hello, first: bool) -> Result<Hello, Response<'static>> {
+        let mut features = Features::FRAMING;
+        if self.unauthenticated().is_err() {
+            features = features.union(Features::AUTH);
+        }
+        let agreed = if first {
+            Hello::new(features).agree(hello).map_err(|e| e.to_string())
+        } else {
+            Err("Hello must be the first command on a connection".to_owned())
+        };
+        agreed.map_err(|message| {
+            let response = Response::Err(ErrorCode::BadRequest, message.into());
+            self.metrics.record_response(&response);
+            response
+        })
+    }
+
+    /// The response to a command that isn't authenticated.
+    pub(crate) fn unauthorized(message: &'static str) -> Response<'static> {
+        Response::Err(ErrorCode::Unauthorized, message.into())
+    }
+
+    /// Checks whether the server will run a command from the client now, or why it's too busy to.
+    /// Every call counts against the client's rate limit.
+    pub(crate) fn admit(&self, client: &Client) -> Result<(), &'static str> {
+        let message = if client.over_capacity {
+            "The server has too many connections, try again later"
+        } else {
+            match (&self.rate_limiter, client.ip) {
+                (Some(rate_limiter), Some(ip)) if !rate_limiter.try_acquire(ip) => {
+                    warn!(%ip, "Client rate limited");
+                    "Too many commands, try again later"
+                }
+                _ => return Ok(()),
+            }
+        };
+        self.metrics.record_response(&Self::busy(message));
+        Err(message)
+    }
+
+    /// The response to a command the server is too busy to run.
+    pub(crate) fn busy(message: &'static str) -> Response<'static> {
+        Response::Err(ErrorCode::Busy, message.into())
+    }
+
+    /// Whether a new connection would be over the connection limit, with `open` connections,
+    /// including the new one.
+    pub(crate) fn over_capacity(&self, open: usize) -> bool {
+        self.options.max_connections.is_some_and(|max| open > max)
+    }
+
+    /// Checks the server has somewhere to listen for connections.
+    pub(crate) fn check_listeners(&self) -> Result<()> {
+        ensure!(
+            self.options.tcp || self.options.unix_socket.is_some(),
+            "The server needs a TCP address or a Unix socket to listen on"
+        );
+        Ok(())
+    }
+
+    /// Starts serving metrics if the options ask for it.
+    pub(crate) fn bind_metrics(self: &Arc<Self>) -> Result<Option<MetricsEndpoint>> {
+        self.options
+            .metrics_addr
+            .map(|addr| MetricsEndpoint::bind(addr, Arc::clone(self)))
+            .transpose()
+    }
+
+    /// Starts serving the HTTP gateway if the options ask for it.
+    pub(crate) fn bind_gateway(self: &Arc<Self>) -> Result<Option<HttpGateway>> {
+        self.options
+            .http_addr
+            .map(|addr| HttpGateway::bind(addr, Arc::clone(self)))
+            .transpose()
+    }
+}
 
 pub struct Server {
     addr: SocketAddr,
-    engine: Engine,
+    shared: Arc<Shared>,
+    pool: Pool,
+    /// Connections handled on threads outside the pool.
+    extra_threads: Counter,
 }
 
 impl Server {
-    pub fn new(engine: Engine, addr: SocketAddr) -> Self {
-        Self { addr, engine }
+    /// Creates a server that handles connections on the pool's threads. Every connection shares
+    /// the same engine.
+    pub fn new(engine: Engine, addr: SocketAddr, pool: Pool, options: ServerOptions) -> Self {
+        Self {
+            addr,
+            shared: Arc::new(Shared::new(engine, options)),
+            pool,
+            extra_threads: Counter::default(),
+        }
     }
 
-    pub fn run(mut self) -> Result<()> {
-        debug!(?self.addr, "Binding server");
-        let listener = TcpListener::bind(self.addr).context("Failed to bind address")?;
-        debug!("Server bound");
+    /// Binds the address and Unix socket and accepts connections on background threads until the
+    /// returned handle shuts the server down.
+    pub fn bind(self) -> Result<ServerHandle> {
+        self.shared.check_listeners()?;
+        let mut listeners = Vec::new();
+        let mut local_addr = None;
+        if self.shared.options.tcp {
+            debug!(?self.addr, "Binding server");
+            let listener = TcpListener::bind(self.addr).context("Failed to bind address")?;
+            local_addr = Some(listener.local_addr()?);
+            listeners.push(Listener::Tcp(listener));
+        }
+        let metrics = self.shared.bind_metrics()?;
+        let gateway = self.shared.bind_gateway()?;
+        let unix_socket = self.shared.options.unix_socket.clone();
+        if let Some(unix_socket) = &unix_socket {
+            debug!(path = ?unix_socket.path, "Binding Unix socket");
+            listeners.push(Listener::Unix(unix_socket.bind()?));
+        }
+        debug!(?local_addr, pool_type = %self.pool.pool_type(), "Server bound");
+
+        let shutting_down = Arc::new(AtomicBool::new(false));
+        let thread = thread::Builder::new()
+            .name("kvs-server".to_owned())
+            .spawn({
+                let shutting_down = Arc::clone(&shutting_down);
+                move || self.serve(listeners, &shutting_down)
+            })
+            .context("Failed to spawn server thread")?;
+
+        Ok(ServerHandle::new(
+            local_addr,
+            unix_socket.map(|unix_socket| unix_socket.path),
+            ShutdownTrigger::Sync(shutting_down),
+            thread,
+            metrics,
+            gateway,
+        ))
+    }
 
-        let mut reader = Reader::new();
+    /// Binds the address and accepts connections until the process exits.
+    pub fn run(self) -> Result<()> {
+        self.bind()?.wait()
+    }
 
-        for stream in listener.incoming() {
-            let mut stream = stream?;
-            info!(?stream, "Received connection");
+    fn serve(self, listeners: Vec<Listener>, shutting_down: &AtomicBool) -> Result<()> {
+        let connections = Arc::new(Connections::default());
+        thread::scope(|scope| {
+            for listener in &listeners {
+                scope.spawn(|| self.accept(listener, &connections, shutting_down));
+            }
+        });
 
-            let cmd = match reader.read_cmd(&mut stream) {
-                Ok(Some(cmd)) => cmd,
-                Ok(None) => {
-                    warn!("Response had no data");
-                    stream.write_all(b"Response had no data")?;
-                    stream.flush()?;
+        drop(listeners);
+        if let Some(unix_socket) = &self.shared.options.unix_socket {
+            unix_socket.remove();
+        }
+        info!("Shutting down, waiting for open connections");
+        connections.close_and_wait();
+        Self::flush(&self.shared.engine)?;
+        info!("Server shut down");
+
+        Ok(())
+    }
+
+    /// Accepts connections on the listener and handles them on the pool until the server shuts
+    /// down.
+    fn accept(
+        &self,
+        listener: &Listener,
+        connections: &Arc<Connections>,
+        shutting_down: &AtomicBool,
+    ) {
+        loop {
+            let accepted = listener.accept();
+            if shutting_down.load(Ordering::SeqCst) {
+                break;
+            }
+            let socket = match accepted {
+                Ok(socket) => socket,
+                Err(e) => {
+                    warn!(?e, "Failed to accept connection");
+                    thread::sleep(ACCEPT_BACKOFF);
                     continue;
                 }
+            };
+            info!(?socket, "Received connection");
+
+            let guard = match connections.track(&socket) {
+                Ok(guard) => guard,
                 Err(e) => {
-                    warn!(?e, "Failed to parse command");
-                    let e = e.to_string();
-                    stream.write_all(e.as_bytes())?;
-                    stream.flush()?;
+                    warn!(?e, "Failed to track connection");
                     continue;
                 }
             };
+            let client = Client {
+                ip: socket.peer_ip(),
+                over_capacity: self.shared.over_capacity(guard.open),
+            };
+            // Idle connections can hold a pool thread until the idle timeout, so a connection
+            // that would wait behind them is handled on its own thread instead.
+            let idle = self.shared.idle_threads.count();
+            let pool_idle = self.pool.threads().is_some_and(|threads| idle >= threads);
+            if client.over_capacity || pool_idle {
+                let Some(extra) = self.extra_threads.try_add(MAX_EXTRA_THREADS) else {
+                    if client.over_capacity {
+                        debug!("Too many connections over capacity, closing connection");
+                        continue;
+                    }
+                    self.spawn_on_pool(socket, client, guard);
+                    continue;
+                };
+                self.spawn_extra(socket, client, guard, extra);
+                continue;
+            }
+            self.spawn_on_pool(socket, client, guard);
+        }
+    }
+
+    fn spawn_on_pool(&self, socket: Socket, client: Client, guard: ConnectionGuard) {
+        let shared = Arc::clone(&self.shared);
+        self.pool.spawn(move || {
+            let _guard = guard;
+            let _connection = shared.metrics.connection_opened();
+            let idle = Some(&shared.idle_threads);
+            if let Err(e) = Self::serve_connection(&shared, socket, client, idle) {
+                warn!(?e, "Failed to handle connection");
+            }
+        });
+    }
+
+    /// Handles a connection on its own thread rather than the pool, so it doesn't wait for a pool
+    /// thread. Connections over capacity get a busy response to their first command.
+    fn spawn_extra(&self, socket: Socket, client: Client, guard: ConnectionGuard, extra: Counted) {
+        let shared = Arc::clone(&self.shared);
+        let spawned = thread::Builder::new()
+            .name("kvs-extra".to_owned())
+            .spawn(move || {
+                let _extra = extra;
+                let _guard = guard;
+                let _connection = shared.metrics.connection_opened();
+                if let Err(e) = Self::serve_connection(&shared, socket, client, None) {
+                    warn!(?e, "Failed to handle connection");
+                }
+            });
+        if let Err(e) = spawned {
+            warn!(?e, "Failed to spawn thread to handle connection");
+        }
+    }
+
+    /// Flushes the engine so every write survives the process exiting.
+    pub(crate) fn flush(engine: &Mutex<Engine>) -> Result<()> {
+        let mut engine = engine.lock().map_err(|_| anyhow!("Engine lock poisoned"))?;
+        engine.flush().context("Failed to flush engine")
+    }
 
-            info!(?cmd, "Parsed command");
-            let response = match cmd.into_cmd() {
-                Cmd::Set(k, v) => Self::handle_set(&mut self.engine, k.to_string(), &v),
-                Cmd::Get(k) => Self::handle_get(&mut self.engine, &k),
-                Cmd::Rm(k) => Self::handle_rm(&mut self.engine, &k),
+    /// Handles a connection in the server's protocol, over TLS if the server uses it. Its thread
+    /// is counted in `idle` while it waits for the client to start sending a command.
+    fn serve_connection(
+        shared: &Shared,
+        socket: Socket,
+        client: Client,
+        idle: Option<&Counter>,
+    ) -> Result<()> {
+        let options = &shared.options;
+        let mut stream = Stream::accept(socket, options.tls.as_ref(), options.read_timeout)?;
+        let handled = match options.protocol {
+            Protocol::Kvs => Self::handle_connection(shared, &mut stream, client, idle),
+            Protocol::Resp => {
+                let session = resp::Session::new(shared, client);
+                frontend::serve(shared, session, &mut stream, client, idle)
+            }
+            Protocol::Memcached => {
+                let session = memcached::Session::new(shared, client);
+                frontend::serve(shared, session, &mut stream, client, idle)
+            }
+        };
+        // The client may have already closed the connection.
+        if let Err(e) = stream.close() {
+            debug!(?e, "Failed to close connection");
+        }
+        handled
+    }
+
+    /// Handles commands in the server's own protocol until the [`Session`] closes the connection.
+    fn handle_connection(
+        shared: &Shared,
+        stream: &mut Stream,
+        client: Client,
+        idle: Option<&Counter>,
+    ) -> Result<()> {
+        let mut session = Session::new(shared, client, stream.peer_addr());
+        let mut reader = Reader::with_limits(shared.options.limits);
+        loop {
+            let deadline = Deadline::new(&mut *stream, session.wait(), shared.options.read_timeout)
+                .counting_idle(idle);
+            let read = reader.read_cmd(deadline).map(|read_result| {
+                read_result.map(|read_result| {
+                    shared.metrics.record_read(read_result.bytes_read());
+                    read_result.into_cmd()
+                })
+            });
+            let reply = match session.on_read(read)? {
+                Action::Read => continue,
+                Action::Execute(cmd) => session.respond(&Self::handle_cmd(shared, cmd))?,
+                Action::Reply(reply) => reply,
+                Action::Close => return Ok(()),
             };
-            response.write(&mut stream)?;
+            stream.write_all(&reply.bytes)?;
             stream.flush()?;
+            shared.metrics.record_written(reply.bytes.len());
+            if reply.close {
+                return Ok(());
+            }
         }
+    }
 
-        Ok(())
+    /// Executes a command on the shared engine, returning a response. Commands are counted and
+    /// timed in the metrics.
+    pub(crate) fn handle_cmd(shared: &Shared, cmd: Cmd) -> Response<'static> {
+        let start = Instant::now();
+        let kind = CommandKind::of(&cmd);
+        let response = Self::execute(shared, cmd);
+        match kind {
+            Some(kind) => shared
+                .metrics
+                .record_command(kind, start.elapsed(), &response),
+            None => shared.metrics.record_response(&response),
+        }
+        response
+    }
+
+    fn execute(shared: &Shared, cmd: Cmd) -> Response<'static> {
+        let Ok(mut engine) = shared.engine.lock() else {
+            warn!("Engine lock poisoned");
+            return Response::Err(ErrorCode::Internal, "Engine unavailable".into());
+        };
+        match cmd {
+            Cmd::Set(k, v) => {
+                if let Err(response) = Self::check_limits(shared, &k, &v) {
+                    return response;
+                }
+                Self::handle_set(&mut *engine, k.into_owned(), &v)
+            }
+            Cmd::Get(k) => Self::handle_get(&mut *engine, &k),
+            Cmd::Rm(k) => Self::handle_rm(&mut *engine, &k),
+            Cmd::Ping => Response::Pong,
+            Cmd::Info => Self::handle_info(&engine, shared.started),
+            Cmd::Compact => Self::handle_compact(&mut engine),
+            Cmd::Flush => Self::handle_flush(&mut *engine),
+            Cmd::KeepAlive | Cmd::Auth(_) | Cmd::Hello(_) => Response::Err(
+                ErrorCode::BadRequest,
+                "Hello, keep-alive and auth must be sent before other commands on a connection"
+                    .into(),
+            ),
+        }
+    }
+    /// Sets the key like a [`Cmd::Set`], unless `exists` is given and doesn't match whether the
+    /// key exists. Returns whether the key existed, and the response to the set, or `None` if it
+    /// was skipped. The check and the write happen under one engine lock, so no other write can
+    /// come between them.
+    pub(crate) fn handle_set_if(
+        shared: &Shared,
+        key: String,
+        value: &str,
+        exists: Option<bool>,
+    ) -> (bool, Option<Response<'static>>) {
+        let start = Instant::now();
+        let mut existed = false;
+        let response = match (
+            Self::check_limits(shared, &key, value),
+            shared.engine.lock(),
+        ) {
+            (Err(response), _) => Some(response),
+            (Ok(()), Err(_)) => {
+                warn!("Engine lock poisoned");
+                Some(Response::Err(
+                    ErrorCode::Internal,
+                    "Engine unavailable".into(),
+                ))
+            }
+            (Ok(()), Ok(mut engine)) => match Self::handle_get(&mut *engine, &key) {
+                get @ (Response::SuccessfulGet(_) | Response::KeyNotFound) => {
+                    existed = matches!(get, Response::SuccessfulGet(_));
+                    exists
+                        .is_none_or(|exists| exists == existed)
+                        .then(|| Self::handle_set(&mut *engine, key, value))
+                }
+                response => Some(response),
+            },
+        };
+        if let Some(response) = &response {
+            shared
+                .metrics
+                .record_command(CommandKind::Set, start.elapsed(), response);
+        }
+        (existed, response)
+    }
+
+    /// Checks a key and value against the server's limits. Commands from other protocols haven't
+    /// been through a reader with the limits.
+    fn check_limits(shared: &Shared, key: &str, value: &str) -> Result<(), Response<'static>> {
+        let limits = shared.options.limits;
+        limits
+            .check_key(key.len() as u64)
+            .and_then(|()| limits.check_value(value.len() as u64))
+            .map_err(|e| Response::Err(ErrorCode::TooLarge, e.to_string().into()))
     }
 
     /// Executes a set command on the passed KvsEngine, returning a response.
     fn handle_set(kvs: &mut impl KvsEngine, key: String, value: &str) -> Response<'static> {
         match kvs.set(key, value) {
             Ok(_) => Response::SuccessfulSet,
+            Err(e) if e.is::<TooLarge>() => {
+                Response::Err(ErrorCode::TooLarge, e.to_string().into())
+            }
             Err(e) => {
                 warn!(?e, "Failed to set key to value");
                 // TODO These .to_string()s are kind of sad. We should be able to write these
                 // bytes directly into the stream. Maybe these should be static methods like
                 // `response::write_err(impl Display)` or something?
-                Response::Err(e.to_string().into())
+                Response::Err(ErrorCode::Storage, e.to_string().into())
             }
         }
     }
@@ -82,7 +655,7 @@ impl Server {
             Ok(None) => Response::KeyNotFound,
             Err(e) => {
                 warn!(?e, "Failed to get key");
-                Response::Err(e.to_string().into())
+                Response::Err(ErrorCode::Storage, e.to_string().into())
             }
         }
     }
@@ -91,10 +664,667 @@ impl Server {
     fn handle_rm(kvs: &mut impl KvsEngine, key: &str) -> Response<'static> {
         match kvs.remove(key) {
             Ok(_) => Response::SuccessfulRm,
+            Err(e) if e.is::<KeyNotFound>() => {
+                Response::Err(ErrorCode::NotFound, e.to_string().into())
+            }
             Err(e) => {
                 warn!(?e, "Failed to remove key");
-                Response::Err(e.to_string().into())
+                Response::Err(ErrorCode::Storage, e.to_string().into())
+            }
+        }
+    }
+
+    /// Describes the server and its engine, returning a response.
+    fn handle_info(engine: &Engine, started: Instant) -> Response<'static> {
+        match engine.stats() {
+            Ok(stats) => Response::Info(ServerInfo {
+                engine: engine.engine_type().to_string().into(),
+                version: env!("CARGO_PKG_VERSION").into(),
+                uptime: started.elapsed(),
+                keys: stats.live_keys as u64,
+                disk_usage: stats.disk_bytes,
+            }),
+            Err(e) => {
+                warn!(?e, "Failed to collect engine stats");
+                Response::Err(ErrorCode::Storage, format!("{e:#}").into())
+            }
+        }
+    }
+
+    /// Compacts the engine's files, returning a response.
+    fn handle_compact(engine: &mut Engine) -> Response<'static> {
+        match engine.compact() {
+            Ok(true) => Response::Compacted,
+            Ok(false) => Response::Err(
+                ErrorCode::BadRequest,
+                format!(
+                    "The {} engine doesn't compact its files",
+                    engine.engine_type()
+                )
+                .into(),
+            ),
+            Err(e) => {
+                warn!(?e, "Failed to compact");
+                Response::Err(ErrorCode::Storage, e.to_string().into())
+            }
+        }
+    }
+
+    /// Flushes the passed KvsEngine, returning a response.
+    fn handle_flush(kvs: &mut impl KvsEngine) -> Response<'static> {
+        match kvs.flush() {
+            Ok(()) => Response::Flushed,
+            Err(e) => {
+                warn!(?e, "Failed to flush");
+                Response::Err(ErrorCode::Storage, e.to_string().into())
+            }
+        }
+    }
+}
+
+/// A connection's client, for deciding whether to run its commands.
+#[derive(Debug, Clone, Copy)]
+pub(crate) struct Client {
+    /// The client's address, if it connected over TCP.
+    pub(crate) ip: Option<IpAddr>,
+    /// Whether the server had too many connections when it accepted this one.
+    pub(crate) over_capacity: bool,
+}
+
+/// Whether the error was caused by a read timing out.
+pub(crate) fn is_timeout(e: &Error) -> bool {
+    e.chain().any(|cause| {
+        cause.downcast_ref::<io::Error>().is_some_and(|e| {
+            matches!(
+                e.kind(),
+                io::ErrorKind::WouldBlock | io::ErrorKind::TimedOut
+            )
+        })
+    })
+}
+
+/// The response to a command that couldn't be read. Commands over the size limits get
+/// [`ErrorCode::TooLarge`], so clients can tell them apart from malformed ones, and commands the
+/// client took too long to send say so.
+pub(crate) fn parse_error_response(e: &Error) -> Response<'static> {
+    if is_timeout(e) {
+        return Response::Err(ErrorCode::BadRequest, "Timed out reading command".into());
+    }
+    let code = if e.chain().any(|cause| cause.is::<TooLarge>()) {
+        ErrorCode::TooLarge
+    } else {
+        ErrorCode::BadRequest
+    };
+    Response::Err(code, format!("{e:#}").into())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::net::{Shutdown, TcpStream};
+    use std::os::unix::fs::PermissionsExt;
+    use std::os::unix::net::UnixStream;
+    use std::path::Path;
+    use std::time::Instant;
+
+    use kvs::KvStore;
+    use protocol::ResponseReader;
+
+    use super::*;
+    use tempfile::TempDir;
+
+    use crate::{AsyncServer, EngineType, PoolType, Runtime};
+
+    /// Sends a command on its own connection and returns the response.
+    fn send(addr: SocketAddr, cmd: Cmd) -> Response<'static> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        cmd.write(&mut stream).unwrap();
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let mut bytes = Vec::new();
+        stream.read_to_end(&mut bytes).unwrap();
+        Response::from_bytes(&bytes).unwrap().into_owned()
+    }
+
+    fn options() -> ServerOptions {
+        ServerOptions {
+            idle_timeout: Duration::from_secs(60),
+            ..ServerOptions::default()
+        }
+    }
+
+    /// Binds a server on the runtime, with a kvs engine in a new temporary directory that has to
+    /// outlive the server.
+    fn bind(runtime: Runtime, options: ServerOptions) -> (ServerHandle, TempDir) {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let handle = match runtime {
+            Runtime::Sync => {
+                let pool = Pool::new(PoolType::SharedQueue, 2).unwrap();
+                Server::new(engine, addr, pool, options).bind()
+            }
+            Runtime::Async => AsyncServer::new(engine, addr, 2, options).bind(),
+        };
+        (handle.unwrap(), dir)
+    }
+
+    /// Generates a test for each runtime that runs the check on it.
+    macro_rules! runtime_tests {
+        ($check:ident => $sync:ident, $async:ident) => {
+            #[test]
+            fn $sync() {
+                $check(Runtime::Sync);
+            }
+
+            #[test]
+            fn $async() {
+                $check(Runtime::Async);
+            }
+        };
+    }
+
+    /// Sends the commands on one connection and returns the unframed response to the last one.
+    fn send_all(addr: SocketAddr, cmds: &[Cmd]) -> Response<'static> {
+        let mut stream = TcpStream::connect(addr).unwrap();
+        for cmd in cmds {
+            cmd.write(&mut stream).unwrap();
+        }
+        stream.shutdown(Shutdown::Write).unwrap();
+
+        let mut bytes = Vec::new();
+        stream.read_to_end(&mut bytes).unwrap();
+        Response::from_bytes(&bytes).unwrap().into_owned()
+    }
+
+    fn auth_options() -> ServerOptions {
+        ServerOptions {
+            auth_token: Some(AuthToken::new("secret").unwrap()),
+            ..options()
+        }
+    }
+
+    fn requires_auth(runtime: Runtime) {
+        let (handle, _dir) = bind(runtime, auth_options());
+        let addr = handle.local_addr().unwrap();
+        let auth = |token: &'static str| Cmd::Auth(token.into());
+
+        assert_eq!(
+            send(addr, Cmd::Get("key".into())),
+            Shared::unauthorized("Authentication required")
+        );
+        // A wrong token closes the connection before the command is read.
+        assert_eq!(
+            send_all(addr, &[auth("wrong"), Cmd::Get("key".into())]),
+            Shared::unauthorized("Invalid auth token")
+        );
+        assert_eq!(
+            send_all(
+                addr,
+                &[auth("secret"), Cmd::Set("key".into(), "value".into())]
+            ),
+            Response::SuccessfulSet
+        );
+
+        let mut kept_alive = TcpStream::connect(addr).unwrap();
+        auth("secret").write(&mut kept_alive).unwrap();
+        Cmd::KeepAlive.write(&mut kept_alive).unwrap();
+        let mut reader = ResponseReader::new();
+        for _ in 0..2 {
+            Cmd::Get("key".into()).write(&mut kept_alive).unwrap();
+            assert_eq!(
+                reader.read_response(&kept_alive).unwrap(),
+                Response::SuccessfulGet("value".into())
+            );
+        }
+        drop(kept_alive);
+
+        handle.shutdown().unwrap();
+    }
+
+    runtime_tests!(requires_auth => sync_server_requires_auth, async_server_requires_auth);
+
+    fn rejects_oversize_commands(runtime: Runtime) {
+        let (handle, _dir) = bind(runtime, limited_options());
+        let addr = handle.local_addr().unwrap();
+        // Only the header is sent, since the server rejects the command without reading the rest.
+        let send_header = |key_len: u32, value_len: u64| {
+            let mut stream = TcpStream::connect(addr).unwrap();
+            stream.write_all(&key_len.to_be_bytes()).unwrap();
+            stream.write_all(&value_len.to_be_bytes()).unwrap();
+            stream.shutdown(Shutdown::Write).unwrap();
+            let mut bytes = Vec::new();
+            stream.read_to_end(&mut bytes).unwrap();
+            Response::from_bytes(&bytes).unwrap().into_owned()
+        };
+
+        for (key_len, value_len) in [(9, 5), (3, 17), (u32::MAX, u64::MAX - 10)] {
+            assert!(matches!(
+                send_header(key_len, value_len),
+                Response::Err(ErrorCode::TooLarge, _)
+            ));
+        }
+        let set = Cmd::Set("k".repeat(8).into(), "v".repeat(16).into());
+        assert_eq!(send(addr, set), Response::SuccessfulSet);
+
+        handle.shutdown().unwrap();
+    }
+
+    fn limited_options() -> ServerOptions {
+        ServerOptions {
+            limits: Limits {
+                max_key_bytes: 8,
+                max_value_bytes: 16,
+            },
+            ..options()
+        }
+    }
+
+    runtime_tests!(
+        rejects_oversize_commands => sync_server_rejects_oversize_commands,
+        async_server_rejects_oversize_commands
+    );
+
+    fn negotiates_protocol(runtime: Runtime) {
+        let (handle, _dir) = bind(runtime, options());
+        let addr = handle.local_addr().unwrap();
+        let hello = |version, features| Cmd::Hello(Hello { version, features });
+        let mut reader = ResponseReader::new();
+
+        let mut framed = TcpStream::connect(addr).unwrap();
+        hello(7, Features::FRAMING.union(Features::COMPRESSION))
+            .write(&mut framed)
+            .unwrap();
+        assert_eq!(
+            reader.read_response(&framed).unwrap(),
+            Response::Hello(Hello::new(Features::FRAMING))
+        );
+        for _ in 0..2 {
+            Cmd::Ping.write(&mut framed).unwrap();
+            assert_eq!(reader.read_response(&framed).unwrap(), Response::Pong);
+        }
+        drop(framed);
+
+        // Without framing, the connection runs one more command like a legacy one.
+        let mut unframed = TcpStream::connect(addr).unwrap();
+        hello(1, Features::NONE).write(&mut unframed).unwrap();
+        Cmd::Ping.write(&mut unframed).unwrap();
+        unframed.shutdown(Shutdown::Write).unwrap();
+        assert_eq!(
+            reader.read_response(&unframed).unwrap(),
+            Response::Hello(Hello::new(Features::NONE))
+        );
+        let mut bytes = Vec::new();
+        unframed.read_to_end(&mut bytes).unwrap();
+        assert_eq!(Response::from_bytes(&bytes).unwrap(), Response::Pong);
+
+        let mut ancient = TcpStream::connect(addr).unwrap();
+        hello(0, Features::FRAMING).write(&mut ancient).unwrap();
+        assert!(matches!(
+            reader.read_response(&ancient).unwrap(),
+            Response::Err(ErrorCode::BadRequest, _)
+        ));
+        drop(ancient);
+
+        let mut late = TcpStream::connect(addr).unwrap();
+        Cmd::KeepAlive.write(&mut late).unwrap();
+        hello(1, Features::FRAMING).write(&mut late).unwrap();
+        assert!(matches!(
+            reader.read_response(&late).unwrap(),
+            Response::Err(ErrorCode::BadRequest, _)
+        ));
+        drop(late);
+        // Clients that predate the handshake keep working.
+        assert_eq!(send(addr, Cmd::Ping), Response::Pong);
+        handle.shutdown().unwrap();
+    }
+
+    runtime_tests!(
+        negotiates_protocol => sync_server_negotiates_protocol,
+        async_server_negotiates_protocol
+    );
+
+    fn limits_connections(runtime: Runtime) {
+        let (handle, _dir) = bind(runtime, busy_options());
+        let addr = handle.local_addr().unwrap();
+        let mut kept_alive = TcpStream::connect(addr).unwrap();
+        Cmd::KeepAlive.write(&mut kept_alive).unwrap();
+        Cmd::Ping.write(&mut kept_alive).unwrap();
+        let mut reader = ResponseReader::new();
+        assert_eq!(reader.read_response(&kept_alive).unwrap(), Response::Pong);
+        assert!(matches!(
+            send(addr, Cmd::Ping),
+            Response::Err(ErrorCode::Busy, _)
+        ));
+
+        // The server notices the connection closed in the background.
+        drop(kept_alive);
+        let mut response = send(addr, Cmd::Ping);
+        for _ in 0..100 {
+            if response == Response::Pong {
+                break;
             }
+            thread::sleep(Duration::from_millis(10));
+            response = send(addr, Cmd::Ping);
+        }
+        assert_eq!(response, Response::Pong);
+
+        // A client that stops partway through a command is cut off.
+        let mut slow = TcpStream::connect(addr).unwrap();
+        slow.write_all(&[0, 0, 0]).unwrap();
+        let mut bytes = Vec::new();
+        slow.read_to_end(&mut bytes).unwrap();
+        assert_eq!(
+            Response::from_bytes(&bytes).unwrap(),
+            Response::Err(ErrorCode::BadRequest, "Timed out reading command".into())
+        );
+
+        handle.shutdown().unwrap();
+    }
+
+    fn rate_limits_clients(runtime: Runtime) {
+        let (handle, _dir) = bind(runtime, rate_limited_options());
+        let addr = handle.local_addr().unwrap();
+        assert_eq!(send(addr, Cmd::Ping), Response::Pong);
+        assert_eq!(send(addr, Cmd::Ping), Response::Pong);
+        assert!(matches!(
+            send(addr, Cmd::Ping),
+            Response::Err(ErrorCode::Busy, _)
+        ));
+
+        handle.shutdown().unwrap();
+    }
+
+    fn busy_options() -> ServerOptions {
+        ServerOptions {
+            read_timeout: Duration::from_millis(200),
+            max_connections: Some(1),
+            ..options()
+        }
+    }
+
+    fn rate_limited_options() -> ServerOptions {
+        ServerOptions {
+            rate_limit: Some(RateLimit {
+                per_sec: 1,
+                burst: 2,
+            }),
+            ..options()
+        }
+    }
+
+    runtime_tests!(
+        limits_connections => sync_server_limits_connections,
+        async_server_limits_connections
+    );
+
+    #[test]
+    fn serves_connections_past_idle_threads() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        let pool = Pool::new(PoolType::SharedQueue, 2).unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let handle = Server::new(engine, addr, pool, options()).bind().unwrap();
+        let addr = handle.local_addr().unwrap();
+
+        // Idle kept-alive connections, like a client's pool, hold every thread in the pool.
+        let mut reader = ResponseReader::new();
+        let mut idle: Vec<_> = (0..2)
+            .map(|_| {
+                let mut stream = TcpStream::connect(addr).unwrap();
+                Cmd::KeepAlive.write(&mut stream).unwrap();
+                Cmd::Ping.write(&mut stream).unwrap();
+                assert_eq!(reader.read_response(&stream).unwrap(), Response::Pong);
+                stream
+            })
+            .collect();
+        thread::sleep(Duration::from_millis(100));
+
+        // A new connection doesn't wait for them to close.
+        assert_eq!(
+            send(addr, Cmd::Set("key".into(), "value".into())),
+            Response::SuccessfulSet
+        );
+        for stream in &mut idle {
+            Cmd::Get("key".into()).write(&mut *stream).unwrap();
+            assert_eq!(
+                reader.read_response(&*stream).unwrap(),
+                Response::SuccessfulGet("value".into())
+            );
+        }
+        handle.shutdown().unwrap();
+    }
+
+    runtime_tests!(
+        rate_limits_clients => sync_server_rate_limits_clients,
+        async_server_rate_limits_clients
+    );
+
+    fn shuts_down_gracefully(runtime: Runtime) {
+        let (handle, dir) = bind(runtime, options());
+        let addr = handle.local_addr().unwrap();
+        assert_ne!(addr.port(), 0);
+        let set = Cmd::Set("key".into(), "value".into());
+        assert_eq!(send(addr, set), Response::SuccessfulSet);
+
+        let mut kept_alive = TcpStream::connect(addr).unwrap();
+        Cmd::KeepAlive.write(&mut kept_alive).unwrap();
+        Cmd::Get("key".into()).write(&mut kept_alive).unwrap();
+        let mut reader = ResponseReader::new();
+        assert_eq!(
+            reader.read_response(&kept_alive).unwrap(),
+            Response::SuccessfulGet("value".into())
+        );
+
+        // The kept-alive connection is closed instead of holding up shutdown until it's idle.
+        let start = Instant::now();
+        handle.shutdown().unwrap();
+        assert!(start.elapsed() < Duration::from_secs(10));
+        assert!(reader.read_response(&kept_alive).is_err());
+        assert!(TcpStream::connect(addr).is_err());
+
+        let mut store = KvStore::open(dir.path()).unwrap();
+        assert_eq!(store.get("key").unwrap(), Some("value".to_owned()));
+    }
+
+    runtime_tests!(shuts_down_gracefully => sync_server_shuts_down, async_server_shuts_down);
+
+    fn unix_only_options(path: &Path) -> ServerOptions {
+        ServerOptions {
+            tcp: false,
+            unix_socket: Some(UnixSocket {
+                path: path.to_owned(),
+                mode: 0o600,
+            }),
+            ..options()
+        }
+    }
+
+    fn serves_unix_socket(runtime: Runtime) {
+        let socket_dir = tempfile::tempdir().unwrap();
+        let options = unix_only_options(&socket_dir.path().join("kvs.sock"));
+        let (handle, _dir) = bind(runtime, options);
+        assert!(handle.local_addr().is_none());
+        let path = handle.unix_path().unwrap().to_owned();
+        let mode = std::fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o600);
+
+        let mut stream = UnixStream::connect(&path).unwrap();
+        Cmd::KeepAlive.write(&mut stream).unwrap();
+        Cmd::Set("key".into(), "value".into())
+            .write(&mut stream)
+            .unwrap();
+        Cmd::Get("key".into()).write(&mut stream).unwrap();
+        let mut reader = ResponseReader::new();
+        assert_eq!(
+            reader.read_response(&stream).unwrap(),
+            Response::SuccessfulSet
+        );
+        assert_eq!(
+            reader.read_response(&stream).unwrap(),
+            Response::SuccessfulGet("value".into())
+        );
+
+        handle.shutdown().unwrap();
+        assert!(reader.read_response(&stream).is_err());
+        assert!(!path.exists());
+    }
+
+    runtime_tests!(
+        serves_unix_socket => sync_server_serves_unix_socket,
+        async_server_serves_unix_socket
+    );
+
+    #[test]
+    fn needs_somewhere_to_listen() {
+        let dir = tempfile::tempdir().unwrap();
+        let engine = Engine::new_in(Some(EngineType::Kvs), dir.path()).unwrap();
+        let pool = Pool::new(PoolType::SharedQueue, 1).unwrap();
+        let addr = "127.0.0.1:0".parse().unwrap();
+        let options = ServerOptions {
+            tcp: false,
+            ..options()
+        };
+
+        assert!(Server::new(engine, addr, pool, options).bind().is_err());
+    }
+
+    #[test]
+    fn enforces_acl() {
+        let dir = tempfile::tempdir().unwrap();
+        let acl_file = dir.path().join("acl.toml");
+        std::fs::write(
+            &acl_file,
+            r#"
+            [[grants]]
+            name = "analytics"
+            token = "analytics-secret"
+            allow = ["read"]
+
+            [[grants]]
+            name = "billing"
+            token = "billing-secret"
+            allow = ["read", "write"]
+            key_prefixes = ["billing/"]
+            "#,
+        )
+        .unwrap();
+        let options = ServerOptions {
+            acl: Some(Acl::from_file(&acl_file).unwrap()),
+            ..auth_options()
+        };
+
+        let (handle, _dir) = bind(Runtime::Sync, options);
+        let addr = handle.local_addr().unwrap();
+        let auth = |token: &'static str| Cmd::Auth(token.into());
+        let forbidden = |grant: &str| format!("The {grant:?} grant doesn't allow this command");
+
+        assert_eq!(
+            send(addr, Cmd::Get("billing/a".into())),
+            Shared::unauthorized("Authentication required")
+        );
+        assert_eq!(
+            send_all(
+                addr,
+                &[
+                    auth("billing-secret"),
+                    Cmd::Set("billing/a".into(), "1".into())
+                ]
+            ),
+            Response::SuccessfulSet
+        );
+        assert_eq!(
+            send_all(
+                addr,
+                &[auth("billing-secret"), Cmd::Set("other".into(), "1".into())]
+            ),
+            Response::Err(ErrorCode::Forbidden, forbidden("billing").into())
+        );
+        assert_eq!(
+            send_all(addr, &[auth("billing-secret"), Cmd::Compact]),
+            Response::Err(ErrorCode::Forbidden, forbidden("billing").into())
+        );
+        // The auth token is still allowed everything.
+        assert_eq!(
+            send_all(
+                addr,
+                &[auth("secret"), Cmd::Set("other".into(), "2".into())]
+            ),
+            Response::SuccessfulSet
+        );
+
+        // A denied command doesn't close a kept-alive connection.
+        let mut kept_alive = TcpStream::connect(addr).unwrap();
+        auth("analytics-secret").write(&mut kept_alive).unwrap();
+        Cmd::KeepAlive.write(&mut kept_alive).unwrap();
+        let mut reader = ResponseReader::new();
+        Cmd::Rm("billing/a".into()).write(&mut kept_alive).unwrap();
+        assert_eq!(
+            reader.read_response(&kept_alive).unwrap(),
+            Response::Err(ErrorCode::Forbidden, forbidden("analytics").into())
+        );
+        for (key, value) in [("billing/a", "1"), ("other", "2")] {
+            Cmd::Get(key.into()).write(&mut kept_alive).unwrap();
+            assert_eq!(
+                reader.read_response(&kept_alive).unwrap(),
+                Response::SuccessfulGet(value.into())
+            );
         }
+        drop(kept_alive);
+
+        handle.shutdown().unwrap();
+    }
+
+    #[test]
+    fn serves_metrics() {
+        let options = ServerOptions {
+            metrics_addr: Some("127.0.0.1:0".parse().unwrap()),
+            ..options()
+        };
+
+        let (handle, _dir) = bind(Runtime::Sync, options);
+        let addr = handle.local_addr().unwrap();
+        send(addr, Cmd::Set("key".into(), "value".into()));
+        send(addr, Cmd::Set("key".into(), "other".into()));
+        send(addr, Cmd::Get("key".into()));
+        send(addr, Cmd::Rm("missing".into()));
+
+        let mut scrape = TcpStream::connect(handle.metrics_addr().unwrap()).unwrap();
+        scrape
+            .write_all(b"GET /metrics HTTP/1.1\r\nHost: localhost\r\n\r\n")
+            .unwrap();
+        let mut response = String::new();
+        scrape.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 200 OK\r\n"), "{response}");
+
+        let lines: Vec<_> = response.lines().collect();
+        for line in [
+            "kvs_commands_total{command=\"set\"} 2",
+            "kvs_commands_total{command=\"get\"} 1",
+            "kvs_commands_total{command=\"rm\"} 1",
+            "kvs_command_duration_seconds_count{command=\"set\"} 2",
+            "kvs_errors_total{code=\"404\",kind=\"not_found\"} 1",
+            "kvs_engine_live_keys 1",
+            "kvs_engine_files 1",
+            "kvs_engine_compactions_total 0",
+        ] {
+            assert!(lines.contains(&line), "missing {line:?} in:\n{response}");
+        }
+        // Connections are counted as closed just after the client sees them close.
+        assert!(lines
+            .iter()
+            .any(|line| line.starts_with("kvs_open_connections ")));
+        assert!(!lines.contains(&"kvs_read_bytes_total 0"));
+        assert!(!lines.contains(&"kvs_engine_dead_bytes 0"));
+
+        let mut not_found = TcpStream::connect(handle.metrics_addr().unwrap()).unwrap();
+        not_found.write_all(b"GET / HTTP/1.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        not_found.read_to_string(&mut response).unwrap();
+        assert!(response.starts_with("HTTP/1.1 404 Not Found\r\n"));
+
+        let metrics_addr = handle.metrics_addr().unwrap();
+        handle.shutdown().unwrap();
+        assert!(TcpStream::connect(metrics_addr).is_err());
     }
 }
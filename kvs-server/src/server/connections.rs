@@ -0,0 +1,114 @@
+//! Tracks the connections a [`Server`][crate::Server] is handling so it can close them and wait
+//! for them to finish when it shuts down.
+
+use std::collections::HashMap;
+use std::io;
+use std::net::Shutdown;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::{Arc, Condvar, Mutex, PoisonError};
+
+use tracing::debug;
+
+use super::stream::Socket;
+
+#[derive(Default)]
+pub(crate) struct Connections {
+    /// Clones of the open connections, by an ID unique to each connection.
+    open: Mutex<(u64, HashMap<u64, Socket>)>,
+    /// Notified whenever a connection finishes.
+    finished: Condvar,
+}
+
+/// Stops tracking its connection when dropped, i.e. once the connection has been handled.
+pub(crate) struct ConnectionGuard {
+    connections: Arc<Connections>,
+    id: u64,
+    /// How many connections were open when this one was tracked, including it.
+    pub(crate) open: usize,
+}
+
+impl Connections {
+    /// Tracks the connection until the returned guard is dropped.
+    pub(crate) fn track(self: &Arc<Self>, socket: &Socket) -> io::Result<ConnectionGuard> {
+        let socket = socket.try_clone()?;
+        // Every operation leaves the map valid, so it's fine to use after a panic.
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        let (next_id, sockets) = &mut *open;
+        let id = *next_id;
+        *next_id += 1;
+        sockets.insert(id, socket);
+
+        Ok(ConnectionGuard {
+            connections: Arc::clone(self),
+            id,
+            open: sockets.len(),
+        })
+    }
+
+    /// Stops reading from every connection, so they finish after responding to the command they're
+    /// handling, and waits for them to finish.
+    pub(crate) fn close_and_wait(&self) {
+        let mut open = self.open.lock().unwrap_or_else(PoisonError::into_inner);
+        for socket in open.1.values() {
+            // The connection may have already been closed by the client.
+            if let Err(e) = socket.shutdown(Shutdown::Read) {
+                debug!(?e, "Failed to close connection");
+            }
+        }
+
+        while !open.1.is_empty() {
+            open = self
+                .finished
+                .wait(open)
+                .unwrap_or_else(PoisonError::into_inner);
+        }
+    }
+}
+
+/// Counts connections or threads in some state, such as threads outside the pool.
+#[derive(Default)]
+pub(crate) struct Counter(Arc<AtomicUsize>);
+
+/// Takes what it counted off the count when dropped, even if handling it panicked.
+pub(crate) struct Counted(Arc<AtomicUsize>);
+
+impl Counter {
+    /// Counts one more until the returned guard is dropped.
+    pub(crate) fn add(&self) -> Counted {
+        self.0.fetch_add(1, Ordering::SeqCst);
+        Counted(Arc::clone(&self.0))
+    }
+
+    /// Like [`Counter::add`], unless `max` are already counted.
+    pub(crate) fn try_add(&self, max: usize) -> Option<Counted> {
+        self.0
+            .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |count| {
+                (count < max).then_some(count + 1)
+            })
+            .ok()?;
+        Some(Counted(Arc::clone(&self.0)))
+    }
+
+    /// How many are counted.
+    pub(crate) fn count(&self) -> usize {
+        self.0.load(Ordering::SeqCst)
+    }
+}
+
+impl Drop for Counted {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::SeqCst);
+    }
+}
+
+impl Drop for ConnectionGuard {
+    fn drop(&mut self) {
+        let mut open = self
+            .connections
+            .open
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        open.1.remove(&self.id);
+        self.connections.finished.notify_all();
+    }
+}
@@ -0,0 +1,145 @@
+//! A [`ServerHandle`] controls a server running in the background.
+
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr, TcpStream};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+
+use anyhow::{anyhow, Result};
+use tokio::sync::watch;
+use tracing::{debug, warn};
+
+use crate::gateway::HttpGateway;
+use crate::metrics::MetricsEndpoint;
+
+/// Handle to a server accepting connections in the background, returned by [`Server::bind`] and
+/// [`AsyncServer::bind`].
+///
+/// Dropping the handle leaves the server running until the process exits.
+///
+/// [`Server::bind`]: crate::Server::bind
+/// [`AsyncServer::bind`]: crate::AsyncServer::bind
+pub struct ServerHandle {
+    local_addr: Option<SocketAddr>,
+    unix_path: Option<PathBuf>,
+    trigger: ShutdownTrigger,
+    thread: JoinHandle<Result<()>>,
+    metrics: Option<MetricsEndpoint>,
+    gateway: Option<HttpGateway>,
+}
+
+/// How to tell a server to shut down.
+pub(crate) enum ShutdownTrigger {
+    /// Set the flag, then connect to each of the server's sockets to wake it from waiting on a
+    /// connection.
+    Sync(Arc<AtomicBool>),
+    /// Send `true` to every task watching for shutdown.
+    Async(watch::Sender<bool>),
+}
+
+impl ServerHandle {
+    pub(crate) fn new(
+        local_addr: Option<SocketAddr>,
+        unix_path: Option<PathBuf>,
+        trigger: ShutdownTrigger,
+        thread: JoinHandle<Result<()>>,
+        metrics: Option<MetricsEndpoint>,
+        gateway: Option<HttpGateway>,
+    ) -> Self {
+        Self {
+            local_addr,
+            unix_path,
+            trigger,
+            thread,
+            metrics,
+            gateway,
+        }
+    }
+
+    /// The TCP address the server is listening on, or `None` if it only listens on a Unix socket.
+    /// If the server was bound to port 0, this has the port the OS assigned.
+    pub fn local_addr(&self) -> Option<SocketAddr> {
+        self.local_addr
+    }
+
+    /// The path of the Unix socket the server is listening on, if it listens on one.
+    pub fn unix_path(&self) -> Option<&Path> {
+        self.unix_path.as_deref()
+    }
+
+    /// The address metrics are served on, if the server was configured to serve them.
+    pub fn metrics_addr(&self) -> Option<SocketAddr> {
+        self.metrics.as_ref().map(MetricsEndpoint::local_addr)
+    }
+
+    /// The address the HTTP gateway is served on, if the server was configured to serve it.
+    pub fn http_addr(&self) -> Option<SocketAddr> {
+        self.gateway.as_ref().map(HttpGateway::local_addr)
+    }
+
+    /// Shuts the server down and waits for it to stop. The server stops accepting connections,
+    /// finishes the commands it's handling, closes its connections and flushes its engine. Metrics
+    /// are served until the server has stopped. The HTTP gateway stops first, so its writes are
+    /// flushed with the rest.
+    pub fn shutdown(mut self) -> Result<()> {
+        debug!(?self.local_addr, ?self.unix_path, "Shutting down server");
+        if let Some(gateway) = self.gateway.take() {
+            gateway.shutdown()?;
+        }
+        match &self.trigger {
+            ShutdownTrigger::Sync(shutting_down) => {
+                shutting_down.store(true, Ordering::SeqCst);
+                if let Some(addr) = self.local_addr {
+                    if let Err(e) = TcpStream::connect(wake_addr(addr)) {
+                        warn!(?e, "Failed to wake server");
+                    }
+                }
+                if let Some(path) = &self.unix_path {
+                    if let Err(e) = UnixStream::connect(path) {
+                        warn!(?e, "Failed to wake server");
+                    }
+                }
+            }
+            ShutdownTrigger::Async(sender) => {
+                let _ = sender.send(true);
+            }
+        }
+        self.wait()
+    }
+
+    /// Waits for the server to stop, which only happens after a call to
+    /// [`ServerHandle::shutdown`] or if the server fails.
+    pub fn wait(self) -> Result<()> {
+        // The trigger has to outlive the server, since dropping the async sender would wake every
+        // task watching for shutdown.
+        let Self {
+            trigger,
+            thread,
+            metrics,
+            gateway,
+            ..
+        } = self;
+        let result = thread.join().map_err(|_| anyhow!("Server thread panicked"));
+        drop(trigger);
+        if let Some(gateway) = gateway {
+            gateway.shutdown()?;
+        }
+        if let Some(metrics) = metrics {
+            metrics.shutdown()?;
+        }
+        result?
+    }
+}
+
+/// The address to connect to in order to reach a listener bound to `addr`, even if it's listening
+/// on every interface.
+pub(crate) fn wake_addr(mut addr: SocketAddr) -> SocketAddr {
+    match addr.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => addr.set_ip(Ipv4Addr::LOCALHOST.into()),
+        IpAddr::V6(ip) if ip.is_unspecified() => addr.set_ip(Ipv6Addr::LOCALHOST.into()),
+        _ => {}
+    }
+    addr
+}
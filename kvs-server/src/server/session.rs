@@ -0,0 +1,240 @@
+//! The state of a connection speaking the server's own protocol, shared by
+//! [`Server`][super::Server] and [`AsyncServer`][crate::AsyncServer], which only do the I/O.
+
+use std::net::SocketAddr;
+use std::time::Duration;
+
+use anyhow::Result;
+use protocol::{Cmd, ErrorCode, Features, Response};
+use tracing::{debug, info, warn};
+
+use super::{is_timeout, parse_error_response, Client, Shared, MAX_AUTH_FAILURES};
+use crate::Grant;
+
+/// What to do after reading a command.
+pub(crate) enum Action<'c> {
+    /// Read the next command.
+    Read,
+    /// Execute the command on the engine and reply with [`Session::respond`].
+    Execute(Cmd<'c>),
+    /// Write the reply.
+    Reply(Reply),
+    /// Close the connection without replying.
+    Close,
+}
+
+/// Encoded bytes to write to the client.
+pub(crate) struct Reply {
+    pub(crate) bytes: Vec<u8>,
+    /// Whether to close the connection after writing the bytes.
+    pub(crate) close: bool,
+}
+
+/// Decides how to handle each command read from a connection.
+///
+/// A connection runs one command and is closed, unless the command is [`Cmd::KeepAlive`], after
+/// which commands are run until the client closes the connection or it's idle for too long.
+///
+/// If the server requires authentication, the first command sent before a valid [`Cmd::Auth`]
+/// gets an unauthorized response and the connection is closed, as is a connection that presents
+/// [`MAX_AUTH_FAILURES`] rejected tokens. Auth commands count against the client's rate limit.
+///
+/// A connection may start with [`Cmd::Hello`] to agree on a protocol version and features, which
+/// gets a framed response. Agreeing on [`Features::FRAMING`] keeps the connection alive.
+pub(crate) struct Session<'a> {
+    shared: &'a Shared,
+    client: Client,
+    peer: Option<SocketAddr>,
+    /// The grant commands are checked against, or why the connection can't run commands.
+    access: Result<&'a Grant, &'static str>,
+    kept_alive: bool,
+    /// Whether no command has been read yet.
+    first: bool,
+    /// How many tokens the connection has presented that were rejected.
+    auth_failures: u32,
+}
+
+impl<'a> Session<'a> {
+    pub(crate) fn new(shared: &'a Shared, client: Client, peer: Option<SocketAddr>) -> Self {
+        Self {
+            shared,
+            client,
+            peer,
+            access: shared.unauthenticated(),
+            kept_alive: false,
+            first: true,
+            auth_failures: 0,
+        }
+    }
+
+    /// How long the client can wait before it starts sending the next command.
+    pub(crate) fn wait(&self) -> Duration {
+        if self.kept_alive {
+            self.shared.options.idle_timeout
+        } else {
+            self.shared.options.read_timeout
+        }
+    }
+
+    /// Decides what to do with the result of reading a command, which is `None` if the client
+    /// closed the connection before sending one.
+    pub(crate) fn on_read<'c>(&mut self, read: Result<Option<Cmd<'c>>>) -> Result<Action<'c>> {
+        let cmd = match read {
+            Ok(Some(cmd)) => cmd,
+            Ok(None) if self.kept_alive => {
+                debug!("Client closed connection");
+                return Ok(Action::Close);
+            }
+            Ok(None) => {
+                warn!("Request had no data");
+                let response = Response::Err(ErrorCode::BadRequest, "Request had no data".into());
+                self.shared.metrics.record_response(&response);
+                return self.close_with(&response, false);
+            }
+            Err(e) if self.kept_alive && is_timeout(&e) => {
+                debug!("Connection idle, closing it");
+                return Ok(Action::Close);
+            }
+            Err(e) => {
+                // The stream can't be trusted to be at the start of a command anymore, so the
+                // connection can't be used for anything else.
+                warn!(?e, "Failed to parse command");
+                let response = parse_error_response(&e);
+                self.shared.metrics.record_response(&response);
+                return self.close_with(&response, self.kept_alive);
+            }
+        };
+
+        info!(?cmd, "Parsed command");
+        let first = std::mem::take(&mut self.first);
+        match cmd {
+            Cmd::Hello(hello) => match self.shared.greet(&hello, first) {
+                Ok(agreed) => {
+                    debug!(?agreed, "Agreed on protocol");
+                    self.kept_alive = agreed.features.contains(Features::FRAMING);
+                    let bytes = encode(&Response::Hello(agreed), true)?;
+                    Ok(Action::Reply(Reply {
+                        bytes,
+                        close: false,
+                    }))
+                }
+                Err(response) => self.close_with(&response, true),
+            },
+            Cmd::Auth(token) => {
+                if let Err(message) = self.shared.admit(&self.client) {
+                    // The client expects a single response, to the command after the token.
+                    return self.close_with(&Shared::busy(message), self.kept_alive);
+                }
+                self.access = match self.shared.authenticate(&token) {
+                    Some(grant) => {
+                        debug!(grant = grant.name, "Connection authenticated");
+                        Ok(grant)
+                    }
+                    None => {
+                        // Clients send the token along with their first command, so the
+                        // rejection is the response to that command.
+                        warn!(target: "audit", peer = ?self.peer, "Rejected auth token");
+                        self.auth_failures += 1;
+                        if self.auth_failures >= MAX_AUTH_FAILURES {
+                            let response = Shared::unauthorized("Invalid auth token");
+                            self.shared.metrics.record_response(&response);
+                            return self.close_with(&response, self.kept_alive);
+                        }
+                        Err("Invalid auth token")
+                    }
+                };
+                Ok(Action::Read)
+            }
+            Cmd::KeepAlive => {
+                if !self.kept_alive {
+                    debug!("Keeping connection alive");
+                    self.kept_alive = true;
+                }
+                Ok(Action::Read)
+            }
+            cmd => match (self.shared.admit(&self.client), self.access) {
+                // A connection over the limit is closed, so it stops counting against it.
+                (Err(message), _) if self.client.over_capacity => {
+                    self.close_with(&Shared::busy(message), self.kept_alive)
+                }
+                (Err(message), _) => self.respond(&Shared::busy(message)).map(Action::Reply),
+                (Ok(()), Ok(grant)) => match self.shared.authorize(grant, &cmd) {
+                    Ok(()) => Ok(Action::Execute(cmd)),
+                    Err(response) => self.respond(&response).map(Action::Reply),
+                },
+                (Ok(()), Err(message)) => {
+                    let response = Shared::unauthorized(message);
+                    self.shared.metrics.record_response(&response);
+                    self.close_with(&response, self.kept_alive)
+                }
+            },
+        }
+    }
+
+    /// The reply to a command, framed if the connection is kept alive and closing it otherwise.
+    pub(crate) fn respond(&self, response: &Response) -> Result<Reply> {
+        Ok(Reply {
+            bytes: encode(response, self.kept_alive)?,
+            close: !self.kept_alive,
+        })
+    }
+
+    /// Replies with the last response on the connection.
+    fn close_with<'c>(&self, response: &Response, framed: bool) -> Result<Action<'c>> {
+        Ok(Action::Reply(Reply {
+            bytes: encode(response, framed)?,
+            close: true,
+        }))
+    }
+}
+
+fn encode(response: &Response, framed: bool) -> Result<Vec<u8>> {
+    let mut bytes = Vec::new();
+    if framed {
+        response.write_framed(&mut bytes)?;
+    } else {
+        response.write(&mut bytes)?;
+    }
+    Ok(bytes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::frontend::test_util::{auth_options, client, shared};
+    use crate::{RateLimit, ServerOptions};
+
+    fn auth(session: &mut Session, token: &'static str) -> Action<'static> {
+        session.on_read(Ok(Some(Cmd::Auth(token.into())))).unwrap()
+    }
+
+    #[test]
+    fn closes_after_auth_failures() {
+        let (shared, _dir) = shared(auth_options());
+        let mut session = Session::new(&shared, client(), None);
+        assert!(matches!(auth(&mut session, "wrong"), Action::Read));
+        assert!(matches!(auth(&mut session, "secret"), Action::Read));
+        assert!(matches!(auth(&mut session, "wrong"), Action::Read));
+        assert!(matches!(
+            auth(&mut session, "wrong"),
+            Action::Reply(Reply { close: true, .. })
+        ));
+    }
+
+    #[test]
+    fn rate_limits_auth() {
+        let limit = RateLimit {
+            per_sec: 1,
+            burst: 1,
+        };
+        let (shared, _dir) = shared(ServerOptions {
+            rate_limit: Some(limit),
+            ..auth_options()
+        });
+        let mut session = Session::new(&shared, client(), None);
+        assert!(matches!(auth(&mut session, "secret"), Action::Read));
+        // The token used up the client's limit, so the command after it is rejected.
+        let busy = session.on_read(Ok(Some(Cmd::Ping))).unwrap();
+        assert!(matches!(busy, Action::Reply(Reply { close: true, .. })));
+    }
+}
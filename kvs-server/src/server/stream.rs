@@ -0,0 +1,238 @@
+//! A connection to a [`Server`][crate::Server], over plain TCP, TLS or a Unix socket.
+
+use std::io::{self, Read, Write};
+use std::net::{IpAddr, Shutdown, SocketAddr, TcpListener, TcpStream};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::time::{Duration, Instant};
+
+use anyhow::{Context, Result};
+use rustls::{ServerConnection, StreamOwned};
+
+use super::connections::Counter;
+use crate::ServerTls;
+
+/// A socket the server accepts connections on.
+pub(crate) enum Listener {
+    Tcp(TcpListener),
+    Unix(UnixListener),
+}
+
+impl Listener {
+    pub(crate) fn accept(&self) -> io::Result<Socket> {
+        match self {
+            Self::Tcp(listener) => listener.accept().map(|(stream, _)| Socket::Tcp(stream)),
+            Self::Unix(listener) => listener.accept().map(|(stream, _)| Socket::Unix(stream)),
+        }
+    }
+}
+
+/// An accepted connection, before any TLS handshake.
+#[derive(Debug)]
+pub(crate) enum Socket {
+    Tcp(TcpStream),
+    Unix(UnixStream),
+}
+
+impl Socket {
+    pub(crate) fn try_clone(&self) -> io::Result<Self> {
+        match self {
+            Self::Tcp(stream) => stream.try_clone().map(Self::Tcp),
+            Self::Unix(stream) => stream.try_clone().map(Self::Unix),
+        }
+    }
+
+    pub(crate) fn shutdown(&self, how: Shutdown) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.shutdown(how),
+            Self::Unix(stream) => stream.shutdown(how),
+        }
+    }
+
+    /// The client's IP, if it connected over TCP.
+    pub(crate) fn peer_ip(&self) -> Option<IpAddr> {
+        match self {
+            Self::Tcp(stream) => stream.peer_addr().ok().map(|addr| addr.ip()),
+            Self::Unix(_) => None,
+        }
+    }
+
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Tcp(stream) => stream.set_read_timeout(timeout),
+            Self::Unix(stream) => stream.set_read_timeout(timeout),
+        }
+    }
+}
+
+pub(crate) enum Stream {
+    Plain(Socket),
+    Tls(Box<StreamOwned<ServerConnection, TcpStream>>),
+}
+
+impl Stream {
+    /// Wraps an accepted connection, completing a TLS handshake first if the server uses TLS.
+    /// Connections over a Unix socket never use TLS, since they don't leave the host. The
+    /// handshake has to finish within the read timeout.
+    pub(crate) fn accept(
+        socket: Socket,
+        tls: Option<&ServerTls>,
+        read_timeout: Duration,
+    ) -> Result<Self> {
+        let (tls, mut stream) = match (tls, socket) {
+            (Some(tls), Socket::Tcp(stream)) => (tls, stream),
+            (_, socket) => return Ok(Self::Plain(socket)),
+        };
+
+        let mut connection =
+            ServerConnection::new(tls.config()).context("Failed to start TLS session")?;
+        let mut deadline = Deadline::new(&mut stream, read_timeout, read_timeout);
+        while connection.is_handshaking() {
+            connection
+                .complete_io(&mut deadline)
+                .context("TLS handshake failed")?;
+        }
+        Ok(Self::Tls(Box::new(StreamOwned::new(connection, stream))))
+    }
+
+    /// The client's address, if it connected over TCP.
+    pub(crate) fn peer_addr(&self) -> Option<SocketAddr> {
+        match self {
+            Self::Plain(Socket::Tcp(stream)) => stream.peer_addr().ok(),
+            Self::Plain(Socket::Unix(_)) => None,
+            Self::Tls(stream) => stream.sock.peer_addr().ok(),
+        }
+    }
+
+    /// Tells a TLS client that the server won't send anything else, so it can tell the response
+    /// wasn't cut short.
+    pub(crate) fn close(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(_) => Ok(()),
+            Self::Tls(stream) => {
+                stream.conn.send_close_notify();
+                stream.flush()
+            }
+        }
+    }
+}
+
+impl Read for Stream {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(Socket::Tcp(stream)) => stream.read(buf),
+            Self::Plain(Socket::Unix(stream)) => stream.read(buf),
+            // Clients that close without a TLS close_notify, and connections closed for the server
+            // to shut down, end like a plain TCP connection would. Commands carry their lengths,
+            // so a command that's cut short is still detected.
+            Self::Tls(stream) => match stream.read(buf) {
+                Err(e) if e.kind() == io::ErrorKind::UnexpectedEof => Ok(0),
+                result => result,
+            },
+        }
+    }
+}
+
+impl Write for Stream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        match self {
+            Self::Plain(Socket::Tcp(stream)) => stream.write(buf),
+            Self::Plain(Socket::Unix(stream)) => stream.write(buf),
+            Self::Tls(stream) => stream.write(buf),
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self {
+            Self::Plain(Socket::Tcp(stream)) => stream.flush(),
+            Self::Plain(Socket::Unix(stream)) => stream.flush(),
+            Self::Tls(stream) => stream.flush(),
+        }
+    }
+}
+
+/// A stream whose reads can time out.
+pub(crate) trait ReadTimeout {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()>;
+}
+
+impl ReadTimeout for TcpStream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        TcpStream::set_read_timeout(self, timeout)
+    }
+}
+
+impl ReadTimeout for Stream {
+    fn set_read_timeout(&self, timeout: Option<Duration>) -> io::Result<()> {
+        match self {
+            Self::Plain(socket) => socket.set_read_timeout(timeout),
+            Self::Tls(stream) => stream.sock.set_read_timeout(timeout),
+        }
+    }
+}
+
+/// Reads a command from a stream by a deadline, unlike a read timeout, which a client can keep
+/// from expiring by sending a byte at a time. The client can wait before it starts sending the
+/// command, then has the read timeout to send the rest. Reads after the deadline fail with
+/// [`io::ErrorKind::TimedOut`].
+pub(crate) struct Deadline<'a, S> {
+    stream: &'a mut S,
+    deadline: Instant,
+    read_timeout: Duration,
+    /// Whether any bytes of the command have been read.
+    started: bool,
+    /// Counts the thread while it waits for the command to start, if it's set.
+    idle: Option<&'a Counter>,
+}
+
+impl<'a, S: ReadTimeout> Deadline<'a, S> {
+    pub(crate) fn new(stream: &'a mut S, wait: Duration, read_timeout: Duration) -> Self {
+        Self {
+            stream,
+            deadline: Instant::now() + wait,
+            read_timeout,
+            started: false,
+            idle: None,
+        }
+    }
+
+    /// Counts the thread reading in `idle` while it waits for the client to start sending a
+    /// command.
+    pub(crate) fn counting_idle(self, idle: Option<&'a Counter>) -> Self {
+        Self { idle, ..self }
+    }
+
+    /// Starts reading the next command, which the client can wait before sending.
+    pub(crate) fn next_command(&mut self, wait: Duration) {
+        self.deadline = Instant::now() + wait;
+        self.started = false;
+    }
+}
+
+impl<S: Read + ReadTimeout> Read for Deadline<'_, S> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let remaining = self.deadline.saturating_duration_since(Instant::now());
+        if remaining.is_zero() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        self.stream.set_read_timeout(Some(remaining))?;
+        let idle = self.idle.filter(|_| !self.started).map(Counter::add);
+        let read = self.stream.read(buf);
+        drop(idle);
+        let read = read?;
+        if read > 0 && !self.started {
+            self.started = true;
+            self.deadline = self.deadline.min(Instant::now() + self.read_timeout);
+        }
+        Ok(read)
+    }
+}
+
+impl<S: Write> Write for Deadline<'_, S> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.stream.write(buf)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()
+    }
+}
@@ -0,0 +1,169 @@
+//! Thread pools for running jobs, such as handling connections, concurrently.
+
+use std::fmt;
+use std::str::FromStr;
+
+use anyhow::{anyhow, Error, Result};
+
+pub use naive::NaiveThreadPool;
+pub use shared_queue::SharedQueueThreadPool;
+pub use work_stealing::WorkStealingThreadPool;
+
+mod naive;
+mod shared_queue;
+mod work_stealing;
+
+/// A pool of threads that jobs can be spawned onto.
+pub trait ThreadPool: Sized {
+    /// Creates a pool that runs jobs on the specified number of threads.
+    fn new(threads: usize) -> Result<Self>;
+
+    /// Runs the job on one of the pool's threads. A job that panics doesn't take down the pool.
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static;
+}
+
+/// Represents a type of [`ThreadPool`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PoolType {
+    Naive,
+    SharedQueue,
+    WorkStealing,
+}
+
+impl fmt::Display for PoolType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Naive => f.write_str("naive"),
+            Self::SharedQueue => f.write_str("shared-queue"),
+            Self::WorkStealing => f.write_str("work-stealing"),
+        }
+    }
+}
+
+impl FromStr for PoolType {
+    type Err = Error;
+
+    fn from_str(str: &str) -> Result<Self, Self::Err> {
+        match str {
+            "naive" => Ok(Self::Naive),
+            "shared-queue" => Ok(Self::SharedQueue),
+            "work-stealing" => Ok(Self::WorkStealing),
+            other => Err(anyhow!("unknown pool type {other:?}")),
+        }
+    }
+}
+
+/// Static dispatch enum for `ThreadPool` implementations.
+pub enum Pool {
+    Naive(NaiveThreadPool),
+    SharedQueue(SharedQueueThreadPool),
+    WorkStealing(WorkStealingThreadPool),
+}
+
+impl Pool {
+    /// Creates a pool of the specified type with the specified number of threads.
+    pub fn new(pool_type: PoolType, threads: usize) -> Result<Self> {
+        match pool_type {
+            PoolType::Naive => Ok(Self::Naive(NaiveThreadPool::new(threads)?)),
+            PoolType::SharedQueue => Ok(Self::SharedQueue(SharedQueueThreadPool::new(threads)?)),
+            PoolType::WorkStealing => Ok(Self::WorkStealing(WorkStealingThreadPool::new(threads)?)),
+        }
+    }
+
+    /// Reports the type of the pool.
+    pub fn pool_type(&self) -> PoolType {
+        match self {
+            Self::Naive(_) => PoolType::Naive,
+            Self::SharedQueue(_) => PoolType::SharedQueue,
+            Self::WorkStealing(_) => PoolType::WorkStealing,
+        }
+    }
+
+    /// Most jobs the pool runs at once, or `None` if every job gets its own thread.
+    pub fn threads(&self) -> Option<usize> {
+        match self {
+            Self::Naive(_) => None,
+            Self::SharedQueue(p) => Some(p.threads()),
+            Self::WorkStealing(p) => Some(p.threads()),
+        }
+    }
+
+    /// Runs the job on one of the pool's threads.
+    pub fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        match self {
+            Self::Naive(p) => p.spawn(job),
+            Self::SharedQueue(p) => p.spawn(job),
+            Self::WorkStealing(p) => p.spawn(job),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::sync::{mpsc, Arc};
+
+    use super::*;
+
+    fn runs_every_job(pool_type: PoolType) {
+        let pool = Pool::new(pool_type, 4).unwrap();
+        if pool_type != PoolType::Naive {
+            assert_eq!(pool.threads(), Some(4));
+        }
+        let count = Arc::new(AtomicUsize::new(0));
+        let (sender, receiver) = mpsc::channel();
+
+        for _ in 0..100 {
+            let count = Arc::clone(&count);
+            let sender = sender.clone();
+            pool.spawn(move || {
+                count.fetch_add(1, Ordering::SeqCst);
+                sender.send(()).unwrap();
+            });
+        }
+        for _ in 0..100 {
+            receiver.recv().unwrap();
+        }
+
+        assert_eq!(count.load(Ordering::SeqCst), 100);
+    }
+
+    fn survives_panics(pool_type: PoolType) {
+        let pool = Pool::new(pool_type, 2).unwrap();
+        for _ in 0..4 {
+            pool.spawn(|| panic!("job panicked"));
+        }
+
+        let (sender, receiver) = mpsc::channel();
+        for _ in 0..4 {
+            let sender = sender.clone();
+            pool.spawn(move || sender.send(()).unwrap());
+        }
+        for _ in 0..4 {
+            receiver.recv().unwrap();
+        }
+    }
+
+    #[test]
+    fn naive() {
+        runs_every_job(PoolType::Naive);
+        survives_panics(PoolType::Naive);
+    }
+
+    #[test]
+    fn shared_queue() {
+        runs_every_job(PoolType::SharedQueue);
+        survives_panics(PoolType::SharedQueue);
+    }
+
+    #[test]
+    fn work_stealing() {
+        runs_every_job(PoolType::WorkStealing);
+        survives_panics(PoolType::WorkStealing);
+    }
+}
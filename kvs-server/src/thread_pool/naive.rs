@@ -0,0 +1,25 @@
+//! A [`ThreadPool`] that spawns a new thread for every job.
+
+use std::thread;
+
+use anyhow::Result;
+
+use super::ThreadPool;
+
+/// A [`ThreadPool`] that isn't really a pool: every job gets its own, new, thread. The number of
+/// threads is ignored.
+pub struct NaiveThreadPool;
+
+impl ThreadPool for NaiveThreadPool {
+    fn new(_threads: usize) -> Result<Self> {
+        Ok(Self)
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        // A panic only takes down the job's own thread.
+        thread::spawn(job);
+    }
+}
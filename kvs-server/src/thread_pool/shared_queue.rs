@@ -0,0 +1,92 @@
+//! A [`ThreadPool`] whose threads all pull jobs from a single queue.
+
+use std::panic::{self, AssertUnwindSafe};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+
+use anyhow::{ensure, Context, Result};
+use tracing::{debug, warn};
+
+use super::ThreadPool;
+
+type Job = Box<dyn FnOnce() + Send + 'static>;
+
+/// A [`ThreadPool`] with a fixed number of threads that take turns pulling jobs off of a shared
+/// queue.
+///
+/// Dropping the pool waits for every queued job to finish.
+pub struct SharedQueueThreadPool {
+    sender: Option<mpsc::Sender<Job>>,
+    workers: Vec<JoinHandle<()>>,
+}
+
+impl ThreadPool for SharedQueueThreadPool {
+    fn new(threads: usize) -> Result<Self> {
+        ensure!(threads > 0, "Thread pool needs at least one thread");
+
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let receiver = Arc::new(Mutex::new(receiver));
+        let workers = (0..threads)
+            .map(|i| {
+                let receiver = Arc::clone(&receiver);
+                thread::Builder::new()
+                    .name(format!("shared-queue-{i}"))
+                    .spawn(move || Self::work(&receiver))
+                    .context("spawning worker thread")
+            })
+            .collect::<Result<_>>()?;
+
+        Ok(Self {
+            sender: Some(sender),
+            workers,
+        })
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        let sender = self.sender.as_ref().expect("sender lives until drop");
+        sender
+            .send(Box::new(job))
+            .expect("workers live until the sender is dropped");
+    }
+}
+
+impl SharedQueueThreadPool {
+    /// How many threads the pool runs jobs on.
+    pub fn threads(&self) -> usize {
+        self.workers.len()
+    }
+
+    /// Runs jobs from the queue until the pool is dropped.
+    fn work(receiver: &Mutex<mpsc::Receiver<Job>>) {
+        loop {
+            // Only hold the lock while waiting for a job, not while running it.
+            let job = match receiver.lock() {
+                Ok(receiver) => receiver.recv(),
+                Err(_) => return,
+            };
+            let Ok(job) = job else {
+                debug!("Thread pool dropped, stopping worker");
+                return;
+            };
+
+            if panic::catch_unwind(AssertUnwindSafe(job)).is_err() {
+                warn!("Job panicked");
+            }
+        }
+    }
+}
+
+impl Drop for SharedQueueThreadPool {
+    fn drop(&mut self) {
+        // Closing the queue tells the workers to stop once it's empty.
+        drop(self.sender.take());
+        for worker in self.workers.drain(..) {
+            if worker.join().is_err() {
+                warn!("Worker thread panicked");
+            }
+        }
+    }
+}
@@ -0,0 +1,36 @@
+//! A [`ThreadPool`] whose threads steal jobs from each other, backed by `rayon`.
+
+use anyhow::{Context, Result};
+
+use super::ThreadPool;
+
+/// A [`ThreadPool`] where each thread has its own queue of jobs and idle threads steal jobs from
+/// busy threads' queues.
+pub struct WorkStealingThreadPool(rayon::ThreadPool);
+
+impl WorkStealingThreadPool {
+    /// How many threads the pool runs jobs on.
+    pub fn threads(&self) -> usize {
+        self.0.current_num_threads()
+    }
+}
+
+impl ThreadPool for WorkStealingThreadPool {
+    fn new(threads: usize) -> Result<Self> {
+        let pool = rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .thread_name(|i| format!("work-stealing-{i}"))
+            // Without a handler, rayon aborts the process when a job panics.
+            .panic_handler(|_| tracing::warn!("Job panicked"))
+            .build()
+            .context("building rayon thread pool")?;
+        Ok(Self(pool))
+    }
+
+    fn spawn<F>(&self, job: F)
+    where
+        F: FnOnce() + Send + 'static,
+    {
+        self.0.spawn(job);
+    }
+}
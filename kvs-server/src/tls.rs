@@ -0,0 +1,125 @@
+//! TLS for connections to the server.
+
+use std::fmt;
+use std::path::Path;
+use std::sync::Arc;
+
+use anyhow::{ensure, Context, Result};
+use rustls::crypto::ring;
+use rustls::pki_types::pem::PemObject;
+use rustls::pki_types::{CertificateDer, PrivateKeyDer};
+use rustls::server::WebPkiClientVerifier;
+use rustls::{RootCertStore, ServerConfig};
+
+/// The certificate and key the server presents to clients and, for mutual TLS, the CA
+/// certificates that clients' certificates must be signed by.
+#[derive(Clone)]
+pub struct ServerTls {
+    config: Arc<ServerConfig>,
+    verifies_clients: bool,
+}
+
+impl ServerTls {
+    /// Reads the server's certificate chain and private key from PEM files. If there's a client
+    /// CA file, clients must present a certificate signed by one of the CA certificates in it.
+    pub fn from_files(
+        cert: impl AsRef<Path>,
+        key: impl AsRef<Path>,
+        client_ca: Option<&Path>,
+    ) -> Result<Self> {
+        let certs = read_certs(cert.as_ref())?;
+        let key = PrivateKeyDer::from_pem_file(key.as_ref())
+            .with_context(|| format!("Failed to read TLS key file {}", key.as_ref().display()))?;
+
+        let provider = Arc::new(ring::default_provider());
+        let builder = ServerConfig::builder_with_provider(Arc::clone(&provider))
+            .with_safe_default_protocol_versions()
+            .context("Failed to configure TLS")?;
+        let builder = match client_ca {
+            Some(client_ca) => {
+                let roots = read_roots(client_ca)?;
+                let verifier =
+                    WebPkiClientVerifier::builder_with_provider(Arc::new(roots), provider)
+                        .build()
+                        .context("Failed to configure client certificate verification")?;
+                builder.with_client_cert_verifier(verifier)
+            }
+            None => builder.with_no_client_auth(),
+        };
+        let config = builder
+            .with_single_cert(certs, key)
+            .context("The TLS certificate doesn't match the key")?;
+
+        Ok(Self {
+            config: Arc::new(config),
+            verifies_clients: client_ca.is_some(),
+        })
+    }
+
+    pub(crate) fn config(&self) -> Arc<ServerConfig> {
+        Arc::clone(&self.config)
+    }
+}
+
+impl fmt::Debug for ServerTls {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("ServerTls")
+            .field("verifies_clients", &self.verifies_clients)
+            .finish_non_exhaustive()
+    }
+}
+
+/// Reads every certificate in a PEM file, which must have at least one.
+fn read_certs(path: &Path) -> Result<Vec<CertificateDer<'static>>> {
+    let certs = CertificateDer::pem_file_iter(path)
+        .and_then(|certs| certs.collect::<Result<Vec<_>, _>>())
+        .with_context(|| format!("Failed to read TLS certificate file {}", path.display()))?;
+    ensure!(
+        !certs.is_empty(),
+        "No certificates in TLS certificate file {}",
+        path.display()
+    );
+    Ok(certs)
+}
+
+/// Reads CA certificates to trust from a PEM file.
+fn read_roots(path: &Path) -> Result<RootCertStore> {
+    let mut roots = RootCertStore::empty();
+    for cert in read_certs(path)? {
+        roots
+            .add(cert)
+            .with_context(|| format!("Invalid CA certificate in {}", path.display()))?;
+    }
+    Ok(roots)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn reads_pem_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let path = |name: &str| dir.path().join(name);
+        let server = rcgen::generate_simple_self_signed(["127.0.0.1".to_owned()]).unwrap();
+        let other = rcgen::generate_simple_self_signed(["127.0.0.1".to_owned()]).unwrap();
+        std::fs::write(path("server.pem"), server.cert.pem()).unwrap();
+        std::fs::write(path("server.key"), server.key_pair.serialize_pem()).unwrap();
+        std::fs::write(path("other.key"), other.key_pair.serialize_pem()).unwrap();
+        std::fs::write(path("empty.pem"), "").unwrap();
+
+        let tls = ServerTls::from_files(path("server.pem"), path("server.key"), None).unwrap();
+        assert!(!tls.verifies_clients);
+        let tls = ServerTls::from_files(
+            path("server.pem"),
+            path("server.key"),
+            Some(&path("server.pem")),
+        )
+        .unwrap();
+        assert!(tls.verifies_clients);
+
+        assert!(ServerTls::from_files(path("server.pem"), path("other.key"), None).is_err());
+        assert!(ServerTls::from_files(path("empty.pem"), path("server.key"), None).is_err());
+        assert!(ServerTls::from_files(path("missing.pem"), path("server.key"), None).is_err());
+    }
+}
@@ -0,0 +1,93 @@
+//! Unix domain sockets for connections from the same host.
+
+use std::fs::{self, Permissions};
+use std::io;
+use std::os::unix::fs::{FileTypeExt, PermissionsExt};
+use std::os::unix::net::{UnixListener, UnixStream};
+use std::path::PathBuf;
+
+use anyhow::{bail, Context, Result};
+use tracing::{debug, warn};
+
+/// A Unix socket for the server to listen on, alongside or instead of its TCP address. Only users
+/// the socket file's permissions let write to it can connect.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct UnixSocket {
+    pub path: PathBuf,
+    /// Permission bits for the socket file, e.g. `0o660` to let its owner and group connect.
+    pub mode: u32,
+}
+
+impl UnixSocket {
+    /// The default permissions, letting the socket's owner and group connect.
+    pub const DEFAULT_MODE: u32 = 0o660;
+
+    /// A socket at the path with the default permissions.
+    pub fn new(path: impl Into<PathBuf>) -> Self {
+        Self {
+            path: path.into(),
+            mode: Self::DEFAULT_MODE,
+        }
+    }
+
+    /// Binds the socket and sets its permissions. A socket file left behind by a server that
+    /// didn't shut down cleanly is replaced, but not one another server is still listening on.
+    pub(crate) fn bind(&self) -> Result<UnixListener> {
+        let path = &self.path;
+        match fs::symlink_metadata(path) {
+            Ok(metadata) if !metadata.file_type().is_socket() => {
+                bail!("{} exists and isn't a socket", path.display())
+            }
+            Ok(_) if UnixStream::connect(path).is_ok() => {
+                bail!("Another server is listening on {}", path.display())
+            }
+            Ok(_) => {
+                debug!(?path, "Removing stale socket");
+                fs::remove_file(path)
+                    .with_context(|| format!("Failed to remove stale socket {}", path.display()))?;
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => {}
+            Err(e) => {
+                return Err(e).with_context(|| format!("Failed to check {}", path.display()));
+            }
+        }
+
+        let listener = UnixListener::bind(path)
+            .with_context(|| format!("Failed to bind Unix socket {}", path.display()))?;
+        fs::set_permissions(path, Permissions::from_mode(self.mode))
+            .with_context(|| format!("Failed to set permissions of {}", path.display()))?;
+        Ok(listener)
+    }
+
+    /// Removes the socket file once the server has stopped listening on it.
+    pub(crate) fn remove(&self) {
+        if let Err(e) = fs::remove_file(&self.path) {
+            warn!(?e, path = ?self.path, "Failed to remove Unix socket");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn replaces_only_stale_sockets() {
+        let dir = tempfile::tempdir().unwrap();
+        let socket = UnixSocket::new(dir.path().join("kvs.sock"));
+
+        let listener = socket.bind().unwrap();
+        let mode = fs::metadata(&socket.path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+        assert!(socket.bind().is_err());
+
+        drop(listener);
+        let _listener = socket.bind().unwrap();
+        socket.remove();
+        assert!(!socket.path.exists());
+
+        fs::write(&socket.path, "data").unwrap();
+        assert!(socket.bind().is_err());
+        assert_eq!(fs::read_to_string(&socket.path).unwrap(), "data");
+    }
+}
@@ -7,4 +7,8 @@ pub trait KvsEngine {
     // TODO Why does this take &mut self?
     fn get<K: Borrow<str>>(&mut self, key: K) -> Result<Option<String>>;
     fn remove<K: Borrow<str>>(&mut self, key: K) -> Result<()>;
+    /// Lists every key that currently has a value, in no particular order.
+    fn keys(&self) -> Result<Vec<String>>;
+    /// Makes every completed write durable, e.g. before the process exits.
+    fn flush(&mut self) -> Result<()>;
 }
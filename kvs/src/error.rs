@@ -1,2 +1,17 @@
+use std::fmt;
+
 // TODO `thiserror`
 pub use anyhow::{Error, Result};
+
+/// Error returned when removing a key that isn't present in the store. This can be recovered from
+/// an [`Error`] with [`Error::is`] or [`Error::downcast_ref`].
+#[derive(Debug)]
+pub struct KeyNotFound;
+
+impl fmt::Display for KeyNotFound {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.write_str("Key not found")
+    }
+}
+
+impl std::error::Error for KeyNotFound {}
@@ -0,0 +1,109 @@
+//! Headers identifying the format of the log files a [`KvStore`][crate::KvStore] writes.
+//!
+//! Every log file written by this version of the store starts with a [`FileHeader`]. Log files
+//! written before headers were introduced ("legacy" files) start directly with a record. Legacy
+//! files can't be confused with headered files because the magic bytes, read as the key length of
+//! a legacy record, would describe a key over a gigabyte long.
+
+use std::io::{ErrorKind, Read, Write};
+
+use anyhow::{bail, Context};
+
+use crate::Result;
+
+/// Bytes every headered log file starts with.
+const MAGIC: [u8; 4] = *b"KVSL";
+const VERSION_BYTES: usize = 2;
+const CREATED_AT_BYTES: usize = 8;
+
+/// The number of bytes a [`FileHeader`] takes up on disk.
+pub(crate) const HEADER_BYTES: usize = MAGIC.len() + VERSION_BYTES + CREATED_AT_BYTES;
+
+/// The format version written into new log files.
+pub(crate) const CURRENT_VERSION: u16 = 1;
+
+/// The layout of a log file on disk.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum FileFormat {
+    /// The file has no header and records start at the beginning of the file.
+    Legacy,
+    /// The file has a header of the specified version, after which the records start.
+    Versioned(FileHeader),
+}
+
+impl FileFormat {
+    /// The offset of the first record in the file.
+    pub(crate) fn data_start(&self) -> u64 {
+        match self {
+            Self::Legacy => 0,
+            Self::Versioned(_) => HEADER_BYTES as u64,
+        }
+    }
+}
+
+/// Describes the format of the records in a log file and when the file was created.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct FileHeader {
+    version: u16,
+    /// Seconds since the Unix epoch when the file was created.
+    created_at: i64,
+}
+
+impl FileHeader {
+    /// Creates a header of the current format version, created now.
+    pub(crate) fn new() -> Self {
+        Self {
+            version: CURRENT_VERSION,
+            created_at: time::OffsetDateTime::now_utc().unix_timestamp(),
+        }
+    }
+
+    /// Writes the header into the provided writer.
+    pub(crate) fn write(&self, mut w: impl Write) -> Result<()> {
+        let mut bytes = [0; HEADER_BYTES];
+        let (magic, rest) = bytes.split_at_mut(MAGIC.len());
+        let (version, created_at) = rest.split_at_mut(VERSION_BYTES);
+        magic.copy_from_slice(&MAGIC);
+        version.copy_from_slice(&self.version.to_be_bytes());
+        created_at.copy_from_slice(&self.created_at.to_be_bytes());
+
+        w.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Determines the format of the file the reader is positioned at the start of. Returns an `Err`
+    /// if the file was written by a newer, unsupported, format version.
+    pub(crate) fn read_format(mut r: impl Read) -> Result<FileFormat> {
+        let mut bytes = [0; HEADER_BYTES];
+        let mut total_read = 0;
+        while total_read < bytes.len() {
+            match r.read(&mut bytes[total_read..]) {
+                Ok(0) => break,
+                Ok(n) => total_read += n,
+                Err(e) if e.kind() == ErrorKind::Interrupted => continue,
+                Err(e) => return Err(e).context("reading file header"),
+            }
+        }
+
+        if total_read < MAGIC.len() || bytes[..MAGIC.len()] != MAGIC {
+            return Ok(FileFormat::Legacy);
+        }
+        if total_read < HEADER_BYTES {
+            bail!("Truncated file header");
+        }
+
+        let (_, rest) = bytes.split_at(MAGIC.len());
+        let (version, created_at) = rest.split_at(VERSION_BYTES);
+        let version = u16::from_be_bytes(version.try_into().expect("specified 2 bytes"));
+        let created_at = i64::from_be_bytes(created_at.try_into().expect("specified 8 bytes"));
+
+        if version > CURRENT_VERSION {
+            bail!("Unsupported log file format version {version}");
+        }
+
+        Ok(FileFormat::Versioned(Self {
+            version,
+            created_at,
+        }))
+    }
+}
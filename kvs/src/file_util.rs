@@ -3,6 +3,9 @@ use std::path::Path;
 
 use crate::Result;
 
+/// Extension of the log files written by a [`KvStore`][crate::KvStore].
+pub(crate) const LOG_FILE_EXTENSION: &str = "pingcap";
+
 pub(crate) fn open_file(path: impl AsRef<Path>) -> Result<File> {
     Ok(std::fs::File::options()
         .create(true)
@@ -12,11 +15,17 @@ pub(crate) fn open_file(path: impl AsRef<Path>) -> Result<File> {
 }
 
 pub(crate) fn file_name() -> String {
-    // TODO versioning
     format!(
-        "{}.pingcap",
+        "{}.{LOG_FILE_EXTENSION}",
         time::OffsetDateTime::now_utc()
             .format(&time::format_description::well_known::Rfc3339)
             .expect("RFC-3339 is a valid format")
     )
 }
+
+/// Whether the path looks like a log file written by a [`KvStore`][crate::KvStore].
+pub(crate) fn is_log_file(path: impl AsRef<Path>) -> bool {
+    path.as_ref()
+        .extension()
+        .is_some_and(|extension| extension == LOG_FILE_EXTENSION)
+}
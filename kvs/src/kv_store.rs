@@ -5,14 +5,17 @@ use std::collections::HashMap;
 use std::fs::File;
 use std::io::{Seek, SeekFrom, Write};
 use std::path::PathBuf;
+use std::time::{Duration, Instant};
 
+use anyhow::Context;
 use protocol::{Cmd, CmdReader, Reader};
 use tracing::{debug, trace};
 
 use crate::compaction_policy::{CompactionContext, CompactionPolicy, MaxFilePolicy};
 use crate::engine::KvsEngine;
+use crate::file_header::{FileFormat, FileHeader};
 use crate::file_util;
-use crate::{Error, Result};
+use crate::{KeyNotFound, Limits, Result, StoreStats};
 
 // TODO Need to find a balance between:
 //     1. Not opening too many files (i.e. larger files)
@@ -28,22 +31,50 @@ pub struct KvStore<C = MaxFilePolicy> {
     dir: PathBuf,
     immutable_files: Vec<LogFile>,
     index: HashMap<String, Index>,
+    /// Total length of the commands in the index, kept up to date so stats don't have to walk it.
+    live_bytes: u64,
+    /// Reads commands back from the log files, which were within the limits when they were
+    /// written, so the reader doesn't limit them.
     cmd_reader: Reader,
+    /// Largest key and value that can be set.
+    limits: Limits,
+    compactions: u64,
+    compaction_time: Duration,
+    syncs: u64,
 }
 struct Index {
     file_idx: usize,
     file_offset: u64,
+    /// Length of the command, in bytes.
+    len: u64,
 }
 struct LogFile {
     path: PathBuf,
     file: File,
     len: u64,
+    format: FileFormat,
 }
 impl LogFile {
+    /// Opens the log file at the path. New files are given a header of the current format version
+    /// and existing files are read according to the format their header indicates.
     fn new(path: PathBuf) -> Result<Self> {
-        file_util::open_file(&path).and_then(|file| {
-            let len = file.metadata()?.len();
-            Ok(Self { path, file, len })
+        let mut file = file_util::open_file(&path)?;
+        let mut len = file.metadata()?.len();
+        let format = if len == 0 {
+            let header = FileHeader::new();
+            header.write(&file)?;
+            len = file.metadata()?.len();
+            FileFormat::Versioned(header)
+        } else {
+            FileHeader::read_format(&mut file)
+                .with_context(|| format!("reading header of {}", path.display()))?
+        };
+
+        Ok(Self {
+            path,
+            file,
+            len,
+            format,
         })
     }
 }
@@ -63,6 +94,7 @@ impl<C> KvStore<C> {
         let mut paths = dir
             .into_iter()
             .map(|dir_entry| Ok(dir_entry.map(|dir| dir.path())?))
+            .filter(|path| path.as_ref().map_or(true, file_util::is_log_file))
             .collect::<Result<Vec<_>>>()?;
         paths.sort_unstable();
 
@@ -78,28 +110,61 @@ impl<C> KvStore<C> {
 
         let mut this = KvStore {
             index: Default::default(),
+            live_bytes: 0,
             active_file,
             immutable_files,
             dir: dir_path,
             compaction_policy,
-            cmd_reader: Default::default(),
+            cmd_reader: Reader::with_limits(Limits::NONE),
+            limits: Limits::default(),
+            compactions: 0,
+            compaction_time: Duration::ZERO,
+            syncs: 0,
         };
 
         this.hydrate()?;
+        this.live_bytes = this.index.values().map(|index| index.len).sum();
 
         Ok(this)
     }
 
+    /// Sets the largest key and value [`KvsEngine::set`] accepts. Larger ones get a
+    /// [`TooLarge`][crate::TooLarge] error. Data already in the store is unaffected.
+    pub fn with_limits(mut self, limits: Limits) -> Self {
+        self.limits = limits;
+        self
+    }
+
+    /// Describes the store's files.
+    pub fn stats(&self) -> StoreStats {
+        let files = || {
+            self.immutable_files
+                .iter()
+                .chain(std::iter::once(&self.active_file))
+        };
+        let total_bytes: u64 = files().map(|f| f.len - f.format.data_start()).sum();
+
+        StoreStats {
+            live_keys: self.index.len(),
+            files: self.immutable_files.len() + 1,
+            dead_bytes: total_bytes.saturating_sub(self.live_bytes),
+            disk_bytes: files().map(|f| f.len).sum(),
+            compactions: self.compactions,
+            compaction_time: self.compaction_time,
+            syncs: self.syncs,
+        }
+    }
+
     /// Builds an index of log pointers from the stored path. After this, gets are optimized to
     /// just read the most recent command for the key in the file.
     fn hydrate(&mut self) -> Result<()> {
         for (file_idx, f) in self.immutable_files.iter_mut().enumerate() {
-            Self::hydrate_file(&mut self.index, &mut self.cmd_reader, &mut f.file, file_idx)?;
+            Self::hydrate_file(&mut self.index, &mut self.cmd_reader, f, file_idx)?;
         }
         Self::hydrate_file(
             &mut self.index,
             &mut self.cmd_reader,
-            &mut self.active_file.file,
+            &mut self.active_file,
             ACTIVE_FILE_IDX,
         )?;
         Ok(())
@@ -108,24 +173,26 @@ impl<C> KvStore<C> {
     fn hydrate_file(
         in_memory_index: &mut HashMap<String, Index>,
         reader: &mut Reader,
-        file: &mut File,
+        log_file: &mut LogFile,
         file_idx: usize,
     ) -> Result<()> {
-        file.rewind()?;
+        let mut file_offset = log_file.format.data_start();
+        let file = &mut log_file.file;
+        file.seek(SeekFrom::Start(file_offset))?;
 
-        let mut file_offset = 0;
         while let Some(read_result) = reader.read_cmd(&mut *file)? {
             let bytes_read = read_result.bytes_read();
             let index = Index {
                 file_idx,
                 file_offset,
+                len: bytes_read as u64,
             };
             match read_result.into_cmd() {
                 Cmd::Set(key, _) => in_memory_index.insert(key.into_owned(), index),
                 Cmd::Rm(key) => in_memory_index.remove(key.as_ref()),
 
                 // TODO Should there be another type to prevent this confusion?
-                Cmd::Get(_) => panic!("Found Get command stored in file!"),
+                other => panic!("Found {other:?} command stored in file!"),
             };
 
             file_offset += bytes_read as u64;
@@ -134,6 +201,38 @@ impl<C> KvStore<C> {
         Ok(())
     }
 
+    /// Makes the active file immutable and starts writing to a new one. The active file is synced
+    /// first, since immutable files aren't synced again.
+    fn rotate_active_file(&mut self) -> Result<()> {
+        self.active_file.file.sync_data()?;
+        self.syncs += 1;
+        let next_file = self.dir.join(file_util::file_name());
+        let file = LogFile::new(next_file)?;
+        let old_file = std::mem::replace(&mut self.active_file, file);
+        self.immutable_files.push(old_file);
+
+        // Any indexed values for the active file now get moved to reference the immutable file
+        // list.
+        for file_index in self.index.values_mut() {
+            if file_index.file_idx == ACTIVE_FILE_IDX {
+                file_index.file_idx = self.immutable_files.len() - 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// Compacts every log file now, regardless of the compaction policy, so that only the latest
+    /// value of each key is left on disk.
+    pub fn compact(&mut self) -> Result<()> {
+        if self.active_file.len > self.active_file.format.data_start() {
+            self.rotate_active_file()?;
+        }
+        if self.immutable_files.is_empty() {
+            return Ok(());
+        }
+        self.compactify()
+    }
+
     // TODO More atomically? How do we handle concurrent compaction requests? Should probably take
     // `&self` or work on a separate thread or something.
     // One option here could be to:
@@ -150,6 +249,8 @@ impl<C> KvStore<C> {
     // TODO hint file?
     // https://github.com/basho/bitcask/blob/develop/doc/bitcask-intro.pdf
     fn compactify(&mut self) -> Result<()> {
+        let start = Instant::now();
+
         // TODO Hack to ensure we don't consider this file active the next time around
         let compacted_file_name = format!("0000-{}", file_util::file_name());
         let mut compacted_path = self.dir.clone();
@@ -166,7 +267,8 @@ impl<C> KvStore<C> {
             let mut file = &self.immutable_files[file_index.file_idx].file;
 
             file.seek(SeekFrom::Start(file_index.file_offset))?;
-            let cmd_bytes_len = CmdReader::new(file).read_cmd_bytes(&mut cmd_reader_buf)?;
+            let cmd_bytes_len =
+                CmdReader::with_limits(file, Limits::NONE).read_cmd_bytes(&mut cmd_reader_buf)?;
 
             // TODO impl Read for CmdReader and use std::io::copy
             let file_offset = compacted_file.len;
@@ -178,13 +280,21 @@ impl<C> KvStore<C> {
             *file_index = Index {
                 file_idx: 0,
                 file_offset,
+                len: cmd_bytes_len as u64,
             };
         }
+        // The compacted file and its directory entry have to be on disk before the files it
+        // replaces are removed.
+        compacted_file.file.sync_data()?;
+        File::open(&self.dir)?.sync_all()?;
+        self.syncs += 2;
         for log_file in self.immutable_files.drain(..) {
             std::fs::remove_file(log_file.path)?;
         }
         self.immutable_files.push(compacted_file);
 
+        self.compactions += 1;
+        self.compaction_time += start.elapsed();
         Ok(())
     }
 }
@@ -202,29 +312,23 @@ impl<C: CompactionPolicy> KvStore<C> {
             // TODO Should there be another type to prevent this confusion?
             Cmd::Rm(key) | Cmd::Get(key) => key,
             Cmd::Set(key, _) => key,
+            other => panic!("{other:?} commands shouldn't be written!"),
         };
 
         let index = Index {
             file_offset,
             file_idx: ACTIVE_FILE_IDX,
+            len: len as u64,
         };
 
-        self.index.insert(key.into_owned(), index);
+        if let Some(replaced) = self.index.insert(key.into_owned(), index) {
+            self.live_bytes -= replaced.len;
+        }
+        self.live_bytes += len as u64;
 
         // TODO Configure?
         if file_offset > FILE_SIZE_LIMIT {
-            let next_file = self.dir.join(file_util::file_name());
-            let file = LogFile::new(next_file)?;
-            let old_file = std::mem::replace(&mut self.active_file, file);
-            self.immutable_files.push(old_file);
-
-            // Any indexed values for the active file now get moved to reference the immutable file
-            // list.
-            for file_index in self.index.values_mut() {
-                if file_index.file_idx == ACTIVE_FILE_IDX {
-                    file_index.file_idx = self.immutable_files.len() - 1;
-                }
-            }
+            self.rotate_active_file()?;
         }
 
         let state = CompactionContext {
@@ -246,6 +350,7 @@ impl<C: CompactionPolicy> KvsEngine for KvStore<C> {
             Some(Index {
                 file_offset,
                 file_idx,
+                ..
             }) => {
                 let mut file = match *file_idx {
                     ACTIVE_FILE_IDX => &self.active_file.file,
@@ -264,7 +369,7 @@ impl<C: CompactionPolicy> KvsEngine for KvStore<C> {
                     Cmd::Set(_, value) => Ok(Some(value.into_owned())),
                     Cmd::Rm(_) => panic!("Rm'ved keys shouldn't be in the index!"),
                     // TODO Should there be another type to prevent this confusion?
-                    Cmd::Get(_) => panic!("Get commands shouldn't be written!"),
+                    other => panic!("{other:?} commands shouldn't be written!"),
                 }
             }
             None => Ok(None),
@@ -272,8 +377,10 @@ impl<C: CompactionPolicy> KvsEngine for KvStore<C> {
     }
 
     /// Associate the passed value with the passed key in the store. This can later be retrieved
-    /// with `get`.
+    /// with `get`. Keys and values larger than the store's limits are rejected.
     fn set<V: AsRef<str>>(&mut self, key: String, value: V) -> Result<()> {
+        self.limits.check_key(key.len() as u64)?;
+        self.limits.check_value(value.as_ref().len() as u64)?;
         let cmd = Cmd::Set(key.into(), Cow::Borrowed(value.as_ref()));
         self.write_cmd(cmd)
     }
@@ -286,14 +393,28 @@ impl<C: CompactionPolicy> KvsEngine for KvStore<C> {
                 let result = self.write_cmd(Cmd::Rm(key.borrow().into()));
                 if result.is_ok() {
                     trace!(key = ?key.borrow(), "Removing key from in-memory index");
-                    self.index.remove(key.borrow());
+                    if let Some(removed) = self.index.remove(key.borrow()) {
+                        self.live_bytes -= removed.len;
+                    }
                 }
                 result
             }
             None => {
                 debug!("Key to remove not found");
-                Err(Error::msg("Key not found"))
+                Err(KeyNotFound.into())
             }
         }
     }
+
+    /// Lists every key currently associated with a value.
+    fn keys(&self) -> Result<Vec<String>> {
+        Ok(self.index.keys().cloned().collect())
+    }
+
+    /// Syncs the active file to disk. Immutable files were synced when compacted or rotated out.
+    fn flush(&mut self) -> Result<()> {
+        self.active_file.file.sync_data()?;
+        self.syncs += 1;
+        Ok(())
+    }
 }
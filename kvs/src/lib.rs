@@ -6,9 +6,16 @@
 mod compaction_policy;
 mod engine;
 mod error;
+mod file_header;
 mod file_util;
 mod kv_store;
+mod stats;
+mod upgrade;
 
+pub use compaction_policy::{CompactionPolicy, MaxFilePolicy, NeverPolicy};
 pub use engine::KvsEngine;
-pub use error::{Error, Result};
+pub use error::{Error, KeyNotFound, Result};
 pub use kv_store::KvStore;
+pub use protocol::{Limits, TooLarge};
+pub use stats::StoreStats;
+pub use upgrade::upgrade_dir;
@@ -0,0 +1,24 @@
+//! Statistics describing a [`KvStore`][crate::KvStore]'s files, e.g. for monitoring.
+
+use std::time::Duration;
+
+/// A snapshot of how much of a store's data is live and how much compaction has reclaimed.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct StoreStats {
+    /// Number of keys currently associated with a value.
+    pub live_keys: usize,
+    /// Number of log files, including the active file.
+    pub files: usize,
+    /// Bytes in the log files taken up by overwritten values and removals, which compaction
+    /// would reclaim.
+    pub dead_bytes: u64,
+    /// Total size of the log files, in bytes.
+    pub disk_bytes: u64,
+    /// Number of compactions run since the store was opened.
+    pub compactions: u64,
+    /// Total time spent in those compactions.
+    pub compaction_time: Duration,
+    /// Number of times a file or the store's directory was synced to disk since the store was
+    /// opened.
+    pub syncs: u64,
+}
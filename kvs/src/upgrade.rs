@@ -0,0 +1,59 @@
+//! Offline upgrades of log files written in older formats to the current format.
+
+use std::fs::File;
+use std::io::{Seek, SeekFrom};
+use std::path::Path;
+
+use anyhow::Context;
+use protocol::{Limits, Reader};
+use tracing::{debug, info};
+
+use crate::file_header::{FileFormat, FileHeader};
+use crate::file_util;
+use crate::Result;
+
+/// Rewrites every legacy (headerless) log file in the directory into the current format and
+/// returns how many files were rewritten. Files already in the current format are left alone.
+///
+/// This must not be run while a [`KvStore`][crate::KvStore] has the directory open.
+pub fn upgrade_dir(path: impl AsRef<Path>) -> Result<usize> {
+    let mut paths = std::fs::read_dir(path.as_ref())
+        .context("reading log directory")?
+        .map(|dir_entry| Ok(dir_entry.map(|dir| dir.path())?))
+        .filter(|path| path.as_ref().map_or(true, file_util::is_log_file))
+        .collect::<Result<Vec<_>>>()?;
+    paths.sort_unstable();
+
+    let mut upgraded = 0;
+    for path in paths {
+        let mut file = File::open(&path)?;
+        if FileHeader::read_format(&mut file)? != FileFormat::Legacy {
+            debug!(?path, "Log file already versioned");
+            continue;
+        }
+
+        // Make sure the whole file is readable before committing to rewriting it.
+        file.rewind()?;
+        let mut reader = Reader::with_limits(Limits::NONE);
+        while reader
+            .read_cmd(&mut file)
+            .with_context(|| format!("validating {}", path.display()))?
+            .is_some()
+        {}
+
+        // Write to a file that a `KvStore` won't pick up until it's complete, and then atomically
+        // replace the legacy file with it.
+        let upgraded_path = path.with_extension("upgrading");
+        let mut upgraded_file = File::create(&upgraded_path)?;
+        FileHeader::new().write(&upgraded_file)?;
+        file.seek(SeekFrom::Start(0))?;
+        std::io::copy(&mut file, &mut upgraded_file)?;
+        upgraded_file.sync_all()?;
+        std::fs::rename(&upgraded_path, &path)?;
+
+        info!(?path, "Upgraded legacy log file");
+        upgraded += 1;
+    }
+
+    Ok(upgraded)
+}
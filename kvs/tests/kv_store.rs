@@ -1,4 +1,4 @@
-use kvs::{KvStore, KvsEngine, Result};
+use kvs::{KvStore, KvsEngine, Limits, Result, TooLarge};
 use tempfile::TempDir;
 use walkdir::WalkDir;
 
@@ -124,3 +124,200 @@ fn compaction() -> Result<()> {
 
     panic!("No compaction detected");
 }
+
+// Writes a `Set` record in the format used by every log file version.
+fn legacy_set_record(key: &str, value: &str) -> Vec<u8> {
+    let mut bytes = Vec::new();
+    bytes.extend((key.len() as u32).to_be_bytes());
+    bytes.extend((value.len() as u64).to_be_bytes());
+    bytes.extend(key.as_bytes());
+    bytes.extend(value.as_bytes());
+    bytes
+}
+
+// Should write a versioned header at the start of new log files
+#[test]
+fn new_files_have_header() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+    store.set("key1".to_owned(), "value1")?;
+    drop(store);
+
+    for entry in std::fs::read_dir(temp_dir.path())? {
+        let bytes = std::fs::read(entry?.path())?;
+        assert!(bytes.starts_with(b"KVSL\0\x01"));
+    }
+
+    Ok(())
+}
+
+// Should read log files written before headers were introduced
+#[test]
+fn open_legacy_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let legacy_file = temp_dir.path().join("2023-01-01T00:00:00Z.pingcap");
+    std::fs::write(&legacy_file, legacy_set_record("key1", "value1"))?;
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    store.set("key2".to_owned(), "value2")?;
+
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+// Should refuse to open log files written by a newer format version
+#[test]
+fn open_newer_version_fails() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let newer_file = temp_dir.path().join("2023-01-01T00:00:00Z.pingcap");
+    let mut bytes = b"KVSL".to_vec();
+    bytes.extend(u16::MAX.to_be_bytes());
+    bytes.extend(0i64.to_be_bytes());
+    std::fs::write(newer_file, bytes)?;
+
+    assert!(KvStore::open(temp_dir.path()).is_err());
+
+    Ok(())
+}
+
+// Should rewrite legacy log files with a header, keeping their data
+#[test]
+fn upgrade_legacy_files() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let legacy_file = temp_dir.path().join("2023-01-01T00:00:00Z.pingcap");
+    let mut records = legacy_set_record("key1", "value1");
+    records.extend(legacy_set_record("key2", "value2"));
+    std::fs::write(&legacy_file, &records)?;
+
+    assert_eq!(kvs::upgrade_dir(temp_dir.path())?, 1);
+    let bytes = std::fs::read(&legacy_file)?;
+    assert!(bytes.starts_with(b"KVSL"));
+    assert!(bytes.ends_with(&records));
+
+    // Upgrading again is a no-op
+    assert_eq!(kvs::upgrade_dir(temp_dir.path())?, 0);
+
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1".to_owned())?, Some("value1".to_owned()));
+    assert_eq!(store.get("key2".to_owned())?, Some("value2".to_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn rejects_oversize_keys_and_values() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?.with_limits(Limits {
+        max_key_bytes: 4,
+        max_value_bytes: 6,
+    });
+
+    store.set("key1".to_owned(), "value1")?;
+    assert!(store
+        .set("key12".to_owned(), "value1")
+        .unwrap_err()
+        .is::<TooLarge>());
+    assert!(store
+        .set("key1".to_owned(), "value12")
+        .unwrap_err()
+        .is::<TooLarge>());
+    assert_eq!(store.get("key1")?, Some("value1".to_owned()));
+
+    // Data already in the store can still be read with lower limits.
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?.with_limits(Limits {
+        max_key_bytes: 1,
+        max_value_bytes: 1,
+    });
+    assert_eq!(store.get("key1")?, Some("value1".to_owned()));
+
+    Ok(())
+}
+
+#[test]
+fn stats_track_dead_bytes_and_compactions() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1")?;
+    store.set("key2".to_owned(), "value2")?;
+    let stats = store.stats();
+    assert_eq!(stats.live_keys, 2);
+    assert_eq!(stats.files, 1);
+    assert_eq!(stats.dead_bytes, 0);
+
+    store.set("key1".to_owned(), "value3")?;
+    store.remove("key2")?;
+    let stats = store.stats();
+    assert_eq!(stats.live_keys, 1);
+    assert!(stats.dead_bytes > 0);
+    assert_eq!(stats.compactions, 0);
+
+    // Overwrite enough data to roll over files until a compaction runs.
+    let value = "x".repeat(1024);
+    while store.stats().compactions == 0 {
+        store.set("key1".to_owned(), &value)?;
+    }
+    let stats = store.stats();
+    assert_eq!(stats.live_keys, 1);
+    assert!(stats.files >= 2);
+
+    drop(store);
+    let store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.stats().live_keys, 1);
+    Ok(())
+}
+
+#[test]
+fn forced_compaction() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1")?;
+    store.set("key1".to_owned(), "value2")?;
+    store.set("key2".to_owned(), "value3")?;
+    store.remove("key2")?;
+    assert!(store.stats().dead_bytes > 0);
+
+    store.compact()?;
+    let stats = store.stats();
+    assert_eq!(stats.dead_bytes, 0);
+    assert_eq!(stats.compactions, 1);
+    assert_eq!(store.get("key1")?, Some("value2".to_owned()));
+
+    store.set("key3".to_owned(), "value4")?;
+    drop(store);
+    let mut store = KvStore::open(temp_dir.path())?;
+    assert_eq!(store.get("key1")?, Some("value2".to_owned()));
+    assert_eq!(store.get("key2")?, None);
+    assert_eq!(store.get("key3")?, Some("value4".to_owned()));
+    Ok(())
+}
+
+#[test]
+fn syncs_files_before_they_become_immutable() -> Result<()> {
+    let temp_dir = TempDir::new().expect("unable to create temporary working directory");
+    let mut store = KvStore::open(temp_dir.path())?;
+
+    store.set("key1".to_owned(), "value1")?;
+    assert_eq!(store.stats().syncs, 0);
+    store.flush()?;
+    assert_eq!(store.stats().syncs, 1);
+
+    // The active file is rotated out, then the compacted file and the directory are synced.
+    store.compact()?;
+    assert_eq!(store.stats().syncs, 4);
+
+    // Every rotation syncs the file being rotated out.
+    let value = "x".repeat(1024);
+    while store.stats().files < 3 {
+        store.set("key1".to_owned(), &value)?;
+    }
+    assert_eq!(store.stats().syncs, 5);
+    Ok(())
+}
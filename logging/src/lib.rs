@@ -1,15 +1,27 @@
 use tracing::metadata::LevelFilter;
+use tracing_subscriber::filter::ParseError;
 use tracing_subscriber::prelude::*;
 use tracing_subscriber::{fmt, EnvFilter};
 
+/// Logs to stderr, filtered by `RUST_LOG`, or at the info level if that isn't set.
 pub fn configure() {
+    init(
+        EnvFilter::builder()
+            .with_default_directive(LevelFilter::INFO.into())
+            .from_env_lossy(),
+    );
+}
+
+/// Logs to stderr, filtered by `filter` instead of `RUST_LOG`. The filter has the same format as
+/// `RUST_LOG`, e.g. "debug" or "kvs_server=debug,info".
+pub fn configure_with_filter(filter: &str) -> Result<(), ParseError> {
+    init(EnvFilter::builder().parse(filter)?);
+    Ok(())
+}
+
+fn init(filter: EnvFilter) {
     tracing_subscriber::registry()
         .with(fmt::layer().with_writer(std::io::stderr).with_ansi(false))
-        .with(
-            EnvFilter::builder()
-                .with_default_directive(LevelFilter::INFO.into())
-                .from_env_lossy(),
-        )
+        .with(filter)
         .init();
 }
-
@@ -1,12 +1,16 @@
 //! [`Cmd`] represents a request to perform an action on a key.
 
 use std::borrow::Cow;
+use std::fmt;
 use std::io::Write;
 
 // TODO More specific crate error
-use anyhow::{Error, Result};
+use anyhow::{ensure, Context, Error, Result};
 
-pub use reader::{CmdReader, Reader};
+pub use reader::{CmdReader, ReadResult, Reader};
+
+use crate::hello::HELLO_BYTES;
+use crate::{Hello, Limits};
 
 mod reader;
 
@@ -16,12 +20,15 @@ mod reader;
 //
 // The current protocol is:
 //   1. All commands start with 4 bytes for the key length and 8 bytes for the value length.
-//   2. `Get` commands always specify a value length of `GET_VALUE_LEN`. Similarly for `Rm`.
-//   3. Following this header, the key is stored.
+//   2. `Get` commands always specify a value length of `GET_VALUE_LEN`. Similarly for `Rm`,
+//      `KeepAlive` and the admin commands.
+//   3. Following this header, the key is stored. `KeepAlive` and admin commands have an empty
+//      key, `Auth` commands store the token in place of the key, and `Hello` commands store the
+//      encoded `Hello` in place of the key.
 //   4. Finally, for `Set` commands, the value is stored.
 //
 // TODO Can we make these comments unnecessary with a descriptive trait?
-#[derive(Debug, PartialEq)]
+#[derive(PartialEq)]
 pub enum Cmd<'a> {
     /// Command to set a key to a value.
     Set(Cow<'a, str>, Cow<'a, str>),
@@ -29,6 +36,42 @@ pub enum Cmd<'a> {
     Get(Cow<'a, str>),
     /// Command to remove a key.
     Rm(Cow<'a, str>),
+    /// Command, sent as the first command on a connection, asking the server to keep the
+    /// connection open for more commands.
+    ///
+    /// The server doesn't respond to this command. Responses to the commands that follow it are
+    /// framed (see [`Response::write_framed`][crate::Response::write_framed]) and written in the
+    /// order the commands were received, so clients can pipeline commands.
+    KeepAlive,
+    /// Admin command to check the server is responding, answered with
+    /// [`Response::Pong`][crate::Response::Pong].
+    Ping,
+    /// Admin command asking for a description of the server, answered with
+    /// [`Response::Info`][crate::Response::Info].
+    Info,
+    /// Admin command to compact the engine's files now instead of waiting for its compaction
+    /// policy, answered with [`Response::Compacted`][crate::Response::Compacted].
+    Compact,
+    /// Admin command to make every completed write durable, answered with
+    /// [`Response::Flushed`][crate::Response::Flushed].
+    Flush,
+    /// Command presenting a token to authenticate the connection, sent before any other command
+    /// (including [`Cmd::KeepAlive`]) when the server requires authentication.
+    ///
+    /// The server doesn't respond to this command. If the token isn't accepted, the next command
+    /// gets an [`ErrorCode::Unauthorized`][crate::ErrorCode::Unauthorized] error, framed if the
+    /// connection is kept alive, and the connection is closed.
+    Auth(Cow<'a, str>),
+    /// Command offering a protocol version and features, sent as the first command on a
+    /// connection. See [`Hello`].
+    ///
+    /// The server answers with a framed [`Response::Hello`][crate::Response::Hello] holding what
+    /// they agree on, or a framed error if they can't agree, and keeps the connection open for the
+    /// next command. If they agree on [`Features::FRAMING`][crate::Features::FRAMING], every
+    /// response after it is framed and the connection stays open like one kept alive with
+    /// [`Cmd::KeepAlive`]. Otherwise the connection is used for one more command, like a
+    /// connection without a handshake.
+    Hello(Hello),
 }
 
 const HEADER_KEY_BYTES: usize = 4;
@@ -37,6 +80,13 @@ const HEADER_BYTES: usize = HEADER_KEY_BYTES + HEADER_VALUE_BYTES;
 
 const GET_VALUE_LEN: u64 = u64::MAX;
 const RM_VALUE_LEN: u64 = GET_VALUE_LEN - 1;
+const KEEP_ALIVE_VALUE_LEN: u64 = RM_VALUE_LEN - 1;
+const PING_VALUE_LEN: u64 = KEEP_ALIVE_VALUE_LEN - 1;
+const INFO_VALUE_LEN: u64 = PING_VALUE_LEN - 1;
+const COMPACT_VALUE_LEN: u64 = INFO_VALUE_LEN - 1;
+const FLUSH_VALUE_LEN: u64 = COMPACT_VALUE_LEN - 1;
+const AUTH_VALUE_LEN: u64 = FLUSH_VALUE_LEN - 1;
+const HELLO_VALUE_LEN: u64 = AUTH_VALUE_LEN - 1;
 
 impl<'a> Cmd<'a> {
     /// Writes the `Cmd` into the provided writer and returns the number of bytes written.
@@ -62,9 +112,107 @@ impl<'a> Cmd<'a> {
                 w.write_all(key.as_bytes())?;
                 Ok(HEADER_BYTES + key.len())
             }
+            Self::KeepAlive => Self::write_keyless(w, KEEP_ALIVE_VALUE_LEN),
+            Self::Ping => Self::write_keyless(w, PING_VALUE_LEN),
+            Self::Info => Self::write_keyless(w, INFO_VALUE_LEN),
+            Self::Compact => Self::write_keyless(w, COMPACT_VALUE_LEN),
+            Self::Flush => Self::write_keyless(w, FLUSH_VALUE_LEN),
+            Self::Auth(token) => {
+                w.write_all(&(token.len() as u32).to_be_bytes())?;
+                w.write_all(&AUTH_VALUE_LEN.to_be_bytes())?;
+                w.write_all(token.as_bytes())?;
+                Ok(HEADER_BYTES + token.len())
+            }
+            Self::Hello(hello) => {
+                w.write_all(&(HELLO_BYTES as u32).to_be_bytes())?;
+                w.write_all(&HELLO_VALUE_LEN.to_be_bytes())?;
+                w.write_all(&hello.to_bytes())?;
+                Ok(HEADER_BYTES + HELLO_BYTES)
+            }
+        }
+    }
+
+    /// Writes the header of a command that has neither a key nor a value.
+    fn write_keyless<W: Write>(mut w: W, value_len: u64) -> Result<usize> {
+        w.write_all(&0u32.to_be_bytes())?;
+        w.write_all(&value_len.to_be_bytes())?;
+        Ok(HEADER_BYTES)
+    }
+
+    /// Whether this is an admin command, for operators rather than applications.
+    pub fn is_admin(&self) -> bool {
+        match self {
+            Self::Ping | Self::Info | Self::Compact | Self::Flush => true,
+            Self::Set(..)
+            | Self::Get(_)
+            | Self::Rm(_)
+            | Self::KeepAlive
+            | Self::Auth(_)
+            | Self::Hello(_) => false,
+        }
+    }
+
+    /// Converts a `Cmd` borrowing its key and value into one that owns them.
+    pub fn into_owned(self) -> Cmd<'static> {
+        match self {
+            Self::Set(key, value) => Cmd::Set(key.into_owned().into(), value.into_owned().into()),
+            Self::Get(key) => Cmd::Get(key.into_owned().into()),
+            Self::Rm(key) => Cmd::Rm(key.into_owned().into()),
+            Self::KeepAlive => Cmd::KeepAlive,
+            Self::Ping => Cmd::Ping,
+            Self::Info => Cmd::Info,
+            Self::Compact => Cmd::Compact,
+            Self::Flush => Cmd::Flush,
+            Self::Auth(token) => Cmd::Auth(token.into_owned().into()),
+            Self::Hello(hello) => Cmd::Hello(hello),
         }
     }
 
+    /// Attempts to parse a `Cmd` from the start of the passed bytes, without waiting on a reader
+    /// for more data. This allows for reading commands from non-blocking sources: append bytes to
+    /// a buffer as they arrive and try to decode the buffer each time.
+    ///
+    /// Returns `Ok(None)` if the bytes don't contain a complete command yet, and an `Err` if they
+    /// can't represent a `Cmd`. Bytes after the command are ignored. A command whose header claims
+    /// a key or value larger than the limits is an error as soon as its header arrives, so the
+    /// rest of it doesn't need to be buffered first. The error is a [`TooLarge`][crate::TooLarge].
+    pub fn decode(bytes: &'a [u8], limits: &Limits) -> Result<Option<ReadResult<'a>>> {
+        let Some(header) = bytes.get(..HEADER_BYTES) else {
+            return Ok(None);
+        };
+        let (key_len, value_len) =
+            Self::parse_header(header.try_into().expect("specified 12 bytes"));
+
+        let bytes_read = Self::encoded_len(key_len, value_len, limits)?;
+        let Some(body) = bytes.get(HEADER_BYTES..bytes_read) else {
+            return Ok(None);
+        };
+
+        let cmd = Self::parse_body(key_len, value_len, body)?;
+        Ok(Some(ReadResult::new(cmd, bytes_read)))
+    }
+
+    /// Computes how many bytes a command with the given key and value lengths takes up, including
+    /// its header, after checking the lengths against the limits.
+    pub(crate) fn encoded_len(key_len: u32, value_len: u64, limits: &Limits) -> Result<usize> {
+        limits.check_key(key_len.into())?;
+        let value_len = match value_len {
+            GET_VALUE_LEN | RM_VALUE_LEN | KEEP_ALIVE_VALUE_LEN | PING_VALUE_LEN
+            | INFO_VALUE_LEN | COMPACT_VALUE_LEN | FLUSH_VALUE_LEN | AUTH_VALUE_LEN
+            | HELLO_VALUE_LEN => 0,
+            value_len => {
+                limits.check_value(value_len)?;
+                value_len
+            }
+        };
+        // Without limits, the lengths can add up to more than fits in memory.
+        usize::try_from(value_len)
+            .ok()
+            .and_then(|value_len| value_len.checked_add(key_len as usize))
+            .and_then(|body_len| body_len.checked_add(HEADER_BYTES))
+            .context("Command is too large to read")
+    }
+
     /// Parses the passed bytes into key and value lengths.
     pub(crate) fn parse_header(header: [u8; HEADER_BYTES]) -> (u32, u64) {
         let (key_len, value_len) = header.split_at(HEADER_KEY_BYTES);
@@ -75,6 +223,12 @@ impl<'a> Cmd<'a> {
         (key_len, value_len)
     }
 
+    /// Checks that a command that doesn't take a key wasn't sent with one.
+    fn keyless(key: &str, cmd: Self) -> Result<Self> {
+        ensure!(key.is_empty(), "Unexpected key for {cmd:?} command");
+        Ok(cmd)
+    }
+
     /// Parses the passed bytes into a command, using the provided key and value lengths.
     pub(crate) fn parse_body(key_len: u32, value_len: u64, bytes: &'a [u8]) -> Result<Self> {
         if bytes.len() < key_len as usize {
@@ -82,12 +236,21 @@ impl<'a> Cmd<'a> {
         }
 
         let (key_bytes, value_bytes) = bytes.split_at(key_len as usize);
+        if value_len == HELLO_VALUE_LEN {
+            return Hello::from_bytes(key_bytes).map(Self::Hello);
+        }
         let key =
             std::str::from_utf8(key_bytes).map_err(|e| Error::new(e).context("Non-UTF8 key"))?;
 
         match value_len {
             GET_VALUE_LEN => Ok(Self::Get(key.into())),
             RM_VALUE_LEN => Ok(Self::Rm(key.into())),
+            KEEP_ALIVE_VALUE_LEN => Self::keyless(key, Self::KeepAlive),
+            PING_VALUE_LEN => Self::keyless(key, Self::Ping),
+            INFO_VALUE_LEN => Self::keyless(key, Self::Info),
+            COMPACT_VALUE_LEN => Self::keyless(key, Self::Compact),
+            FLUSH_VALUE_LEN => Self::keyless(key, Self::Flush),
+            AUTH_VALUE_LEN => Ok(Self::Auth(key.into())),
             value_len => {
                 let value_bytes = value_bytes
                     .get(..value_len as usize)
@@ -100,12 +263,30 @@ impl<'a> Cmd<'a> {
     }
 }
 
+// Tokens are secrets, so they're kept out of anything that logs commands.
+impl fmt::Debug for Cmd<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Set(key, value) => f.debug_tuple("Set").field(key).field(value).finish(),
+            Self::Get(key) => f.debug_tuple("Get").field(key).finish(),
+            Self::Rm(key) => f.debug_tuple("Rm").field(key).finish(),
+            Self::KeepAlive => f.write_str("KeepAlive"),
+            Self::Ping => f.write_str("Ping"),
+            Self::Info => f.write_str("Info"),
+            Self::Compact => f.write_str("Compact"),
+            Self::Flush => f.write_str("Flush"),
+            Self::Auth(_) => f.write_str("Auth(<redacted>)"),
+            Self::Hello(hello) => f.debug_tuple("Hello").field(hello).finish(),
+        }
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
 
     // Helper for tests
-    fn parse(cmd: &[u8]) -> Result<Cmd> {
+    fn parse(cmd: &[u8]) -> Result<Cmd<'_>> {
         if cmd.len() < HEADER_BYTES {
             return Err(Error::msg("Missing proper header"));
         }
@@ -226,6 +407,81 @@ mod tests {
         assert_eq!(parse(&buf).unwrap(), proto);
     }
 
+    #[test]
+    fn keep_alive_identity() {
+        let proto = Cmd::KeepAlive;
+
+        let mut buf = vec![];
+
+        assert_eq!(proto.write(&mut buf).unwrap(), 12);
+
+        assert_eq!(parse(&buf).unwrap(), proto);
+    }
+
+    #[test]
+    fn admin_identity() {
+        for proto in [Cmd::Ping, Cmd::Info, Cmd::Compact, Cmd::Flush] {
+            let mut buf = vec![];
+
+            assert_eq!(proto.write(&mut buf).unwrap(), 12);
+
+            assert_eq!(parse(&buf).unwrap(), proto);
+        }
+    }
+
+    #[test]
+    fn admin_rejects_key() {
+        let mut bytes = Vec::new();
+        bytes.extend(3u32.to_be_bytes());
+        bytes.extend(PING_VALUE_LEN.to_be_bytes());
+        bytes.extend(b"foo");
+
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn auth_identity() {
+        let proto = Cmd::Auth("secret".into());
+
+        let mut buf = vec![];
+
+        assert_eq!(proto.write(&mut buf).unwrap(), 18);
+
+        assert_eq!(parse(&buf).unwrap(), proto);
+        assert_eq!(format!("{proto:?}"), "Auth(<redacted>)");
+    }
+
+    #[test]
+    fn hello_identity() {
+        let proto = Cmd::Hello(Hello::new(crate::Features::FRAMING));
+
+        let mut buf = vec![];
+
+        assert_eq!(proto.write(&mut buf).unwrap(), 18);
+
+        assert_eq!(parse(&buf).unwrap(), proto);
+    }
+
+    #[test]
+    fn hello_checks_len() {
+        let mut bytes = Vec::new();
+        bytes.extend(2u32.to_be_bytes());
+        bytes.extend(HELLO_VALUE_LEN.to_be_bytes());
+        bytes.extend([0, 1]);
+
+        assert!(parse(&bytes).is_err());
+    }
+
+    #[test]
+    fn keep_alive_rejects_key() {
+        let mut bytes = Vec::new();
+        bytes.extend(3u32.to_be_bytes());
+        bytes.extend(KEEP_ALIVE_VALUE_LEN.to_be_bytes());
+        bytes.extend(b"foo");
+
+        assert!(parse(&bytes).is_err());
+    }
+
     mod len_check_tests {
         use super::*;
 
@@ -273,4 +529,65 @@ mod tests {
     fn empty_command() {
         assert!(parse(b"").is_err());
     }
+
+    mod decode_tests {
+        use super::*;
+
+        #[test]
+        fn decodes_complete_cmd() {
+            let mut bytes = Vec::new();
+            let set = Cmd::Set("foo".into(), "foobar".into());
+            set.write(&mut bytes).unwrap();
+            bytes.extend(b"next");
+
+            let result = Cmd::decode(&bytes, &Limits::default()).unwrap().unwrap();
+            assert_eq!(result.bytes_read(), 21);
+            assert_eq!(result.into_cmd(), set);
+        }
+
+        #[test]
+        fn waits_for_complete_cmd() {
+            let mut bytes = Vec::new();
+            Cmd::Get("foo".into()).write(&mut bytes).unwrap();
+
+            for len in 0..bytes.len() {
+                assert_eq!(
+                    Cmd::decode(&bytes[..len], &Limits::default()).unwrap(),
+                    None
+                );
+            }
+            assert!(Cmd::decode(&bytes, &Limits::default()).unwrap().is_some());
+        }
+
+        #[test]
+        fn rejects_invalid_cmd() {
+            let mut bytes = Vec::new();
+            bytes.extend(1u32.to_be_bytes());
+            bytes.extend(GET_VALUE_LEN.to_be_bytes());
+            bytes.push(255);
+
+            assert!(Cmd::decode(&bytes, &Limits::default()).is_err());
+        }
+
+        #[test]
+        fn rejects_oversize_cmd_from_header() {
+            let limits = Limits {
+                max_key_bytes: 3,
+                max_value_bytes: 5,
+            };
+            let header = |key_len: u32, value_len: u64| {
+                let mut bytes = key_len.to_be_bytes().to_vec();
+                bytes.extend(value_len.to_be_bytes());
+                bytes
+            };
+
+            for bytes in [header(4, GET_VALUE_LEN), header(1, 6), header(u32::MAX, 0)] {
+                let e = Cmd::decode(&bytes, &limits).unwrap_err();
+                assert!(e.is::<crate::TooLarge>(), "{e:#}");
+            }
+            assert_eq!(Cmd::decode(&header(3, 5), &limits).unwrap(), None);
+            // Lengths that would overflow are rejected even without limits.
+            assert!(Cmd::decode(&header(u32::MAX, u64::MAX - 16), &Limits::NONE).is_err());
+        }
+    }
 }
@@ -9,7 +9,8 @@ use std::io::{ErrorKind, Read};
 // TODO More specific crate error
 use anyhow::{ensure, Context, Result};
 
-use super::{Cmd, GET_VALUE_LEN, HEADER_BYTES, RM_VALUE_LEN};
+use super::{Cmd, HEADER_BYTES};
+use crate::Limits;
 
 /// Result of reading a command with a [`Reader`]. It communicates the [`Cmd`] and how many bytes
 /// were read, as would be expected from a [`Read`] implementation.
@@ -20,6 +21,10 @@ pub struct ReadResult<'a> {
 }
 
 impl<'a> ReadResult<'a> {
+    pub(crate) fn new(cmd: Cmd<'a>, bytes_read: usize) -> Self {
+        Self { cmd, bytes_read }
+    }
+
     /// See how many bytes were read in order to read this command.
     pub fn bytes_read(&self) -> usize {
         self.bytes_read
@@ -33,24 +38,35 @@ impl<'a> ReadResult<'a> {
 
 pub struct CmdReader<R> {
     reader: R,
+    limits: Limits,
 }
 
 impl<R> CmdReader<R> {
+    /// Creates a reader that accepts commands within the default [`Limits`].
     pub fn new(reader: R) -> Self {
-        Self { reader }
+        Self::with_limits(reader, Limits::default())
+    }
+
+    /// Creates a reader that accepts commands within the limits.
+    pub fn with_limits(reader: R, limits: Limits) -> Self {
+        Self { reader, limits }
     }
 }
 
 impl<R: Read> CmdReader<R> {
+    /// Reads the bytes of a command into the buffer, growing it if it's too small, and returns how
+    /// many bytes the command took up. The lengths in the command's header are checked against
+    /// the limits before anything is allocated for it, and a command beyond them is a
+    /// [`TooLarge`][crate::TooLarge] error.
     pub fn read_cmd_bytes(&mut self, buf: &mut Vec<u8>) -> Result<usize> {
         let mut header_bytes = [0; HEADER_BYTES];
         let mut total_read = 0;
-        loop {
+        // Reading into an empty buffer can block on a socket, so stop once the header is full.
+        while total_read < header_bytes.len() {
             match self.reader.read(&mut header_bytes[total_read..]) {
                 Ok(0) => break,
                 Ok(n) => {
                     total_read += n;
-                    continue;
                 }
                 Err(e) if e.kind() == ErrorKind::Interrupted => continue,
                 err => return err.context("reading cmd header"),
@@ -68,11 +84,7 @@ impl<R: Read> CmdReader<R> {
         );
 
         let (key_len, value_len) = Cmd::parse_header(header_bytes);
-        let body_len = match value_len {
-            GET_VALUE_LEN | RM_VALUE_LEN => key_len as usize,
-            value_len => key_len as usize + value_len as usize,
-        };
-        let total_len = header_bytes.len() + body_len;
+        let total_len = Cmd::encoded_len(key_len, value_len, &self.limits)?;
 
         if buf.len() < total_len {
             buf.resize(total_len, 0);
@@ -95,14 +107,23 @@ impl<R: Read> CmdReader<R> {
 #[derive(Default)]
 pub struct Reader {
     buf: Vec<u8>,
+    limits: Limits,
 }
 
 impl Reader {
-    /// Instantiates a default `Reader`.
+    /// Instantiates a `Reader` that accepts commands within the default [`Limits`].
     pub fn new() -> Self {
         Self::default()
     }
 
+    /// Instantiates a `Reader` that accepts commands within the limits.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            buf: Vec::new(),
+            limits,
+        }
+    }
+
     /// Attempts to read a [`Cmd`] out of the provided reader.
     ///
     /// If the reader is empty, `Ok(None)` is returned. This allows for calling this in a loop on a
@@ -110,8 +131,8 @@ impl Reader {
     ///
     /// If the reader fails to provide data, or if the reader has data not representing a `Cmd`, an
     /// `Err` is returned.
-    pub fn read_cmd(&mut self, reader: impl Read) -> Result<Option<ReadResult>> {
-        let mut cmd_reader = CmdReader::new(reader);
+    pub fn read_cmd(&mut self, reader: impl Read) -> Result<Option<ReadResult<'_>>> {
+        let mut cmd_reader = CmdReader::with_limits(reader, self.limits);
 
         // Clear buffer because `read_to_end` appends bytes.
         let bytes_read = cmd_reader
@@ -129,7 +150,7 @@ impl Reader {
         let cmd =
             Cmd::parse_body(key_len, value_len, body_bytes).context("parsing command body")?;
 
-        Ok(Some(ReadResult { cmd, bytes_read }))
+        Ok(Some(ReadResult::new(cmd, bytes_read)))
     }
 }
 
@@ -162,4 +183,51 @@ mod tests {
 
         assert!(result.is_none());
     }
+
+    #[test]
+    fn stops_reading_after_cmd() {
+        /// A reader like a socket, which would block if asked to read into an empty buffer.
+        struct NoEmptyReads<'a>(&'a [u8]);
+
+        impl Read for NoEmptyReads<'_> {
+            fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+                assert!(!buf.is_empty(), "read into an empty buffer");
+                self.0.read(buf)
+            }
+        }
+
+        let mut bytes = Vec::new();
+        Cmd::Ping.write(&mut bytes).unwrap();
+        let mut reader = Reader::new();
+        let result = reader.read_cmd(NoEmptyReads(&bytes)).unwrap().unwrap();
+        assert_eq!(result.into_cmd(), Cmd::Ping);
+    }
+
+    #[test]
+    fn rejects_oversize_cmd_before_allocating() {
+        let mut bytes = Vec::new();
+        bytes.extend(u32::MAX.to_be_bytes());
+        bytes.extend((u64::MAX - 2).to_be_bytes());
+
+        let e = Reader::new().read_cmd(&*bytes).unwrap_err();
+        assert!(
+            e.chain().any(|cause| cause.is::<crate::TooLarge>()),
+            "{e:#}"
+        );
+
+        let mut bytes = Vec::new();
+        Cmd::Set("key".into(), "value".into())
+            .write(&mut bytes)
+            .unwrap();
+        let limits = Limits {
+            max_key_bytes: 3,
+            max_value_bytes: 4,
+        };
+        assert!(Reader::with_limits(limits).read_cmd(&*bytes).is_err());
+        let mut buf = Vec::new();
+        assert!(CmdReader::with_limits(&*bytes, limits)
+            .read_cmd_bytes(&mut buf)
+            .is_err());
+        assert!(buf.is_empty());
+    }
 }
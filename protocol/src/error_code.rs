@@ -0,0 +1,122 @@
+//! [`ErrorCode`]s classify failed requests so callers can react to them programmatically.
+
+use std::fmt;
+
+/// Machine-readable classification of an [`Err`][crate::Response::Err] response.
+///
+/// The numeric values mirror the closest HTTP status code, in keeping with the analogies used for
+/// [`Response`][crate::Response].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ErrorCode {
+    /// The request couldn't be parsed. Think of this like HTTP status code 400.
+    BadRequest,
+    /// The request didn't carry valid credentials. Think of this like HTTP status code 401.
+    Unauthorized,
+    /// The credentials aren't allowed to issue the request. Think of this like HTTP status code
+    /// 403.
+    Forbidden,
+    /// The requested key doesn't exist. Think of this like HTTP status code 404.
+    NotFound,
+    /// The request exceeded a configured size limit. Think of this like HTTP status code 413.
+    TooLarge,
+    /// The server failed for a reason unrelated to storage. Think of this like HTTP status code
+    /// 500.
+    Internal,
+    /// The server is too busy to handle the request, because it has too many connections or the
+    /// client has sent too many requests. Think of this like HTTP status code 503.
+    Busy,
+    /// The storage engine failed to process the request. Think of this like HTTP status code 507.
+    Storage,
+    /// A code this version of the protocol doesn't know about, likely from a newer server.
+    Unknown(u16),
+}
+
+impl ErrorCode {
+    /// Whether the same request might succeed if it's issued again.
+    pub fn is_retryable(&self) -> bool {
+        match self {
+            Self::Busy | Self::Storage => true,
+            Self::BadRequest
+            | Self::Unauthorized
+            | Self::Forbidden
+            | Self::NotFound
+            | Self::TooLarge
+            | Self::Internal
+            | Self::Unknown(_) => false,
+        }
+    }
+
+    /// The numeric representation of the code, as written on the wire.
+    pub fn as_u16(&self) -> u16 {
+        match self {
+            Self::BadRequest => 400,
+            Self::Unauthorized => 401,
+            Self::Forbidden => 403,
+            Self::NotFound => 404,
+            Self::TooLarge => 413,
+            Self::Internal => 500,
+            Self::Busy => 503,
+            Self::Storage => 507,
+            Self::Unknown(code) => *code,
+        }
+    }
+}
+
+impl From<u16> for ErrorCode {
+    fn from(code: u16) -> Self {
+        match code {
+            400 => Self::BadRequest,
+            401 => Self::Unauthorized,
+            403 => Self::Forbidden,
+            404 => Self::NotFound,
+            413 => Self::TooLarge,
+            500 => Self::Internal,
+            503 => Self::Busy,
+            507 => Self::Storage,
+            other => Self::Unknown(other),
+        }
+    }
+}
+
+impl fmt::Display for ErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::BadRequest => f.write_str("bad request"),
+            Self::Unauthorized => f.write_str("unauthorized"),
+            Self::Forbidden => f.write_str("forbidden"),
+            Self::NotFound => f.write_str("not found"),
+            Self::TooLarge => f.write_str("too large"),
+            Self::Internal => f.write_str("internal error"),
+            Self::Busy => f.write_str("busy"),
+            Self::Storage => f.write_str("storage error"),
+            Self::Unknown(code) => write!(f, "unknown error {code}"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_known_codes() {
+        for code in [
+            ErrorCode::BadRequest,
+            ErrorCode::Unauthorized,
+            ErrorCode::Forbidden,
+            ErrorCode::NotFound,
+            ErrorCode::TooLarge,
+            ErrorCode::Internal,
+            ErrorCode::Busy,
+            ErrorCode::Storage,
+        ] {
+            assert_eq!(ErrorCode::from(code.as_u16()), code);
+        }
+    }
+
+    #[test]
+    fn preserves_unknown_codes() {
+        assert_eq!(ErrorCode::from(999), ErrorCode::Unknown(999));
+        assert_eq!(ErrorCode::Unknown(999).as_u16(), 999);
+    }
+}
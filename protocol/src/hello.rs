@@ -0,0 +1,194 @@
+//! A [`Hello`] lets a client and server agree on a protocol version and the optional features
+//! they'll use on a connection, so the protocol can change without breaking older peers.
+
+use std::fmt;
+
+use anyhow::{ensure, Result};
+
+const VERSION_BYTES: usize = 2;
+const FEATURES_BYTES: usize = 4;
+/// Length of an encoded [`Hello`].
+pub(crate) const HELLO_BYTES: usize = VERSION_BYTES + FEATURES_BYTES;
+
+/// Sent by a client as the first command on a connection in [`Cmd::Hello`][crate::Cmd::Hello], and
+/// answered by the server with what they agree on in [`Response::Hello`][crate::Response::Hello].
+///
+/// The handshake is optional. A connection that starts with any other command speaks version 1
+/// with none of the features, which is how clients that predate the handshake talk to the server.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Hello {
+    /// The newest protocol version the peer speaks.
+    pub version: u16,
+    /// The features the peer supports.
+    pub features: Features,
+}
+
+impl Hello {
+    /// The newest protocol version this crate speaks.
+    pub const VERSION: u16 = 1;
+
+    /// The oldest protocol version this crate speaks.
+    pub const MIN_VERSION: u16 = 1;
+
+    /// A hello for this crate's newest version, offering the features.
+    pub fn new(features: Features) -> Self {
+        Self {
+            version: Self::VERSION,
+            features,
+        }
+    }
+
+    /// What two peers agree on after exchanging hellos: the older of their versions and the
+    /// features both support. Fails if the agreed version is too old for this crate to speak.
+    pub fn agree(&self, other: &Hello) -> Result<Hello> {
+        let version = self.version.min(other.version);
+        ensure!(
+            version >= Self::MIN_VERSION,
+            "Protocol version {version} isn't supported, only {} to {}",
+            Self::MIN_VERSION,
+            Self::VERSION
+        );
+        Ok(Hello {
+            version,
+            features: self.features.intersection(other.features),
+        })
+    }
+
+    /// Encodes the hello as 2 bytes of version followed by 4 bytes of feature flags.
+    pub(crate) fn to_bytes(self) -> [u8; HELLO_BYTES] {
+        let mut bytes = [0; HELLO_BYTES];
+        bytes[..VERSION_BYTES].copy_from_slice(&self.version.to_be_bytes());
+        bytes[VERSION_BYTES..].copy_from_slice(&self.features.bits().to_be_bytes());
+        bytes
+    }
+
+    /// Parses a hello encoded by [`Hello::to_bytes`]. Feature flags this crate doesn't know about
+    /// are kept, so they can be ignored when agreeing on features.
+    pub(crate) fn from_bytes(bytes: &[u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() == HELLO_BYTES,
+            "Hello must be {HELLO_BYTES} bytes, not {}",
+            bytes.len()
+        );
+        let (version, features) = bytes.split_at(VERSION_BYTES);
+        Ok(Self {
+            version: u16::from_be_bytes(version.try_into().expect("specified 2 bytes")),
+            features: Features::from_bits(u32::from_be_bytes(
+                features.try_into().expect("specified 4 bytes"),
+            )),
+        })
+    }
+}
+
+/// Optional protocol features, as a set of flags.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct Features(u32);
+
+impl Features {
+    /// No features, which is what a connection without a handshake uses.
+    pub const NONE: Self = Self(0);
+    /// The connection stays open after the handshake, and every response is framed like on a
+    /// connection kept alive with [`Cmd::KeepAlive`][crate::Cmd::KeepAlive].
+    pub const FRAMING: Self = Self(1);
+    /// Keys and values can be arbitrary bytes rather than UTF-8.
+    pub const BINARY_VALUES: Self = Self(1 << 1);
+    /// The connection authenticates with [`Cmd::Auth`][crate::Cmd::Auth]. A server only agrees to
+    /// this if it requires authentication, so clients know to send their token.
+    pub const AUTH: Self = Self(1 << 2);
+    /// Values can be compressed.
+    pub const COMPRESSION: Self = Self(1 << 3);
+
+    /// The features with the flags, including ones this crate doesn't know about.
+    pub fn from_bits(bits: u32) -> Self {
+        Self(bits)
+    }
+
+    /// The flags, as written on the wire.
+    pub fn bits(self) -> u32 {
+        self.0
+    }
+
+    /// Whether every feature in `other` is in this set.
+    pub fn contains(self, other: Self) -> bool {
+        self.0 & other.0 == other.0
+    }
+
+    /// The features in either set.
+    pub const fn union(self, other: Self) -> Self {
+        Self(self.0 | other.0)
+    }
+
+    /// The features in both sets.
+    pub const fn intersection(self, other: Self) -> Self {
+        Self(self.0 & other.0)
+    }
+}
+
+impl fmt::Debug for Features {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let names = [
+            (Self::FRAMING, "FRAMING"),
+            (Self::BINARY_VALUES, "BINARY_VALUES"),
+            (Self::AUTH, "AUTH"),
+            (Self::COMPRESSION, "COMPRESSION"),
+        ];
+        let mut set = f.debug_set();
+        let mut known = Self::NONE;
+        for (feature, name) in names {
+            if self.contains(feature) {
+                set.entry(&format_args!("{name}"));
+            }
+            known = known.union(feature);
+        }
+        let unknown = self.0 & !known.0;
+        if unknown != 0 {
+            set.entry(&format_args!("{unknown:#x}"));
+        }
+        set.finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips() {
+        let hello = Hello {
+            version: 7,
+            features: Features::FRAMING.union(Features(1 << 20)),
+        };
+        assert_eq!(Hello::from_bytes(&hello.to_bytes()).unwrap(), hello);
+        assert!(Hello::from_bytes(&[0, 1]).is_err());
+    }
+
+    #[test]
+    fn agrees_on_common_version_and_features() {
+        let client = Hello {
+            version: 3,
+            features: Features::FRAMING.union(Features::COMPRESSION),
+        };
+        let server = Hello::new(Features::FRAMING.union(Features::AUTH));
+        assert_eq!(
+            server.agree(&client).unwrap(),
+            Hello {
+                version: 1,
+                features: Features::FRAMING,
+            }
+        );
+
+        let ancient = Hello {
+            version: 0,
+            features: Features::NONE,
+        };
+        assert!(server.agree(&ancient).is_err());
+    }
+
+    #[test]
+    fn debugs_feature_names() {
+        let features = Features::FRAMING
+            .union(Features::AUTH)
+            .union(Features(1 << 9));
+        assert_eq!(format!("{features:?}"), "{FRAMING, AUTH, 0x200}");
+    }
+}
@@ -4,7 +4,15 @@
 //! [`Response`], for consuming responses from the database.
 
 mod cmd;
+mod error_code;
+mod hello;
+mod limits;
 mod response;
+mod server_info;
 
-pub use cmd::{Cmd, CmdReader, Reader};
-pub use response::Response;
+pub use cmd::{Cmd, CmdReader, ReadResult, Reader};
+pub use error_code::ErrorCode;
+pub use hello::{Features, Hello};
+pub use limits::{Limits, TooLarge};
+pub use response::{Response, ResponseReader};
+pub use server_info::ServerInfo;
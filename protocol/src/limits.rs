@@ -0,0 +1,110 @@
+//! [`Limits`] on the size of keys and values, so a peer can't make a reader allocate more memory
+//! than it's willing to by claiming a huge command or response is coming.
+
+use std::fmt;
+
+/// Room in a response for everything besides a value, like an error message or server info.
+const RESPONSE_OVERHEAD: usize = 64 * 1024;
+
+/// The largest key and value a [`Cmd`][crate::Cmd] can carry. Tokens sent with
+/// [`Cmd::Auth`][crate::Cmd::Auth] count as keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Limits {
+    pub max_key_bytes: usize,
+    pub max_value_bytes: usize,
+}
+
+impl Limits {
+    /// The default limit on keys, 64 KiB.
+    pub const DEFAULT_MAX_KEY_BYTES: usize = 64 * 1024;
+    /// The default limit on values, 64 MiB.
+    pub const DEFAULT_MAX_VALUE_BYTES: usize = 64 * 1024 * 1024;
+
+    /// No limits beyond what fits in memory, for reading data that was accepted when it was
+    /// written, like a store's own files.
+    pub const NONE: Self = Self {
+        max_key_bytes: usize::MAX,
+        max_value_bytes: usize::MAX,
+    };
+
+    /// Checks that a key of `len` bytes is within the limit.
+    pub fn check_key(&self, len: u64) -> Result<(), TooLarge> {
+        TooLarge::check("key", len, self.max_key_bytes)
+    }
+
+    /// Checks that a value of `len` bytes is within the limit.
+    pub fn check_value(&self, len: u64) -> Result<(), TooLarge> {
+        TooLarge::check("value", len, self.max_value_bytes)
+    }
+
+    /// Checks that a framed [`Response`][crate::Response] of `len` bytes is within the limit,
+    /// which is the largest value with room for the rest of the response.
+    pub fn check_response(&self, len: u64) -> Result<(), TooLarge> {
+        let max = self.max_value_bytes.saturating_add(RESPONSE_OVERHEAD);
+        TooLarge::check("response", len, max)
+    }
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Self {
+            max_key_bytes: Self::DEFAULT_MAX_KEY_BYTES,
+            max_value_bytes: Self::DEFAULT_MAX_VALUE_BYTES,
+        }
+    }
+}
+
+/// Error for a key or value larger than its [`Limits`]. This can be recovered from an
+/// [`anyhow::Error`] with [`anyhow::Error::is`] or [`anyhow::Error::downcast_ref`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TooLarge {
+    what: &'static str,
+    len: u64,
+    max: usize,
+}
+
+impl TooLarge {
+    fn check(what: &'static str, len: u64, max: usize) -> Result<(), Self> {
+        if usize::try_from(len).is_ok_and(|len| len <= max) {
+            Ok(())
+        } else {
+            Err(Self { what, len, max })
+        }
+    }
+}
+
+impl fmt::Display for TooLarge {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "The {} is {} bytes, more than the limit of {} bytes",
+            self.what, self.len, self.max
+        )
+    }
+}
+
+impl std::error::Error for TooLarge {}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn checks_sizes() {
+        let limits = Limits {
+            max_key_bytes: 3,
+            max_value_bytes: 5,
+        };
+        assert!(limits.check_key(3).is_ok());
+        assert!(limits.check_value(5).is_ok());
+        assert_eq!(
+            limits.check_key(4).unwrap_err().to_string(),
+            "The key is 4 bytes, more than the limit of 3 bytes"
+        );
+        assert!(limits.check_value(6).is_err());
+        assert!(limits.check_response(5 + 64 * 1024).is_ok());
+        assert!(limits.check_response(6 + 64 * 1024).is_err());
+        assert!(Limits::NONE.check_response(u64::MAX).is_ok());
+        assert!(Limits::NONE.check_key(u32::MAX.into()).is_ok());
+    }
+}
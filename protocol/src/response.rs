@@ -1,9 +1,15 @@
 //! Responses to a [`Cmd`][crate::Cmd].
 
 use std::borrow::Cow;
-use std::io::Write;
+use std::io::{Read, Write};
 
-use anyhow::Result;
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{ErrorCode, Hello, Limits, ServerInfo};
+
+pub use reader::ResponseReader;
+
+mod reader;
 
 // Implementation details:
 //
@@ -12,7 +18,13 @@ use anyhow::Result;
 //   2. Successful `Rm` responses are encoded as a single `r`
 //   3. Successful `Get` responses are encoded as an `g` followed by the value for the key
 //   4. Unsuccessful `Get` responses are encoded as an `n` (for "not found")
-//   5. Errors are encoded as an `e` followed by the error
+//   5. Errors are encoded as an `e` followed by a 2 byte error code and then the error message
+//   6. Responses to admin commands are encoded as a `p` for pong, `c` for compacted, `f` for
+//      flushed, or an `i` followed by the encoded `ServerInfo`
+//   7. Responses to `Hello` commands are encoded as an `h` followed by the encoded `Hello`
+//
+// On connections kept alive with `Cmd::KeepAlive`, each response is framed: it's preceded by 8
+// bytes holding the length of the encoded response.
 //
 // TODO Can we make these comments unnecessary with a descriptive trait?
 const SUCCESSFUL_SET_BYTE: u8 = b's';
@@ -20,6 +32,13 @@ const SUCCESSFUL_RM_BYTE: u8 = b'r';
 const SUCCESSFUL_GET_BYTE: u8 = b'g';
 const NOT_FOUND_BYTE: u8 = b'n';
 const ERROR_BYTE: u8 = b'e';
+const PONG_BYTE: u8 = b'p';
+const INFO_BYTE: u8 = b'i';
+const COMPACTED_BYTE: u8 = b'c';
+const FLUSHED_BYTE: u8 = b'f';
+const HELLO_BYTE: u8 = b'h';
+const ERROR_CODE_BYTES: usize = 2;
+const FRAME_LEN_BYTES: usize = 8;
 
 /// A response to a [`Cmd`][crate::Cmd].
 #[derive(Debug, PartialEq)]
@@ -32,26 +51,128 @@ pub enum Response<'a> {
     SuccessfulGet(Cow<'a, str>),
     /// The Get command was requested for an unknown key. Think of this like HTTP status code 404.
     KeyNotFound,
-    /// An error occurred while processing the command. Think of this like HTTP status code 500.
-    Err(Cow<'a, str>),
+    /// An error occurred while processing the command. The [`ErrorCode`] classifies the error and
+    /// the message describes it.
+    Err(ErrorCode, Cow<'a, str>),
+    /// The server is responding to the Ping command.
+    Pong,
+    /// A description of the server, for the Info command.
+    Info(ServerInfo<'a>),
+    /// The Compact command finished compacting the engine's files.
+    Compacted,
+    /// The Flush command made every completed write durable.
+    Flushed,
+    /// The protocol version and features the server agreed to, for the Hello command.
+    Hello(Hello),
 }
 
 impl<'a> Response<'a> {
-    /// Parses a `Response` from the given bytes.
-    pub fn from_bytes(bytes: &'a [u8]) -> Self {
-        match std::str::from_utf8(bytes) {
-            Ok(response) => match bytes.first().copied() {
-                Some(SUCCESSFUL_SET_BYTE) => Self::SuccessfulSet,
-                Some(SUCCESSFUL_RM_BYTE) => Self::SuccessfulRm,
-                Some(SUCCESSFUL_GET_BYTE) => Self::SuccessfulGet(response[1..].into()),
-                Some(NOT_FOUND_BYTE) => Self::KeyNotFound,
-                Some(ERROR_BYTE) => Self::Err(response[1..].into()),
-                Some(_) | None => Self::Err("Invalid start byte".into()),
-            },
-            Err(_) => Self::Err("Invalid utf8".into()),
+    /// Parses a `Response` from the given bytes. Returns an `Err` if the bytes don't represent a
+    /// `Response`.
+    pub fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        let Some((&first, rest)) = bytes.split_first() else {
+            bail!("Empty response");
+        };
+        let response = match first {
+            SUCCESSFUL_SET_BYTE => Self::SuccessfulSet,
+            SUCCESSFUL_RM_BYTE => Self::SuccessfulRm,
+            SUCCESSFUL_GET_BYTE => Self::SuccessfulGet(Self::parse_str(rest)?.into()),
+            NOT_FOUND_BYTE => Self::KeyNotFound,
+            ERROR_BYTE => {
+                ensure!(rest.len() >= ERROR_CODE_BYTES, "Missing error code");
+                let (code, message) = rest.split_at(ERROR_CODE_BYTES);
+                let code = u16::from_be_bytes(code.try_into().expect("specified 2 bytes"));
+                Self::Err(code.into(), Self::parse_str(message)?.into())
+            }
+            PONG_BYTE => Self::Pong,
+            INFO_BYTE => Self::Info(ServerInfo::from_bytes(rest)?),
+            COMPACTED_BYTE => Self::Compacted,
+            FLUSHED_BYTE => Self::Flushed,
+            HELLO_BYTE => Self::Hello(Hello::from_bytes(rest)?),
+            other => bail!("Invalid start byte {other:#04x}"),
+        };
+        Ok(response)
+    }
+
+    fn parse_str(bytes: &[u8]) -> Result<&str> {
+        std::str::from_utf8(bytes).map_err(|_| anyhow::Error::msg("Invalid utf8"))
+    }
+
+    /// Converts a `Response` borrowing its data into one that owns it.
+    pub fn into_owned(self) -> Response<'static> {
+        match self {
+            Self::SuccessfulSet => Response::SuccessfulSet,
+            Self::SuccessfulRm => Response::SuccessfulRm,
+            Self::SuccessfulGet(value) => Response::SuccessfulGet(value.into_owned().into()),
+            Self::KeyNotFound => Response::KeyNotFound,
+            Self::Err(code, e) => Response::Err(code, e.into_owned().into()),
+            Self::Pong => Response::Pong,
+            Self::Info(info) => Response::Info(info.into_owned()),
+            Self::Compacted => Response::Compacted,
+            Self::Flushed => Response::Flushed,
+            Self::Hello(hello) => Response::Hello(hello),
         }
     }
 
+    /// Writes the `Response` into a writer, preceded by its length, so that multiple responses can
+    /// be written to the same writer. These can be read with a [`ResponseReader`].
+    pub fn write_framed<W: Write>(&self, mut writer: W) -> Result<()> {
+        let mut bytes = vec![0; FRAME_LEN_BYTES];
+        self.write(&mut bytes)?;
+        let len = (bytes.len() - FRAME_LEN_BYTES) as u64;
+        bytes[..FRAME_LEN_BYTES].copy_from_slice(&len.to_be_bytes());
+
+        writer.write_all(&bytes)?;
+        Ok(())
+    }
+
+    /// Attempts to parse a response written by [`Response::write_framed`] from the start of the
+    /// passed bytes, without waiting on a reader for more data. Like [`Cmd::decode`], this allows
+    /// for reading responses from non-blocking sources.
+    ///
+    /// Returns the response and how many bytes it took up, `Ok(None)` if the bytes don't contain a
+    /// complete response yet, and an `Err` if they can't represent a `Response`. A response larger
+    /// than the limits allow is an `Err` as soon as its length arrives.
+    ///
+    /// [`Cmd::decode`]: crate::Cmd::decode
+    pub fn decode_frame(bytes: &'a [u8], limits: &Limits) -> Result<Option<(Self, usize)>> {
+        let Some(len_bytes) = bytes.get(..FRAME_LEN_BYTES) else {
+            return Ok(None);
+        };
+        let len = Self::frame_len(len_bytes.try_into().expect("specified 8 bytes"), limits)?;
+
+        let bytes_read = FRAME_LEN_BYTES
+            .checked_add(len)
+            .context("Response is too large to read")?;
+        let Some(body) = bytes.get(FRAME_LEN_BYTES..bytes_read) else {
+            return Ok(None);
+        };
+        Ok(Some((Self::from_bytes(body)?, bytes_read)))
+    }
+
+    /// Reads the bytes of a response written by [`Response::write_framed`] into `buf`, replacing
+    /// its contents, so they can be parsed with [`Response::from_bytes`]. A [`ResponseReader`]
+    /// does both. A response larger than the limits allow is an `Err`, before anything is
+    /// allocated for it.
+    pub fn read_frame<R: Read>(mut reader: R, buf: &mut Vec<u8>, limits: &Limits) -> Result<()> {
+        let mut len_bytes = [0; FRAME_LEN_BYTES];
+        reader
+            .read_exact(&mut len_bytes)
+            .context("reading response length")?;
+        let len = Self::frame_len(len_bytes, limits)?;
+
+        buf.resize(len, 0);
+        reader.read_exact(buf).context("reading response body")?;
+        Ok(())
+    }
+
+    /// Parses the length of a framed response, checking it against the limits.
+    fn frame_len(len_bytes: [u8; FRAME_LEN_BYTES], limits: &Limits) -> Result<usize> {
+        let len = u64::from_be_bytes(len_bytes);
+        limits.check_response(len)?;
+        usize::try_from(len).context("response too long")
+    }
+
     /// Writes the `Response` into a writer.
     pub fn write<W: Write>(&self, mut writer: W) -> Result<()> {
         match self {
@@ -63,10 +184,22 @@ impl<'a> Response<'a> {
                 writer.write_all(val.as_bytes())?;
             }
             Self::KeyNotFound => writer.write_all(&[NOT_FOUND_BYTE])?,
-            Self::Err(e) => {
+            Self::Err(code, e) => {
                 writer.write_all(&[ERROR_BYTE])?;
+                writer.write_all(&code.as_u16().to_be_bytes())?;
                 writer.write_all(e.as_bytes())?;
             }
+            Self::Pong => writer.write_all(&[PONG_BYTE])?,
+            Self::Info(info) => {
+                writer.write_all(&[INFO_BYTE])?;
+                info.write(&mut writer)?;
+            }
+            Self::Compacted => writer.write_all(&[COMPACTED_BYTE])?,
+            Self::Flushed => writer.write_all(&[FLUSHED_BYTE])?,
+            Self::Hello(hello) => {
+                writer.write_all(&[HELLO_BYTE])?;
+                writer.write_all(&hello.to_bytes())?;
+            }
         }
         Ok(())
     }
@@ -74,6 +207,8 @@ impl<'a> Response<'a> {
 
 #[cfg(test)]
 mod tests {
+    use std::time::Duration;
+
     use super::*;
 
     #[test]
@@ -81,7 +216,7 @@ mod tests {
         let mut buf = Vec::new();
         let expected = Response::SuccessfulSet;
         expected.write(&mut buf).unwrap();
-        let actual = Response::from_bytes(&buf);
+        let actual = Response::from_bytes(&buf).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -91,7 +226,7 @@ mod tests {
         let mut buf = Vec::new();
         let expected = Response::SuccessfulRm;
         expected.write(&mut buf).unwrap();
-        let actual = Response::from_bytes(&buf);
+        let actual = Response::from_bytes(&buf).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -101,7 +236,7 @@ mod tests {
         let mut buf = Vec::new();
         let expected = Response::SuccessfulGet("foo".into());
         expected.write(&mut buf).unwrap();
-        let actual = Response::from_bytes(&buf);
+        let actual = Response::from_bytes(&buf).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -111,7 +246,7 @@ mod tests {
         let mut buf = Vec::new();
         let expected = Response::SuccessfulGet("".into());
         expected.write(&mut buf).unwrap();
-        let actual = Response::from_bytes(&buf);
+        let actual = Response::from_bytes(&buf).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -121,7 +256,7 @@ mod tests {
         let mut buf = Vec::new();
         let expected = Response::KeyNotFound;
         expected.write(&mut buf).unwrap();
-        let actual = Response::from_bytes(&buf);
+        let actual = Response::from_bytes(&buf).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -129,9 +264,72 @@ mod tests {
     #[test]
     fn communicates_error() {
         let mut buf = Vec::new();
-        let expected = Response::Err("some error".into());
+        let expected = Response::Err(ErrorCode::NotFound, "some error".into());
         expected.write(&mut buf).unwrap();
-        let actual = Response::from_bytes(&buf);
+        let actual = Response::from_bytes(&buf).unwrap();
+
+        assert_eq!(actual, expected);
+    }
+
+    #[test]
+    fn communicates_admin_responses() {
+        let info = ServerInfo {
+            engine: "kvs".into(),
+            version: "0.1.0".into(),
+            uptime: Duration::from_millis(1234),
+            keys: 5,
+            disk_usage: Some(4096),
+        };
+        let unknown_usage = ServerInfo {
+            disk_usage: None,
+            ..info.clone()
+        };
+        for expected in [
+            Response::Pong,
+            Response::Info(info),
+            Response::Info(unknown_usage),
+            Response::Compacted,
+            Response::Flushed,
+        ] {
+            let mut buf = Vec::new();
+            expected.write(&mut buf).unwrap();
+            let actual = Response::from_bytes(&buf).unwrap();
+
+            assert_eq!(actual, expected);
+        }
+    }
+
+    #[test]
+    fn communicates_hello() {
+        let expected = Response::Hello(Hello::new(crate::Features::FRAMING));
+        let mut buf = Vec::new();
+        expected.write(&mut buf).unwrap();
+        assert_eq!(Response::from_bytes(&buf).unwrap(), expected);
+        assert!(Response::from_bytes(&buf[..3]).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_info() {
+        let bytes = [INFO_BYTE, 0, 0];
+        assert!(Response::from_bytes(&bytes).is_err());
+    }
+
+    #[test]
+    fn into_owned() {
+        let value = String::from("foo");
+        let response = Response::SuccessfulGet(Cow::Borrowed(&value));
+        let owned: Response<'static> = response.into_owned();
+        drop(value);
+
+        assert_eq!(owned, Response::SuccessfulGet("foo".into()));
+    }
+
+    #[test]
+    fn communicates_unknown_error_code() {
+        let mut buf = Vec::new();
+        let expected = Response::Err(ErrorCode::Unknown(999), "from the future".into());
+        expected.write(&mut buf).unwrap();
+        let actual = Response::from_bytes(&buf).unwrap();
 
         assert_eq!(actual, expected);
     }
@@ -141,29 +339,90 @@ mod tests {
 
         #[test]
         fn handles_non_utf8() {
-            let bytes = [255];
-            let actual = Response::from_bytes(&bytes);
-            let expected = Response::Err("Invalid utf8".into());
+            let bytes = [SUCCESSFUL_GET_BYTE, 255];
+            let actual = Response::from_bytes(&bytes).unwrap_err();
 
-            assert_eq!(actual, expected);
+            assert_eq!(actual.to_string(), "Invalid utf8");
         }
 
         #[test]
         fn handles_invalid_payload() {
             let bytes = b"blahblah";
-            let actual = Response::from_bytes(bytes);
-            let expected = Response::Err("Invalid start byte".into());
+            let actual = Response::from_bytes(bytes).unwrap_err();
 
-            assert_eq!(actual, expected);
+            assert_eq!(actual.to_string(), "Invalid start byte 0x62");
         }
 
         #[test]
         fn handles_empty_payload() {
             let bytes = b"";
-            let actual = Response::from_bytes(bytes);
-            let expected = Response::Err("Invalid start byte".into());
+            let actual = Response::from_bytes(bytes).unwrap_err();
+
+            assert_eq!(actual.to_string(), "Empty response");
+        }
+
+        #[test]
+        fn handles_missing_error_code() {
+            let bytes = b"e1";
+            let actual = Response::from_bytes(bytes).unwrap_err();
+
+            assert_eq!(actual.to_string(), "Missing error code");
+        }
+    }
+
+    mod decode_frame_tests {
+        use super::*;
+        use crate::TooLarge;
+
+        #[test]
+        fn decodes_complete_response() {
+            let mut bytes = Vec::new();
+            let expected = Response::SuccessfulGet("foo".into());
+            expected.write_framed(&mut bytes).unwrap();
+            bytes.extend(b"next");
 
+            let (actual, bytes_read) = Response::decode_frame(&bytes, &Limits::default())
+                .unwrap()
+                .unwrap();
             assert_eq!(actual, expected);
+            assert_eq!(bytes_read, 12);
+        }
+
+        #[test]
+        fn waits_for_complete_response() {
+            let mut bytes = Vec::new();
+            Response::SuccessfulGet("foo".into())
+                .write_framed(&mut bytes)
+                .unwrap();
+
+            for len in 0..bytes.len() {
+                let decoded = Response::decode_frame(&bytes[..len], &Limits::default());
+                assert!(decoded.unwrap().is_none());
+            }
+        }
+
+        #[test]
+        fn handles_invalid_response() {
+            let mut bytes = 1u64.to_be_bytes().to_vec();
+            bytes.push(b'x');
+
+            let actual = Response::decode_frame(&bytes, &Limits::default()).unwrap_err();
+            assert_eq!(actual.to_string(), "Invalid start byte 0x78");
+        }
+
+        #[test]
+        fn rejects_large_response() {
+            let limits = Limits {
+                max_key_bytes: 3,
+                max_value_bytes: 5,
+            };
+            let bytes = (1024 * 1024u64).to_be_bytes();
+            let e = Response::decode_frame(&bytes, &limits).unwrap_err();
+            assert!(e.is::<TooLarge>());
+
+            // A length that would overflow is rejected even without limits.
+            let bytes = u64::MAX.to_be_bytes();
+            assert!(Response::decode_frame(&bytes, &Limits::NONE).is_err());
         }
     }
 }
@@ -0,0 +1,102 @@
+//! A [`ResponseReader`] reads framed [`Response`]s from a connection kept alive with
+//! [`Cmd::KeepAlive`][crate::Cmd::KeepAlive].
+
+use std::io::Read;
+
+use anyhow::Result;
+
+use super::Response;
+use crate::Limits;
+
+/// Reader for framed [`Response`]s, as written by [`Response::write_framed`]. Like
+/// [`Reader`][crate::Reader], this holds a single allocation to read every response into.
+#[derive(Default)]
+pub struct ResponseReader {
+    buf: Vec<u8>,
+    limits: Limits,
+}
+
+impl ResponseReader {
+    /// Instantiates a `ResponseReader` that accepts responses within the default [`Limits`].
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Creates a reader that accepts responses carrying values within the limits.
+    pub fn with_limits(limits: Limits) -> Self {
+        Self {
+            buf: Vec::new(),
+            limits,
+        }
+    }
+
+    /// Reads the next [`Response`] out of the provided reader.
+    ///
+    /// Unlike reading commands, running out of data is an `Err`, because responses are only read
+    /// after sending a command that the server must respond to.
+    pub fn read_response(&mut self, reader: impl Read) -> Result<Response<'_>> {
+        Response::read_frame(reader, &mut self.buf, &self.limits)?;
+        Response::from_bytes(&self.buf)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::ErrorCode;
+
+    use super::*;
+
+    #[test]
+    fn reads_each_response() {
+        let responses = [
+            Response::SuccessfulSet,
+            Response::SuccessfulGet("foo".into()),
+            Response::KeyNotFound,
+            Response::Err(ErrorCode::NotFound, "Key not found".into()),
+            Response::SuccessfulRm,
+        ];
+
+        let mut bytes = Vec::new();
+        for response in &responses {
+            response.write_framed(&mut bytes).unwrap();
+        }
+
+        let mut bytes = &*bytes;
+        let mut reader = ResponseReader::new();
+        for response in &responses {
+            assert_eq!(&reader.read_response(&mut bytes).unwrap(), response);
+        }
+        assert!(reader.read_response(&mut bytes).is_err());
+    }
+
+    #[test]
+    fn truncated_response() {
+        let mut bytes = Vec::new();
+        Response::SuccessfulGet("foo".into())
+            .write_framed(&mut bytes)
+            .unwrap();
+        bytes.pop();
+
+        assert!(ResponseReader::new().read_response(&*bytes).is_err());
+    }
+
+    #[test]
+    fn limits_responses() {
+        let mut bytes = Vec::new();
+        Response::SuccessfulGet("foo".into())
+            .write_framed(&mut bytes)
+            .unwrap();
+        let limits = Limits {
+            max_key_bytes: 0,
+            max_value_bytes: 0,
+        };
+        assert!(ResponseReader::with_limits(limits)
+            .read_response(&*bytes)
+            .is_ok());
+
+        let huge = u64::MAX.to_be_bytes();
+        assert!(ResponseReader::with_limits(limits)
+            .read_response(&huge[..])
+            .is_err());
+    }
+}
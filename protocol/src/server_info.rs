@@ -0,0 +1,88 @@
+//! [`ServerInfo`] describes a running server, in response to [`Cmd::Info`][crate::Cmd::Info].
+
+use std::borrow::Cow;
+use std::io::Write;
+use std::time::Duration;
+
+use anyhow::{ensure, Context, Result};
+
+// Implementation details:
+//
+// Info is encoded as 8 bytes each for the uptime in milliseconds, the key count and the disk
+// usage (`u64::MAX` if unknown), then 4 bytes for the engine's length, the engine and finally the
+// version.
+const U64_BYTES: usize = 8;
+const ENGINE_LEN_BYTES: usize = 4;
+const UNKNOWN_DISK_USAGE: u64 = u64::MAX;
+
+/// Description of a running server.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ServerInfo<'a> {
+    /// Type of engine storing the data, e.g. "kvs".
+    pub engine: Cow<'a, str>,
+    /// Version of the server.
+    pub version: Cow<'a, str>,
+    /// How long the server has been running.
+    pub uptime: Duration,
+    /// Number of keys associated with a value.
+    pub keys: u64,
+    /// Bytes the engine's files take up on disk, if the engine knows.
+    pub disk_usage: Option<u64>,
+}
+
+impl<'a> ServerInfo<'a> {
+    pub(crate) fn write<W: Write>(&self, mut writer: W) -> Result<()> {
+        let uptime = u64::try_from(self.uptime.as_millis()).unwrap_or(u64::MAX);
+        writer.write_all(&uptime.to_be_bytes())?;
+        writer.write_all(&self.keys.to_be_bytes())?;
+        let disk_usage = self.disk_usage.unwrap_or(UNKNOWN_DISK_USAGE);
+        writer.write_all(&disk_usage.to_be_bytes())?;
+        writer.write_all(&(self.engine.len() as u32).to_be_bytes())?;
+        writer.write_all(self.engine.as_bytes())?;
+        writer.write_all(self.version.as_bytes())?;
+        Ok(())
+    }
+
+    pub(crate) fn from_bytes(bytes: &'a [u8]) -> Result<Self> {
+        ensure!(
+            bytes.len() >= 3 * U64_BYTES + ENGINE_LEN_BYTES,
+            "Missing info fields"
+        );
+        let (numbers, rest) = bytes.split_at(3 * U64_BYTES);
+        let mut numbers = numbers
+            .chunks_exact(U64_BYTES)
+            .map(|chunk| u64::from_be_bytes(chunk.try_into().expect("chunks of 8 bytes")));
+        let uptime = numbers.next().expect("3 numbers");
+        let keys = numbers.next().expect("3 numbers");
+        let disk_usage = numbers.next().expect("3 numbers");
+
+        let (engine_len, rest) = rest.split_at(ENGINE_LEN_BYTES);
+        let engine_len = u32::from_be_bytes(engine_len.try_into().expect("specified 4 bytes"));
+        let (engine, version) = rest
+            .split_at_checked(engine_len as usize)
+            .context("Insufficient data for engine")?;
+
+        Ok(Self {
+            engine: parse_str(engine)?.into(),
+            version: parse_str(version)?.into(),
+            uptime: Duration::from_millis(uptime),
+            keys,
+            disk_usage: (disk_usage != UNKNOWN_DISK_USAGE).then_some(disk_usage),
+        })
+    }
+
+    /// Converts `ServerInfo` borrowing its strings into one that owns them.
+    pub fn into_owned(self) -> ServerInfo<'static> {
+        ServerInfo {
+            engine: self.engine.into_owned().into(),
+            version: self.version.into_owned().into(),
+            uptime: self.uptime,
+            keys: self.keys,
+            disk_usage: self.disk_usage,
+        }
+    }
+}
+
+fn parse_str(bytes: &[u8]) -> Result<&str> {
+    std::str::from_utf8(bytes).map_err(|_| anyhow::Error::msg("Invalid utf8"))
+}
@@ -1,6 +1,16 @@
 use assert_cmd::prelude::*;
+use kvs_client::{
+    AsyncClient, Client, ClientTls, Connection, ErrorCode, Features, Hello, RetryPolicy,
+    ServerError,
+};
 use predicates::str::{contains, is_empty};
+use protocol::{Cmd, Response};
+use rcgen::{BasicConstraints, CertificateParams, IsCa, KeyPair};
 use std::fs::{self, File};
+use std::io::{Read, Write};
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::PermissionsExt;
+use std::path::Path;
 use std::process::Command;
 use std::sync::mpsc;
 use std::thread;
@@ -164,6 +174,7 @@ fn cli_log_configuration() {
         .unwrap();
     thread::sleep(Duration::from_secs(1));
     child.kill().expect("server exited before killed");
+    child.wait().unwrap();
 
     let content = fs::read_to_string(&stderr_path).expect("unable to read from stderr file");
     assert!(content.contains(env!("CARGO_PKG_VERSION")));
@@ -184,6 +195,7 @@ fn cli_wrong_engine() {
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         cmd.args(["--engine", "kvs", "--addr", "127.0.0.1:4003"])
@@ -203,6 +215,7 @@ fn cli_wrong_engine() {
             .unwrap();
         thread::sleep(Duration::from_secs(1));
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
 
         let mut cmd = Command::cargo_bin("kvs-server").unwrap();
         cmd.args(["--engine", "sled", "--addr", "127.0.0.1:4003"])
@@ -212,12 +225,13 @@ fn cli_wrong_engine() {
     }
 }
 
-fn cli_access_server(engine: &str, addr: &str) {
+fn cli_access_server(engine: &str, addr: &str, server_args: &[&str]) {
     let (sender, receiver) = mpsc::sync_channel(0);
     let temp_dir = TempDir::new().unwrap();
     let mut server = Command::cargo_bin("kvs-server").unwrap();
-    let child = server
+    let mut child = server
         .args(["--engine", engine, "--addr", addr])
+        .args(server_args)
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
@@ -231,6 +245,7 @@ fn cli_access_server(engine: &str, addr: &str) {
             .spawn()
             .unwrap();
         kill.wait().expect("server exited before killed");
+        child.wait().unwrap();
     });
     thread::sleep(Duration::from_secs(1));
 
@@ -310,12 +325,14 @@ fn cli_access_server(engine: &str, addr: &str) {
     let mut server = Command::cargo_bin("kvs-server").unwrap();
     let mut child = server
         .args(["--engine", engine, "--addr", addr])
+        .args(server_args)
         .current_dir(&temp_dir)
         .spawn()
         .unwrap();
     let handle = thread::spawn(move || {
         let _ = receiver.recv(); // wait for main thread to finish
         child.kill().expect("server exited before killed");
+        child.wait().unwrap();
     });
     thread::sleep(Duration::from_secs(1));
 
@@ -339,10 +356,749 @@ fn cli_access_server(engine: &str, addr: &str) {
 
 #[test]
 fn cli_access_server_kvs_engine() {
-    cli_access_server("kvs", "127.0.0.1:4004");
+    cli_access_server("kvs", "127.0.0.1:4004", &[]);
 }
 
 #[test]
 fn cli_access_server_sled_engine() {
-    cli_access_server("sled", "127.0.0.1:4005");
+    cli_access_server("sled", "127.0.0.1:4005", &[]);
+}
+
+#[test]
+fn cli_access_server_async_runtime() {
+    cli_access_server("kvs", "127.0.0.1:4010", &["--runtime", "async"]);
+}
+
+#[test]
+fn cli_access_server_memory_engine() {
+    let addr = "127.0.0.1:4006";
+    let temp_dir = TempDir::new().unwrap();
+    let mut server = Command::cargo_bin("kvs-server").unwrap();
+    let mut child = server
+        .args(["--engine", "memory", "--addr", addr])
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["set", "key1", "value1", "--addr", addr])
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["rm", "key1", "--addr", addr])
+        .assert()
+        .success()
+        .stdout(is_empty());
+
+    Command::cargo_bin("kvs-client")
+        .unwrap()
+        .args(["get", "key1", "--addr", addr])
+        .assert()
+        .success()
+        .stdout(contains("Key not found"));
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+
+    // The memory engine shouldn't have written anything to disk.
+    assert_eq!(fs::read_dir(&temp_dir).unwrap().count(), 0);
+}
+
+#[test]
+fn cli_access_server_thread_pools() {
+    for (pool, addr) in [
+        ("naive", "127.0.0.1:4007"),
+        ("shared-queue", "127.0.0.1:4008"),
+        ("work-stealing", "127.0.0.1:4009"),
+    ] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = server
+            .args(["--engine", "memory", "--addr", addr, "--pool", pool])
+            .args(["--threads", "2"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let clients = (0..8)
+            .map(|i| {
+                thread::spawn(move || {
+                    let key = format!("key{i}");
+                    let value = format!("value{i}");
+                    Command::cargo_bin("kvs-client")
+                        .unwrap()
+                        .args(["set", &key, &value, "--addr", addr])
+                        .assert()
+                        .success();
+                    Command::cargo_bin("kvs-client")
+                        .unwrap()
+                        .args(["get", &key, "--addr", addr])
+                        .assert()
+                        .success()
+                        .stdout(format!("{value}\n"));
+                })
+            })
+            .collect::<Vec<_>>();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn persistent_connection_pipelining() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4011"), ("async", "127.0.0.1:4012")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = server
+            .args(["--engine", "memory", "--addr", addr, "--runtime", runtime])
+            .args(["--threads", "2"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let mut connection = Connection::connect(addr.parse().unwrap()).unwrap();
+        connection.set("key1", "value1").unwrap();
+        assert_eq!(connection.get("key1").unwrap().as_deref(), Some("value1"));
+
+        let responses = connection
+            .pipeline(&[
+                Cmd::Set("key2".into(), "value2".into()),
+                Cmd::Get("key2".into()),
+                Cmd::Rm("key1".into()),
+                Cmd::Rm("key1".into()),
+                Cmd::Get("key1".into()),
+            ])
+            .unwrap();
+        assert_eq!(
+            responses,
+            [
+                Response::SuccessfulSet,
+                Response::SuccessfulGet("value2".into()),
+                Response::SuccessfulRm,
+                Response::Err(ErrorCode::NotFound, "Key not found".into()),
+                Response::KeyNotFound,
+            ]
+        );
+
+        // One-shot clients still work alongside persistent connections.
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["get", "key2", "--addr", addr])
+            .assert()
+            .success()
+            .stdout("value2\n");
+        assert_eq!(connection.get("key2").unwrap().as_deref(), Some("value2"));
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn persistent_connection_hello() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4029"), ("async", "127.0.0.1:4030")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = server
+            .args(["--engine", "memory", "--addr", addr, "--runtime", runtime])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let mut connection =
+            Connection::connect_with_hello(addr.parse().unwrap(), None, None).unwrap();
+        assert_eq!(
+            connection.hello(),
+            Some(&Hello {
+                version: 1,
+                features: Features::FRAMING,
+            })
+        );
+        connection.set("key1", "value1").unwrap();
+        assert_eq!(connection.get("key1").unwrap().as_deref(), Some("value1"));
+
+        // Clients that don't send a hello still work alongside ones that do.
+        Command::cargo_bin("kvs-client")
+            .unwrap()
+            .args(["get", "key1", "--addr", addr])
+            .assert()
+            .success()
+            .stdout("value1\n");
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn persistent_connection_idle_timeout() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4013"), ("async", "127.0.0.1:4014")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = server
+            .args(["--engine", "memory", "--addr", addr, "--runtime", runtime])
+            .args(["--idle-timeout-secs", "1"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let mut connection = Connection::connect(addr.parse().unwrap()).unwrap();
+        connection.set("key1", "value1").unwrap();
+        thread::sleep(Duration::from_secs(2));
+        assert!(connection.get("key1").is_err());
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn client_timeout_and_retries() {
+    // A "server" that accepts connections but never responds.
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (sender, receiver) = mpsc::channel();
+    thread::spawn(move || {
+        for stream in listener.incoming() {
+            sender.send(stream.unwrap()).unwrap();
+        }
+    });
+
+    let mut client = Client::builder(addr.into())
+        .read_timeout(Duration::from_millis(100))
+        .retry_policy(
+            RetryPolicy::new(2).with_backoff(Duration::from_millis(10), Duration::from_millis(10)),
+        )
+        .build();
+
+    // Gets are retried, so the server sees every attempt.
+    assert!(client.get("key1").is_err());
+    let connections = receiver.try_iter().collect::<Vec<_>>();
+    assert_eq!(connections.len(), 3);
+
+    // Sets aren't retried unless the caller asks for it.
+    assert!(client.set("key1", "value1").is_err());
+    assert_eq!(receiver.try_iter().count(), 1);
+    assert!(client.set_retrying("key1", "value1").is_err());
+    assert_eq!(receiver.try_iter().count(), 3);
+}
+
+#[test]
+fn client_connection_pool() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4015"), ("async", "127.0.0.1:4016")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = server
+            .args(["--engine", "memory", "--addr", addr, "--runtime", runtime])
+            .args(["--threads", "4"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let client = Client::builder(addr.parse().unwrap())
+            .read_timeout(Duration::from_secs(5))
+            .pool_max_idle(2)
+            .build();
+        let clients = (0..4)
+            .map(|i| {
+                let mut client = client.clone();
+                thread::spawn(move || {
+                    for j in 0..10 {
+                        let key = format!("key{i}-{j}");
+                        let value = format!("value{i}-{j}");
+                        client.set(&key, &value).unwrap();
+                        assert_eq!(client.get(&key).unwrap().as_deref(), Some(value.as_str()));
+                    }
+                    client.rm(&format!("key{i}-0")).unwrap();
+                    assert_eq!(client.get(&format!("key{i}-0")).unwrap(), None);
+                })
+            })
+            .collect::<Vec<_>>();
+        for client in clients {
+            client.join().unwrap();
+        }
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[tokio::test(flavor = "multi_thread")]
+async fn async_client_concurrent_requests() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4017"), ("async", "127.0.0.1:4018")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut server = Command::cargo_bin("kvs-server").unwrap();
+        let mut child = server
+            .args(["--engine", "memory", "--addr", addr, "--runtime", runtime])
+            .args(["--threads", "2", "--idle-timeout-secs", "1"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        tokio::time::sleep(Duration::from_secs(1)).await;
+
+        let client = AsyncClient::connect(addr.parse().unwrap()).await.unwrap();
+        let mut tasks = tokio::task::JoinSet::new();
+        for i in 0..20 {
+            let client = client.clone();
+            tasks.spawn(async move {
+                let key = format!("key{i}");
+                let value = format!("value{i}");
+                client.set(&key, &value).await.unwrap();
+                assert_eq!(client.get(&key).await.unwrap(), Some(value));
+                client.rm(&key).await.unwrap();
+                assert_eq!(client.get(&key).await.unwrap(), None);
+            });
+        }
+        while let Some(task) = tasks.join_next().await {
+            task.unwrap();
+        }
+
+        let e = client.rm("key0").await.unwrap_err();
+        let e = e.downcast_ref::<ServerError>().unwrap();
+        assert_eq!(e.code(), ErrorCode::NotFound);
+
+        // After the server closes the idle connection, the client reconnects.
+        tokio::time::sleep(Duration::from_secs(2)).await;
+        client.set("key1", "value1").await.unwrap();
+        assert_eq!(client.get("key1").await.unwrap().as_deref(), Some("value1"));
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn server_cli_config_precedence() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("kvs.toml");
+    fs::write(
+        &config_path,
+        r#"
+        addr = "127.0.0.1:5000"
+
+        [engine]
+        type = "sled"
+
+        [engine.sled]
+        compression = true
+
+        [server]
+        runtime = "async"
+        threads = 2
+
+        [limits]
+        idle_timeout_secs = 30
+        "#,
+    )
+    .unwrap();
+
+    // Arguments override environment variables, which override the file.
+    let output = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args([
+            "--print-config",
+            "--threads",
+            "3",
+            "--sled-compression=false",
+        ])
+        .arg("--config")
+        .arg(&config_path)
+        .env("KVS_ADDR", "127.0.0.1:6000")
+        .env("KVS_THREADS", "4")
+        .env("KVS_IDLE_TIMEOUT_SECS", "10")
+        .current_dir(&temp_dir)
+        .output()
+        .unwrap();
+    assert!(output.status.success());
+
+    let printed = String::from_utf8(output.stdout).unwrap();
+    let config: kvs_server::Config = toml::from_str(&printed).unwrap();
+    assert_eq!(config.addr, "127.0.0.1:6000".parse().unwrap());
+    assert_eq!(
+        config.engine.engine_type,
+        Some(kvs_server::EngineType::Sled)
+    );
+    assert!(!config.engine.sled.compression);
+    assert_eq!(config.server.runtime, kvs_server::Runtime::Async);
+    assert_eq!(config.server.threads, Some(3));
+    assert_eq!(config.limits.idle_timeout_secs, 10);
+    assert_eq!(
+        config.engine.dir.unwrap().canonicalize().unwrap(),
+        temp_dir.path().canonicalize().unwrap()
+    );
+}
+
+#[test]
+fn server_cli_invalid_config() {
+    let temp_dir = TempDir::new().unwrap();
+    let config_path = temp_dir.path().join("kvs.toml");
+    fs::write(&config_path, "[engine]\nsnapshot = \"snapshot\"\n").unwrap();
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .arg("--config")
+        .arg(&config_path)
+        .current_dir(&temp_dir)
+        .assert()
+        .failure()
+        .stderr(contains("only supported by the memory engine"));
+
+    Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--print-config", "--engine", "memory"])
+        .arg("--config")
+        .arg(&config_path)
+        .current_dir(&temp_dir)
+        .assert()
+        .success()
+        .stdout(contains("snapshot = \"snapshot\""));
+}
+
+#[test]
+fn client_cli_admin_commands() {
+    for (engine, addr) in [("kvs", "127.0.0.1:4019"), ("sled", "127.0.0.1:4020")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--engine", engine, "--addr", addr])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let client = |args: &[&str]| {
+            let mut command = Command::cargo_bin("kvs-client").unwrap();
+            command.args(args).args(["--addr", addr]);
+            command
+        };
+
+        client(&["ping"]).assert().success().stdout("PONG\n");
+        client(&["set", "key1", "value1"]).assert().success();
+        client(&["set", "key1", "value2"]).assert().success();
+        client(&["info"])
+            .assert()
+            .success()
+            .stdout(contains(format!("engine: {engine}\n")))
+            .stdout(contains("keys: 1\n"))
+            .stdout(contains("uptime_secs: "));
+        client(&["flush"]).assert().success().stdout(is_empty());
+
+        if engine == "kvs" {
+            client(&["compact"]).assert().success().stdout(is_empty());
+            client(&["get", "key1"])
+                .assert()
+                .success()
+                .stdout("value2\n");
+        } else {
+            client(&["compact"])
+                .assert()
+                .failure()
+                .stderr(contains("doesn't compact"));
+        }
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn client_cli_token_auth() {
+    let temp_dir = TempDir::new().unwrap();
+    let token_file = temp_dir.path().join("token");
+    fs::write(&token_file, "secret\n").unwrap();
+    let addr = "127.0.0.1:4021";
+    let mut child = Command::cargo_bin("kvs-server")
+        .unwrap()
+        .args(["--addr", addr, "--auth-token-file"])
+        .arg(&token_file)
+        .current_dir(&temp_dir)
+        .spawn()
+        .unwrap();
+    thread::sleep(Duration::from_secs(1));
+
+    let client = |args: &[&str]| {
+        let mut command = Command::cargo_bin("kvs-client").unwrap();
+        command
+            .args(args)
+            .args(["--addr", addr])
+            .env_remove("KVS_TOKEN");
+        command
+    };
+
+    client(&["set", "key1", "value1"])
+        .assert()
+        .failure()
+        .stderr(contains("Authentication required"));
+    client(&["set", "key1", "value1", "--token", "wrong"])
+        .assert()
+        .failure()
+        .stderr(contains("Invalid auth token"));
+    client(&["set", "key1", "value1", "--token", "secret"])
+        .assert()
+        .success();
+    client(&["get", "key1"])
+        .env("KVS_TOKEN", "secret")
+        .assert()
+        .success()
+        .stdout("value1\n");
+
+    child.kill().expect("server exited before killed");
+    child.wait().unwrap();
+}
+
+#[test]
+fn client_cli_unix_socket() {
+    let temp_dir = TempDir::new().unwrap();
+    let socket = temp_dir.path().join("kvs.sock");
+    let unix_addr = format!("unix://{}", socket.display());
+    let tcp_addr = "127.0.0.1:4024";
+
+    // The server is killed without removing its socket, so the second server has to replace it.
+    for runtime in ["sync", "async"] {
+        let mut child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args([
+                "--runtime",
+                runtime,
+                "--addr",
+                tcp_addr,
+                "--unix-only",
+                "--unix",
+            ])
+            .arg(&socket)
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+        let mode = fs::metadata(&socket).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o660);
+
+        let client = |args: &[&str], addr: &str| {
+            let mut command = Command::cargo_bin("kvs-client").unwrap();
+            command.args(args).args(["--addr", addr]);
+            command
+        };
+        client(&["set", "key1", runtime], &unix_addr)
+            .assert()
+            .success();
+        client(&["get", "key1"], &unix_addr)
+            .assert()
+            .success()
+            .stdout(format!("{runtime}\n"));
+        client(&["get", "key1"], tcp_addr).assert().failure();
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn server_cli_resp_protocol() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4025"), ("async", "127.0.0.1:4026")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", addr, "--runtime", runtime, "--protocol", "resp"])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        // Pipelined, as redis-cli sends them with `--pipe`, with an inline command in between.
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"*3\r\n$3\r\nSET\r\n$4\r\nkey1\r\n$6\r\nvalue1\r\n\
+                  GET key1\r\n\
+                  *3\r\n$6\r\nEXISTS\r\n$4\r\nkey1\r\n$4\r\nkey2\r\n\
+                  *2\r\n$3\r\nDEL\r\n$4\r\nkey1\r\n\
+                  *2\r\n$3\r\nGET\r\n$4\r\nkey1\r\n\
+                  *2\r\n$5\r\nLPUSH\r\n$4\r\nlist\r\n\
+                  *1\r\n$4\r\nQUIT\r\n",
+            )
+            .unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "+OK\r\n$6\r\nvalue1\r\n:1\r\n:1\r\n$-1\r\n-ERR unknown command 'lpush'\r\n+OK\r\n"
+        );
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+#[test]
+fn server_cli_memcached_protocol() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4027"), ("async", "127.0.0.1:4028")] {
+        let temp_dir = TempDir::new().unwrap();
+        let mut child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args([
+                "--addr",
+                addr,
+                "--runtime",
+                runtime,
+                "--protocol",
+                "memcached",
+            ])
+            .current_dir(&temp_dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let mut stream = TcpStream::connect(addr).unwrap();
+        stream
+            .write_all(
+                b"set key1 0 0 6\r\nvalue1\r\n\
+                  add key1 0 0 1\r\nx\r\n\
+                  set key2 0 0 1 noreply\r\n2\r\n\
+                  get key1 key2 key3\r\n\
+                  set key3 5 0 1\r\n3\r\n\
+                  delete key1\r\n\
+                  delete key1\r\n\
+                  incr key2 1\r\n\
+                  quit\r\n",
+            )
+            .unwrap();
+        let mut replies = String::new();
+        stream.read_to_string(&mut replies).unwrap();
+        assert_eq!(
+            replies,
+            "STORED\r\nNOT_STORED\r\n\
+             VALUE key1 0 6\r\nvalue1\r\nVALUE key2 0 1\r\n2\r\nEND\r\n\
+             CLIENT_ERROR flags aren't supported, only 0\r\n\
+             DELETED\r\nNOT_FOUND\r\nERROR\r\n"
+        );
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
+}
+
+/// Writes a CA certificate, and a certificate and key signed by it for each name, as PEM files in
+/// the directory.
+fn write_certs(dir: &Path, ca_name: &str, names: &[&str]) {
+    let ca_key = KeyPair::generate().unwrap();
+    let mut ca_params = CertificateParams::new(Vec::<String>::new()).unwrap();
+    ca_params.is_ca = IsCa::Ca(BasicConstraints::Unconstrained);
+    let ca = ca_params.self_signed(&ca_key).unwrap();
+    fs::write(dir.join(format!("{ca_name}.pem")), ca.pem()).unwrap();
+
+    for name in names {
+        let key = KeyPair::generate().unwrap();
+        let params =
+            CertificateParams::new(vec!["127.0.0.1".to_owned(), "localhost".to_owned()]).unwrap();
+        let cert = params.signed_by(&key, &ca, &ca_key).unwrap();
+        fs::write(dir.join(format!("{name}.pem")), cert.pem()).unwrap();
+        fs::write(dir.join(format!("{name}.key")), key.serialize_pem()).unwrap();
+    }
+}
+
+#[test]
+fn client_cli_mutual_tls() {
+    for (runtime, addr) in [("sync", "127.0.0.1:4022"), ("async", "127.0.0.1:4023")] {
+        let temp_dir = TempDir::new().unwrap();
+        let dir = temp_dir.path();
+        write_certs(dir, "ca", &["server", "client"]);
+        write_certs(dir, "other-ca", &["other-client"]);
+        let mut child = Command::cargo_bin("kvs-server")
+            .unwrap()
+            .args(["--addr", addr, "--runtime", runtime])
+            .args(["--tls-cert", "server.pem", "--tls-key", "server.key"])
+            .args(["--tls-client-ca", "ca.pem"])
+            .current_dir(dir)
+            .spawn()
+            .unwrap();
+        thread::sleep(Duration::from_secs(1));
+
+        let client = |args: &[&str]| {
+            let mut command = Command::cargo_bin("kvs-client").unwrap();
+            command.args(args).args(["--addr", addr]).current_dir(dir);
+            command
+        };
+        let tls = [
+            "--tls-ca",
+            "ca.pem",
+            "--tls-cert",
+            "client.pem",
+            "--tls-key",
+            "client.key",
+        ];
+
+        client(&["set", "key1", "value1"])
+            .args(tls)
+            .assert()
+            .success();
+        client(&["get", "key1", "--tls-server-name", "localhost"])
+            .args(tls)
+            .assert()
+            .success()
+            .stdout("value1\n");
+
+        // Plain TCP, no client certificate, a client certificate from another CA, and a server
+        // certificate from a CA other than the pinned one are all rejected.
+        client(&["get", "key1"]).assert().failure();
+        client(&["get", "key1", "--tls-ca", "ca.pem"])
+            .assert()
+            .failure();
+        client(&["get", "key1", "--tls-ca", "ca.pem"])
+            .args([
+                "--tls-cert",
+                "other-client.pem",
+                "--tls-key",
+                "other-client.key",
+            ])
+            .assert()
+            .failure();
+        client(&["get", "key1", "--tls-ca", "other-ca.pem"])
+            .args(["--tls-cert", "client.pem", "--tls-key", "client.key"])
+            .assert()
+            .failure()
+            .stderr(contains("TLS handshake failed"));
+        client(&["get", "key1", "--tls-server-name", "kvs.example.com"])
+            .args(tls)
+            .assert()
+            .failure()
+            .stderr(contains("TLS handshake failed"));
+
+        // Pooled connections are kept alive over TLS too.
+        let tls = ClientTls::pinned(dir.join("ca.pem"))
+            .unwrap()
+            .with_client_cert(dir.join("client.pem"), dir.join("client.key"))
+            .unwrap();
+        let mut client = Client::builder(addr.parse().unwrap())
+            .pool_max_idle(1)
+            .tls(tls)
+            .build();
+        for value in ["value2", "value3", "value4"] {
+            client.set("key2", value).unwrap();
+            assert_eq!(client.get("key2").unwrap().as_deref(), Some(value));
+        }
+
+        child.kill().expect("server exited before killed");
+        child.wait().unwrap();
+    }
 }